@@ -0,0 +1,55 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Context;
+use tokio::io::AsyncWriteExt;
+
+/// Reads the keys of already downloaded episodes, one per line.
+/// A missing archive file is treated as empty.
+pub(crate) async fn read_archive(path: &Path) -> Result<HashSet<String>, anyhow::Error> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err).context("failed to read archive file"),
+    };
+
+    Ok(parse_archive(&content))
+}
+
+fn parse_archive(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+/// Appends the key of a downloaded episode to the archive.
+pub(crate) async fn append_to_archive(path: &Path, key: &str) -> Result<(), anyhow::Error> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .context("failed to open archive file")?;
+    file.write_all(format!("{key}\n").as_bytes())
+        .await
+        .context("failed to write to archive file")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::parse_archive;
+
+    #[test]
+    fn test_parse_archive() {
+        let archive = parse_archive("aniworld detektiv-conan S01E002 GerDub\n\n  sto dark S02E001 GerDub  \n");
+        assert_eq!(archive.len(), 2);
+        assert!(archive.contains("aniworld detektiv-conan S01E002 GerDub"));
+        assert!(archive.contains("sto dark S02E001 GerDub"));
+    }
+}
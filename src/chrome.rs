@@ -1,23 +1,60 @@
+use std::fs::File;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Context;
+use clap::ValueEnum;
+use fs2::FileExt;
 use selenium_manager::SeleniumManager;
 use thirtyfour::common::config::WebDriverConfigBuilder;
+use thirtyfour::extensions::addons::firefox::FirefoxTools;
 use thirtyfour::extensions::query::ElementPollerNoWait;
-use thirtyfour::ChromiumLikeCapabilities;
+use thirtyfour::{ChromiumLikeCapabilities, FirefoxPreferences};
+use url::Url;
 
+use crate::cookies;
 use crate::download::{self, Downloader, InternalDownloadTask};
-use crate::utils::{remove_dir_all_ignore_not_exists, remove_file_ignore_not_exists};
+use crate::utils::{parse_version_cache, remove_dir_all_ignore_not_exists, remove_file_ignore_not_exists};
 
+/// Delay before the first retry of connecting to the WebDriver, which is
+/// doubled after each attempt up to the maximum.
+const DRIVER_CONNECT_INITIAL_DELAY: Duration = Duration::from_millis(50);
+const DRIVER_CONNECT_MAX_DELAY: Duration = Duration::from_secs(1);
 const UBLOCK_GITHUB_API_URL: &str = "https://api.github.com/repos/gorhill/uBlock/releases/latest";
+const UBLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const UBLOCK_CUSTOM_RULES_KEY: &str = "sdl-custom-rules";
+const UBLOCK_CUSTOM_RULES_PATH: &str = "assets/user/sdl-custom-rules.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum WebDriverBackend {
+    Chrome,
+    Firefox,
+}
+
+impl WebDriverBackend {
+    fn driver_name(&self) -> &'static str {
+        match self {
+            WebDriverBackend::Chrome => "ChromeDriver",
+            WebDriverBackend::Firefox => "GeckoDriver",
+        }
+    }
+}
 
 pub(crate) struct ChromeDriver<'a> {
     data_dir: &'a Path,
     downloader: &'a Downloader,
+    ublock_rules: Option<&'a Path>,
+    backend: WebDriverBackend,
+    proxy: Option<&'a Url>,
+    user_agent: Option<&'a str>,
+    user_data_dir: Option<&'a Path>,
+    ublock: bool,
+    update_ublock: bool,
+    connect_timeout: Duration,
+    browser_args: &'a [String],
 }
 
 impl<'a> ChromeDriver<'a> {
@@ -25,38 +62,120 @@ impl<'a> ChromeDriver<'a> {
         data_dir: &'a Path,
         downloader: &'a Downloader,
         headless: bool,
+        ublock_rules: Option<&'a Path>,
+        backend: WebDriverBackend,
+        proxy: Option<&'a Url>,
+        user_agent: Option<&'a str>,
+        user_data_dir: Option<&'a Path>,
+        ublock: bool,
+        update_ublock: bool,
+        connect_timeout: Duration,
+        browser_args: &'a [String],
     ) -> Result<(thirtyfour::WebDriver, Child), anyhow::Error> {
-        let chrome_driver = ChromeDriver { data_dir, downloader };
+        let chrome_driver = ChromeDriver {
+            data_dir,
+            downloader,
+            ublock_rules,
+            backend,
+            proxy,
+            user_agent,
+            user_data_dir,
+            ublock,
+            update_ublock,
+            connect_timeout,
+            browser_args,
+        };
         chrome_driver.chrome_driver(headless).await
     }
 
     async fn chrome_driver(&self, headless: bool) -> Result<(thirtyfour::WebDriver, Child), anyhow::Error> {
-        // Launch ChromeDriver
-        let (chromedriver_path, browser_path) = Self::get_chromedriver_and_browser_path()
-            .await
-            .context("failed to find or fetch ChromeDriver")?;
+        let driver_name = self.backend.driver_name();
+
+        // Launch ChromeDriver or GeckoDriver
+        let (driver_path, browser_path) = match self.backend {
+            WebDriverBackend::Chrome => Self::get_chromedriver_and_browser_path(self.proxy).await,
+            WebDriverBackend::Firefox => Self::get_geckodriver_and_browser_path(self.proxy).await,
+        }
+        .with_context(|| format!("failed to find or fetch {driver_name}"))?;
 
         let Some(port) = portpicker::pick_unused_port() else {
-            anyhow::bail!("no free port found for ChromeDriver");
+            anyhow::bail!("no free port found for {}", driver_name);
         };
 
-        log::trace!("Starting ChromeDriver on port {}", port);
+        log::trace!("Starting {} on port {}", driver_name, port);
 
-        let mut chromedriver_cmd = Command::new(chromedriver_path);
+        let mut driver_cmd = Command::new(driver_path);
 
         if headless {
-            chromedriver_cmd
+            driver_cmd
                 .stdin(Stdio::null())
                 .stdout(Stdio::null())
                 .stderr(Stdio::null());
         }
 
-        let child_process = chromedriver_cmd
+        let child_process = driver_cmd
             .arg(format!("--port={}", port))
             .spawn()
-            .context("failed to start ChromeDriver")?;
+            .with_context(|| format!("failed to start {driver_name}"))?;
+
+        // Capabilities
+        let caps = match self.backend {
+            WebDriverBackend::Chrome => self.chrome_capabilities(&browser_path, headless).await?,
+            WebDriverBackend::Firefox => self.firefox_capabilities(&browser_path, headless)?,
+        };
+
+        // Initialize WebDriver, while it is still starting up
+        let driver = {
+            let deadline = Instant::now() + self.connect_timeout;
+            let mut delay = DRIVER_CONNECT_INITIAL_DELAY;
+
+            loop {
+                match thirtyfour::WebDriver::new_with_config(
+                    &format!("http://localhost:{}", port),
+                    caps.clone(),
+                    WebDriverConfigBuilder::new()
+                        .poller(Arc::new(ElementPollerNoWait))
+                        .build(),
+                )
+                .await
+                {
+                    Ok(driver) => {
+                        break driver;
+                    }
+                    Err(err) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+
+                        if remaining.is_zero() {
+                            return Err(err).with_context(|| {
+                                format!(
+                                    "could not connect to {driver_name} within {}s",
+                                    self.connect_timeout.as_secs_f32()
+                                )
+                            });
+                        }
+
+                        log::trace!("Failed to connect to {}, retrying: {}", driver_name, err);
+                        tokio::time::sleep(delay.min(remaining)).await;
+                        delay = (delay * 2).min(DRIVER_CONNECT_MAX_DELAY);
+                    }
+                }
+            }
+        };
+
+        match self.backend {
+            WebDriverBackend::Chrome => Self::patch_chrome(&driver).await,
+            WebDriverBackend::Firefox if self.ublock => self.install_firefox_ublock(&driver).await,
+            WebDriverBackend::Firefox => log::debug!("Skipping uBlock Origin"),
+        }
 
-        // ChromeDriver Capabilities
+        Ok((driver, child_process))
+    }
+
+    async fn chrome_capabilities(
+        &self,
+        browser_path: &str,
+        headless: bool,
+    ) -> Result<thirtyfour::Capabilities, anyhow::Error> {
         let mut caps = thirtyfour::DesiredCapabilities::chrome();
         caps.set_binary(&browser_path)
             .with_context(|| format!("failed to set browser path to: {}", browser_path))?;
@@ -71,8 +190,47 @@ impl<'a> ChromeDriver<'a> {
             caps.add_exclude_switch("enable-logging").unwrap();
         }
         caps.add_exclude_switch("enable-automation").unwrap();
+        if let Some(proxy) = self.proxy {
+            // Chrome always resolves host names through SOCKS5 proxies, so socks5h is just socks5
+            let scheme = if proxy.scheme() == "socks5h" {
+                "socks5"
+            } else {
+                proxy.scheme()
+            };
+            let host = proxy.host_str().context("proxy url has no host")?;
+            let port = proxy.port_or_known_default().context("proxy url has no port")?;
+            caps.add_arg(&format!("--proxy-server={scheme}://{host}:{port}"))
+                .unwrap();
+        }
+        if let Some(user_agent) = self.user_agent {
+            caps.add_arg(&format!("--user-agent={user_agent}")).unwrap();
+        }
+        if let Some(user_data_dir) = self.user_data_dir {
+            // Keeps cookies, e.g. of solved captchas, between runs
+            let user_data_dir = user_data_dir
+                .to_str()
+                .context("path to browser profile is not valid UTF-8")?;
+            caps.add_arg(&format!("--user-data-dir={user_data_dir}")).unwrap();
+        }
+
+        // Add uBlock Origin extension, if possible. The browser is still
+        // launched without it, if it fails.
+        if self.ublock {
+            self.add_chrome_ublock(&mut caps).await;
+        } else {
+            log::debug!("Skipping uBlock Origin");
+        }
 
-        // Add uBlock Origin extension, if possible
+        // Added last, so that the defaults can be overridden
+        for browser_arg in self.browser_args {
+            caps.add_arg(browser_arg)
+                .with_context(|| format!("failed to add browser argument: {}", browser_arg))?;
+        }
+
+        Ok(caps.into())
+    }
+
+    async fn add_chrome_ublock(&self, caps: &mut thirtyfour::ChromeCapabilities) {
         let ublock_dir = self.data_dir.join("uBlock");
 
         if let Err(err) = self.prepare_ublock(&ublock_dir).await {
@@ -81,6 +239,10 @@ impl<'a> ChromeDriver<'a> {
 
         match Self::get_ublock_directory(&ublock_dir).await {
             Ok(ublock_dir) => {
+                if let Err(err) = self.install_ublock_rules(&ublock_dir).await {
+                    log::warn!("Failed to install custom uBlock Origin rules: {:#}", err);
+                }
+
                 if let Some(ublock_dir) = ublock_dir.to_str() {
                     caps.add_arg(&format!("--load-extension={ublock_dir}")).unwrap();
                 } else {
@@ -89,37 +251,65 @@ impl<'a> ChromeDriver<'a> {
             }
             Err(err) => log::warn!("Failed to add uBlock Origin as extension: {:#}", err),
         }
+    }
 
-        // Initialize ChromeDriver (try for 5 seconds)
-        let driver = {
-            let mut tries = 0u8;
+    fn firefox_capabilities(
+        &self,
+        browser_path: &str,
+        headless: bool,
+    ) -> Result<thirtyfour::Capabilities, anyhow::Error> {
+        let mut caps = thirtyfour::DesiredCapabilities::firefox();
+        caps.set_firefox_binary(browser_path)
+            .with_context(|| format!("failed to set browser path to: {}", browser_path))?;
+        caps.add_firefox_arg("--width=1920").unwrap();
+        caps.add_firefox_arg("--height=1080").unwrap();
+        if headless {
+            caps.set_headless().unwrap();
+        }
 
-            loop {
-                match thirtyfour::WebDriver::new_with_config(
-                    &format!("http://localhost:{}", port),
-                    caps.clone(),
-                    WebDriverConfigBuilder::new()
-                        .poller(Arc::new(ElementPollerNoWait))
-                        .build(),
-                )
-                .await
-                {
-                    Ok(driver) => {
-                        break driver;
-                    }
-                    Err(err) => {
-                        tries += 1;
+        // Hide navigator.webdriver property, since there is no CDP for Firefox
+        let mut preferences = FirefoxPreferences::new();
+        preferences.set("dom.webdriver.enabled", false).unwrap();
+        preferences.set("useAutomationExtension", false).unwrap();
+        if let Some(proxy) = self.proxy {
+            Self::set_firefox_proxy(&mut preferences, proxy)?;
+        }
+        if let Some(user_agent) = self.user_agent {
+            preferences.set("general.useragent.override", user_agent).unwrap();
+        }
+        caps.set_preferences(preferences).unwrap();
+        for browser_arg in self.browser_args {
+            caps.add_firefox_arg(browser_arg)
+                .with_context(|| format!("failed to add browser argument: {}", browser_arg))?;
+        }
 
-                        if tries == 100 {
-                            return Err(err).context("could not connect to ChromeDriver");
-                        }
+        Ok(caps.into())
+    }
 
-                        tokio::time::sleep(Duration::from_millis(50)).await;
-                    }
-                }
-            }
-        };
+    fn set_firefox_proxy(preferences: &mut FirefoxPreferences, proxy: &Url) -> Result<(), anyhow::Error> {
+        let host = proxy.host_str().context("proxy url has no host")?;
+        let port = proxy.port_or_known_default().context("proxy url has no port")?;
+
+        preferences.set("network.proxy.type", 1).unwrap(); // manual configuration
 
+        if proxy.scheme().starts_with("socks") {
+            preferences.set("network.proxy.socks", host).unwrap();
+            preferences.set("network.proxy.socks_port", port).unwrap();
+            preferences.set("network.proxy.socks_version", 5).unwrap();
+            preferences
+                .set("network.proxy.socks_remote_dns", proxy.scheme() == "socks5h")
+                .unwrap();
+        } else {
+            preferences.set("network.proxy.http", host).unwrap();
+            preferences.set("network.proxy.http_port", port).unwrap();
+            preferences.set("network.proxy.ssl", host).unwrap();
+            preferences.set("network.proxy.ssl_port", port).unwrap();
+        }
+
+        Ok(())
+    }
+
+    async fn patch_chrome(driver: &thirtyfour::WebDriver) {
         let dev_tools = thirtyfour::extensions::cdp::ChromeDevTools::new(driver.handle.clone());
 
         // Remove window.cdc_... properties
@@ -153,16 +343,44 @@ impl<'a> ChromeDriver<'a> {
             )
             .await
             .unwrap();
+    }
 
-        Ok((driver, child_process))
+    async fn install_firefox_ublock(&self, driver: &thirtyfour::WebDriver) {
+        let ublock_xpi = self.data_dir.join("uBlock.xpi");
+
+        if let Err(err) = self.prepare_ublock(&ublock_xpi).await {
+            log::warn!("Failed to prepare uBlock Origin: {:#}", err);
+        }
+
+        if self.ublock_rules.is_some() {
+            log::warn!("Custom uBlock Origin rules are only supported for Chrome");
+        }
+
+        let Some(ublock_xpi) = ublock_xpi.to_str() else {
+            log::warn!("Failed to add uBlock Origin as extension: path to file is not valid UTF-8");
+            return;
+        };
+
+        if let Err(err) = FirefoxTools::new(driver.handle.clone())
+            .install_addon(ublock_xpi, Some(true))
+            .await
+        {
+            log::warn!("Failed to add uBlock Origin as extension: {:#}", err);
+        }
     }
 
-    async fn get_chromedriver_and_browser_path() -> Result<(PathBuf, String), anyhow::Error> {
+    async fn get_chromedriver_and_browser_path(proxy: Option<&Url>) -> Result<(PathBuf, String), anyhow::Error> {
+        let proxy = proxy.map(|proxy| proxy.to_string());
+
         match selenium_manager::chrome::ChromeManager::new() {
             Ok(mut manager) => {
                 let setup_result = tokio::task::spawn_blocking(move || {
                     const CHROME_VERSION: usize = 116;
 
+                    if let Some(proxy) = proxy {
+                        manager.set_proxy(proxy)?;
+                    }
+
                     manager.set_browser_version(CHROME_VERSION.to_string());
                     manager.discover_driver_version_and_download_browser_if_necessary()?;
 
@@ -194,8 +412,48 @@ impl<'a> ChromeDriver<'a> {
         }
     }
 
-    async fn prepare_ublock(&self, ublock_dir: &PathBuf) -> Result<(), anyhow::Error> {
-        let current_version_file = self.data_dir.join("current_ublock_version");
+    async fn get_geckodriver_and_browser_path(proxy: Option<&Url>) -> Result<(PathBuf, String), anyhow::Error> {
+        let proxy = proxy.map(|proxy| proxy.to_string());
+
+        match selenium_manager::firefox::FirefoxManager::new() {
+            Ok(mut manager) => {
+                let setup_result = tokio::task::spawn_blocking(move || {
+                    if let Some(proxy) = proxy {
+                        manager.set_proxy(proxy)?;
+                    }
+
+                    manager
+                        .setup()
+                        .map(|driver_path| (driver_path, manager.get_browser_path().to_owned()))
+                })
+                .await;
+
+                match setup_result {
+                    Ok(Ok((driver_path, browser_path))) => Ok((driver_path, browser_path)),
+                    Ok(Err(err)) => Err(err).context("failed to set up GeckoDriver"),
+                    Err(err) => Err(err).context("failed to set up GeckoDriver"),
+                }
+            }
+            Err(err) => Err(err).context("failed to create Firefox Manager"),
+        }
+    }
+
+    /// Prepares the extracted extension directory for Chrome or the `.xpi` file for Firefox.
+    /// The GitHub API is queried at most once per [UBLOCK_CHECK_INTERVAL],
+    /// unless an update is forced.
+    async fn prepare_ublock(&self, ublock_path: &Path) -> Result<(), anyhow::Error> {
+        let current_version_file = self.data_dir.join(match self.backend {
+            WebDriverBackend::Chrome => "current_ublock_version",
+            WebDriverBackend::Firefox => "current_ublock_firefox_version",
+        });
+        let check_cache_file = self.data_dir.join(match self.backend {
+            WebDriverBackend::Chrome => "last_ublock_check",
+            WebDriverBackend::Firefox => "last_ublock_firefox_check",
+        });
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("system time before Unix epoch")?
+            .as_secs();
 
         let current_version_read = tokio::fs::read_to_string(&current_version_file).await;
         let current_version = match current_version_read.as_deref() {
@@ -209,7 +467,47 @@ impl<'a> ChromeDriver<'a> {
             }
         };
 
-        let github_response = download::get_page_json(UBLOCK_GITHUB_API_URL, None, None, None).await?;
+        if let (Some(current_version), false) = (current_version, self.update_ublock) {
+            let cached_latest_version = match tokio::fs::read_to_string(&check_cache_file).await {
+                Ok(contents) => parse_version_cache(&contents).and_then(|(checked_at, latest_version)| {
+                    (now.saturating_sub(checked_at) < UBLOCK_CHECK_INTERVAL.as_secs()).then_some(latest_version)
+                }),
+                Err(err) => {
+                    if err.kind() != ErrorKind::NotFound {
+                        log::warn!("Failed to read uBlock Origin check cache file: {err}");
+                    }
+
+                    None
+                }
+            };
+
+            if cached_latest_version.as_deref() == Some(current_version) {
+                log::trace!("uBlock Origin up-to-date (cached)");
+                return Ok(());
+            }
+        }
+
+        let github_response =
+            download::get_response(self.downloader.client(), UBLOCK_GITHUB_API_URL, None, None, None).await?;
+        let status = github_response.status();
+
+        if matches!(
+            status,
+            reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::TOO_MANY_REQUESTS
+        ) {
+            if let Some(current_version) = current_version {
+                log::debug!("GitHub API is rate limited, using installed uBlock Origin {current_version}");
+                return Ok(());
+            }
+
+            anyhow::bail!("GitHub API is rate limited");
+        }
+
+        let github_response: serde_json::Value = github_response
+            .response()
+            .json()
+            .await
+            .context("failed to parse GitHub API response as json")?;
 
         const UNEXPECT_JSON_ERR_MSG: &str = "unexpected GitHub API json response";
         let serde_json::Value::Object(json_object) = github_response else {
@@ -219,6 +517,10 @@ impl<'a> ChromeDriver<'a> {
             anyhow::bail!(UNEXPECT_JSON_ERR_MSG)
         };
 
+        if let Err(err) = tokio::fs::write(&check_cache_file, format!("{now}\n{latest_version}")).await {
+            log::warn!("Failed to write uBlock Origin check cache file: {err}");
+        }
+
         let download = if let Some(current_version) = current_version {
             if latest_version == current_version {
                 log::trace!("uBlock Origin up-to-date");
@@ -236,7 +538,10 @@ impl<'a> ChromeDriver<'a> {
             return Ok(());
         }
 
-        let ublock_download_file_path = self.data_dir.join("uBlock.zip");
+        let ublock_download_file_path = match self.backend {
+            WebDriverBackend::Chrome => self.data_dir.join("uBlock.zip"),
+            WebDriverBackend::Firefox => ublock_path.to_path_buf(),
+        };
 
         if let Err(err) = remove_file_ignore_not_exists(&ublock_download_file_path).await {
             return Err(err).context("failed to remove old uBlock Origin asset file");
@@ -252,7 +557,12 @@ impl<'a> ChromeDriver<'a> {
                 anyhow::bail!(UNEXPECT_JSON_ERR_MSG)
             };
 
-            if !asset_name.contains("chromium") {
+            let is_matching_asset = match self.backend {
+                WebDriverBackend::Chrome => asset_name.contains("chromium"),
+                WebDriverBackend::Firefox => asset_name.contains("firefox") && asset_name.ends_with(".xpi"),
+            };
+
+            if !is_matching_asset {
                 continue;
             }
 
@@ -272,24 +582,30 @@ impl<'a> ChromeDriver<'a> {
         }
 
         if !found_asset {
-            anyhow::bail!("could not find the latest uBlock Origin asset for Chromium");
+            match self.backend {
+                WebDriverBackend::Chrome => anyhow::bail!("could not find the latest uBlock Origin asset for Chromium"),
+                WebDriverBackend::Firefox => anyhow::bail!("could not find the latest uBlock Origin asset for Firefox"),
+            }
         }
 
-        if let Err(err) = remove_dir_all_ignore_not_exists(ublock_dir).await {
-            return Err(err).context("failed to remove old uBlock Origin extension directory");
-        }
+        if self.backend == WebDriverBackend::Chrome {
+            if let Err(err) = remove_dir_all_ignore_not_exists(ublock_path).await {
+                return Err(err).context("failed to remove old uBlock Origin extension directory");
+            }
 
-        tokio::fs::create_dir_all(ublock_dir)
-            .await
-            .context("failed to create uBlock Origin extension directory")?;
+            tokio::fs::create_dir_all(ublock_path)
+                .await
+                .context("failed to create uBlock Origin extension directory")?;
 
-        if let Err(err) = zip_extensions::zip_extract(&ublock_download_file_path, ublock_dir) {
-            let _ = tokio::fs::remove_file(&current_version_file).await;
-            let _ = tokio::fs::remove_dir_all(ublock_dir).await;
-            return Err(err).context("failed to extract uBlock Origin asset file");
+            if let Err(err) = zip_extensions::zip_extract(&ublock_download_file_path, &ublock_path.to_path_buf()) {
+                let _ = tokio::fs::remove_file(&current_version_file).await;
+                let _ = tokio::fs::remove_dir_all(ublock_path).await;
+                return Err(err).context("failed to extract uBlock Origin asset file");
+            }
+
+            let _ = tokio::fs::remove_file(&ublock_download_file_path).await;
         }
 
-        let _ = tokio::fs::remove_file(&ublock_download_file_path).await;
         tokio::fs::write(&current_version_file, &latest_version)
             .await
             .context("failed to update uBlock Origin version file")?;
@@ -297,6 +613,66 @@ impl<'a> ChromeDriver<'a> {
         Ok(())
     }
 
+    /// Adds the custom rules as an additional filter list to the extension,
+    /// which is enabled by default. Without custom rules, a previously added
+    /// filter list is removed again.
+    async fn install_ublock_rules(&self, ublock_dir: &Path) -> Result<(), anyhow::Error> {
+        let assets_json_path = ublock_dir.join("assets").join("assets.json");
+        let rules_path = ublock_dir.join(UBLOCK_CUSTOM_RULES_PATH);
+
+        let assets_json = tokio::fs::read_to_string(&assets_json_path)
+            .await
+            .context("failed to read uBlock Origin assets file")?;
+        let mut assets_json: serde_json::Value =
+            serde_json::from_str(&assets_json).context("failed to parse uBlock Origin assets file")?;
+        let serde_json::Value::Object(assets) = &mut assets_json else {
+            anyhow::bail!("unexpected uBlock Origin assets file");
+        };
+
+        match self.ublock_rules {
+            Some(ublock_rules) => {
+                let rules = tokio::fs::read_to_string(ublock_rules)
+                    .await
+                    .with_context(|| format!("failed to read uBlock Origin rules file: {}", ublock_rules.display()))?;
+
+                if let Some(rules_dir) = rules_path.parent() {
+                    tokio::fs::create_dir_all(rules_dir)
+                        .await
+                        .context("failed to create uBlock Origin rules directory")?;
+                }
+
+                tokio::fs::write(&rules_path, rules)
+                    .await
+                    .context("failed to write uBlock Origin rules")?;
+
+                assets.insert(
+                    UBLOCK_CUSTOM_RULES_KEY.to_string(),
+                    serde_json::json!({
+                        "content": "filters",
+                        "group": "custom",
+                        "title": "sdl custom rules",
+                        "contentURL": UBLOCK_CUSTOM_RULES_PATH,
+                    }),
+                );
+            }
+            None => {
+                if assets.remove(UBLOCK_CUSTOM_RULES_KEY).is_none() {
+                    return Ok(());
+                }
+
+                remove_file_ignore_not_exists(&rules_path)
+                    .await
+                    .context("failed to remove uBlock Origin rules")?;
+            }
+        }
+
+        tokio::fs::write(&assets_json_path, assets_json.to_string())
+            .await
+            .context("failed to write uBlock Origin assets file")?;
+
+        Ok(())
+    }
+
     async fn get_ublock_directory(ublock_dir: &Path) -> Result<PathBuf, anyhow::Error> {
         let mut ublock_dir_files = tokio::fs::read_dir(&ublock_dir)
             .await
@@ -334,6 +710,80 @@ impl<'a> ChromeDriver<'a> {
     }
 }
 
+/// Locks the browser profile, so that concurrent runs do not use the same one.
+/// If it is already in use, a numbered sibling directory is used instead.
+/// The profile stays locked, until the returned file is dropped.
+pub(crate) async fn lock_profile_dir(profile_dir: &Path) -> Result<(PathBuf, File), anyhow::Error> {
+    if let Some(parent) = profile_dir.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("failed to create parent directory of browser profile")?;
+    }
+
+    for i in 0..100u32 {
+        let candidate_dir = if i == 0 {
+            profile_dir.to_path_buf()
+        } else {
+            let mut candidate_dir = profile_dir.as_os_str().to_owned();
+            candidate_dir.push(format!("-{i}"));
+            PathBuf::from(candidate_dir)
+        };
+        let mut lock_path = candidate_dir.as_os_str().to_owned();
+        lock_path.push(".lock");
+
+        let lock_file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)
+            .context("failed to open lock file of browser profile")?;
+
+        if lock_file.try_lock_exclusive().is_ok() {
+            return Ok((candidate_dir, lock_file));
+        }
+    }
+
+    anyhow::bail!("all browser profiles are in use")
+}
+
+/// Adds the cookies of the cookies file for the host of the url to the
+/// browser. Since the browser only accepts cookies of the current page, the
+/// site is visited first.
+pub(crate) async fn add_cookies(driver: &thirtyfour::WebDriver, url: &Url) -> Result<(), anyhow::Error> {
+    let Some(cookies) = cookies::get() else {
+        return Ok(());
+    };
+    let host_cookies: Vec<_> = cookies.for_host(url).collect();
+
+    if host_cookies.is_empty() {
+        return Ok(());
+    }
+
+    driver
+        .goto(url.origin().ascii_serialization())
+        .await
+        .context("failed to go to the site")?;
+
+    for cookie in host_cookies {
+        let mut browser_cookie = thirtyfour::Cookie::new(cookie.name.clone(), cookie.value.clone());
+        browser_cookie.set_domain(if cookie.include_subdomains {
+            format!(".{}", cookie.domain)
+        } else {
+            cookie.domain.clone()
+        });
+        browser_cookie.set_path(cookie.path.clone());
+        browser_cookie.set_secure(cookie.secure);
+        browser_cookie.set_http_only(cookie.http_only);
+
+        driver
+            .add_cookie(browser_cookie)
+            .await
+            .with_context(|| format!("failed to add cookie {}", cookie.name))?;
+    }
+
+    log::debug!("Added cookies for {}", url.host_str().unwrap_or_default());
+    Ok(())
+}
+
 pub async fn get_user_agent(driver: &thirtyfour::WebDriver) -> Option<String> {
     driver
         .execute("return navigator.userAgent;", vec![])
@@ -1,12 +1,31 @@
+use std::ffi::OsString;
 use std::fmt::Display;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
 use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
-use clap::{Parser, ValueEnum};
+use anyhow::Context;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, ValueEnum};
+use reqwest::header::{HeaderName, HeaderValue};
+use url::Url;
 
-use crate::downloaders::{AllOrSpecific, DownloadSettings, EpisodesRequest, Language, VideoType};
+use crate::chrome::WebDriverBackend;
+use crate::download::{
+    AudioExtraction, AudioFormat, Container, IpFamily, NetworkConfig, Quality, RedirectPolicy, RetryBudget,
+    SubtitleOptions,
+};
+use crate::downloaders::utils::jitter_duration;
+use crate::downloaders::{
+    AllOrSpecific, DownloadSettings, EpisodesRequest, Language, RecentEpisodes, TypePreference, VideoType,
+    RANGE_OPEN_END,
+};
+use crate::exec::{split_args, ExecCommand};
+use crate::mpv::{MpvArgs, MpvOptions};
+use crate::output_template::{OutputTemplate, DEFAULT_OUTPUT_TEMPLATE};
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -24,26 +43,114 @@ pub(crate) struct Args {
     #[arg(short = 't', value_parser = parse_shorthand, default_value_t = VideoType::Unspecified(Language::Unspecified), hide_default_value = true, conflicts_with_all = ["video_type", "language"])]
     pub(crate) type_language: VideoType,
 
+    /// Try dubs or subs first, if the video type is not specified [default: depends on the site]
+    #[arg(value_enum, long, ignore_case = true, value_name = "TYPE")]
+    pub(crate) prefer: Option<TypePreference>,
+
+    /// Only use streams of this extractor, episodes without one fail
+    #[arg(long, value_name = "NAME")]
+    pub(crate) only_extractor: Option<String>,
+
     /// Only download specific episodes
-    #[arg(short, long, value_parser = parse_ranges, default_value_t = SimpleRanges::Unspecified, hide_default_value = true, value_name = "RANGES")]
+    #[arg(short, long, value_parser = parse_episode_ranges, allow_hyphen_values = true, default_value_t = SimpleRanges::Unspecified, hide_default_value = true, value_name = "RANGES")]
     pub(crate) episodes: SimpleRanges,
 
-    /// Only download specific seasons
-    #[arg(short, long, value_parser = parse_ranges, default_value_t = SimpleRanges::Unspecified, hide_default_value = true, conflicts_with_all = ["episodes"], value_name = "RANGES")]
+    /// Only download specific seasons, where season 0 are the movies
+    #[arg(short, long, value_parser = parse_season_ranges, allow_hyphen_values = true, default_value_t = SimpleRanges::Unspecified, hide_default_value = true, value_name = "RANGES")]
     pub(crate) seasons: SimpleRanges,
 
+    /// Only download the movies, same as --seasons 0
+    #[arg(long, conflicts_with = "seasons")]
+    pub(crate) movies: bool,
+
+    /// Only download the most recent episodes
+    #[arg(long, conflicts_with_all = ["episodes", "since"], value_name = "NUMBER")]
+    pub(crate) latest: Option<NonZeroU32>,
+
+    /// Only download the episodes after this one, e.g. S02E05
+    #[arg(long, value_parser = parse_since, conflicts_with = "episodes", value_name = "EPISODE")]
+    pub(crate) since: Option<RecentEpisodes>,
+
+    /// Scrape the selected episodes in random order
+    #[arg(long)]
+    pub(crate) shuffle: bool,
+
     /// Use underlying extractors directly
-    #[arg(short = 'u', long, num_args = 0..=1, require_equals = true, value_parser = parse_extractor, default_missing_value = "auto", conflicts_with_all = ["video_type", "language", "type_language", "episodes", "seasons", "concurrent_downloads", "ddos_wait_episodes", "ddos_wait_ms"], value_name = "NAME")]
+    #[arg(short = 'u', long, num_args = 0..=1, require_equals = true, value_parser = parse_extractor, default_missing_value = "auto", conflicts_with_all = ["video_type", "language", "type_language", "prefer", "only_extractor", "episodes", "seasons", "movies", "latest", "since", "shuffle", "concurrent_downloads", "extractor_timeout", "strict", "ddos_wait_episodes", "ddos_wait_ms", "ddos_wait_jitter_ms", "page_load_wait_ms", "scrape_timeout", "write_info_json", "write_nfo", "exec", "concurrent_extractions", "ublock_rules", "no_ublock", "update_ublock", "user_data_dir", "browser", "driver_timeout", "chrome_args", "dump_json", "season_folders", "archive", "list", "interactive"], value_name = "NAME")]
     pub(crate) extractor: Option<Extractor>,
 
+    /// Use a best-effort extractor for sites without a dedicated one
+    #[arg(long)]
+    pub(crate) generic: bool,
+
     /// Concurrent downloads
     #[arg(short = 'N', long, value_parser = parse_optional_with_inf_as_none::<NonZeroU32>, default_value = "5", value_name = "INF|NUMBER")]
     pub(crate) concurrent_downloads: OptionWrapper<NonZeroU32>,
 
+    /// Quality of m3u8 streams, e.g. best, worst, 720 or <=720
+    #[arg(short = 'q', long, value_parser = parse_quality, default_value_t = Quality::Best, value_name = "QUALITY")]
+    pub(crate) quality: Quality,
+
+    /// Concurrent video url extractions
+    #[arg(long, default_value = "1", value_name = "NUMBER")]
+    pub(crate) concurrent_extractions: NonZeroU32,
+
+    /// Concurrent segment downloads of m3u8 streams
+    #[arg(long, default_value = "4", value_name = "NUMBER")]
+    pub(crate) segment_concurrency: NonZeroU32,
+
+    /// Maximum concurrent requests to the same host, e.g. segments from one CDN [default: unlimited]
+    #[arg(long, value_name = "NUMBER")]
+    pub(crate) per_host_concurrency: Option<NonZeroUsize>,
+
+    /// Maximum total download speed in bytes per second, e.g. 500K or 2M (binary, like 2MiB) or 2MB (decimal)
+    #[arg(long, value_parser = parse_rate, value_name = "RATE")]
+    pub(crate) limit_rate: Option<NonZeroU64>,
+
+    /// Disk space, which has to stay free in the save directory, e.g. 2G or 500M
+    #[arg(long, value_parser = parse_size, value_name = "SIZE")]
+    pub(crate) min_free_space: Option<NonZeroU64>,
+
+    /// Smaller downloads, which are not m3u8 streams, are placeholders and fail, 0 to allow any size
+    #[arg(long, value_parser = parse_bytes, default_value = "64K", value_name = "SIZE")]
+    pub(crate) min_file_size: u64,
+
     /// Number of download retries
     #[arg(short = 'r', long, value_parser = parse_optional_with_inf_as_none::<NonZeroU32>, default_value = "5", value_name = "INF|NUMBER")]
     pub(crate) retries: OptionWrapper<NonZeroU32>,
 
+    /// Number of retries of all requests of an m3u8 stream together, before another stream is tried
+    #[arg(long, value_parser = parse_optional_with_inf_as_none::<u32>, default_value = "100", value_name = "INF|NUMBER")]
+    pub(crate) episode_retries: OptionWrapper<u32>,
+
+    /// Total seconds waited before retries of an m3u8 stream, before another stream is tried
+    #[arg(long, value_parser = parse_optional_with_inf_as_none::<u64>, default_value = "inf", value_name = "INF|SECONDS")]
+    pub(crate) episode_retry_wait: OptionWrapper<u64>,
+
+    /// Maximum number of redirects followed by a download request
+    #[arg(long, default_value_t = 10, value_name = "NUMBER")]
+    pub(crate) max_redirects: u32,
+
+    /// Stop following redirects at a media file url, which a dry run takes as reachable without requesting it
+    #[arg(long)]
+    pub(crate) stop_redirects_at_media: bool,
+
+    /// Send the origin of the referer with the keys and segments of m3u8 streams, which some hosts require
+    #[arg(long)]
+    pub(crate) send_origin: bool,
+
+    /// Number of retries for scraping and extractor requests
+    #[arg(long, default_value_t = 5, value_name = "NUMBER")]
+    pub(crate) extract_retries: u32,
+
+    /// Seconds after which extracting the video url from a stream is given up
+    #[arg(long, value_parser = parse_optional_with_never_as_none::<NonZeroU64>, default_value = "60", value_name = "NEVER|SECONDS")]
+    pub(crate) extractor_timeout: OptionWrapper<NonZeroU64>,
+
+    /// Exit with code 3 instead of 1, if only episodes failed, for which no stream platform has an extractor
+    #[arg(long)]
+    pub(crate) strict: bool,
+
     /// Amount of requests before waiting
     #[arg(long, value_parser = parse_optional_with_never_as_none::<NonZeroU32>, default_value = "4", value_name = "NEVER|NUMBER")]
     pub(crate) ddos_wait_episodes: OptionWrapper<NonZeroU32>,
@@ -52,16 +159,227 @@ pub(crate) struct Args {
     #[arg(long, default_value_t = 60 * 1000, value_name = "MILLISECONDS")]
     pub(crate) ddos_wait_ms: u32,
 
+    /// Maximum random deviation in milliseconds from the wait duration
+    #[arg(long, default_value_t = 0, value_name = "MILLISECONDS")]
+    pub(crate) ddos_wait_jitter_ms: u32,
+
+    /// Range in milliseconds to wait for a page to load, e.g. 1000-2000
+    #[arg(long, value_parser = parse_ms_range, default_value = "1000-2000", value_name = "MIN-MAX")]
+    pub(crate) page_load_wait_ms: RangeInclusive<u64>,
+
+    /// Seconds after which a page navigation or element query of a site is given up
+    #[arg(long, value_parser = parse_optional_with_never_as_none::<NonZeroU64>, default_value = "120", value_name = "NEVER|SECONDS")]
+    pub(crate) scrape_timeout: OptionWrapper<NonZeroU64>,
+
+    /// Write series metadata to a JSON file
+    #[arg(long)]
+    pub(crate) write_info_json: bool,
+
+    /// Write Kodi .nfo files for the series and each downloaded episode
+    #[arg(long)]
+    pub(crate) write_nfo: bool,
+
+    /// Run a command after each download, with the tokens {} for the file, {series}, {season} and {episode}
+    #[arg(long, value_name = "COMMAND")]
+    pub(crate) exec: Option<ExecCommand>,
+
+    /// Directory to save the downloads into, which is created if missing [default: current directory]
+    #[arg(long, value_name = "DIRECTORY")]
+    pub(crate) output_dir: Option<PathBuf>,
+
+    /// Template for output file names, with the tokens {series}, {season}, {episode}, {lang}, {title} and {ext}
+    #[arg(short, long, default_value = DEFAULT_OUTPUT_TEMPLATE, value_name = "TEMPLATE")]
+    pub(crate) output_template: OutputTemplate,
+
+    /// Put the episodes of each season into their own folder
+    #[arg(long)]
+    pub(crate) season_folders: bool,
+
+    /// Skip episodes listed in this file, and record downloaded ones
+    #[arg(long, value_name = "FILE")]
+    pub(crate) archive: Option<PathBuf>,
+
+    /// Print episode metadata as JSON lines instead of downloading
+    #[arg(long, conflicts_with_all = ["mpv", "continue_downloads", "extract_audio", "verify"])]
+    pub(crate) dump_json: bool,
+
+    /// Print the available seasons and episodes instead of downloading
+    #[arg(long, conflicts_with_all = ["mpv", "continue_downloads", "extract_audio", "verify", "dump_json", "archive"])]
+    pub(crate) list: bool,
+
+    /// Pick the language and episodes to download from a list, if running in a terminal
+    #[arg(long, conflicts_with_all = ["from_file", "list", "dump_json", "episodes", "seasons", "movies", "latest", "since"])]
+    pub(crate) interactive: bool,
+
+    /// Check that the video url of every episode is reachable instead of downloading
+    #[arg(long, conflicts_with_all = ["mpv", "continue_downloads", "extract_audio", "verify", "dump_json", "list", "write_info_json", "write_nfo", "exec"])]
+    pub(crate) dry_run: bool,
+
+    /// Print the path each episode would be saved to instead of downloading
+    #[arg(long, conflicts_with_all = ["mpv", "extractor", "continue_downloads", "verify", "dump_json", "list", "dry_run", "write_info_json", "write_nfo", "exec"])]
+    pub(crate) print_filename: bool,
+
+    /// Download every url listed in this file, one per line with optional arguments
+    #[arg(long, value_name = "FILE")]
+    pub(crate) from_file: Option<PathBuf>,
+
+    /// Series of the batch file scraped at once, each with its own browser
+    #[arg(
+        long,
+        requires = "from_file",
+        conflicts_with = "mpv",
+        default_value = "1",
+        value_name = "NUMBER"
+    )]
+    pub(crate) concurrent_series: NonZeroUsize,
+
+    /// Custom uBlock Origin filter rules
+    #[arg(long, value_name = "FILE")]
+    pub(crate) ublock_rules: Option<PathBuf>,
+
+    /// Do not load uBlock Origin into the browser
+    #[arg(long, conflicts_with_all = ["ublock_rules", "update_ublock"])]
+    pub(crate) no_ublock: bool,
+
+    /// Look for a new release of uBlock Origin, which is otherwise done once per day
+    #[arg(long)]
+    pub(crate) update_ublock: bool,
+
+    /// Chrome profile, which keeps cookies between runs [default: chrome-profile in the data directory]
+    #[arg(long, value_name = "DIR")]
+    pub(crate) user_data_dir: Option<PathBuf>,
+
+    /// User agent of the browser and all requests, which is otherwise the one of the browser
+    #[arg(long, value_name = "USER_AGENT")]
+    pub(crate) user_agent: Option<String>,
+
+    /// Netscape cookies.txt file, whose cookies are sent to the matching hosts, e.g. for sites behind a login
+    #[arg(long, value_name = "FILE")]
+    pub(crate) cookies: Option<PathBuf>,
+
+    /// Additional HTTP header for downloads, e.g. "Cookie: name=value"
+    #[arg(long = "header", value_parser = parse_header, value_name = "HEADER")]
+    pub(crate) headers: Vec<(HeaderName, String)>,
+
+    /// Proxy for scraping, downloads and fetching the browser and FFmpeg, e.g. http://host:port or socks5://host:port
+    #[arg(long, value_parser = parse_proxy, value_name = "URL")]
+    pub(crate) proxy: Option<Url>,
+
+    /// Connect to IPv4 addresses first, and only fall back to IPv6
+    #[arg(long, conflicts_with = "prefer_ipv6")]
+    pub(crate) prefer_ipv4: bool,
+
+    /// Connect to IPv6 addresses first, and only fall back to IPv4
+    #[arg(long)]
+    pub(crate) prefer_ipv6: bool,
+
+    /// Seconds to wait for a connection to be established
+    #[arg(long, default_value = "20", value_name = "SECONDS")]
+    pub(crate) connect_timeout: NonZeroU64,
+
+    /// Maximum number of idle connections kept open per host [default: unlimited]
+    #[arg(long, value_name = "NUMBER")]
+    pub(crate) pool_max_idle: Option<usize>,
+
+    /// Browser used for scraping
+    #[arg(long, default_value = "chrome")]
+    pub(crate) browser: WebDriverBackend,
+
+    /// Seconds to wait for ChromeDriver or GeckoDriver to start
+    #[arg(long, default_value = "5", value_name = "SECONDS")]
+    pub(crate) driver_timeout: NonZeroU64,
+
+    /// Additional argument of the browser, e.g. --chrome-arg=--lang=de-DE
+    #[arg(long = "chrome-arg", value_parser = parse_chrome_arg, allow_hyphen_values = true, value_name = "ARG")]
+    pub(crate) chrome_args: Vec<String>,
+
+    /// Container of downloaded m3u8 streams
+    #[arg(long, default_value = "mp4")]
+    pub(crate) container: Container,
+
+    /// Fail instead of keeping the unconverted m3u8 stream, if FFmpeg is not installed
+    #[arg(long)]
+    pub(crate) require_ffmpeg: bool,
+
+    /// FFmpeg binary, which is used instead of the one in the PATH or a downloaded one
+    #[arg(long, value_name = "FILE")]
+    pub(crate) ffmpeg_path: Option<PathBuf>,
+
+    /// Keep the downloaded .ts file of m3u8 streams after converting it
+    #[arg(long)]
+    pub(crate) keep_ts: bool,
+
+    /// Write the resolved media playlist of m3u8 streams as .m3u8 file instead of downloading them
+    #[arg(long, conflicts_with_all = ["mpv", "list", "archive", "extract_audio", "verify", "continue_downloads"])]
+    pub(crate) write_playlist: bool,
+
+    /// Download subtitles of m3u8 streams
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "unspecified", value_name = "LANGUAGE")]
+    pub(crate) subs: Option<Language>,
+
+    /// Embed downloaded subtitles into the video
+    #[arg(long, requires = "subs")]
+    pub(crate) embed_subs: bool,
+
+    /// Extract the audio track after downloading
+    #[arg(short = 'x', long)]
+    pub(crate) extract_audio: bool,
+
+    /// Format of the extracted audio
+    #[arg(long, default_value = "mp3", requires = "extract_audio", value_name = "FORMAT")]
+    pub(crate) audio_format: AudioFormat,
+
+    /// Delete the video after extracting the audio
+    #[arg(long, requires = "extract_audio")]
+    pub(crate) audio_only: bool,
+
+    /// Continue partially downloaded files
+    #[arg(short = 'c', long = "continue")]
+    pub(crate) continue_downloads: bool,
+
+    /// Replace files, which already exist
+    #[arg(long, conflicts_with_all = ["extractor", "mpv", "continue_downloads"])]
+    pub(crate) overwrite: bool,
+
+    /// Skip episodes, whose file already exists
+    #[arg(long, conflicts_with_all = ["extractor", "mpv", "continue_downloads", "overwrite"])]
+    pub(crate) skip_existing: bool,
+
+    /// Check downloaded m3u8 streams for decode errors with FFmpeg
+    #[arg(long)]
+    pub(crate) verify: bool,
+
+    /// Print the progress as JSON lines to stdout instead of progress bars
+    #[arg(long, conflicts_with_all = ["mpv", "dump_json", "list", "dry_run", "print_filename"])]
+    pub(crate) progress_json: bool,
+
     /// Play in mpv
-    #[arg(long, conflicts_with_all = ["concurrent_downloads", "retries"])]
+    #[arg(long, conflicts_with_all = ["concurrent_downloads", "retries", "episode_retries", "episode_retry_wait", "segment_concurrency", "per_host_concurrency", "limit_rate", "min_free_space", "min_file_size", "max_redirects", "stop_redirects_at_media", "send_origin", "exec", "extract_audio", "continue_downloads", "verify"])]
     pub(crate) mpv: bool,
 
+    /// Number of upcoming episodes, whose playlist and first segments are downloaded ahead while playing in mpv
+    #[arg(long, default_value_t = 0, requires = "mpv", value_name = "NUMBER")]
+    pub(crate) mpv_prefetch: usize,
+
+    /// Options passed through to mpv for each episode, e.g. "--hwdec=auto --volume=50"
+    #[arg(long, allow_hyphen_values = true, requires = "mpv", value_name = "ARGS")]
+    pub(crate) mpv_args: Option<MpvArgs>,
+
+    /// Check once per day whether a newer version is available
+    #[arg(long)]
+    pub(crate) check_updates: bool,
+
+    /// Also write all log messages, including debug ones, to this file
+    #[arg(long, value_name = "FILE")]
+    pub(crate) log_file: Option<PathBuf>,
+
     /// Enable debug mode
     #[arg(short, long)]
     pub(crate) debug: bool,
 
     /// Download URL
-    pub(crate) url: String,
+    #[arg(required_unless_present = "from_file")]
+    pub(crate) url: Option<String>,
 }
 
 impl Args {
@@ -78,32 +396,206 @@ impl Args {
         }
     }
 
-    pub(crate) fn get_episodes_request(self) -> EpisodesRequest {
-        match (self.episodes, self.seasons) {
-            (SimpleRanges::Unspecified, SimpleRanges::Unspecified) => EpisodesRequest::Unspecified,
-            (SimpleRanges::Custom(episodes), SimpleRanges::Unspecified) => {
-                EpisodesRequest::Episodes(AllOrSpecific::Specific(episodes))
-            }
-            (SimpleRanges::Unspecified, SimpleRanges::Custom(seasons)) => {
-                EpisodesRequest::Seasons(AllOrSpecific::Specific(seasons))
-            }
-            (SimpleRanges::All, SimpleRanges::Unspecified) => EpisodesRequest::Episodes(AllOrSpecific::All),
-            (SimpleRanges::Unspecified, SimpleRanges::All) => EpisodesRequest::Seasons(AllOrSpecific::All),
-            (SimpleRanges::All, SimpleRanges::All) | (SimpleRanges::Custom(_), SimpleRanges::Custom(_)) => {
-                unreachable!()
-            }
-            (SimpleRanges::All, SimpleRanges::Custom(_)) | (SimpleRanges::Custom(_), SimpleRanges::All) => {
-                unreachable!()
-            }
+    pub(crate) fn get_episodes_request(&self) -> EpisodesRequest {
+        if let Some(recent) = self.latest.map(RecentEpisodes::Latest).or(self.since) {
+            return EpisodesRequest::Recent {
+                seasons: self.get_seasons(),
+                recent,
+            };
+        }
+
+        match (self.episodes.to_all_or_specific(), self.get_seasons()) {
+            (None, None) => EpisodesRequest::Unspecified,
+            (Some(episodes), None) => EpisodesRequest::Episodes(episodes),
+            (None, Some(seasons)) => EpisodesRequest::Seasons(seasons),
+            (Some(episodes), Some(seasons)) => EpisodesRequest::Combined { seasons, episodes },
+        }
+    }
+
+    fn get_seasons(&self) -> Option<AllOrSpecific> {
+        if self.movies {
+            Some(AllOrSpecific::Specific(vec![0..=0]))
+        } else {
+            self.seasons.to_all_or_specific()
+        }
+    }
+
+    pub(crate) fn get_mpv_options(&self) -> MpvOptions {
+        MpvOptions {
+            quality: self.quality,
+            args: self.mpv_args.clone().unwrap_or_default(),
+            debug: self.debug,
+        }
+    }
+
+    pub(crate) fn get_subtitle_options(&self) -> Option<SubtitleOptions> {
+        self.subs.map(|language| SubtitleOptions {
+            language,
+            embed: self.embed_subs,
+        })
+    }
+
+    pub(crate) fn get_retry_budget(&self) -> RetryBudget {
+        RetryBudget {
+            max_retries: self.episode_retries.inner().copied(),
+            max_wait: self
+                .episode_retry_wait
+                .inner()
+                .map(|&seconds| Duration::from_secs(seconds)),
         }
     }
 
+    /// Whether episodes are downloaded, instead of only being played or printed.
+    pub(crate) fn downloads_files(&self) -> bool {
+        !self.mpv && !self.dump_json && !self.list && !self.dry_run && !self.print_filename
+    }
+
+    pub(crate) fn get_network_config(&self) -> NetworkConfig {
+        let prefer_ip = if self.prefer_ipv4 {
+            Some(IpFamily::V4)
+        } else if self.prefer_ipv6 {
+            Some(IpFamily::V6)
+        } else {
+            None
+        };
+
+        NetworkConfig {
+            prefer_ip,
+            connect_timeout: Duration::from_secs(self.connect_timeout.get()),
+            pool_max_idle_per_host: self.pool_max_idle,
+        }
+    }
+
+    pub(crate) fn get_redirect_policy(&self) -> RedirectPolicy {
+        RedirectPolicy {
+            max_redirects: self.max_redirects,
+            stop_at_media: self.stop_redirects_at_media,
+        }
+    }
+
+    pub(crate) fn get_audio_extraction(&self) -> Option<AudioExtraction> {
+        self.extract_audio.then_some(AudioExtraction {
+            format: self.audio_format,
+            keep_video: !self.audio_only,
+        })
+    }
+
     pub(crate) fn get_download_settings(&self) -> DownloadSettings<impl FnMut() -> Duration> {
-        let wait_duration = Duration::from_millis(self.ddos_wait_ms as u64);
-        let wait_fn = move || wait_duration;
+        let wait_ms = self.ddos_wait_ms as u64;
+        let wait_jitter_ms = self.ddos_wait_jitter_ms as u64;
+        let wait_fn = move || jitter_duration(wait_ms, wait_jitter_ms);
 
         DownloadSettings::new(self.ddos_wait_episodes.inner().copied(), wait_fn)
+            .extract_retries(self.extract_retries)
+            .concurrent_extractions(self.concurrent_extractions)
+            .extractor_timeout(self.get_extractor_timeout())
+            .shuffle(self.shuffle)
+            .strict(self.strict)
+            .prefer(self.prefer)
+            .only_extractor(self.only_extractor.clone())
+    }
+
+    pub(crate) fn get_extractor_timeout(&self) -> Option<Duration> {
+        self.extractor_timeout
+            .inner()
+            .map(|seconds| Duration::from_secs(seconds.get()))
+    }
+
+    pub(crate) fn get_scrape_timeout(&self) -> Option<Duration> {
+        self.scrape_timeout
+            .inner()
+            .map(|seconds| Duration::from_secs(seconds.get()))
     }
+
+    pub(crate) fn get_driver_timeout(&self) -> Duration {
+        Duration::from_secs(self.driver_timeout.get())
+    }
+}
+
+/// Options, which are set up once for all entries of a batch file.
+const SESSION_OPTIONS: &[&str] = &[
+    "generic",
+    "per_host_concurrency",
+    "limit_rate",
+    "retries",
+    "extract_retries",
+    "page_load_wait_ms",
+    "scrape_timeout",
+    "from_file",
+    "concurrent_series",
+    "ublock_rules",
+    "no_ublock",
+    "update_ublock",
+    "user_data_dir",
+    "user_agent",
+    "cookies",
+    "proxy",
+    "prefer_ipv4",
+    "prefer_ipv6",
+    "connect_timeout",
+    "pool_max_idle",
+    "browser",
+    "driver_timeout",
+    "chrome_args",
+    "ffmpeg_path",
+    "progress_json",
+    "check_updates",
+    "log_file",
+    "debug",
+];
+
+/// Parses each line of the batch file, e.g. `URL -s 2 -e 3`, as a url with
+/// arguments, which are added to the ones of the command line.
+/// Empty lines and lines starting with `#` are skipped.
+pub(crate) fn read_batch_file(path: &Path) -> Result<Vec<(usize, Result<Args, clap::Error>)>, anyhow::Error> {
+    let content = std::fs::read_to_string(path).context("failed to read batch file")?;
+    let base_args: Vec<OsString> = std::env::args_os().collect();
+
+    let entries = content
+        .lines()
+        .enumerate()
+        .map(|(line_index, line)| (line_index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| (line_number, parse_batch_line(&base_args, line)))
+        .collect();
+
+    Ok(entries)
+}
+
+/// Parses a line of the batch file, which must not set any of the [SESSION_OPTIONS].
+fn parse_batch_line(base_args: &[OsString], line: &str) -> Result<Args, clap::Error> {
+    // Quoted values, e.g. of an output template, are kept together
+    let line_args: Vec<OsString> = split_args(line)
+        .map_err(|err| Args::command().error(ErrorKind::InvalidValue, err))?
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+    let args = Args::try_parse_from(base_args.iter().chain(&line_args))?;
+
+    // Only the options of the line are looked at, which do not have to be valid on their own
+    let mut command = Args::command().ignore_errors(true);
+    let line_matches = command.try_get_matches_from_mut(std::iter::once(OsString::new()).chain(line_args));
+
+    if let Ok(line_matches) = line_matches {
+        for id in SESSION_OPTIONS {
+            if line_matches.value_source(id) != Some(ValueSource::CommandLine) {
+                continue;
+            }
+
+            let long = command
+                .get_arguments()
+                .find(|arg| arg.get_id().as_str() == *id)
+                .and_then(|arg| arg.get_long())
+                .unwrap_or(*id);
+
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("--{long} applies to all lines and can only be set on the command line"),
+            ));
+        }
+    }
+
+    Ok(args)
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -188,6 +680,16 @@ pub(crate) enum SimpleRanges {
     Custom(Vec<RangeInclusive<u32>>),
 }
 
+impl SimpleRanges {
+    fn to_all_or_specific(&self) -> Option<AllOrSpecific> {
+        match self {
+            SimpleRanges::Unspecified => None,
+            SimpleRanges::All => Some(AllOrSpecific::All),
+            SimpleRanges::Custom(ranges) => Some(AllOrSpecific::Specific(ranges.clone())),
+        }
+    }
+}
+
 impl Display for SimpleRanges {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -198,7 +700,16 @@ impl Display for SimpleRanges {
     }
 }
 
-fn parse_ranges(input: &str) -> Result<SimpleRanges, String> {
+fn parse_episode_ranges(input: &str) -> Result<SimpleRanges, String> {
+    parse_ranges(input, 1)
+}
+
+/// Like episodes, but an open start includes season 0, i.e. the movies.
+fn parse_season_ranges(input: &str) -> Result<SimpleRanges, String> {
+    parse_ranges(input, 0)
+}
+
+fn parse_ranges(input: &str, open_start: u32) -> Result<SimpleRanges, String> {
     const BEFORE_LAST: u32 = u32::MAX - 1;
 
     if input.eq_ignore_ascii_case("unspecified") {
@@ -215,12 +726,29 @@ fn parse_ranges(input: &str) -> Result<SimpleRanges, String> {
 
     for part in parts {
         if let Some((begin, end)) = part.split_once('-') {
-            let Ok(begin @ ..=BEFORE_LAST) = begin.parse::<u32>() else {
-                return Err(format!("failed to parse \"{begin}\" as integer in range \"{part}\""));
+            if begin.is_empty() && end.is_empty() {
+                return Err(format!("range needs at least a start or an end: \"{part}\""));
+            }
+
+            // An open start begins at the first, and an open end stops at the last
+            let begin = if begin.is_empty() {
+                open_start
+            } else {
+                let Ok(begin @ ..=BEFORE_LAST) = begin.parse::<u32>() else {
+                    return Err(format!("failed to parse \"{begin}\" as integer in range \"{part}\""));
+                };
+
+                begin
             };
 
-            let Ok(end @ ..=BEFORE_LAST) = end.parse::<u32>() else {
-                return Err(format!("failed to parse \"{end}\" as integer in range \"{part}\""));
+            let end = if end.is_empty() {
+                RANGE_OPEN_END
+            } else {
+                let Ok(end @ ..=BEFORE_LAST) = end.parse::<u32>() else {
+                    return Err(format!("failed to parse \"{end}\" as integer in range \"{part}\""));
+                };
+
+                end
             };
 
             if begin > end {
@@ -257,12 +785,136 @@ fn parse_ranges(input: &str) -> Result<SimpleRanges, String> {
     Ok(SimpleRanges::Custom(merged_ranges))
 }
 
+fn parse_quality(input: &str) -> Result<Quality, String> {
+    if input.eq_ignore_ascii_case("best") {
+        return Ok(Quality::Best);
+    }
+
+    if input.eq_ignore_ascii_case("worst") {
+        return Ok(Quality::Worst);
+    }
+
+    let height = input.strip_prefix("<=").unwrap_or(input).trim();
+    let height = height.strip_suffix(['p', 'P']).unwrap_or(height);
+
+    match height.parse::<u32>() {
+        Ok(height) => Ok(Quality::MaxHeight(height)),
+        Err(_) => Err(format!("failed to parse \"{input}\" as quality")),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum Extractor {
     Auto,
     Name(String),
 }
 
+fn parse_header(input: &str) -> Result<(HeaderName, String), String> {
+    let Some((name, value)) = input.split_once(':') else {
+        return Err(format!("header \"{input}\" must have the format \"Name: Value\""));
+    };
+
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|err| format!("invalid header name: {err}"))?;
+    let value = value.trim();
+    HeaderValue::from_str(value).map_err(|err| format!("invalid header value: {err}"))?;
+
+    Ok((name, value.to_owned()))
+}
+
+fn parse_since(input: &str) -> Result<RecentEpisodes, String> {
+    let invalid = || format!("expected an episode like S02E05, got: {input}");
+    let lowercase = input.trim().to_ascii_lowercase();
+    let (season, episode) = lowercase
+        .strip_prefix('s')
+        .and_then(|rest| rest.split_once('e'))
+        .ok_or_else(invalid)?;
+    let season = season.parse().map_err(|_| invalid())?;
+    let episode = episode.parse().map_err(|_| invalid())?;
+
+    Ok(RecentEpisodes::Since { season, episode })
+}
+
+fn parse_rate(input: &str) -> Result<NonZeroU64, String> {
+    let input = input.trim();
+    let rate = parse_bytes(input.strip_suffix("/s").unwrap_or(input)).map_err(|err| format!("invalid rate: {err}"))?;
+
+    NonZeroU64::new(rate).ok_or_else(|| "rate must be at least one byte per second".to_owned())
+}
+
+fn parse_size(input: &str) -> Result<NonZeroU64, String> {
+    let size = parse_bytes(input).map_err(|err| format!("invalid size: {err}"))?;
+
+    NonZeroU64::new(size).ok_or_else(|| "size must be at least one byte".to_owned())
+}
+
+/// Single letter units are binary like the sizes shown by the progress bars,
+/// e.g. `M` is the same as `MiB`, while `MB` is decimal.
+fn parse_bytes(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let unit_start = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(unit_start);
+    let unit = unit.trim();
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KIB" => 1 << 10,
+        "M" | "MIB" => 1 << 20,
+        "G" | "GIB" => 1 << 30,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        _ => return Err(format!("unknown unit \"{unit}\"")),
+    };
+    let number = number.parse::<f64>().map_err(|err| err.to_string())?;
+    let bytes = (number * multiplier as f64).round();
+
+    if bytes >= u64::MAX as f64 {
+        return Err("number is too large".to_owned());
+    }
+
+    Ok(bytes as u64)
+}
+
+fn parse_ms_range(input: &str) -> Result<RangeInclusive<u64>, String> {
+    let (min, max) = input.split_once('-').unwrap_or((input, input));
+    let min = min
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| format!("invalid minimum: {err}"))?;
+    let max = max
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| format!("invalid maximum: {err}"))?;
+
+    if min > max {
+        return Err("minimum must not be greater than maximum".to_owned());
+    }
+
+    Ok(min..=max)
+}
+
+fn parse_proxy(input: &str) -> Result<Url, String> {
+    let url = Url::parse(input).map_err(|err| format!("invalid proxy url: {err}"))?;
+
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!("unsupported proxy scheme \"{}\"", url.scheme()));
+    }
+
+    if url.host_str().is_none() {
+        return Err("proxy url must have a host".to_owned());
+    }
+
+    Ok(url)
+}
+
+fn parse_chrome_arg(input: &str) -> Result<String, String> {
+    match input.strip_prefix("--") {
+        Some(name) if !name.is_empty() && !name.starts_with('=') => Ok(input.to_owned()),
+        _ => Err("browser arguments must start with --, e.g. --lang=de-DE".to_owned()),
+    }
+}
+
 fn parse_extractor(input: &str) -> Result<Extractor, String> {
     if input.eq_ignore_ascii_case("auto") {
         Ok(Extractor::Auto)
@@ -307,3 +959,138 @@ where
 {
     parse_optional_with_none(input, "never")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use clap::CommandFactory;
+
+    use crate::cli::{
+        parse_batch_line, parse_chrome_arg, parse_episode_ranges, parse_rate, parse_season_ranges, parse_since,
+        parse_size, Args, SimpleRanges, SESSION_OPTIONS,
+    };
+    use crate::downloaders::{RecentEpisodes, RANGE_OPEN_END};
+
+    #[test]
+    fn test_parse_ranges() {
+        let custom_ranges = |input: &str, ranges: Result<SimpleRanges, String>| match ranges {
+            Ok(SimpleRanges::Custom(ranges)) => Ok(ranges),
+            Ok(other) => panic!("unexpected ranges for \"{input}\": {other}"),
+            Err(err) => Err(err),
+        };
+        let parse = |input: &str| custom_ranges(input, parse_episode_ranges(input));
+        let parse_seasons = |input: &str| custom_ranges(input, parse_season_ranges(input));
+
+        assert_eq!(parse("1,3-6,9"), Ok(vec![1..=1, 3..=6, 9..=9]));
+        assert_eq!(parse("5-"), Ok(vec![5..=RANGE_OPEN_END]));
+        assert_eq!(parse("-3"), Ok(vec![1..=3]));
+        assert_eq!(parse("-3, 8-, 10"), Ok(vec![1..=3, 8..=RANGE_OPEN_END]));
+        assert_eq!(parse("2-4,3-"), Ok(vec![2..=RANGE_OPEN_END]));
+        assert!(parse("-").is_err());
+        assert!(parse("5-3").is_err());
+        assert!(parse("a-").is_err());
+
+        // An open start of seasons includes the movies
+        assert_eq!(parse_seasons("-3"), Ok(vec![0..=3]));
+        assert_eq!(parse_seasons("2-"), Ok(vec![2..=RANGE_OPEN_END]));
+        assert_eq!(parse_seasons("1-3"), Ok(vec![1..=3]));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(
+            parse_since("S02E05").unwrap(),
+            RecentEpisodes::Since { season: 2, episode: 5 }
+        );
+        assert_eq!(
+            parse_since("s1e120").unwrap(),
+            RecentEpisodes::Since {
+                season: 1,
+                episode: 120
+            }
+        );
+        assert!(parse_since("2x05").is_err());
+        assert!(parse_since("S02").is_err());
+    }
+
+    #[test]
+    fn test_parse_chrome_arg() {
+        assert_eq!(parse_chrome_arg("--lang=de-DE").as_deref(), Ok("--lang=de-DE"));
+        assert_eq!(parse_chrome_arg("--disable-gpu").as_deref(), Ok("--disable-gpu"));
+        assert!(parse_chrome_arg("lang=de-DE").is_err());
+        assert!(parse_chrome_arg("-lang").is_err());
+        assert!(parse_chrome_arg("--").is_err());
+        assert!(parse_chrome_arg("--=x").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        let parse = |input: &str| parse_rate(input).map(|rate| rate.get());
+
+        assert_eq!(parse("50K"), Ok(51_200));
+        assert_eq!(parse("50k"), Ok(51_200));
+        assert_eq!(parse("4.2MiB"), Ok(4_404_019));
+        assert_eq!(parse("1MB"), Ok(1_000_000));
+        assert_eq!(parse("1 mb/s"), Ok(1_000_000));
+        assert_eq!(parse("2M"), parse("2MiB"));
+        assert_eq!(parse("1G"), Ok(1 << 30));
+        assert_eq!(parse("1GB"), Ok(1_000_000_000));
+        assert_eq!(parse("500"), Ok(500));
+        assert_eq!(parse("500B"), Ok(500));
+        assert!(parse("0").is_err());
+        assert!(parse("0.1B").is_err());
+        assert!(parse("1TB").is_err());
+        assert!(parse("1.2.3M").is_err());
+        assert!(parse("M").is_err());
+        assert!(parse("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_parse_batch_line() {
+        let base_args = ["sdl", "--from-file", "urls.txt", "--retries", "3"].map(OsString::from);
+        let url = "https://s.to/serie/stream/higurashi-no-naku-koro-ni";
+
+        let args = parse_batch_line(&base_args, &format!("{url} -s 2 -e 5-8")).unwrap();
+        assert_eq!(args.url.as_deref(), Some(url));
+        assert!(matches!(args.seasons, SimpleRanges::Custom(ranges) if ranges == vec![2..=2]));
+
+        let args = parse_batch_line(&base_args, &format!(r#"{url} -o "{{series}} - {{episode}}""#)).unwrap();
+        assert_eq!(args.url.as_deref(), Some(url));
+        assert_eq!(args.output_template.to_string(), "{series} - {episode}");
+        assert!(parse_batch_line(&base_args, &format!(r#"{url} -o "{{series}}"#)).is_err());
+
+        for line_option in [
+            "--limit-rate 2M",
+            "--cookies cookies.txt",
+            "--chrome-arg=--lang=de-DE",
+            "--scrape-timeout 10",
+            "--extract-retries 2",
+            "--concurrent-series 2",
+            "--progress-json",
+        ] {
+            assert!(
+                parse_batch_line(&base_args, &format!("{url} {line_option}")).is_err(),
+                "{line_option} was accepted"
+            );
+        }
+
+        let command = Args::command();
+
+        for id in SESSION_OPTIONS {
+            assert!(
+                command.get_arguments().any(|arg| arg.get_id().as_str() == *id),
+                "unknown option {id}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("2GiB").map(|size| size.get()), Ok(2 << 30));
+        assert_eq!(parse_size("500M").map(|size| size.get()), Ok(500 << 20));
+        assert_eq!(parse_size("1.5GB").map(|size| size.get()), Ok(1_500_000_000));
+        assert!(parse_size("0G").is_err());
+        assert!(parse_size("2G/s").is_err());
+    }
+}
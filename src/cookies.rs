@@ -0,0 +1,210 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use url::Url;
+
+/// Prefix of lines of cookies, which are only accessible over HTTP.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+static COOKIES: OnceCell<Cookies> = OnceCell::new();
+
+/// Cookies of a `cookies.txt` file in the Netscape format, as exported by
+/// browser extensions, e.g. for sites behind a login.
+#[derive(Debug, Default)]
+pub struct Cookies {
+    cookies: Vec<Cookie>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cookie {
+    /// Without the leading dot
+    pub(crate) domain: String,
+    pub(crate) include_subdomains: bool,
+    pub(crate) path: String,
+    pub(crate) secure: bool,
+    pub(crate) http_only: bool,
+    /// Seconds since the Unix epoch, where 0 is a session cookie
+    pub(crate) expires: u64,
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+impl Cookies {
+    /// Each line has the tab separated fields domain, include subdomains,
+    /// path, secure, expires, name and value. Other comments and empty lines
+    /// are skipped.
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let mut cookies = vec![];
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let &[domain, include_subdomains, path, secure, expires, name, value] = fields.as_slice() else {
+                anyhow::bail!("line {} has {} instead of 7 fields", index + 1, fields.len());
+            };
+            let expires = expires
+                .parse::<f64>()
+                .ok()
+                .filter(|expires| *expires >= 0.0)
+                .with_context(|| format!("line {} has an invalid expiration time: {}", index + 1, expires))?;
+
+            cookies.push(Cookie {
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE") || domain.starts_with('.'),
+                path: path.to_owned(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                expires: expires as u64,
+                name: name.to_owned(),
+                value: value.to_owned(),
+            });
+        }
+
+        Ok(Self { cookies })
+    }
+
+    /// Cookies of the host of the url, which have not expired yet, regardless
+    /// of their path.
+    pub(crate) fn for_host(&self, url: &Url) -> impl Iterator<Item = &Cookie> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let host = url.host_str().map(|host| host.to_ascii_lowercase());
+
+        self.cookies.iter().filter(move |cookie| {
+            host.as_deref().is_some_and(|host| cookie.matches_host(host))
+                && (cookie.expires == 0 || cookie.expires > now)
+        })
+    }
+
+    /// Cookies, which are sent to the url and have not expired yet.
+    pub(crate) fn matching(&self, url: &Url) -> impl Iterator<Item = &Cookie> {
+        let is_https = url.scheme() == "https";
+        let path = url.path().to_owned();
+
+        self.for_host(url)
+            .filter(move |cookie| cookie.matches_path(&path) && (is_https || !cookie.secure))
+    }
+
+    /// Value of the `Cookie` header for the url, if any cookie matches.
+    pub(crate) fn header_value(&self, url: &Url) -> Option<String> {
+        let header_value = self
+            .matching(url)
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if header_value.is_empty() {
+            None
+        } else {
+            Some(header_value)
+        }
+    }
+}
+
+impl Cookie {
+    fn matches_host(&self, host: &str) -> bool {
+        host == self.domain
+            || (self.include_subdomains
+                && host
+                    .strip_suffix(&self.domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')))
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        path == self.path
+            || (path.starts_with(&self.path) && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')))
+    }
+}
+
+pub async fn load(path: &Path) -> Result<Cookies, anyhow::Error> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    Cookies::parse(&content).with_context(|| format!("invalid {}", path.display()))
+}
+
+/// Sets the cookies sent with all requests. Only the first call has an effect.
+/// The library does not load a cookies file, so users of the library have to
+/// call this before the first request.
+pub fn set_cookies(cookies: Cookies) {
+    let _ = COOKIES.set(cookies);
+}
+
+/// Cookies, if a file was given.
+pub(crate) fn get() -> Option<&'static Cookies> {
+    COOKIES.get()
+}
+
+/// Value of the `Cookie` header for the url, if cookies were set and any
+/// matches.
+pub(crate) fn header_value(url: &Url) -> Option<String> {
+    COOKIES.get()?.header_value(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use crate::cookies::Cookies;
+
+    #[test]
+    fn test_cookies() {
+        let cookies = Cookies::parse(
+            "# Netscape HTTP Cookie File\n\
+             \n\
+             .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+             #HttpOnly_login.example.com\tFALSE\t/account\tTRUE\t4102444800\ttoken\txyz\r\n\
+             other.to\tFALSE\t/\tFALSE\t1\texpired\t1\n",
+        )
+        .unwrap();
+        let header_value = |url: &str| cookies.header_value(&Url::parse(url).unwrap());
+
+        assert_eq!(header_value("http://example.com/").as_deref(), Some("session=abc"));
+        assert_eq!(
+            header_value("https://cdn.example.com/v.mp4").as_deref(),
+            Some("session=abc")
+        );
+        assert_eq!(
+            header_value("https://login.example.com/account/settings").as_deref(),
+            Some("session=abc; token=xyz")
+        );
+        assert_eq!(
+            header_value("http://login.example.com/account").as_deref(),
+            Some("session=abc")
+        );
+        assert_eq!(
+            header_value("https://login.example.com/accounts").as_deref(),
+            Some("session=abc")
+        );
+        assert_eq!(
+            header_value("https://sub.login.example.com/account").as_deref(),
+            Some("session=abc")
+        );
+        assert_eq!(header_value("https://notexample.com/"), None);
+        assert_eq!(header_value("https://other.to/"), None);
+        assert!(cookies.cookies[1].http_only);
+        assert_eq!(
+            cookies
+                .for_host(&Url::parse("https://login.example.com/").unwrap())
+                .count(),
+            2
+        );
+
+        assert!(Cookies::parse("example.com\tFALSE\t/\tFALSE\t0\tname\n").is_err());
+        assert!(Cookies::parse("example.com\tFALSE\t/\tFALSE\tnever\tname\tvalue\n").is_err());
+    }
+}
@@ -1,7 +1,9 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 
+use crate::utils::remove_file_ignore_not_exists;
+
 pub(crate) async fn get_data_dir() -> Result<PathBuf, anyhow::Error> {
     let data_dir = dirs::data_dir().map(|path| path.join("sdl")).or_else(|| {
         std::env::current_exe()
@@ -17,8 +19,69 @@ pub(crate) async fn get_data_dir() -> Result<PathBuf, anyhow::Error> {
     }
 }
 
+/// The custom save directory relative to the current working directory, or
+/// the current working directory itself.
 pub(crate) fn get_save_directory(custom_save_directory: Option<PathBuf>) -> Result<PathBuf, anyhow::Error> {
-    custom_save_directory
-        .ok_or(())
-        .or_else(|_| std::env::current_dir().context("failed to get current working directory"))
+    let current_dir = || std::env::current_dir().context("failed to get current working directory");
+
+    match custom_save_directory {
+        Some(save_directory) if save_directory.is_absolute() => Ok(save_directory),
+        Some(save_directory) => Ok(current_dir()?.join(save_directory)),
+        None => current_dir(),
+    }
+}
+
+/// Creates the save directory if it is missing, and checks that files can be
+/// created in it.
+pub(crate) async fn prepare_save_directory(save_directory: &Path) -> Result<(), anyhow::Error> {
+    tokio::fs::create_dir_all(save_directory)
+        .await
+        .context("failed to create directory")?;
+
+    let metadata = tokio::fs::metadata(save_directory)
+        .await
+        .context("failed to read directory metadata")?;
+
+    if !metadata.is_dir() {
+        anyhow::bail!("not a directory");
+    }
+
+    let probe_path = save_directory.join(format!(".sdl-write-test-{}", std::process::id()));
+    tokio::fs::write(&probe_path, b"")
+        .await
+        .context("directory is not writable")?;
+    remove_file_ignore_not_exists(&probe_path)
+        .await
+        .context("failed to remove write test file")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::dirs::{get_save_directory, prepare_save_directory};
+
+    #[tokio::test]
+    async fn test_prepare_save_directory() {
+        let current_dir = std::env::current_dir().unwrap();
+        assert_eq!(get_save_directory(None).unwrap(), current_dir);
+        assert_eq!(
+            get_save_directory(Some(PathBuf::from("downloads"))).unwrap(),
+            current_dir.join("downloads")
+        );
+
+        let base_dir = std::env::temp_dir().join(format!("sdl-test-save-dir-{}", std::process::id()));
+        let save_directory = get_save_directory(Some(base_dir.join("series").join("season"))).unwrap();
+        prepare_save_directory(&save_directory).await.unwrap();
+        assert!(save_directory.is_dir());
+        assert_eq!(std::fs::read_dir(&save_directory).unwrap().count(), 0);
+
+        let file_path = base_dir.join("file");
+        std::fs::write(&file_path, b"").unwrap();
+        assert!(prepare_save_directory(&file_path).await.is_err());
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
 }
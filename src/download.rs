@@ -1,64 +1,282 @@
 use std::borrow::Cow;
-use std::cell::RefCell;
-use std::fmt::Write;
-use std::num::NonZeroU32;
-use std::ops::Deref;
-use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Write};
+use std::io::{ErrorKind, SeekFrom};
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use aes::cipher::block_padding::{Padding as _, UnpadError};
 use aes::cipher::inout::InOutBuf;
 use aes::cipher::{BlockDecryptMut as _, KeyIvInit as _};
 use anyhow::Context;
+use clap::ValueEnum;
 use futures_util::StreamExt;
 use m3u8_rs::KeyMethod;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
 use reqwest::header::HeaderName;
 use reqwest::redirect::Policy;
 use reqwest::IntoUrl;
 use reqwest_partial_retry::{ClientExt, Config};
 use reqwest_retry::policies::ExponentialBackoffBuilder;
 use reqwest_retry::DefaultRetryableStrategy;
+use retry::after::{RetryAfterPolicy, RetryAfterStrategy};
+use retry::budget::BudgetedRetryPolicy;
 use retry::strategy::CustomRetryStrategy;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use serde::Serialize;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
-use crate::downloaders::{DownloadTask, EpisodeInfo, EpisodeNumber, Language, SeriesInfo, VideoType};
-use crate::logger::log_wrapper::SetLogWrapper;
+use crate::archive;
+use crate::cookies;
+use crate::downloaders::{
+    extract_from_candidates, DownloadTask, EpisodeInfo, EpisodeNumber, Language, ScrapeOutcomes, SelectedEpisodes,
+    SeriesInfo, VideoType,
+};
+use crate::exec::ExecCommand;
+use crate::nfo;
+use crate::output_template::{OutputTemplate, TemplateValues};
+use crate::sample_aes;
+use crate::subtitles::{merge_webvtt, WebVttSegment};
 use crate::utils::remove_file_ignore_not_exists;
 
 const DEFAULT_USER_AGENT: &str =
-    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36";
+
+/// Playlists, keys and subtitles are far smaller, so a larger response is
+/// rejected, instead of being read into memory.
+const MAX_PLAYLIST_SIZE: usize = 32 * 1024 * 1024;
+
+/// Bytes of an m3u8 stream written, after which the free space is checked again.
+const FREE_SPACE_CHECK_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// Number of download tasks which may wait for a free download slot.
+pub(crate) const QUEUED_DOWNLOAD_TASKS: usize = 2;
+
+static DEFAULT_CLIENT_CONFIG: OnceCell<DefaultClientConfig> = OnceCell::new();
 
 static DEFAULT_RETRY_CLIENT_NO_REDIRECT: Lazy<reqwest_partial_retry::Client> = Lazy::new(|| {
-    reqwest::Client::builder()
-        .user_agent(DEFAULT_USER_AGENT)
-        .connect_timeout(Duration::from_secs(20))
-        .redirect(Policy::none()) // redirects handled in get_response
-        .build()
-        .unwrap()
-        .resumable_with_config(
-            Config::builder()
-                .retry_policy(
-                    ExponentialBackoffBuilder::default()
-                        .retry_bounds(Duration::from_secs(1), Duration::from_secs(10))
-                        .build_with_max_retries(5),
-                )
-                .retryable_strategy(CustomRetryStrategy)
-                .stream_timeout(Some(Duration::from_secs(60)))
-                .build(),
-        )
+    let config = DEFAULT_CLIENT_CONFIG.get_or_init(DefaultClientConfig::default);
+    let mut client_builder = config
+        .network
+        .apply(reqwest::Client::builder())
+        .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .redirect(Policy::none()); // redirects handled in get_response
+
+    if let Some(proxy) = &config.proxy {
+        client_builder = client_builder.proxy(proxy.clone());
+    }
+
+    client_builder.build().unwrap().resumable_with_config(
+        Config::builder()
+            .retry_policy(RetryAfterPolicy(
+                ExponentialBackoffBuilder::default()
+                    .retry_bounds(Duration::from_secs(1), Duration::from_secs(10))
+                    .build_with_max_retries(config.retries),
+            ))
+            .retryable_strategy(RetryAfterStrategy(CustomRetryStrategy))
+            .stream_timeout(Some(Duration::from_secs(60)))
+            .build(),
+    )
 });
 
+/// Configuration of the client used for page fetches, e.g. by the extractors.
+#[derive(Debug, Clone)]
+pub struct DefaultClientConfig {
+    pub retries: u32,
+    pub proxy: Option<reqwest::Proxy>,
+    /// Sent by requests, which do not set one themselves
+    pub user_agent: Option<String>,
+    pub network: NetworkConfig,
+}
+
+impl Default for DefaultClientConfig {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            proxy: None,
+            user_agent: None,
+            network: NetworkConfig::default(),
+        }
+    }
+}
+
+/// Connection settings shared by the page and download clients.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    /// Addresses of this family are connected to first, and the other family
+    /// is only used as a fallback
+    pub prefer_ip: Option<IpFamily>,
+    pub connect_timeout: Duration,
+    /// Maximum number of idle connections kept open per host, where `None` is
+    /// unlimited
+    pub pool_max_idle_per_host: Option<usize>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            prefer_ip: None,
+            connect_timeout: Duration::from_secs(20),
+            pool_max_idle_per_host: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    fn apply(&self, mut client_builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        client_builder = client_builder.connect_timeout(self.connect_timeout);
+
+        if let Some(family) = self.prefer_ip {
+            client_builder = client_builder.dns_resolver(Arc::new(PreferIpResolver(family)));
+        }
+
+        if let Some(pool_max_idle_per_host) = self.pool_max_idle_per_host {
+            client_builder = client_builder.pool_max_idle_per_host(pool_max_idle_per_host);
+        }
+
+        client_builder
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Resolves with the system resolver, but orders the addresses of the
+/// preferred family first. The connector tries the family of the first
+/// address, and only falls back to the other one if connecting fails.
+struct PreferIpResolver(IpFamily);
+
+impl Resolve for PreferIpResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let addrs: Addrs = Box::new(sort_by_ip_family(addrs, family).into_iter());
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(addrs)
+        })
+    }
+}
+
+fn sort_by_ip_family(mut addrs: Vec<SocketAddr>, family: IpFamily) -> Vec<SocketAddr> {
+    // Stable, so that the order of the resolver is kept within a family
+    addrs.sort_by_key(|addr| !family.matches(addr));
+    addrs
+}
+
+/// How redirects are followed by requests.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RedirectPolicy {
+    pub(crate) max_redirects: u32,
+    /// A redirect to a url, which looks like a media file, is not followed.
+    /// Downloads request the media url on its own, while dry runs use it as it is
+    pub(crate) stop_at_media: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            stop_at_media: false,
+        }
+    }
+}
+
+/// Limits the retries of all requests of an episode together, so that a flaky
+/// server does not retry each of hundreds of segments on its own. Once the
+/// budget is exhausted, the download fails, and a fallback stream is tried.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RetryBudget {
+    /// Maximum number of retries, where `None` is unlimited
+    pub(crate) max_retries: Option<u32>,
+    /// Maximum total time waited before retries, where `None` is unlimited
+    pub(crate) max_wait: Option<Duration>,
+}
+
+/// Limits the requests in flight to each host, so that concurrent downloads
+/// from the same CDN do not trigger its rate limiting. Clones share the limits.
+#[derive(Debug, Clone)]
+pub(crate) struct HostLimiter {
+    max_per_host: usize,
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl HostLimiter {
+    pub(crate) fn new(max_per_host: NonZeroUsize) -> Self {
+        Self {
+            max_per_host: max_per_host.get(),
+            semaphores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Waits until another request to the host of the url may be made, which
+    /// counts as in flight until the permit is dropped.
+    pub(crate) async fn acquire(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let semaphore = self
+            .semaphores
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone();
+
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+/// Has to be called before the first request is made, otherwise the default
+/// configuration is used.
+pub(crate) fn set_default_client_config(config: DefaultClientConfig) -> Result<(), anyhow::Error> {
+    DEFAULT_CLIENT_CONFIG
+        .set(config)
+        .map_err(|_| anyhow::anyhow!("default client config was already set"))
+}
+
 pub(crate) struct DownloadManager {
     downloader: Downloader,
-    rx_stream: UnboundedReceiverStream<DownloadTask>,
+    rx_stream: ReceiverStream<DownloadTask>,
     max_concurrent: Option<usize>,
     save_directory: PathBuf,
     series_info: SeriesInfo,
+    quality: Quality,
+    subtitles: Option<SubtitleOptions>,
+    resume: bool,
+    overwrite: bool,
+    skip_existing: bool,
+    output_template: OutputTemplate,
+    season_folders: bool,
+    archive: Option<PathBuf>,
+    extractor_timeout: Option<Duration>,
+    write_nfo: bool,
+    exec: Option<ExecCommand>,
+    cancellation_token: CancellationToken,
+    scrape_outcomes: ScrapeOutcomes,
 }
 
 impl DownloadManager {
@@ -67,9 +285,11 @@ impl DownloadManager {
         max_concurrent: Option<NonZeroU32>,
         save_directory: PathBuf,
         series_info: SeriesInfo,
-    ) -> (Self, UnboundedSender<DownloadTask>) {
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<DownloadTask>();
-        let rx_stream = UnboundedReceiverStream::new(rx);
+    ) -> (Self, Sender<DownloadTask>) {
+        // Besides the tasks being downloaded, only a few are queued, so that
+        // the scraper does not extract video urls far ahead of the downloads
+        let (tx, rx) = tokio::sync::mpsc::channel::<DownloadTask>(QUEUED_DOWNLOAD_TASKS);
+        let rx_stream = ReceiverStream::new(rx);
 
         let manager = DownloadManager {
             downloader,
@@ -77,33 +297,258 @@ impl DownloadManager {
             max_concurrent: max_concurrent.map(|n| n.get() as usize),
             save_directory,
             series_info,
+            quality: Quality::Best,
+            subtitles: None,
+            resume: false,
+            overwrite: false,
+            skip_existing: false,
+            output_template: OutputTemplate::default(),
+            season_folders: false,
+            archive: None,
+            extractor_timeout: None,
+            write_nfo: false,
+            exec: None,
+            cancellation_token: CancellationToken::new(),
+            scrape_outcomes: ScrapeOutcomes::default(),
         };
 
         (manager, tx)
     }
 
-    pub(crate) async fn progress_downloads(self) {
+    pub(crate) fn quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub(crate) fn subtitles(mut self, subtitles: Option<SubtitleOptions>) -> Self {
+        self.subtitles = subtitles;
+        self
+    }
+
+    pub(crate) fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Replaces existing files instead of failing.
+    pub(crate) fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Treats episodes as downloaded, whose file already exists.
+    pub(crate) fn skip_existing(mut self, skip_existing: bool) -> Self {
+        self.skip_existing = skip_existing;
+        self
+    }
+
+    pub(crate) fn output_template(mut self, output_template: OutputTemplate) -> Self {
+        self.output_template = output_template;
+        self
+    }
+
+    /// Puts the episodes of each season into their own subdirectory.
+    pub(crate) fn season_folders(mut self, season_folders: bool) -> Self {
+        self.season_folders = season_folders;
+        self
+    }
+
+    /// Records the archive keys of successfully downloaded episodes in this file.
+    pub(crate) fn archive(mut self, archive: Option<PathBuf>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Maximum duration of extracting the video url of a fallback stream.
+    pub(crate) fn extractor_timeout(mut self, extractor_timeout: Option<Duration>) -> Self {
+        self.extractor_timeout = extractor_timeout;
+        self
+    }
+
+    /// Writes Kodi `.nfo` files for the series and each downloaded episode.
+    pub(crate) fn write_nfo(mut self, write_nfo: bool) -> Self {
+        self.write_nfo = write_nfo;
+        self
+    }
+
+    /// Command, which is run after each successful download.
+    pub(crate) fn exec(mut self, exec: Option<ExecCommand>) -> Self {
+        self.exec = exec;
+        self
+    }
+
+    /// No new download tasks are started after the token is cancelled.
+    pub(crate) fn cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Episodes, which were skipped or failed while scraping, and are part of
+    /// the returned outcomes.
+    pub(crate) fn scrape_outcomes(mut self, scrape_outcomes: ScrapeOutcomes) -> Self {
+        self.scrape_outcomes = scrape_outcomes;
+        self
+    }
+
+    /// Number of episodes, which will be downloaded in total, as far as the
+    /// scraper selected them yet.
+    pub(crate) fn selected_episodes(mut self, selected_episodes: SelectedEpisodes) -> Self {
+        self.downloader.total_episodes = selected_episodes;
+        self
+    }
+
+    /// Downloads the episodes, and returns their outcomes, including the ones
+    /// of the episodes which never reached the downloader, sorted by episode.
+    pub(crate) async fn progress_downloads(self) -> Vec<EpisodeOutcome> {
         let anime_name_for_file = prepare_series_name_for_file(&self.series_info.title);
+        let tvshow_nfo_written = Cell::new(false);
+        let outcomes = RefCell::new(vec![]);
         let download_future = self
             .rx_stream
-            .for_each_concurrent(self.max_concurrent, |download_task| {
-                let output_name = get_episode_name(
+            .take_until(self.cancellation_token.cancelled())
+            .enumerate()
+            .for_each_concurrent(self.max_concurrent, |(index, download_task)| {
+                let output_path_no_extension = get_episode_output_path(
+                    &self.save_directory,
+                    &self.output_template,
+                    self.season_folders,
                     anime_name_for_file.as_deref(),
-                    Some(&download_task.language),
-                    &download_task.episode_info,
-                    false,
+                    &download_task,
                 );
-                let output_path_no_extension = self.save_directory.join(&output_name);
-
-                let internal_task = InternalDownloadTask::new(output_path_no_extension, download_task.download_url)
-                    .output_path_has_extension(false)
-                    .referer(download_task.referer);
+                let output_name = output_path_no_extension
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let output_directory = output_path_no_extension
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| self.save_directory.clone());
+                let create_internal_task = move |url: String, referer: Option<String>| {
+                    InternalDownloadTask::new(output_path_no_extension.clone(), url)
+                        .output_path_has_extension(false)
+                        .referer(referer)
+                        .quality(self.quality)
+                        .subtitles(self.subtitles)
+                        .resume(self.resume)
+                        .overwrite_file(self.overwrite)
+                        .skip_existing(self.skip_existing)
+                };
                 let downloader_borrowed = &self.downloader;
+                let archive_borrowed = self.archive.as_deref();
+                let extractor_timeout = self.extractor_timeout;
+                let cancellation_token_borrowed = &self.cancellation_token;
+                let nfo_series =
+                    self.write_nfo
+                        .then_some((&self.save_directory, &self.series_info, &tvshow_nfo_written));
+                let exec_borrowed = self.exec.as_ref();
+                let series_title = &self.series_info.title;
+                let outcomes_borrowed = &outcomes;
+                let episode = download_task.episode_info.season_episode();
 
                 async move {
-                    if let Err(err) = downloader_borrowed.download_to_file(internal_task).await {
-                        log::warn!("Failed download of {}: {:#}", output_name, err);
+                    let status = async {
+                        if let Err(err) = tokio::fs::create_dir_all(&*output_directory).await {
+                            log::warn!("Failed to create directory \"{}\": {}", output_directory.display(), err);
+                            return EpisodeStatus::Failed(format!("failed to create directory: {err}"));
+                        }
+
+                        let mut internal_task = create_internal_task(download_task.download_url, download_task.referer);
+                        let mut fallback_candidates = download_task.fallback_candidates;
+
+                        // If the download fails, e.g. due to an expired url, extract the
+                        // next stream of the episode and download from there instead
+                        loop {
+                            let err = match downloader_borrowed.download_file(internal_task).await {
+                                Ok(DownloadedFile {
+                                    path: final_path,
+                                    skipped,
+                                }) => {
+                                    if let (Some(archive), Some(archive_key)) =
+                                        (archive_borrowed, &download_task.archive_key)
+                                    {
+                                        if let Err(err) = archive::append_to_archive(archive, archive_key).await {
+                                            log::warn!("Failed to add {} to the archive: {:#}", output_name, err);
+                                        }
+                                    }
+
+                                    if let Some((save_directory, series_info, tvshow_nfo_written)) = nfo_series {
+                                        if !tvshow_nfo_written.replace(true) {
+                                            if let Err(err) = nfo::write_tvshow_nfo(save_directory, series_info).await {
+                                                log::warn!("Failed to write series nfo: {:#}", err);
+                                            }
+                                        }
+
+                                        if let Err(err) = nfo::write_episode_nfo(
+                                            &final_path,
+                                            series_info,
+                                            &download_task.episode_info,
+                                        )
+                                        .await
+                                        {
+                                            log::warn!("Failed to write nfo of {}: {:#}", output_name, err);
+                                        }
+                                    }
+
+                                    if let Some(exec) = exec_borrowed {
+                                        if let Err(err) =
+                                            exec.run(&final_path, series_title, &download_task.episode_info).await
+                                        {
+                                            log::warn!("Failed to run command for {}: {:#}", output_name, err);
+                                        }
+                                    }
+
+                                    break if skipped {
+                                        EpisodeStatus::Skipped
+                                    } else {
+                                        EpisodeStatus::Downloaded
+                                    };
+                                }
+                                Err(err) => err,
+                            };
+
+                            if cancellation_token_borrowed.is_cancelled() {
+                                log::debug!("Cancelled download of {}", output_name);
+                                break EpisodeStatus::Failed("download was cancelled".to_owned());
+                            }
+
+                            // Another stream would not have more space
+                            if is_insufficient_space(&err) {
+                                log::warn!("Failed download of {}: {:#}", output_name, err);
+                                break EpisodeStatus::Failed(format!("{err:#}"));
+                            }
+
+                            if fallback_candidates.is_empty() {
+                                log::warn!("Failed download of {}: {:#}", output_name, err);
+                                break EpisodeStatus::Failed(format!("{err:#}"));
+                            }
+
+                            log::info!("Failed download of {}, trying next stream: {:#}", output_name, err);
+
+                            match extract_from_candidates(&fallback_candidates, extractor_timeout).await {
+                                Ok(extracted_candidate) => {
+                                    let video = extracted_candidate.video;
+                                    internal_task = create_internal_task(video.url, video.referer)
+                                        .overwrite_file(true)
+                                        .skip_existing(false);
+                                    fallback_candidates = extracted_candidate.remaining_candidates;
+                                }
+                                Err(err) => {
+                                    log::warn!("Failed download of {}: {:#}", output_name, err);
+                                    break EpisodeStatus::Failed(format!("{err:#}"));
+                                }
+                            }
+                        }
                     }
+                    .await;
+
+                    outcomes_borrowed.borrow_mut().push((
+                        index,
+                        EpisodeOutcome {
+                            episode,
+                            name: output_name,
+                            status,
+                        },
+                    ));
                 }
             });
 
@@ -113,16 +558,117 @@ impl DownloadManager {
             _ = download_future => {}
             _ = self.downloader.tick() => unreachable!()
         }
+
+        let mut outcomes = outcomes.into_inner();
+        outcomes.sort_by_key(|&(index, _)| index);
+        let mut outcomes: Vec<EpisodeOutcome> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+
+        for scrape_outcome in self.scrape_outcomes.take() {
+            let output_name = get_episode_name(
+                &self.output_template,
+                anime_name_for_file.as_deref(),
+                scrape_outcome.language.as_ref(),
+                &scrape_outcome.episode_info,
+            );
+            let output_name = Path::new(&output_name)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or(output_name);
+
+            outcomes.push(EpisodeOutcome {
+                episode: scrape_outcome.episode_info.season_episode(),
+                name: output_name,
+                status: match scrape_outcome.failure {
+                    Some(reason) => EpisodeStatus::Failed(reason),
+                    None => EpisodeStatus::Skipped,
+                },
+            });
+        }
+
+        // Stable, so that episodes with the same number keep their order
+        outcomes.sort_by(|a, b| a.episode.cmp(&b.episode));
+        outcomes
+    }
+}
+
+/// How the download of an episode ended, for the summary at the end.
+#[derive(Debug, Clone)]
+pub(crate) struct EpisodeOutcome {
+    /// E.g. `S01E001`
+    pub(crate) episode: String,
+    /// Name of the file without extension
+    pub(crate) name: String,
+    pub(crate) status: EpisodeStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EpisodeStatus {
+    Downloaded,
+    /// The file already existed, or the episode is in the archive
+    Skipped,
+    /// With the reason
+    Failed(String),
+}
+
+/// Returned, if the free space of the save directory falls below the minimum.
+#[derive(Debug)]
+pub(crate) struct InsufficientSpace {
+    available: u64,
+    minimum: u64,
+}
+
+impl Display for InsufficientSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "only {} of disk space is free, but at least {} are required",
+            indicatif::BinaryBytes(self.available),
+            indicatif::BinaryBytes(self.minimum)
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+fn is_insufficient_space(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<InsufficientSpace>())
+}
+
+/// Returned, if the video url only led to a placeholder, e.g. a tracking
+/// pixel or an empty playlist, because the real video is not ready yet.
+#[derive(Debug)]
+pub(crate) struct PlaceholderVideo(String);
+
+impl Display for PlaceholderVideo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "video is only a placeholder: {}", self.0)
     }
 }
 
+impl std::error::Error for PlaceholderVideo {}
+
+fn is_placeholder_video(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<PlaceholderVideo>())
+}
+
+/// File produced by a download.
+pub(crate) struct DownloadedFile {
+    pub(crate) path: PathBuf,
+    /// Whether the file already existed, so that nothing was downloaded
+    pub(crate) skipped: bool,
+}
+
 pub(crate) struct InternalDownloadTask {
     url: String,
     output_path: PathBuf,
     output_path_has_extension: bool,
     overwrite_file: bool,
+    skip_existing: bool,
     custom_message: Option<String>,
     referer: Option<String>,
+    quality: Quality,
+    subtitles: Option<SubtitleOptions>,
+    resume: bool,
 }
 
 impl InternalDownloadTask {
@@ -132,8 +678,12 @@ impl InternalDownloadTask {
             output_path,
             output_path_has_extension: true,
             overwrite_file: false,
+            skip_existing: false,
             custom_message: None,
             referer: None,
+            quality: Quality::Best,
+            subtitles: None,
+            resume: false,
         }
     }
 
@@ -147,6 +697,12 @@ impl InternalDownloadTask {
         self
     }
 
+    /// Returns an existing file as the result instead of downloading it again.
+    pub(crate) fn skip_existing(mut self, skip_existing: bool) -> Self {
+        self.skip_existing = skip_existing;
+        self
+    }
+
     pub(crate) fn custom_message(mut self, custom_message: Option<String>) -> Self {
         self.custom_message = custom_message;
         self
@@ -156,6 +712,133 @@ impl InternalDownloadTask {
         self.referer = referer;
         self
     }
+
+    pub(crate) fn quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub(crate) fn subtitles(mut self, subtitles: Option<SubtitleOptions>) -> Self {
+        self.subtitles = subtitles;
+        self
+    }
+
+    /// Continues a previously interrupted download instead of failing if the file already exists.
+    pub(crate) fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+}
+
+/// Which variant of a m3u8 master playlist is downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Best,
+    Worst,
+    /// The best variant not exceeding the height, otherwise the worst variant
+    MaxHeight(u32),
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Best => write!(f, "best"),
+            Quality::Worst => write!(f, "worst"),
+            Quality::MaxHeight(max_height) => write!(f, "<={max_height}"),
+        }
+    }
+}
+
+/// Whether and which subtitles of m3u8 streams are downloaded.
+#[derive(Debug, Clone, Copy)]
+pub struct SubtitleOptions {
+    pub language: Language,
+    pub embed: bool,
+}
+
+struct SubtitleTrack {
+    tag: String,
+    url: Url,
+}
+
+fn get_subtitle_tracks(
+    playlist: &m3u8_rs::MasterPlaylist,
+    variant: &m3u8_rs::VariantStream,
+    language: Language,
+    base_url: &Url,
+) -> Vec<SubtitleTrack> {
+    playlist
+        .alternatives
+        .iter()
+        .filter(|media| matches!(media.media_type, m3u8_rs::AlternativeMediaType::Subtitles))
+        .filter(|media| {
+            variant
+                .subtitles
+                .as_ref()
+                .map_or(true, |group_id| *group_id == media.group_id)
+        })
+        .filter(|media| match &media.language {
+            Some(language_tag) => language.matches_language_tag(language_tag),
+            None => language == Language::Unspecified,
+        })
+        .filter_map(|media| {
+            let url = base_url.join(media.uri.as_ref()?).ok()?;
+            let tag = media
+                .language
+                .as_ref()
+                .unwrap_or(&media.name)
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect();
+
+            Some(SubtitleTrack { tag, url })
+        })
+        .collect()
+}
+
+fn compare_variants(a: &m3u8_rs::VariantStream, b: &m3u8_rs::VariantStream) -> std::cmp::Ordering {
+    if let (Some(res_a), Some(res_b)) = (a.resolution, b.resolution) {
+        let res_a_pixels = res_a.width * res_a.height;
+        let res_b_pixels = res_b.width * res_b.height;
+
+        if res_a_pixels != res_b_pixels {
+            return res_a_pixels.cmp(&res_b_pixels);
+        }
+    }
+
+    if let (Some(bw_a), Some(bw_b)) = (a.average_bandwidth, b.average_bandwidth) {
+        return bw_a.cmp(&bw_b);
+    }
+
+    a.bandwidth.cmp(&b.bandwidth)
+}
+
+fn select_variant(variants: &[m3u8_rs::VariantStream], quality: Quality) -> Option<&m3u8_rs::VariantStream> {
+    let mut candidates = variants.iter().filter(|variant| !variant.is_i_frame);
+
+    match quality {
+        Quality::Best => candidates.max_by(|a, b| compare_variants(a, b)),
+        Quality::Worst => candidates.min_by(|a, b| compare_variants(a, b)),
+        Quality::MaxHeight(max_height) => {
+            let candidates: Vec<_> = candidates.collect();
+            let with_resolution = || {
+                candidates
+                    .iter()
+                    .copied()
+                    .filter(|variant| variant.resolution.is_some())
+            };
+
+            with_resolution()
+                .filter(|variant| {
+                    variant
+                        .resolution
+                        .is_some_and(|res| res.height <= u64::from(max_height))
+                })
+                .max_by(|a, b| compare_variants(a, b))
+                .or_else(|| with_resolution().min_by(|a, b| compare_variants(a, b)))
+                .or_else(|| candidates.iter().copied().max_by(|a, b| compare_variants(a, b)))
+        }
+    }
 }
 
 enum ProgressBarOrResult {
@@ -190,44 +873,142 @@ impl ProgressBarOrResult {
     }
 }
 
+/// Container of downloaded m3u8 streams. Direct video files are saved as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Container {
+    Mp4,
+    /// Keeps all video, audio and subtitle streams
+    Mkv,
+    /// The concatenated segments without remuxing
+    Ts,
+}
+
+impl Container {
+    fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::Ts => "ts",
+        }
+    }
+
+    fn subtitle_codec(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mov_text",
+            Container::Mkv => "srt",
+            Container::Ts => unreachable!("subtitles are not embedded into ts"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AudioFormat {
+    Mp3,
+    M4a,
+    Opus,
+}
+
+impl AudioFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "libmp3lame",
+            AudioFormat::M4a => "aac",
+            AudioFormat::Opus => "libopus",
+        }
+    }
+
+    /// Codecs tried in order. Copying keeps the quality and is fast, but only
+    /// works if the audio track already has a codec supported by the format,
+    /// e.g. AAC for m4a.
+    fn codecs(&self) -> [&'static str; 2] {
+        ["copy", self.encoder()]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioExtraction {
+    pub format: AudioFormat,
+    pub keep_video: bool,
+}
+
 pub(crate) struct Downloader {
     client: Option<reqwest_partial_retry::Client>,
     multi_progress: indicatif::MultiProgress,
     total_progress: RefCell<Option<indicatif::ProgressBar>>,
     sub_progresses: RefCell<Vec<ProgressBarOrResult>>,
+    total_episodes: SelectedEpisodes,
+    /// Estimated end of all downloads, shown as the ETA of the total progress bar
+    series_finish: Arc<Mutex<Option<Instant>>>,
     ffmpeg_path: Option<PathBuf>,
     user_agent: Option<String>,
+    audio_extraction: Option<AudioExtraction>,
+    headers: Vec<(HeaderName, String)>,
+    segment_concurrency: NonZeroU32,
+    container: Container,
+    /// Shared by all concurrent downloads, so that the limit applies to their sum
+    rate_limiter: Option<async_speed_limit::Limiter>,
+    /// Shared by all concurrent downloads, so that the limit applies per host
+    host_limiter: Option<HostLimiter>,
+    require_ffmpeg: bool,
+    keep_intermediate: bool,
+    verify: bool,
+    cancellation_token: CancellationToken,
+    redirect_policy: RedirectPolicy,
+    retry_budget: RetryBudget,
+    /// Sends the origin of the referer with the keys and segments of m3u8 streams
+    send_origin: bool,
+    /// Bytes, which have to stay free in the directory of a download
+    min_free_space: Option<u64>,
+    /// Smaller downloads, which are not m3u8 streams, are placeholders
+    min_file_size: u64,
+    /// Writes the resolved media playlist of m3u8 streams instead of downloading them
+    write_playlist: bool,
+    progress_json: bool,
     debug: bool,
 }
 
 impl Downloader {
     pub(crate) fn new(
-        log_wrapper: &mut SetLogWrapper,
+        multi_progress: indicatif::MultiProgress,
         debug: bool,
         ffmpeg_path: Option<PathBuf>,
         user_agent: Option<String>,
         retries: Option<Option<NonZeroU32>>,
+        proxy: Option<reqwest::Proxy>,
+        network: &NetworkConfig,
     ) -> Self {
-        let multi_progress = indicatif::MultiProgress::new();
-        log_wrapper.set_multi(Some(multi_progress.clone()));
-
+        // Also used by all segment requests of m3u8 streams
         let client = if let Some(retries) = retries {
-            let client = reqwest::Client::builder()
+            let mut client_builder = network
+                .apply(reqwest::Client::builder())
                 .user_agent(DEFAULT_USER_AGENT)
-                .connect_timeout(Duration::from_secs(20))
-                .build()
-                .unwrap()
-                .resumable_with_config(
-                    Config::builder()
-                        .retry_policy(
-                            ExponentialBackoffBuilder::default()
-                                .retry_bounds(Duration::from_secs(1), Duration::from_secs(10))
-                                .build_with_max_retries(retries.map(|x| x.get()).unwrap_or(u32::MAX)),
-                        )
-                        .retryable_strategy(DefaultRetryableStrategy)
-                        .stream_timeout(Some(Duration::from_secs(60)))
-                        .build(),
-                );
+                .gzip(true)
+                .brotli(true)
+                .deflate(true);
+
+            if let Some(proxy) = proxy {
+                client_builder = client_builder.proxy(proxy);
+            }
+
+            let client = client_builder.build().unwrap().resumable_with_config(
+                Config::builder()
+                    .retry_policy(BudgetedRetryPolicy(RetryAfterPolicy(
+                        ExponentialBackoffBuilder::default()
+                            .retry_bounds(Duration::from_secs(1), Duration::from_secs(10))
+                            .build_with_max_retries(retries.map(|x| x.get()).unwrap_or(u32::MAX)),
+                    )))
+                    .retryable_strategy(RetryAfterStrategy(DefaultRetryableStrategy))
+                    .stream_timeout(Some(Duration::from_secs(60)))
+                    .build(),
+            );
 
             Some(client)
         } else {
@@ -239,45 +1020,270 @@ impl Downloader {
             multi_progress,
             total_progress: RefCell::new(None),
             sub_progresses: RefCell::new(vec![]),
+            total_episodes: SelectedEpisodes::default(),
+            series_finish: Arc::new(Mutex::new(None)),
             ffmpeg_path,
             user_agent,
+            audio_extraction: None,
+            headers: vec![],
+            segment_concurrency: NonZeroU32::MIN,
+            container: Container::Mp4,
+            rate_limiter: None,
+            host_limiter: None,
+            require_ffmpeg: false,
+            keep_intermediate: false,
+            verify: false,
+            cancellation_token: CancellationToken::new(),
+            redirect_policy: RedirectPolicy::default(),
+            retry_budget: RetryBudget::default(),
+            send_origin: false,
+            min_free_space: None,
+            min_file_size: 0,
+            write_playlist: false,
+            progress_json: false,
             debug,
         }
     }
 
-    pub(crate) async fn download_to_file(&self, task: InternalDownloadTask) -> Result<(), anyhow::Error> {
-        let url = Url::parse(&task.url).context("failed to parse URL")?;
-        let response = get_response(
-            self.client.as_ref(),
-            url.clone(),
-            self.user_agent.as_deref(),
-            task.referer.as_deref(),
-            None,
-        )
-        .await?;
-        let is_m3u8 = is_m3u8_url(response.url());
-
-        let output_path = if !task.output_path_has_extension {
-            match (
-                task.output_path.parent(),
-                task.output_path.file_name().map(|file_name| file_name.to_owned()),
-            ) {
-                (Some(parent), Some(mut file_name)) => {
-                    let extension = if is_m3u8 { ".ts" } else { ".mp4" };
-                    file_name.push(extension);
-                    parent.join(file_name)
-                }
-                _ => task.output_path,
-            }
-        } else {
-            task.output_path
-        };
+    pub(crate) fn audio_extraction(mut self, audio_extraction: Option<AudioExtraction>) -> Self {
+        self.audio_extraction = audio_extraction;
+        self
+    }
+
+    /// Additional headers sent with every request of a download.
+    pub(crate) fn headers(mut self, headers: Vec<(HeaderName, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Number of m3u8 segments, which are downloaded concurrently.
+    pub(crate) fn segment_concurrency(mut self, segment_concurrency: NonZeroU32) -> Self {
+        self.segment_concurrency = segment_concurrency;
+        self
+    }
+
+    pub(crate) fn container(mut self, container: Container) -> Self {
+        self.container = container;
+        self
+    }
+
+    /// Limits the total download speed. Clones of the limiter are shared, so
+    /// that the downloads of several downloaders are limited together.
+    pub(crate) fn rate_limiter(mut self, rate_limiter: Option<async_speed_limit::Limiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Limits the requests in flight to each host. Files hold their request
+    /// while downloading, while m3u8 streams do so for each segment.
+    pub(crate) fn host_limiter(mut self, host_limiter: Option<HostLimiter>) -> Self {
+        self.host_limiter = host_limiter;
+        self
+    }
+
+    /// Fails m3u8 downloads, which cannot be converted due to FFmpeg not being installed.
+    pub(crate) fn require_ffmpeg(mut self, require_ffmpeg: bool) -> Self {
+        self.require_ffmpeg = require_ffmpeg;
+        self
+    }
+
+    /// Keeps the downloaded `.ts` file of m3u8 streams after converting it.
+    pub(crate) fn keep_intermediate(mut self, keep_intermediate: bool) -> Self {
+        self.keep_intermediate = keep_intermediate;
+        self
+    }
+
+    /// Checks finished m3u8 downloads for decode errors with FFmpeg.
+    pub(crate) fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Prints the progress as JSON lines to stdout instead of drawing the
+    /// progress bars.
+    pub(crate) fn progress_json(mut self, progress_json: bool) -> Self {
+        self.progress_json = progress_json;
+        self
+    }
+
+    /// Running downloads are stopped after the token is cancelled, and their
+    /// partial files deleted, unless they are meant to be resumed.
+    pub(crate) fn cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    pub(crate) fn redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self
+    }
+
+    /// Retries shared by all requests of an m3u8 stream.
+    pub(crate) fn retry_budget(mut self, retry_budget: RetryBudget) -> Self {
+        self.retry_budget = retry_budget;
+        self
+    }
+
+    /// Sends an `Origin` header, which is derived from the referer, with the
+    /// keys and segments of m3u8 streams, since some hosts reject them otherwise.
+    pub(crate) fn send_origin(mut self, send_origin: bool) -> Self {
+        self.send_origin = send_origin;
+        self
+    }
+
+    /// Checks the free space before each download and while downloading
+    /// m3u8 streams, and stops the download, if less is available.
+    pub(crate) fn min_free_space(mut self, min_free_space: Option<u64>) -> Self {
+        self.min_free_space = min_free_space;
+        self
+    }
+
+    /// Fails downloads, which are not m3u8 streams and have fewer bytes, with
+    /// [`PlaceholderVideo`], so that the next stream is tried instead.
+    pub(crate) fn min_file_size(mut self, min_file_size: u64) -> Self {
+        self.min_file_size = min_file_size;
+        self
+    }
+
+    /// Writes the media playlist of m3u8 streams with absolute uris as `.m3u8`
+    /// file, instead of downloading their segments.
+    pub(crate) fn write_playlist(mut self, write_playlist: bool) -> Self {
+        self.write_playlist = write_playlist;
+        self
+    }
+
+    /// Fails with [`InsufficientSpace`], if less than the minimum is free in
+    /// the directory of the path.
+    fn check_free_space(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let Some(min_free_space) = self.min_free_space else {
+            return Ok(());
+        };
+
+        let directory = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let available = fs2::available_space(directory)
+            .with_context(|| format!("failed to get free space of \"{}\"", directory.display()))?;
+
+        if available < min_free_space {
+            return Err(InsufficientSpace {
+                available,
+                minimum: min_free_space,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// The client of this downloader, which uses its proxy and retry settings.
+    pub(crate) fn client(&self) -> Option<&reqwest_partial_retry::Client> {
+        self.client.as_ref()
+    }
+
+    /// Returns the path of the produced file.
+    pub(crate) async fn download_to_file(&self, task: InternalDownloadTask) -> Result<PathBuf, anyhow::Error> {
+        self.download_file(task).await.map(|file| file.path)
+    }
+
+    /// Like [`Self::download_to_file`], but also tells whether the file was
+    /// already downloaded before.
+    pub(crate) async fn download_file(&self, task: InternalDownloadTask) -> Result<DownloadedFile, anyhow::Error> {
+        let url = Url::parse(&task.url).context("failed to parse URL")?;
+
+        // Checked before the first request, since the type of the stream is not known yet
+        if task.skip_existing {
+            if let Some(existing_path) = self.find_existing_file(&task.output_path, task.output_path_has_extension) {
+                log::info!(
+                    "Skipping \"{}\", since it already exists",
+                    existing_path.file_name().unwrap_or_default().to_string_lossy()
+                );
+                return Ok(DownloadedFile {
+                    path: existing_path,
+                    skipped: true,
+                });
+            }
+        }
+
+        self.check_free_space(&task.output_path)?;
+
+        let extra_headers: Vec<(HeaderName, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.as_str()))
+            .collect();
+        let host_permit = self.acquire_host(&url).await;
+
+        // A resumed download requests the rest of the existing file right away.
+        // Its path is guessed from the url, since the type of the stream is not
+        // known yet.
+        let mut resume_len = if task.resume && !task.overwrite_file && !is_m3u8_url(&url) {
+            let guessed_path = if task.output_path_has_extension {
+                task.output_path.clone()
+            } else {
+                append_stream_extension(task.output_path.clone(), false)
+            };
+
+            tokio::fs::metadata(&guessed_path)
+                .await
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let range = format!("bytes={}-", resume_len);
+        let mut first_headers = extra_headers.clone();
+
+        if resume_len > 0 {
+            first_headers.push((reqwest::header::RANGE, range.as_str()));
+        }
+
+        let mut response = get_response_with_redirect_policy(
+            self.client.as_ref(),
+            url.clone(),
+            self.user_agent.as_deref(),
+            task.referer.as_deref(),
+            Some(&first_headers),
+            self.redirect_policy,
+        )
+        .await?;
+        let is_m3u8 = is_m3u8_url(response.url());
+
+        // The url redirected to a playlist, which must not be requested partially
+        if is_m3u8 && resume_len > 0 {
+            resume_len = 0;
+            response = get_response_with_redirect_policy(
+                self.client.as_ref(),
+                url.clone(),
+                self.user_agent.as_deref(),
+                task.referer.as_deref(),
+                Some(&extra_headers),
+                self.redirect_policy,
+            )
+            .await?;
+        }
+
+        // The segments of m3u8 streams acquire their own permits, so the one of
+        // the playlist must not be held while they are downloaded
+        let _host_permit = if is_m3u8 {
+            drop(host_permit);
+            None
+        } else {
+            host_permit
+        };
+
+        let output_path = if !task.output_path_has_extension {
+            append_stream_extension(task.output_path, is_m3u8)
+        } else {
+            task.output_path
+        };
 
         let message = if let Some(custom_message) = task.custom_message {
             custom_message
         } else {
             let final_path = if is_m3u8 {
-                Cow::Owned(output_path.with_extension("mp4"))
+                Cow::Owned(output_path.with_extension(self.container.extension()))
             } else {
                 Cow::Borrowed(&output_path)
             };
@@ -289,13 +1295,99 @@ impl Downloader {
                 .to_string()
         };
 
-        let target_file = if task.overwrite_file {
+        if is_m3u8 && self.write_playlist {
+            let (media_playlist_url, mut media_playlist, _) = self
+                .resolve_media_playlist(
+                    response,
+                    task.referer.as_deref(),
+                    &extra_headers,
+                    task.quality,
+                    None,
+                    url,
+                )
+                .await?;
+            make_playlist_uris_absolute(&mut media_playlist, &media_playlist_url)?;
+
+            let mut playlist_bytes = Vec::new();
+            media_playlist
+                .write_to(&mut playlist_bytes)
+                .context("failed to write m3u8 playlist")?;
+
+            let playlist_path = output_path.with_extension("m3u8");
+            tokio::fs::write(&playlist_path, playlist_bytes)
+                .await
+                .context("failed to write m3u8 playlist file")?;
+            log::info!("Wrote playlist of \"{}\"", message);
+
+            return Ok(DownloadedFile {
+                path: playlist_path,
+                skipped: false,
+            });
+        }
+
+        if is_m3u8 && self.require_ffmpeg && self.container != Container::Ts && self.ffmpeg_path.is_none() {
+            anyhow::bail!(
+                "FFmpeg is required for converting to {}, but not installed",
+                self.container.extension().to_ascii_uppercase()
+            );
+        }
+
+        if task.resume && is_m3u8 {
+            let container_path = output_path.with_extension(self.container.extension());
+            let output_path_exists = output_path.try_exists().unwrap_or(false);
+            let existing_path = if self.container != Container::Ts
+                && !output_path_exists
+                && container_path.try_exists().unwrap_or(false)
+            {
+                Some(container_path)
+            } else if output_path_exists
+                && !get_segments_file_path(&output_path).try_exists().unwrap_or(true)
+                && (self.container == Container::Ts || !self.require_ffmpeg)
+            {
+                // Without remuxing, only a missing completed segments file marks the end
+                Some(output_path.clone())
+            } else {
+                None
+            };
+
+            if let Some(existing_path) = existing_path {
+                log::info!("Skipping \"{}\", since it was already downloaded", message);
+                return Ok(DownloadedFile {
+                    path: existing_path,
+                    skipped: true,
+                });
+            }
+        }
+
+        // Only files created by this download are deleted, if it is cancelled
+        let partial_paths = if task.resume {
+            vec![]
+        } else if is_m3u8 {
+            let container_path = output_path.with_extension(self.container.extension());
+            let mut partial_paths = vec![output_path.clone(), get_segments_file_path(&output_path)];
+
+            if self.container != Container::Ts && !container_path.try_exists().unwrap_or(true) {
+                partial_paths.push(container_path);
+            }
+
+            partial_paths
+        } else {
+            vec![output_path.clone()]
+        };
+
+        let mut target_file = if task.overwrite_file {
             tokio::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
                 .truncate(true)
                 .open(&output_path)
                 .await
+        } else if task.resume {
+            tokio::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .open(&output_path)
+                .await
         } else {
             tokio::fs::OpenOptions::new()
                 .write(true)
@@ -305,18 +1397,347 @@ impl Downloader {
         }
         .context("failed to open download target file")?;
 
-        if is_m3u8 {
-            self.m3u8_download(
+        let download_future = async {
+            let final_path = if is_m3u8 {
+                let m3u8_future = self.m3u8_download(
+                    response,
+                    task.referer.as_deref(),
+                    &extra_headers,
+                    task.quality,
+                    task.subtitles,
+                    url,
+                    target_file,
+                    output_path,
+                    message,
+                    task.resume,
+                    task.overwrite_file,
+                );
+                retry::budget::scope(self.retry_budget, m3u8_future).await?
+            } else {
+                if resume_len == 0 {
+                    self.simple_download(response, target_file, 0, message).await?;
+                } else {
+                    match response.status() {
+                        reqwest::StatusCode::PARTIAL_CONTENT => {
+                            target_file
+                                .seek(SeekFrom::End(0))
+                                .await
+                                .context("failed to seek to end of download target file")?;
+                            self.simple_download(response, target_file, resume_len, message).await?;
+                        }
+                        reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                            // The range only starts past the end, if the file is already complete
+                            let total_len = get_content_range_total(&response);
+
+                            if total_len == Some(resume_len) {
+                                log::info!("Skipping \"{}\", since it was already downloaded", message);
+                                return Ok(DownloadedFile {
+                                    path: output_path,
+                                    skipped: true,
+                                });
+                            }
+
+                            log::debug!(
+                                "Existing file of \"{}\" has {} bytes, but the server has {:?}, starting from scratch",
+                                message,
+                                resume_len,
+                                total_len
+                            );
+                            target_file
+                                .set_len(0)
+                                .await
+                                .context("failed to truncate download target file")?;
+                            let full_response = get_response_with_redirect_policy(
+                                self.client.as_ref(),
+                                url,
+                                self.user_agent.as_deref(),
+                                task.referer.as_deref(),
+                                Some(&extra_headers),
+                                self.redirect_policy,
+                            )
+                            .await?;
+                            self.simple_download(full_response, target_file, 0, message).await?;
+                        }
+                        _ => {
+                            // Server does not support ranges, so start from scratch
+                            target_file
+                                .set_len(0)
+                                .await
+                                .context("failed to truncate download target file")?;
+                            self.simple_download(response, target_file, 0, message).await?;
+                        }
+                    }
+                }
+
+                let downloaded_len = tokio::fs::metadata(&output_path)
+                    .await
+                    .context("failed to get length of download target file")?
+                    .len();
+
+                if downloaded_len < self.min_file_size {
+                    return Err(PlaceholderVideo(format!(
+                        "only {} were downloaded, but at least {} are expected",
+                        indicatif::BinaryBytes(downloaded_len),
+                        indicatif::BinaryBytes(self.min_file_size)
+                    ))
+                    .into());
+                }
+
+                output_path
+            };
+
+            if let Some(audio_extraction) = &self.audio_extraction {
+                let audio_path = self
+                    .extract_audio(&final_path, audio_extraction)
+                    .await
+                    .context("failed to extract audio")?;
+
+                if !audio_extraction.keep_video {
+                    return Ok(DownloadedFile {
+                        path: audio_path,
+                        skipped: false,
+                    });
+                }
+            }
+
+            Ok::<_, anyhow::Error>(DownloadedFile {
+                path: final_path,
+                skipped: false,
+            })
+        };
+
+        tokio::select! {
+            result = download_future => {
+                // Not worth keeping, since the disk would run full again, or it is no video
+                if result
+                    .as_ref()
+                    .is_err_and(|err| is_insufficient_space(err) || is_placeholder_video(err))
+                {
+                    remove_partial_files(&partial_paths).await;
+                }
+
+                result
+            }
+            _ = self.cancellation_token.cancelled() => {
+                remove_partial_files(&partial_paths).await;
+                anyhow::bail!("download was cancelled");
+            }
+        }
+    }
+
+    /// Writes the media playlist of an m3u8 stream next to its first segments,
+    /// while the other segments stay remote, so that a player can start it
+    /// without buffering. Returns the paths of the playlist and the downloaded
+    /// subtitles, or `None`, if it is no m3u8 stream.
+    pub(crate) async fn prefetch_m3u8_head(
+        &self,
+        task: InternalDownloadTask,
+        segment_count: usize,
+    ) -> Result<Option<(PathBuf, Vec<PathBuf>)>, anyhow::Error> {
+        let url = Url::parse(&task.url).context("failed to parse URL")?;
+        let extra_headers: Vec<(HeaderName, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.as_str()))
+            .collect();
+        let response = get_response_with_redirect_policy(
+            self.client.as_ref(),
+            url.clone(),
+            self.user_agent.as_deref(),
+            task.referer.as_deref(),
+            Some(&extra_headers),
+            self.redirect_policy,
+        )
+        .await?;
+
+        if !is_m3u8_url(response.url()) {
+            return Ok(None);
+        }
+
+        let (media_playlist_url, mut media_playlist, subtitle_tracks) = self
+            .resolve_media_playlist(
                 response,
                 task.referer.as_deref(),
+                &extra_headers,
+                task.quality,
+                task.subtitles,
                 url,
-                target_file,
-                output_path,
-                message,
             )
+            .await?;
+        let byte_ranges = get_segment_byte_ranges(&media_playlist.segments);
+        make_playlist_uris_absolute(&mut media_playlist, &media_playlist_url)?;
+
+        let playlist_path = task.output_path.with_extension("m3u8");
+        let file_stem = playlist_path
+            .file_stem()
+            .context("failed to get file name")?
+            .to_string_lossy()
+            .into_owned();
+
+        for (index, (segment, byte_range)) in media_playlist.segments.iter_mut().zip(byte_ranges).enumerate() {
+            if index < segment_count {
+                let segment_url = Url::parse(&segment.uri).context("failed to parse segment url")?;
+                // FFmpeg only accepts local segments with known extensions
+                let extension = Path::new(segment_url.path())
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .filter(|extension| matches!(*extension, "m4s" | "mp4" | "aac"))
+                    .unwrap_or("ts");
+                let segment_bytes = self
+                    .download_segment(
+                        segment_url.clone(),
+                        byte_range,
+                        None,
+                        task.referer.as_deref(),
+                        &extra_headers,
+                    )
+                    .await?;
+                let segment_file_name = format!("{file_stem}.{index}.{extension}");
+
+                tokio::fs::write(playlist_path.with_file_name(&segment_file_name), segment_bytes)
+                    .await
+                    .context("failed to write prefetched segment")?;
+                segment.uri = segment_file_name;
+                segment.byte_range = None;
+            } else if let Some(byte_range) = byte_range {
+                // Implicit offsets would refer to the replaced segments
+                segment.byte_range = Some(m3u8_rs::ByteRange {
+                    length: byte_range.end - byte_range.start,
+                    offset: Some(byte_range.start),
+                });
+            }
+        }
+
+        let mut playlist_bytes = Vec::new();
+        media_playlist
+            .write_to(&mut playlist_bytes)
+            .context("failed to write m3u8 playlist")?;
+        tokio::fs::write(&playlist_path, playlist_bytes)
             .await
-        } else {
-            self.simple_download(response, target_file, message).await
+            .context("failed to write m3u8 playlist file")?;
+
+        let subtitle_paths = self
+            .download_subtitles(
+                &subtitle_tracks,
+                task.referer.as_deref(),
+                &extra_headers,
+                &playlist_path,
+            )
+            .await;
+
+        Ok(Some((playlist_path, subtitle_paths)))
+    }
+
+    /// Returns the path of the extracted audio.
+    async fn extract_audio(
+        &self,
+        video_path: &Path,
+        audio_extraction: &AudioExtraction,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let Some(ffmpeg_path) = &self.ffmpeg_path else {
+            anyhow::bail!("FFmpeg is required, but not installed");
+        };
+
+        let audio_path = video_path.with_extension(audio_extraction.format.extension());
+        let mut result = Ok(());
+
+        for codec in audio_extraction.format.codecs() {
+            result = self
+                .run_audio_extraction(ffmpeg_path, video_path, &audio_path, codec)
+                .await;
+
+            let Err(err) = &result else {
+                break;
+            };
+
+            log::debug!("Failed to extract audio with codec {}: {:#}", codec, err);
+            remove_file_ignore_not_exists(&audio_path)
+                .await
+                .context("failed to delete incomplete audio file")?;
+        }
+
+        result?;
+
+        if !audio_extraction.keep_video {
+            remove_file_ignore_not_exists(video_path)
+                .await
+                .context("failed to delete video file")?;
+        }
+
+        Ok(audio_path)
+    }
+
+    async fn run_audio_extraction(
+        &self,
+        ffmpeg_path: &Path,
+        video_path: &Path,
+        audio_path: &Path,
+        codec: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut ffmpeg_cmd = tokio::process::Command::new(ffmpeg_path);
+        ffmpeg_cmd.kill_on_drop(true);
+
+        if !self.debug {
+            ffmpeg_cmd
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            ffmpeg_cmd.arg("-nostdin");
+        }
+
+        // The audio of an earlier download of the video is replaced, instead of
+        // FFmpeg asking whether to overwrite it
+        let ffmpeg_result = ffmpeg_cmd
+            .arg("-y")
+            .arg("-i")
+            .arg(video_path)
+            .arg("-vn")
+            .arg("-c:a")
+            .arg(codec)
+            .arg(audio_path)
+            .status()
+            .await
+            .context("failed to run FFmpeg")?;
+
+        match ffmpeg_result.code() {
+            Some(0) => Ok(()),
+            Some(code) => anyhow::bail!("FFmpeg failed with exit code {}", code),
+            None => anyhow::bail!("FFmpeg failed due to signal termination"),
+        }
+    }
+
+    /// Decodes the whole video with FFmpeg and fails if any errors are reported.
+    async fn verify_video(&self, video_path: &Path) -> Result<(), anyhow::Error> {
+        let Some(ffmpeg_path) = &self.ffmpeg_path else {
+            anyhow::bail!("FFmpeg is required, but not installed");
+        };
+
+        let ffmpeg_output = tokio::process::Command::new(ffmpeg_path)
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .arg("-nostdin")
+            .arg("-v")
+            .arg("error")
+            .arg("-i")
+            .arg(video_path)
+            .arg("-f")
+            .arg("null")
+            .arg("-")
+            .output()
+            .await
+            .context("failed to run FFmpeg")?;
+        let errors = String::from_utf8_lossy(&ffmpeg_output.stderr);
+
+        if let Some(first_error) = errors.lines().find(|line| !line.trim().is_empty()) {
+            anyhow::bail!("FFmpeg reported decode errors, e.g.: {}", first_error.trim());
+        }
+
+        match ffmpeg_output.status.code() {
+            Some(0) => Ok(()),
+            Some(code) => anyhow::bail!("FFmpeg failed with exit code {}", code),
+            None => anyhow::bail!("FFmpeg failed due to signal termination"),
         }
     }
 
@@ -324,9 +1745,10 @@ impl Downloader {
         &self,
         response: reqwest_partial_retry::ResumableResponse,
         target_file: tokio::fs::File,
+        offset: u64,
         message: String,
     ) -> Result<(), anyhow::Error> {
-        let content_length = response.content_length();
+        let content_length = response.content_length().map(|content_length| content_length + offset);
 
         let (sub_progresses_index, progress_bar) = if let Some(content_length) = content_length {
             self.create_progress_bar(message, content_length)
@@ -336,7 +1758,7 @@ impl Downloader {
 
         let mut input_stream = response.bytes_stream_resumable();
         let mut output_stream = tokio::io::BufWriter::new(target_file);
-        let mut downloaded = 0;
+        let mut downloaded = offset;
 
         while let Some(item) = input_stream.next().await {
             let mut chunk = match item {
@@ -348,6 +1770,7 @@ impl Downloader {
             };
 
             downloaded += chunk.len() as u64;
+            self.limit_rate(chunk.len()).await;
 
             if let Err(err) = output_stream.write_all_buf(&mut chunk).await {
                 self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
@@ -357,6 +1780,19 @@ impl Downloader {
             self.update_progress(&progress_bar, downloaded, content_length);
         }
 
+        if let Some(content_length) = content_length {
+            if !is_length_within_tolerance(downloaded, content_length) {
+                // Keep what was written, so that the download can be continued
+                let _ = Self::clean_up_write(output_stream).await;
+                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                anyhow::bail!(
+                    "downloaded {} bytes, but the server announced {} bytes",
+                    downloaded,
+                    content_length
+                );
+            }
+        }
+
         // Replace estimation with total size after download finished
         // Useful if Content-Length was unavailable or inaccurate
         self.update_progress(&progress_bar, downloaded, Some(downloaded));
@@ -371,75 +1807,66 @@ impl Downloader {
         Ok(())
     }
 
-    async fn m3u8_download(
+    /// Parses the playlist of the response, and downloads the media playlist of
+    /// the variant selected by the quality, if it is a master playlist.
+    /// Returns the url of the media playlist, which its uris are relative to.
+    async fn resolve_media_playlist(
         &self,
         response: reqwest_partial_retry::ResumableResponse,
         referer: Option<&str>,
+        extra_headers: &[(HeaderName, &str)],
+        quality: Quality,
+        subtitles: Option<SubtitleOptions>,
         m3u8_url: Url,
-        target_file: tokio::fs::File,
-        target_path: PathBuf,
-        message: String,
-    ) -> Result<(), anyhow::Error> {
-        let m3u8_bytes = get_response_bytes(response.response()).await?;
+    ) -> Result<(Url, m3u8_rs::MediaPlaylist, Vec<SubtitleTrack>), anyhow::Error> {
+        let m3u8_bytes = get_response_bytes_limited(response.response(), MAX_PLAYLIST_SIZE)
+            .await
+            .context("failed to get m3u8 playlist bytes")?;
 
-        let (media_playlist_url, media_playlist) = match m3u8_rs::parse_playlist_res(&m3u8_bytes) {
-            Ok(m3u8_rs::Playlist::MasterPlaylist(mut playlist)) => {
+        let (media_playlist_url, media_playlist, subtitle_tracks) = match m3u8_rs::parse_playlist_res(&m3u8_bytes) {
+            Ok(m3u8_rs::Playlist::MasterPlaylist(playlist)) => {
                 if playlist.variants.is_empty() {
                     anyhow::bail!("could not find any media playlists");
                 }
 
-                let highest_quality_variant = playlist
-                    .variants
-                    .select_nth_unstable_by(0, |a, b| {
-                        use std::cmp::Ordering;
-
-                        match (a.is_i_frame, b.is_i_frame) {
-                            (true, true) => return Ordering::Equal,
-                            (true, false) => return Ordering::Greater,
-                            (false, true) => return Ordering::Less,
-                            (false, false) => {}
-                        }
-
-                        if let (Some(res_a), Some(res_b)) = (a.resolution, b.resolution) {
-                            let res_a_pixels = res_a.width * res_a.height;
-                            let res_b_pixels = res_b.width * res_b.height;
-
-                            if res_a_pixels != res_b_pixels {
-                                return res_a_pixels.cmp(&res_b_pixels).reverse();
-                            }
-                        }
-
-                        if let (Some(bw_a), Some(bw_b)) = (a.average_bandwidth, b.average_bandwidth) {
-                            return bw_a.cmp(&bw_b).reverse();
-                        }
-
-                        a.bandwidth.cmp(&b.bandwidth).reverse()
-                    })
-                    .1;
-
-                if highest_quality_variant.is_i_frame {
+                let Some(selected_variant) = select_variant(&playlist.variants, quality) else {
                     anyhow::bail!("could not find a non-iframe media playlist");
+                };
+
+                if let (Quality::MaxHeight(max_height), Some(resolution)) = (quality, selected_variant.resolution) {
+                    if resolution.height != u64::from(max_height) {
+                        log::info!(
+                            "Requested quality {}p is not available, using {}p instead",
+                            max_height,
+                            resolution.height
+                        );
+                    }
                 }
 
+                let subtitle_tracks = match subtitles {
+                    Some(subtitles) => get_subtitle_tracks(&playlist, selected_variant, subtitles.language, &m3u8_url),
+                    None => vec![],
+                };
+
                 let media_playlist_url = m3u8_url
-                    .join(&highest_quality_variant.uri)
+                    .join(&selected_variant.uri)
                     .context("failed to create m3u8 media playlist url")?;
-                let m3u8_media_bytes = get_response(
+                let m3u8_media_response = get_response_with_redirect_policy(
                     self.client.as_ref(),
                     media_playlist_url.as_str(),
                     self.user_agent.as_deref(),
                     referer,
-                    None,
+                    Some(extra_headers),
+                    self.redirect_policy,
                 )
                 .await
-                .context("failed to get m3u8 media playlist response")?
-                .response()
-                .bytes()
-                .await
-                .context("failed to get m3u8 media playlist bytes")?;
+                .context("failed to get m3u8 media playlist response")?;
+                let m3u8_media_bytes = get_response_bytes_limited(m3u8_media_response.response(), MAX_PLAYLIST_SIZE)
+                    .await
+                    .context("failed to get m3u8 media playlist bytes")?;
 
                 match m3u8_rs::parse_media_playlist_res(&m3u8_media_bytes) {
-                    Ok(media_playlist) => (media_playlist_url, media_playlist),
+                    Ok(media_playlist) => (media_playlist_url, media_playlist, subtitle_tracks),
                     Err(_) => anyhow::bail!("failed to parse m3u8 media playlist"),
                 }
             }
@@ -448,135 +1875,239 @@ impl Downloader {
                     anyhow::bail!("is iframe media playlist");
                 }
 
-                (m3u8_url, playlist)
+                (m3u8_url, playlist, vec![])
             }
             Err(_) => anyhow::bail!("failed to parse m3u8"),
         };
 
-        let (sub_progresses_index, progress_bar) = self.create_progress_bar(message, u64::MAX);
-        let mut output_stream = tokio::io::BufWriter::new(target_file);
-        let mut downloaded_bytes = 0;
-        let total_duration: f64 = media_playlist
-            .segments
-            .iter()
-            .map(|segment| segment.duration as f64)
-            .sum();
+        if media_playlist.segments.is_empty() {
+            return Err(PlaceholderVideo("m3u8 media playlist has no segments".to_owned()).into());
+        }
+
+        Ok((media_playlist_url, media_playlist, subtitle_tracks))
+    }
+
+    async fn m3u8_download(
+        &self,
+        response: reqwest_partial_retry::ResumableResponse,
+        referer: Option<&str>,
+        extra_headers: &[(HeaderName, &str)],
+        quality: Quality,
+        subtitles: Option<SubtitleOptions>,
+        m3u8_url: Url,
+        mut target_file: tokio::fs::File,
+        target_path: PathBuf,
+        message: String,
+        resume: bool,
+        overwrite: bool,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let (media_playlist_url, media_playlist, subtitle_tracks) = self
+            .resolve_media_playlist(response, referer, extra_headers, quality, subtitles, m3u8_url)
+            .await?;
+
+        // Record completed segments, so that an interrupted download can be resumed
+        let segments_file_path = get_segments_file_path(&target_path);
+        let completed_segments = if resume {
+            match tokio::fs::read_to_string(&segments_file_path).await {
+                Ok(contents) => CompletedSegments::parse(&contents),
+                Err(err) if err.kind() == ErrorKind::NotFound => CompletedSegments::default(),
+                Err(err) => return Err(err).context("failed to read completed segments file"),
+            }
+        } else {
+            CompletedSegments::default()
+        };
+
+        target_file
+            .set_len(completed_segments.output_len)
+            .await
+            .context("failed to truncate download target file")?;
+        target_file
+            .seek(SeekFrom::End(0))
+            .await
+            .context("failed to seek to end of download target file")?;
+
+        let mut segments_file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&segments_file_path)
+            .await
+            .context("failed to open completed segments file")?;
+        segments_file
+            .set_len(completed_segments.record_len as u64)
+            .await
+            .context("failed to truncate completed segments file")?;
+        segments_file
+            .seek(SeekFrom::End(0))
+            .await
+            .context("failed to seek to end of completed segments file")?;
+
+        let (sub_progresses_index, progress_bar) = self.create_progress_bar(message, u64::MAX);
+        let mut output_stream = tokio::io::BufWriter::new(target_file);
+        let mut downloaded_bytes = completed_segments.output_len;
+        let total_duration: f64 = media_playlist
+            .segments
+            .iter()
+            .map(|segment| segment.duration as f64)
+            .sum();
         let mut downloaded_duration: f64 = 0.0;
         let mut total_bytes_estimation = None;
 
-        enum EncryptionMethod {
-            Aes128,
-        }
         struct Encryption {
             method: EncryptionMethod,
             key: [u8; 16],
             iv: Option<[u8; 16]>,
         }
-        enum Decryptor {
-            None,
-            Aes128 {
-                decryptor: cbc::Decryptor<aes::Aes128>,
-                last_chunk: Option<bytes::Bytes>,
-                rest_to_decrypt: Vec<u8>,
-            },
+        let mut fetched_keys = HashMap::new();
+        let mut segment_jobs = vec![];
+
+        // An Origin header given by the user is not replaced
+        let origin = referer
+            .filter(|_| self.send_origin)
+            .filter(|_| {
+                !extra_headers
+                    .iter()
+                    .any(|(header, _)| header == reqwest::header::ORIGIN)
+            })
+            .and_then(get_referer_origin);
+        let mut segment_headers = extra_headers.to_vec();
+
+        if let Some(origin) = &origin {
+            segment_headers.push((reqwest::header::ORIGIN, origin.as_str()));
         }
-        let mut current_encryption = None;
 
-        for (segement_index, segment) in
+        let segment_headers = segment_headers.as_slice();
+        let byte_ranges = get_segment_byte_ranges(&media_playlist.segments);
+        let init_sections = get_segment_init_sections(&media_playlist.segments);
+        let segment_keys = match get_segment_keys(&media_playlist.segments) {
+            Ok(segment_keys) => segment_keys,
+            Err(err) => {
+                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                return Err(err);
+            }
+        };
+        let mut previous_init_section = None;
+
+        for ((((segement_index, segment), byte_range), init_section), segment_key) in
             std::iter::successors(Some(u128::from(media_playlist.media_sequence)), |&prev| Some(prev + 1))
                 .zip(media_playlist.segments)
+                .zip(byte_ranges)
+                .zip(init_sections)
+                .zip(segment_keys)
         {
-            if let Some(encryption_key) = segment.key {
-                let encryption_method = match encryption_key.method {
-                    KeyMethod::None => None,
-                    KeyMethod::AES128 => Some(EncryptionMethod::Aes128),
-                    KeyMethod::SampleAES => {
-                        self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                        anyhow::bail!("m3u8 SAMPLE-AES decryption not implemented");
-                    }
-                    KeyMethod::Other(other) => {
-                        self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                        anyhow::bail!("m3u8 \"{other}\" decryption not implemented");
-                    }
-                };
+            // The encoding may change after a discontinuity, so the init section is written again
+            if segment.discontinuity {
+                previous_init_section = None;
+            }
 
-                if let Some(encryption_method) = encryption_method {
-                    // Get IV, if there is one
-                    let encryption_iv = match encryption_key.iv {
-                        Some(encryption_iv) => {
-                            let encryption_iv = match encryption_iv
-                                .strip_prefix("0x")
-                                .or_else(|| encryption_iv.strip_prefix("0X"))
-                            {
-                                Some(encryption_iv) => encryption_iv,
-                                None => {
+            // The init section is written in front of the first segment, and again whenever it changes
+            let init_section_changed = init_section.is_some() && init_section != previous_init_section;
+            previous_init_section = init_section.clone();
+
+            if completed_segments.sequence_numbers.contains(&segement_index) {
+                downloaded_duration += segment.duration as f64;
+                continue;
+            }
+
+            let segment_iv = segment_key
+                .as_ref()
+                .map(|segment_key| segment_key.get_iv(segement_index));
+
+            // Each key is only fetched once, even if it is declared again, e.g. after a discontinuity
+            let current_encryption = match segment_key {
+                Some(segment_key) => {
+                    let key_array = match fetched_keys.get(&segment_key.uri) {
+                        Some(key_array) => *key_array,
+                        None => {
+                            let key_url = match media_playlist_url.join(&segment_key.uri) {
+                                Ok(key_url) => key_url,
+                                Err(err) => {
                                     self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                                    anyhow::bail!("decryption iv not in hexadecimal format: {}", encryption_iv);
+                                    return Err(err).context("failed to create m3u8 decryption key url");
                                 }
                             };
-                            let encryption_iv = match u128::from_str_radix(encryption_iv, 16) {
-                                Ok(encryption_iv) => encryption_iv,
+                            let key_response = get_response_with_redirect_policy(
+                                self.client.as_ref(),
+                                key_url,
+                                self.user_agent.as_deref(),
+                                referer,
+                                Some(segment_headers),
+                                self.redirect_policy,
+                            )
+                            .await;
+                            let key_response = match key_response {
+                                Ok(key_response) => key_response,
                                 Err(err) => {
                                     self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                                    return Err(err).context("failed to parse decryption iv to integer");
+                                    return Err(err).context("failed to get response of decryption key");
                                 }
-                            }
-                            .to_be_bytes();
-                            Some(encryption_iv)
-                        }
-                        None => None,
-                    };
+                            };
+                            let key = get_response_bytes_limited(key_response.response(), MAX_PLAYLIST_SIZE).await;
+                            let key = match key {
+                                Ok(key) => key,
+                                Err(err) => {
+                                    self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                                    return Err(err).context("failed to get bytes of decryption key");
+                                }
+                            };
+                            let key_array: [u8; 16] = match Vec::<u8>::from(key).try_into() {
+                                Ok(key_array) => key_array,
+                                Err(_) => {
+                                    self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                                    anyhow::bail!("failed to convert key to array");
+                                }
+                            };
 
-                    // Get key
-                    let relative_key_url = match encryption_key.uri {
-                        Some(relative_key_url) => relative_key_url,
-                        None => {
-                            self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                            anyhow::bail!("no uri for decryption key provided");
-                        }
-                    };
-                    let key_url = match media_playlist_url.join(&relative_key_url) {
-                        Ok(key_url) => key_url,
-                        Err(err) => {
-                            self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                            return Err(err).context("failed to create m3u8 decryption key url");
-                        }
-                    };
-                    let key_response =
-                        get_response(self.client.as_ref(), key_url, self.user_agent.as_deref(), referer, None).await;
-                    let key_response = match key_response {
-                        Ok(key_response) => key_response,
-                        Err(err) => {
-                            self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                            return Err(err).context("failed to get response of decryption key");
+                            fetched_keys.insert(segment_key.uri, key_array);
+                            key_array
                         }
                     };
-                    let key = match key_response.response().bytes().await {
-                        Ok(key) => key,
+
+                    Some(Encryption {
+                        method: segment_key.method,
+                        key: key_array,
+                        iv: segment_key.iv,
+                    })
+                }
+                None => None,
+            };
+
+            let init_section = match init_section.filter(|_| init_section_changed) {
+                Some(init_section) => {
+                    let init_section_url = match media_playlist_url.join(&init_section.uri) {
+                        Ok(init_section_url) => init_section_url,
                         Err(err) => {
                             self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                            return Err(err).context("failed to get bytes of decryption key");
+                            return Err(err).context("failed to create m3u8 init section url");
                         }
                     };
-                    let key_array: [u8; 16] = match Vec::<u8>::from(key).try_into() {
-                        Ok(key_array) => key_array,
-                        Err(_) => {
-                            self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                            anyhow::bail!("failed to convert key to array");
-                        }
+                    // Only AES-128 encrypts the init section, SAMPLE-AES just encrypts the media samples
+                    let encryption = match &current_encryption {
+                        Some(Encryption {
+                            method: EncryptionMethod::Aes128,
+                            key,
+                            iv,
+                        }) => match iv {
+                            Some(iv) => Some(SegmentEncryption {
+                                method: EncryptionMethod::Aes128,
+                                key: *key,
+                                iv: *iv,
+                            }),
+                            None => {
+                                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                                anyhow::bail!("no decryption iv for encrypted m3u8 init section provided");
+                            }
+                        },
+                        _ => None,
                     };
 
-                    // Update encryption
-                    current_encryption = Some(Encryption {
-                        method: encryption_method,
-                        key: key_array,
-                        iv: encryption_iv,
-                    });
-                } else {
-                    // Update encryption
-                    current_encryption = None;
+                    Some(InitSectionJob {
+                        url: init_section_url,
+                        byte_range: init_section.byte_range,
+                        encryption,
+                    })
                 }
-            }
+                None => None,
+            };
 
             let segment_url = match media_playlist_url.join(&segment.uri) {
                 Ok(segment_url) => segment_url,
@@ -585,162 +2116,105 @@ impl Downloader {
                     return Err(err).context("failed to create m3u8 segment url");
                 }
             };
-            let response = match get_response(
-                self.client.as_ref(),
-                segment_url,
-                self.user_agent.as_deref(),
-                referer,
-                None,
-            )
-            .await
-            {
-                Ok(response) => response,
-                Err(err) => {
+            let encryption = current_encryption
+                .as_ref()
+                .zip(segment_iv)
+                .map(|(encryption, iv)| SegmentEncryption {
+                    method: encryption.method,
+                    key: encryption.key,
+                    iv,
+                });
+
+            segment_jobs.push(SegmentJob {
+                sequence_number: segement_index,
+                url: segment_url,
+                byte_range,
+                duration: segment.duration as f64,
+                encryption,
+                init_section,
+            });
+        }
+
+        // Segments are downloaded concurrently, but written in playlist order
+        let mut segment_downloads = futures_util::stream::iter(segment_jobs)
+            .map(|segment_job| async move {
+                let result = async {
+                    let mut segment_bytes = match &segment_job.init_section {
+                        Some(init_section) => self
+                            .download_segment(
+                                init_section.url.clone(),
+                                init_section.byte_range.clone(),
+                                init_section.encryption.as_ref(),
+                                referer,
+                                segment_headers,
+                            )
+                            .await
+                            .context("failed to download init section")?,
+                        None => Vec::new(),
+                    };
+                    let media_bytes = self
+                        .download_segment(
+                            segment_job.url.clone(),
+                            segment_job.byte_range.clone(),
+                            segment_job.encryption.as_ref(),
+                            referer,
+                            segment_headers,
+                        )
+                        .await?;
+                    segment_bytes.extend_from_slice(&media_bytes);
+
+                    Ok::<_, anyhow::Error>(segment_bytes)
+                }
+                .await;
+                (segment_job, result)
+            })
+            .buffered(self.segment_concurrency.get() as usize);
+
+        while let Some((segment_job, result)) = segment_downloads.next().await {
+            let segment_bytes = match result {
+                Ok(segment_bytes) => segment_bytes,
+                Err(err) if retry::budget::is_exhausted() => {
                     self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                    return Err(err).context("failed to get segment response");
+                    return Err(err).context("retry budget of the episode is exhausted");
                 }
-            };
-            let mut input_stream = response.bytes_stream_resumable();
-            let mut decryptor = if let Some(encryption) = &current_encryption {
-                match encryption.method {
-                    EncryptionMethod::Aes128 => Decryptor::Aes128 {
-                        decryptor: cbc::Decryptor::<aes::Aes128>::new(
-                            encryption.key.as_ref().into(),
-                            encryption
-                                .iv
-                                .unwrap_or_else(|| segement_index.to_be_bytes())
-                                .as_ref()
-                                .into(),
-                        ),
-                        last_chunk: None,
-                        rest_to_decrypt: Vec::new(),
-                    },
+                Err(err) => {
+                    self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                    return Err(err);
                 }
-            } else {
-                Decryptor::None
             };
 
-            enum ProcessChunk {
-                NewChunk(bytes::Bytes),
-                FlushLastChunkIfExists,
-            }
-
-            async fn process_chunk(
-                downloader: &Downloader,
-                decryptor: &mut Decryptor,
-                output_stream: &mut tokio::io::BufWriter<tokio::fs::File>,
-                progress_bar: &indicatif::ProgressBar,
-                chunk: ProcessChunk,
-                downloaded_bytes: &mut u64,
-                total_bytes_estimation: &Option<u64>,
-                sub_progresses_index: usize,
-            ) -> Result<(), anyhow::Error> {
-                let chunk_to_write = match decryptor {
-                    Decryptor::None => match chunk {
-                        ProcessChunk::NewChunk(bytes) => Some(bytes),
-                        ProcessChunk::FlushLastChunkIfExists => None,
-                    },
-                    Decryptor::Aes128 {
-                        decryptor,
-                        last_chunk,
-                        rest_to_decrypt,
-                    } => {
-                        let is_last_chunk = match &chunk {
-                            ProcessChunk::NewChunk(_) => false,
-                            ProcessChunk::FlushLastChunkIfExists => true,
-                        };
-                        let current_chunk = match chunk {
-                            ProcessChunk::NewChunk(bytes) => std::mem::replace(last_chunk, Some(bytes)),
-                            ProcessChunk::FlushLastChunkIfExists => std::mem::take(last_chunk),
-                        };
-
-                        if let Some(current_chunk) = current_chunk {
-                            let mut total_to_decrypt: Vec<u8> =
-                                Vec::with_capacity(rest_to_decrypt.len() + current_chunk.len());
-                            total_to_decrypt.extend_from_slice(rest_to_decrypt);
-                            total_to_decrypt.extend_from_slice(&current_chunk);
-
-                            let decryptable_len = total_to_decrypt.len() & !0b1111; // previous multiple of 16
-                            let rest_data = total_to_decrypt.split_off(decryptable_len);
-                            let _ = std::mem::replace(rest_to_decrypt, rest_data);
-
-                            let data: InOutBuf<u8> = total_to_decrypt.as_mut_slice().into();
-                            let (mut blocks, tail) = data.into_chunks();
-                            if !tail.is_empty() {
-                                downloader.error_cleanup_progress_bar(progress_bar, sub_progresses_index);
-                                anyhow::bail!("decryption blocks have tail");
-                            }
-                            decryptor.decrypt_blocks_inout_mut(blocks.reborrow());
-                            if is_last_chunk {
-                                match aes::cipher::block_padding::Pkcs7::unpad_blocks(blocks.into_out()) {
-                                    Ok(truncated_result) => {
-                                        let truncated_len = truncated_result.len();
-                                        total_to_decrypt.truncate(truncated_len);
-                                    }
-                                    Err(UnpadError) => {
-                                        downloader.error_cleanup_progress_bar(progress_bar, sub_progresses_index);
-                                        anyhow::bail!("failed to unpad data");
-                                    }
-                                }
-                            }
-                            Some(bytes::Bytes::from(total_to_decrypt))
-                        } else {
-                            None
-                        }
-                    }
-                };
-
-                if let Some(mut chunk) = chunk_to_write {
-                    *downloaded_bytes += chunk.len() as u64;
+            if let Err(err) = output_stream.write_all(&segment_bytes).await {
+                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                return Err(err).context("failed writing to download file");
+            }
 
-                    if let Err(err) = output_stream.write_all_buf(&mut chunk).await {
-                        downloader.error_cleanup_progress_bar(progress_bar, sub_progresses_index);
-                        return Err(err).context("failed writing to download file");
-                    }
+            if let Err(err) = output_stream.flush().await {
+                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                return Err(err).context("failed flushing to download file");
+            }
 
-                    downloader.update_progress(progress_bar, *downloaded_bytes, *total_bytes_estimation);
+            // Stopped in time, before writing fails without a helpful message
+            if downloaded_bytes / FREE_SPACE_CHECK_INTERVAL
+                != (downloaded_bytes + segment_bytes.len() as u64) / FREE_SPACE_CHECK_INTERVAL
+            {
+                if let Err(err) = self.check_free_space(&target_path) {
+                    self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                    return Err(err);
                 }
-
-                Ok(())
             }
 
-            while let Some(item) = input_stream.next().await {
-                let chunk = match item {
-                    Ok(chunk) => chunk,
-                    Err(err) => {
-                        self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
-                        return Err(err).context("failed download");
-                    }
-                };
+            downloaded_bytes += segment_bytes.len() as u64;
+            let segment_record = format!("{} {}\n", segment_job.sequence_number, downloaded_bytes);
 
-                process_chunk(
-                    self,
-                    &mut decryptor,
-                    &mut output_stream,
-                    &progress_bar,
-                    ProcessChunk::NewChunk(chunk),
-                    &mut downloaded_bytes,
-                    &total_bytes_estimation,
-                    sub_progresses_index,
-                )
-                .await?;
+            if let Err(err) = segments_file.write_all(segment_record.as_bytes()).await {
+                self.error_cleanup_progress_bar(&progress_bar, sub_progresses_index);
+                return Err(err).context("failed writing to completed segments file");
             }
 
-            process_chunk(
-                self,
-                &mut decryptor,
-                &mut output_stream,
-                &progress_bar,
-                ProcessChunk::FlushLastChunkIfExists,
-                &mut downloaded_bytes,
-                &total_bytes_estimation,
-                sub_progresses_index,
-            )
-            .await?;
-
-            downloaded_duration += segment.duration as f64;
+            downloaded_duration += segment_job.duration;
             total_bytes_estimation =
                 Some(((downloaded_bytes as f64 * total_duration) / downloaded_duration).ceil() as u64);
+            self.update_progress(&progress_bar, downloaded_bytes, total_bytes_estimation);
         }
 
         // Replace estimation with total size after download finished
@@ -751,8 +2225,27 @@ impl Downloader {
             return Err(err);
         }
 
-        if let Some(ffmpeg_path) = &self.ffmpeg_path {
+        drop(segments_file);
+
+        if let Err(err) = remove_file_ignore_not_exists(&segments_file_path).await {
+            log::warn!("Failed to delete completed segments file: {}", err);
+        }
+
+        let subtitle_paths = self
+            .download_subtitles(&subtitle_tracks, referer, extra_headers, &target_path)
+            .await;
+        let embedded_subtitles = match subtitles {
+            Some(subtitles) if subtitles.embed && self.container != Container::Ts => subtitle_paths.as_slice(),
+            _ => &[],
+        };
+        let mut final_path = target_path.clone();
+
+        if self.container == Container::Ts {
+            // The concatenated segments are kept as they are
+        } else if let Some(ffmpeg_path) = &self.ffmpeg_path {
+            // A cancelled download must not leave FFmpeg running
             let mut ffmpeg_cmd = tokio::process::Command::new(ffmpeg_path);
+            ffmpeg_cmd.kill_on_drop(true);
 
             if !self.debug {
                 ffmpeg_cmd
@@ -762,23 +2255,62 @@ impl Downloader {
                 ffmpeg_cmd.arg("-nostdin");
             }
 
-            let ffmpeg_spawn_result = ffmpeg_cmd
-                .arg("-i")
-                .arg(&target_path)
-                .arg("-c")
-                .arg("copy")
-                .arg(target_path.with_extension("mp4"))
-                .spawn();
+            // Otherwise FFmpeg refuses to replace the converted file of an earlier download
+            if overwrite {
+                ffmpeg_cmd.arg("-y");
+            }
+
+            ffmpeg_cmd.arg("-i").arg(&target_path);
+
+            for subtitle_path in embedded_subtitles {
+                ffmpeg_cmd.arg("-i").arg(subtitle_path);
+            }
+
+            if self.container == Container::Mkv {
+                ffmpeg_cmd
+                    .arg("-map")
+                    .arg("0:v?")
+                    .arg("-map")
+                    .arg("0:a?")
+                    .arg("-map")
+                    .arg("0:s?");
+            } else if !embedded_subtitles.is_empty() {
+                ffmpeg_cmd.arg("-map").arg("0:v?").arg("-map").arg("0:a?");
+            }
+
+            for input_index in 1..=embedded_subtitles.len() {
+                ffmpeg_cmd.arg("-map").arg(input_index.to_string());
+            }
+
+            ffmpeg_cmd.arg("-c").arg("copy");
+
+            if !embedded_subtitles.is_empty() {
+                ffmpeg_cmd.arg("-c:s").arg(self.container.subtitle_codec());
+            }
+
+            let output_path = target_path.with_extension(self.container.extension());
+            let ffmpeg_spawn_result = ffmpeg_cmd.arg(&output_path).spawn();
 
             match ffmpeg_spawn_result {
                 Ok(mut child) => match child.wait().await {
                     Ok(ffmpeg_result) => match ffmpeg_result.code() {
-                        Some(code) if code != 0 => log::warn!("FFmpeg failed with exit code {}", code),
-                        None => log::warn!("FFmpeg failed due to signal termination"),
+                        Some(code) if code != 0 => log::warn!(
+                            "FFmpeg failed with exit code {}, keeping \"{}\"",
+                            code,
+                            target_path.display()
+                        ),
+                        None => log::warn!(
+                            "FFmpeg failed due to signal termination, keeping \"{}\"",
+                            target_path.display()
+                        ),
                         _ => {
-                            if let Err(err) = remove_file_ignore_not_exists(&target_path).await {
-                                log::warn!("Failed to delete temporary input file for FFmpeg: {}", err);
+                            if !self.keep_intermediate {
+                                if let Err(err) = remove_file_ignore_not_exists(&target_path).await {
+                                    log::warn!("Failed to delete temporary input file for FFmpeg: {}", err);
+                                }
                             }
+
+                            final_path = output_path;
                         }
                     },
                     Err(err) => {
@@ -790,19 +2322,278 @@ impl Downloader {
                 }
             }
         } else {
-            let temp_name = target_path
+            // Keep the unconverted file, but with an extension matching its content
+            let ts_path = target_path.with_extension("ts");
+
+            if ts_path != target_path {
+                match tokio::fs::rename(&target_path, &ts_path).await {
+                    Ok(()) => final_path = ts_path,
+                    Err(err) => log::warn!("Failed to rename unconverted file: {}", err),
+                }
+            }
+
+            let kept_name = final_path
                 .file_name()
-                .unwrap_or(target_path.as_os_str())
+                .unwrap_or(final_path.as_os_str())
                 .to_string_lossy();
             log::info!(
-                "Failed to convert \"{}\" to MP4 due to FFmpeg not being installed",
-                temp_name
+                "Failed to convert \"{}\" to {} due to FFmpeg not being installed",
+                kept_name,
+                self.container.extension().to_ascii_uppercase()
             );
         }
 
+        if self.verify && (self.container == Container::Ts || final_path != target_path) {
+            if let Err(err) = self.verify_video(&final_path).await {
+                log::warn!("Verification of \"{}\" failed: {:#}", final_path.display(), err);
+            }
+        }
+
+        // The progress bar is named after the converted file, which may not have been produced
+        if final_path != target_path.with_extension(self.container.extension()) {
+            if let Some(file_name) = final_path.file_name() {
+                progress_bar.set_message(file_name.to_string_lossy().into_owned());
+            }
+        }
+
         self.clean_up_progress_bar(&progress_bar, sub_progresses_index);
 
-        Ok(())
+        Ok(final_path)
+    }
+
+    /// Downloads a whole m3u8 segment and decrypts it.
+    ///
+    /// Segments with a byte range are fetched with a range request. Since the
+    /// client keeps connections alive, consecutive ranges of the same file
+    /// reuse the connection.
+    async fn download_segment(
+        &self,
+        segment_url: Url,
+        byte_range: Option<Range<u64>>,
+        encryption: Option<&SegmentEncryption>,
+        referer: Option<&str>,
+        extra_headers: &[(HeaderName, &str)],
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let range = byte_range
+            .as_ref()
+            .map(|byte_range| format!("bytes={}-{}", byte_range.start, byte_range.end.saturating_sub(1)));
+        let mut headers = extra_headers.to_vec();
+
+        if let Some(range) = &range {
+            headers.push((reqwest::header::RANGE, range.as_str()));
+        }
+
+        // Held until the whole segment is downloaded
+        let _host_permit = self.acquire_host(&segment_url).await;
+        let response = get_response_with_redirect_policy(
+            self.client.as_ref(),
+            segment_url,
+            self.user_agent.as_deref(),
+            referer,
+            Some(&headers),
+            self.redirect_policy,
+        )
+        .await
+        .context("failed to get segment response")?;
+
+        if range.is_some() && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            anyhow::bail!("server does not support byte ranges of segments");
+        }
+        let mut input_stream = response.bytes_stream_resumable();
+        let mut segment = Vec::new();
+
+        while let Some(item) = input_stream.next().await {
+            let chunk = item.context("failed download")?;
+            self.limit_rate(chunk.len()).await;
+            segment.extend_from_slice(&chunk);
+        }
+
+        match encryption {
+            None => Ok(segment),
+            Some(SegmentEncryption {
+                method: EncryptionMethod::Aes128,
+                key,
+                iv,
+            }) => decrypt_aes128_segment(segment, key, iv),
+            Some(SegmentEncryption {
+                method: EncryptionMethod::SampleAes,
+                key,
+                iv,
+            }) => sample_aes::decrypt_segment(&segment, key, iv).context("failed to decrypt SAMPLE-AES segment"),
+        }
+    }
+
+    /// Without an extension, the output of both m3u8 streams (`.ts` and the
+    /// container) and other videos (`.mp4`) is looked for. A `.ts` file with a
+    /// completed segments file was not finished and does not count.
+    fn find_existing_file(&self, output_path: &Path, has_extension: bool) -> Option<PathBuf> {
+        let candidates = if has_extension {
+            vec![output_path.to_path_buf()]
+        } else {
+            self.get_output_candidates(output_path)
+        };
+
+        candidates.into_iter().find(|candidate| {
+            candidate.try_exists().unwrap_or(false) && !get_segments_file_path(candidate).try_exists().unwrap_or(false)
+        })
+    }
+
+    /// Paths a download without extension may be saved to, depending on
+    /// whether it is an m3u8 stream.
+    fn get_output_candidates(&self, output_path: &Path) -> Vec<PathBuf> {
+        let mut extensions = vec!["ts", "mp4"];
+
+        if !extensions.contains(&self.container.extension()) {
+            extensions.push(self.container.extension());
+        }
+
+        extensions
+            .into_iter()
+            .filter_map(|extension| {
+                let mut file_name = output_path.file_name()?.to_owned();
+                file_name.push(".");
+                file_name.push(extension);
+                Some(output_path.with_file_name(file_name))
+            })
+            .collect()
+    }
+
+    /// Path without extension in the save directory, which does not collide
+    /// with any file the download may produce. If the base name is taken,
+    /// `-1`, `-2`, etc. is appended.
+    pub(crate) fn find_free_output_path(
+        &self,
+        save_directory: &Path,
+        base_name: &str,
+    ) -> Result<PathBuf, anyhow::Error> {
+        for i in 0..=u32::MAX {
+            let output_path = if i == 0 {
+                save_directory.join(base_name)
+            } else {
+                save_directory.join(format!("{}-{}", base_name, i))
+            };
+
+            let mut any_exists = false;
+
+            for candidate in self.get_output_candidates(&output_path) {
+                any_exists |= candidate
+                    .try_exists()
+                    .with_context(|| format!("failed to check if \"{}\" exists", candidate.display()))?;
+            }
+
+            if !any_exists {
+                return Ok(output_path);
+            }
+        }
+
+        anyhow::bail!("all names are taken")
+    }
+
+    /// Failures are only logged, since the video itself was downloaded successfully.
+    async fn download_subtitles(
+        &self,
+        subtitle_tracks: &[SubtitleTrack],
+        referer: Option<&str>,
+        extra_headers: &[(HeaderName, &str)],
+        target_path: &Path,
+    ) -> Vec<PathBuf> {
+        let mut subtitle_paths = Vec::with_capacity(subtitle_tracks.len());
+
+        for subtitle_track in subtitle_tracks {
+            let subtitle_path = if subtitle_tracks.len() == 1 {
+                target_path.with_extension("vtt")
+            } else {
+                target_path.with_extension(format!("{}.vtt", subtitle_track.tag))
+            };
+
+            match self
+                .download_subtitle(subtitle_track, referer, extra_headers, &subtitle_path)
+                .await
+            {
+                Ok(()) => subtitle_paths.push(subtitle_path),
+                Err(err) => log::warn!("Failed to download subtitles \"{}\": {:#}", subtitle_track.tag, err),
+            }
+        }
+
+        subtitle_paths
+    }
+
+    async fn download_subtitle(
+        &self,
+        subtitle_track: &SubtitleTrack,
+        referer: Option<&str>,
+        extra_headers: &[(HeaderName, &str)],
+        subtitle_path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let playlist_response = get_response_with_redirect_policy(
+            self.client.as_ref(),
+            subtitle_track.url.clone(),
+            self.user_agent.as_deref(),
+            referer,
+            Some(extra_headers),
+            self.redirect_policy,
+        )
+        .await
+        .context("failed to get subtitle playlist response")?;
+        let playlist_bytes = get_response_bytes_limited(playlist_response.response(), MAX_PLAYLIST_SIZE)
+            .await
+            .context("failed to get subtitle playlist bytes")?;
+
+        // Some servers directly reference the WebVTT file instead of a playlist
+        if playlist_bytes.starts_with(b"WEBVTT") || playlist_bytes.starts_with(b"\xEF\xBB\xBFWEBVTT") {
+            return tokio::fs::write(subtitle_path, &playlist_bytes)
+                .await
+                .context("failed to write subtitle file");
+        }
+
+        let Ok(playlist) = m3u8_rs::parse_media_playlist_res(&playlist_bytes) else {
+            anyhow::bail!("failed to parse subtitle playlist");
+        };
+        let mut segments = Vec::with_capacity(playlist.segments.len());
+        let mut segment_start = 0.0;
+
+        for segment in playlist.segments {
+            let segment_url = subtitle_track
+                .url
+                .join(&segment.uri)
+                .context("failed to create subtitle segment url")?;
+            let segment_response = get_response_with_redirect_policy(
+                self.client.as_ref(),
+                segment_url,
+                self.user_agent.as_deref(),
+                referer,
+                Some(extra_headers),
+                self.redirect_policy,
+            )
+            .await
+            .context("failed to get subtitle segment response")?;
+            let segment_bytes = get_response_bytes_limited(segment_response.response(), MAX_PLAYLIST_SIZE)
+                .await
+                .context("failed to get subtitle segment bytes")?;
+
+            segments.push(WebVttSegment {
+                content: String::from_utf8_lossy(&segment_bytes).into_owned(),
+                start: segment_start,
+            });
+            segment_start += f64::from(segment.duration);
+        }
+
+        tokio::fs::write(subtitle_path, merge_webvtt(&segments))
+            .await
+            .context("failed to write subtitle file")
+    }
+
+    async fn acquire_host(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        match &self.host_limiter {
+            Some(host_limiter) => host_limiter.acquire(url).await,
+            None => None,
+        }
+    }
+
+    async fn limit_rate(&self, byte_size: usize) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.consume(byte_size).await;
+        }
     }
 
     async fn clean_up_write(mut output_stream: tokio::io::BufWriter<tokio::fs::File>) -> Result<(), anyhow::Error> {
@@ -855,7 +2646,7 @@ impl Downloader {
         let updated_message = if message {
             let total_finished = sub_progresses_lock.iter().filter(|pb| pb.is_finished()).count();
             let total_bars = sub_progresses_lock.len();
-            Some(format!("Total {total_finished}/{total_bars}"))
+            Some((total_finished, total_bars))
         } else {
             None
         };
@@ -864,6 +2655,15 @@ impl Downloader {
 
         let total_progress_lock = self.total_progress.borrow();
         let total_progress = total_progress_lock.as_ref().unwrap();
+        let updated_message = updated_message.map(|(total_finished, total_bars)| match self.total_episodes.get() {
+            Some(total_episodes) => {
+                *self.series_finish.lock().unwrap() =
+                    estimate_series_eta(total_progress.elapsed(), total_finished, total_episodes)
+                        .map(|eta| Instant::now() + eta);
+                format!("Total {total_finished}/{}", total_bars.max(total_episodes))
+            }
+            None => format!("Total {total_finished}/{total_bars}"),
+        });
 
         if let Some((total_downloaded, total_length)) = updated_bytes {
             total_progress.update(|state| {
@@ -879,14 +2679,26 @@ impl Downloader {
         drop(total_progress_lock);
     }
 
-    fn create_total_progress_bar() -> indicatif::ProgressBar {
+    fn create_total_progress_bar(&self) -> indicatif::ProgressBar {
+        let series_finish = self.series_finish.clone();
+        let style = custom_progress_style(
+            indicatif::ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_msg} {binary_bytes_per_sec:>14} {bytes:>10}{total_bytes:<11} [{bar}] {eta:>5} {percent:>3}%",
+            )
+            .unwrap()
+        )
+        .with_key("eta", move |state: &indicatif::ProgressState, w: &mut dyn Write| {
+            // The estimate of the whole series is smoother than the one of the bytes
+            if let Some(series_finish) = *series_finish.lock().unwrap() {
+                let eta = series_finish.saturating_duration_since(Instant::now());
+                let _ = write!(w, "({:#})", indicatif::HumanDuration(eta));
+            } else if state.len() != Some(u64::MAX) {
+                let _ = write!(w, "({:#})", indicatif::HumanDuration(state.eta()));
+            }
+        });
+
         indicatif::ProgressBar::new(u64::MAX)
-            .with_style(custom_progress_style(
-                indicatif::ProgressStyle::with_template(
-                    "[{elapsed_precise}] {wide_msg} {binary_bytes_per_sec:>14} {bytes:>10}{total_bytes:<11} [{bar}] {eta:>5} {percent:>3}%",
-                )
-                .unwrap()
-            ))
+            .with_style(style)
             .with_message("Total 0/1")
     }
 
@@ -918,7 +2730,7 @@ impl Downloader {
         let mut total_progress_lock = self.total_progress.borrow_mut();
 
         if total_progress_lock.is_none() {
-            let new_total_progress = Self::create_total_progress_bar();
+            let new_total_progress = self.create_total_progress_bar();
             *total_progress_lock = Some(self.multi_progress.add(new_total_progress));
         }
 
@@ -941,6 +2753,7 @@ impl Downloader {
 
     fn clean_up_progress_bar(&self, progress_bar: &indicatif::ProgressBar, sub_progresses_index: usize) {
         progress_bar.finish();
+        self.print_progress_json(progress_bar, ProgressStatus::Finished);
 
         let position = progress_bar.position();
         let mut sub_progresses_lock = self.sub_progresses.borrow_mut();
@@ -952,6 +2765,7 @@ impl Downloader {
 
     fn error_cleanup_progress_bar(&self, progress_bar: &indicatif::ProgressBar, sub_progresses_index: usize) {
         progress_bar.abandon();
+        self.print_progress_json(progress_bar, ProgressStatus::Failed);
 
         let position = progress_bar.position();
         let length = progress_bar.length();
@@ -972,7 +2786,28 @@ impl Downloader {
         }
     }
 
+    fn print_progress_json(&self, progress_bar: &indicatif::ProgressBar, status: ProgressStatus) {
+        if !self.progress_json {
+            return;
+        }
+
+        let message = progress_bar.message();
+        let event = ProgressEvent {
+            episode: &message,
+            downloaded: progress_bar.position(),
+            total: progress_bar.length().filter(|&length| length != u64::MAX),
+            speed: progress_bar.per_sec(),
+            status,
+        };
+
+        if let Ok(mut line) = serde_json::to_string(&event) {
+            line.push('\n');
+            let _ = std::io::Write::write_all(&mut std::io::stdout().lock(), line.as_bytes());
+        }
+    }
+
     /// This function never finishes. It should be used in a select! expression.
+    /// With JSON progress, the running downloads are printed on each tick.
     pub(crate) async fn tick(&self) {
         const TICK_INTERVAL: Duration = Duration::from_millis(100);
 
@@ -981,6 +2816,7 @@ impl Downloader {
                 if let ProgressBarOrResult::ProgressBar(pb) = &sub_progress {
                     if !pb.is_finished() {
                         pb.tick();
+                        self.print_progress_json(pb, ProgressStatus::Downloading);
                     }
                 }
             }
@@ -1008,6 +2844,39 @@ impl Drop for Downloader {
     }
 }
 
+/// Progress of a single download, which is printed as a JSON line.
+#[derive(Debug, Serialize)]
+struct ProgressEvent<'a> {
+    episode: &'a str,
+    downloaded: u64,
+    /// Unknown until the size of the download is known or estimated
+    total: Option<u64>,
+    /// Bytes per second
+    speed: f64,
+    status: ProgressStatus,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProgressStatus {
+    Downloading,
+    Finished,
+    Failed,
+}
+
+/// Estimates the remaining time of all downloads from the average time per
+/// finished episode. Concurrent downloads are included, as the elapsed time is
+/// the wall time since the first download started.
+fn estimate_series_eta(elapsed: Duration, finished_episodes: usize, total_episodes: usize) -> Option<Duration> {
+    if finished_episodes == 0 {
+        return None;
+    }
+
+    let remaining_episodes = total_episodes.saturating_sub(finished_episodes);
+    let average = elapsed.div_f64(finished_episodes as f64);
+    Some(average.mul_f64(remaining_episodes as f64))
+}
+
 fn custom_progress_style(progress_style: indicatif::ProgressStyle) -> indicatif::ProgressStyle {
     use indicatif::{HumanDuration, ProgressState};
     use number_prefix::NumberPrefix;
@@ -1076,6 +2945,21 @@ fn custom_progress_style(progress_style: indicatif::ProgressStyle) -> indicatif:
         })
 }
 
+/// Origin of the referer, e.g. `https://example.com` for
+/// `https://example.com/e/abc`, as sent by browsers.
+fn get_referer_origin(referer: &str) -> Option<String> {
+    let origin = Url::parse(referer).ok()?.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+async fn remove_partial_files(partial_paths: &[PathBuf]) {
+    for path in partial_paths {
+        if let Err(err) = remove_file_ignore_not_exists(path).await {
+            log::warn!("Failed to delete partial file \"{}\": {}", path.display(), err);
+        }
+    }
+}
+
 pub(crate) async fn get_response<U: IntoUrl>(
     client: Option<&reqwest_partial_retry::Client>,
     url: U,
@@ -1083,15 +2967,87 @@ pub(crate) async fn get_response<U: IntoUrl>(
     referer: Option<&str>,
     extra_headers: Option<&[(HeaderName, &str)]>,
 ) -> Result<reqwest_partial_retry::ResumableResponse, anyhow::Error> {
+    get_response_with_redirect_policy(
+        client,
+        url,
+        user_agent,
+        referer,
+        extra_headers,
+        RedirectPolicy::default(),
+    )
+    .await
+}
+
+pub(crate) async fn get_response_with_redirect_policy<U: IntoUrl>(
+    client: Option<&reqwest_partial_retry::Client>,
+    url: U,
+    user_agent: Option<&str>,
+    referer: Option<&str>,
+    extra_headers: Option<&[(HeaderName, &str)]>,
+    redirect_policy: RedirectPolicy,
+) -> Result<reqwest_partial_retry::ResumableResponse, anyhow::Error> {
+    let followed = follow_redirects(client, url, user_agent, referer, extra_headers, redirect_policy).await?;
+
+    match followed {
+        FollowedRedirects::Response(response) => Ok(response),
+        FollowedRedirects::MediaUrl { url, redirect_count } => {
+            // The media url is requested on its own, where its redirects, e.g. to a
+            // signed CDN url, are followed again
+            let media_redirect_policy = RedirectPolicy {
+                max_redirects: redirect_policy.max_redirects - redirect_count,
+                stop_at_media: false,
+            };
+
+            match follow_redirects(client, url, user_agent, referer, extra_headers, media_redirect_policy).await? {
+                FollowedRedirects::Response(response) => Ok(response),
+                FollowedRedirects::MediaUrl { .. } => unreachable!("redirects are not stopped at media"),
+            }
+        }
+    }
+}
+
+/// End of the redirects followed by [follow_redirects].
+pub(crate) enum FollowedRedirects {
+    /// Response of the last request, which is not a redirect
+    Response(reqwest_partial_retry::ResumableResponse),
+    /// Url of a media file, which a redirect leads to, but which was not requested,
+    /// since the redirect policy stops there
+    MediaUrl { url: Url, redirect_count: u32 },
+}
+
+/// Follows the redirects of the url, but returns the url of a media file a redirect
+/// leads to without requesting it, if the redirect policy stops at media.
+pub(crate) async fn follow_redirects<U: IntoUrl>(
+    client: Option<&reqwest_partial_retry::Client>,
+    url: U,
+    user_agent: Option<&str>,
+    referer: Option<&str>,
+    extra_headers: Option<&[(HeaderName, &str)]>,
+    redirect_policy: RedirectPolicy,
+) -> Result<FollowedRedirects, anyhow::Error> {
     // We need to handle redirects ourself, because reqwest changes the Referer
     // header on redirection
     let client = client.unwrap_or(DEFAULT_RETRY_CLIENT_NO_REDIRECT.deref());
     let mut last_url = url.as_str().to_string();
     let mut redirect_count = 0u32;
 
+    // A cookie header given explicitly replaces the ones of the cookies file
+    let has_cookie_header =
+        extra_headers.is_some_and(|headers| headers.iter().any(|(header, _)| *header == reqwest::header::COOKIE));
+
     loop {
+        // Matched against each url, so that redirects to other hosts do not receive them
+        let cookie = if has_cookie_header {
+            None
+        } else {
+            Url::parse(&last_url).ok().and_then(|url| cookies::header_value(&url))
+        };
         let mut request = client.get(last_url);
 
+        if let Some(cookie) = cookie {
+            request = request.header(reqwest::header::COOKIE, cookie);
+        }
+
         if let Some(user_agent) = user_agent {
             request = request.header(reqwest::header::USER_AGENT, user_agent);
         }
@@ -1118,39 +3074,148 @@ pub(crate) async fn get_response<U: IntoUrl>(
 
         match (is_redirect_code, location_header) {
             (true, Some(redirect_url)) => {
-                if redirect_count >= 10 {
-                    anyhow::bail!("more than 10 redirects");
+                if redirect_count >= redirect_policy.max_redirects {
+                    anyhow::bail!("more than {} redirects", redirect_policy.max_redirects);
                 }
 
                 redirect_count += 1;
-                last_url = redirect_url
-                    .to_str()
-                    .context("redirect url could not be converted to string")?
-                    .to_string();
+                let redirect_url = response
+                    .url()
+                    .join(
+                        redirect_url
+                            .to_str()
+                            .context("redirect url could not be converted to string")?,
+                    )
+                    .context("failed to parse redirect url")?;
+
+                if redirect_policy.stop_at_media && is_media_url(&redirect_url) {
+                    return Ok(FollowedRedirects::MediaUrl {
+                        url: redirect_url,
+                        redirect_count,
+                    });
+                }
+
+                last_url = redirect_url.into();
             }
-            _ => return Ok(response),
+            _ => return Ok(FollowedRedirects::Response(response)),
+        }
+    }
+}
+
+/// Appends the extension of the stream type to the path of a download.
+fn append_stream_extension(path: PathBuf, is_m3u8: bool) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => {
+            let mut file_name = file_name.to_owned();
+            file_name.push(if is_m3u8 { ".ts" } else { ".mp4" });
+            parent.join(file_name)
+        }
+        _ => path,
+    }
+}
+
+/// Total length of the file from e.g. `Content-Range: bytes */1234`.
+fn get_content_range_total(response: &reqwest_partial_retry::ResumableResponse) -> Option<u64> {
+    let content_range = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    parse_content_range_total(content_range)
+}
+
+fn parse_content_range_total(content_range: &str) -> Option<u64> {
+    let (unit, range) = content_range.trim().split_once(' ')?;
+
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    range.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// Reads the body into memory, but fails as soon as it is larger than the
+/// limit, e.g. if a malicious server sends an endless playlist.
+pub(crate) async fn get_response_bytes_limited(
+    mut response: reqwest::Response,
+    limit: usize,
+) -> Result<bytes::Bytes, anyhow::Error> {
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        anyhow::bail!("response body is larger than {} bytes", limit);
+    }
+
+    let mut body = bytes::BytesMut::new();
+
+    while let Some(chunk) = response.chunk().await.context("failed to get bytes of response body")? {
+        if body.len() + chunk.len() > limit {
+            anyhow::bail!("response body is larger than {} bytes", limit);
         }
+
+        body.extend_from_slice(&chunk);
     }
+
+    Ok(body.freeze())
 }
 
-pub(crate) async fn get_response_bytes(response: reqwest::Response) -> Result<bytes::Bytes, anyhow::Error> {
-    response.bytes().await.context("failed to get bytes of response body")
+/// Makes the uris of the segments, keys and init sections absolute, so that
+/// the playlist can be used without knowing its url.
+fn make_playlist_uris_absolute(playlist: &mut m3u8_rs::MediaPlaylist, playlist_url: &Url) -> Result<(), anyhow::Error> {
+    let make_absolute = |uri: &mut String| -> Result<(), anyhow::Error> {
+        *uri = playlist_url
+            .join(uri.as_str())
+            .with_context(|| format!("failed to create absolute url of {}", uri))?
+            .to_string();
+        Ok(())
+    };
+
+    for segment in &mut playlist.segments {
+        make_absolute(&mut segment.uri)?;
+
+        if let Some(key_uri) = segment.key.as_mut().and_then(|key| key.uri.as_mut()) {
+            make_absolute(key_uri)?;
+        }
+
+        if let Some(map) = &mut segment.map {
+            make_absolute(&mut map.uri)?;
+        }
+    }
+
+    Ok(())
 }
 
+/// Only meant for playlists, so bodies larger than [`MAX_PLAYLIST_SIZE`] fail.
 pub(crate) async fn get_page_bytes<U: IntoUrl>(
     url: U,
     user_agent: Option<&str>,
     referer: Option<&str>,
     extra_headers: Option<&[(HeaderName, &str)]>,
 ) -> Result<bytes::Bytes, anyhow::Error> {
-    get_response_bytes(
+    get_response_bytes_limited(
         get_response(None, url, user_agent, referer, extra_headers)
             .await?
             .response(),
+        MAX_PLAYLIST_SIZE,
     )
     .await
 }
 
+/// Bandwidth of the variant of an m3u8 master playlist, which is selected by
+/// the quality. `None` if the url is no master playlist.
+pub(crate) async fn get_variant_bandwidth(
+    url: &str,
+    referer: Option<&str>,
+    quality: Quality,
+) -> Result<Option<u64>, anyhow::Error> {
+    if !Url::parse(url).is_ok_and(|url| is_m3u8_url(&url)) {
+        return Ok(None);
+    }
+
+    let playlist_bytes = get_page_bytes(url, None, referer, None).await?;
+
+    match m3u8_rs::parse_playlist_res(&playlist_bytes) {
+        Ok(m3u8_rs::Playlist::MasterPlaylist(playlist)) => {
+            Ok(select_variant(&playlist.variants, quality).map(|variant| variant.bandwidth))
+        }
+        _ => Ok(None),
+    }
+}
+
 pub(crate) async fn get_page_text<U: IntoUrl>(
     url: U,
     user_agent: Option<&str>,
@@ -1165,6 +3230,24 @@ pub(crate) async fn get_page_text<U: IntoUrl>(
         .context("failed to parse response body as text")
 }
 
+/// Like [`get_page_text`], but also returns the url of the page after redirects.
+pub(crate) async fn get_page_text_and_url<U: IntoUrl>(
+    url: U,
+    user_agent: Option<&str>,
+    referer: Option<&str>,
+    extra_headers: Option<&[(HeaderName, &str)]>,
+) -> Result<(String, Url), anyhow::Error> {
+    let response = get_response(None, url, user_agent, referer, extra_headers).await?;
+    let page_url = response.url().clone();
+    let text = response
+        .response()
+        .text()
+        .await
+        .context("failed to parse response body as text")?;
+
+    Ok((text, page_url))
+}
+
 pub(crate) async fn get_page_json<U: IntoUrl>(
     url: U,
     user_agent: Option<&str>,
@@ -1205,7 +3288,10 @@ mod retry {
         /// Note that success here means that the request finished without
         /// interruption, not that it was logically OK.
         pub fn default_on_request_success(success: &reqwest::Response) -> Option<Retryable> {
-            let status = success.status();
+            classify_status(success.status())
+        }
+
+        pub(crate) fn classify_status(status: StatusCode) -> Option<Retryable> {
             if status.is_server_error() {
                 Some(Retryable::Transient)
             } else if status.is_client_error()
@@ -1303,6 +3389,407 @@ mod retry {
             None
         }
     }
+
+    pub(crate) mod after {
+        use std::cell::Cell;
+        use std::time::{Duration, SystemTime};
+
+        use reqwest::header::{HeaderMap, RETRY_AFTER};
+        use reqwest::StatusCode;
+        use reqwest_retry::{RetryDecision, RetryPolicy, Retryable, RetryableStrategy};
+
+        /// Servers asking to wait longer are not retried, instead of blocking the
+        /// download for hours.
+        pub(crate) const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+        thread_local! {
+            // The strategy and the policy are called one after another for each
+            // response, without an await in between, so the wait requested by the
+            // response cannot be mixed up with the one of another request
+            static REQUESTED_WAIT: Cell<Option<Duration>> = Cell::new(None);
+        }
+
+        /// Classifies responses as the inner strategy does, and remembers the
+        /// `Retry-After` of 429 and 503 responses for [`RetryAfterPolicy`].
+        pub(crate) struct RetryAfterStrategy<S>(pub(crate) S);
+
+        impl<S: RetryableStrategy> RetryableStrategy for RetryAfterStrategy<S> {
+            fn handle(&self, res: &Result<reqwest::Response, reqwest_middleware::Error>) -> Option<Retryable> {
+                match res {
+                    Ok(response) => remember_retry_after(response.status(), response.headers()),
+                    Err(_) => REQUESTED_WAIT.with(|wait| wait.set(None)),
+                }
+
+                self.0.handle(res)
+            }
+        }
+
+        /// Retries as the inner policy does, but not before the wait requested
+        /// by the `Retry-After` of the last response.
+        pub(crate) struct RetryAfterPolicy<P>(pub(crate) P);
+
+        impl<P: RetryPolicy> RetryPolicy for RetryAfterPolicy<P> {
+            fn should_retry(&self, n_past_retries: u32) -> RetryDecision {
+                let decision = self.0.should_retry(n_past_retries);
+                let requested_wait = REQUESTED_WAIT.with(Cell::take);
+
+                match (decision, requested_wait) {
+                    (RetryDecision::Retry { .. }, Some(wait)) if wait > MAX_RETRY_AFTER => RetryDecision::DoNotRetry,
+                    (RetryDecision::Retry { execute_after }, Some(wait)) => {
+                        let requested_after = chrono::Utc::now()
+                            + chrono::Duration::from_std(wait).unwrap_or_else(|_| chrono::Duration::zero());
+                        RetryDecision::Retry {
+                            execute_after: execute_after.max(requested_after),
+                        }
+                    }
+                    (decision, _) => decision,
+                }
+            }
+        }
+
+        /// Remembers the wait requested by the response for the next call of
+        /// [`RetryAfterPolicy`] on this thread.
+        pub(crate) fn remember_retry_after(status: StatusCode, headers: &HeaderMap) {
+            let requested_wait = if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+            {
+                headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_retry_after(value, SystemTime::now()))
+            } else {
+                None
+            };
+
+            REQUESTED_WAIT.with(|wait| wait.set(requested_wait));
+        }
+
+        /// Parses the delay in seconds or the HTTP-date of a `Retry-After`
+        /// header into the time to wait from now.
+        pub(crate) fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+            let value = value.trim();
+
+            if let Ok(seconds) = value.parse::<u64>() {
+                return Some(Duration::from_secs(seconds));
+            }
+
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            let date = SystemTime::from(date.with_timezone(&chrono::Utc));
+
+            // A date in the past means that the request may be retried right away
+            Some(date.duration_since(now).unwrap_or_default())
+        }
+    }
+
+    pub(crate) mod budget {
+        use std::cell::Cell;
+        use std::future::Future;
+        use std::time::Duration;
+
+        use reqwest_retry::{RetryDecision, RetryPolicy};
+
+        use crate::download::RetryBudget;
+
+        tokio::task_local! {
+            static CURRENT_BUDGET: SpentRetryBudget;
+        }
+
+        struct SpentRetryBudget {
+            budget: RetryBudget,
+            retries: Cell<u32>,
+            waited: Cell<Duration>,
+            exhausted: Cell<bool>,
+        }
+
+        impl SpentRetryBudget {
+            /// Records a retry after the wait, and returns whether it is still
+            /// within the budget.
+            fn try_spend(&self, wait: Duration) -> bool {
+                let retries = self.retries.get().saturating_add(1);
+                let waited = self.waited.get().saturating_add(wait);
+                self.retries.set(retries);
+                self.waited.set(waited);
+
+                let within_budget = self.budget.max_retries.map_or(true, |max| retries <= max)
+                    && self.budget.max_wait.map_or(true, |max| waited <= max);
+
+                if !within_budget {
+                    self.exhausted.set(true);
+                }
+
+                within_budget
+            }
+        }
+
+        /// Requests made by the future share the budget, if their client uses
+        /// [`BudgetedRetryPolicy`].
+        pub(crate) async fn scope<F: Future>(budget: RetryBudget, future: F) -> F::Output {
+            let spent_budget = SpentRetryBudget {
+                budget,
+                retries: Cell::new(0),
+                waited: Cell::new(Duration::ZERO),
+                exhausted: Cell::new(false),
+            };
+
+            CURRENT_BUDGET.scope(spent_budget, future).await
+        }
+
+        /// Whether a retry was refused, because the budget of the current scope
+        /// is exhausted.
+        pub(crate) fn is_exhausted() -> bool {
+            CURRENT_BUDGET
+                .try_with(|budget| budget.exhausted.get())
+                .unwrap_or(false)
+        }
+
+        /// Retries as the inner policy does, as long as the budget of the
+        /// current scope is not exhausted. Outside of a scope, the retries are
+        /// not limited.
+        pub(crate) struct BudgetedRetryPolicy<P>(pub(crate) P);
+
+        impl<P: RetryPolicy> RetryPolicy for BudgetedRetryPolicy<P> {
+            fn should_retry(&self, n_past_retries: u32) -> RetryDecision {
+                let decision = self.0.should_retry(n_past_retries);
+
+                if let RetryDecision::Retry { execute_after } = &decision {
+                    let wait = (*execute_after - chrono::Utc::now()).to_std().unwrap_or_default();
+                    let within_budget = CURRENT_BUDGET.try_with(|budget| budget.try_spend(wait)).unwrap_or(true);
+
+                    if !within_budget {
+                        return RetryDecision::DoNotRetry;
+                    }
+                }
+
+                decision
+            }
+        }
+    }
+}
+
+/// Whether the downloaded length matches the Content-Length, allowing for
+/// slightly inaccurate values sent by some servers.
+fn is_length_within_tolerance(downloaded: u64, content_length: u64) -> bool {
+    const RELATIVE_TOLERANCE: f64 = 0.001;
+
+    downloaded.abs_diff(content_length) as f64 <= content_length as f64 * RELATIVE_TOLERANCE
+}
+
+fn get_segments_file_path(target_path: &Path) -> PathBuf {
+    let mut segments_file_path = target_path.as_os_str().to_owned();
+    segments_file_path.push(".segments");
+    PathBuf::from(segments_file_path)
+}
+
+/// Progress of an interrupted m3u8 download.
+///
+/// Each record is a line with the media sequence number of a completed segment
+/// and the length of the output file after writing it.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CompletedSegments {
+    sequence_numbers: HashSet<u128>,
+    output_len: u64,
+    record_len: usize,
+}
+
+impl CompletedSegments {
+    fn parse(contents: &str) -> Self {
+        let mut completed_segments = CompletedSegments::default();
+
+        // An incomplete last line means the download was interrupted while writing it
+        for line in contents.split_inclusive('\n') {
+            let Some((sequence_number, output_len)) = line.strip_suffix('\n').and_then(|line| line.split_once(' '))
+            else {
+                break;
+            };
+            let (Ok(sequence_number), Ok(output_len)) = (sequence_number.parse(), output_len.parse()) else {
+                break;
+            };
+
+            completed_segments.sequence_numbers.insert(sequence_number);
+            completed_segments.output_len = output_len;
+            completed_segments.record_len += line.len();
+        }
+
+        completed_segments
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EncryptionMethod {
+    Aes128,
+    SampleAes,
+}
+
+/// Encryption of a single m3u8 segment, whose IV is already resolved.
+struct SegmentEncryption {
+    method: EncryptionMethod,
+    key: [u8; 16],
+    iv: [u8; 16],
+}
+
+struct SegmentJob {
+    sequence_number: u128,
+    url: Url,
+    byte_range: Option<Range<u64>>,
+    duration: f64,
+    encryption: Option<SegmentEncryption>,
+    /// Init section to write in front of the segment
+    init_section: Option<InitSectionJob>,
+}
+
+struct InitSectionJob {
+    url: Url,
+    byte_range: Option<Range<u64>>,
+    encryption: Option<SegmentEncryption>,
+}
+
+/// Decryption key of m3u8 segments (`#EXT-X-KEY`), which still has to be fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SegmentKey {
+    method: EncryptionMethod,
+    uri: String,
+    /// Without an IV, the media sequence number of each segment is used
+    iv: Option<[u8; 16]>,
+}
+
+impl SegmentKey {
+    /// IV of the segment with the media sequence number.
+    fn get_iv(&self, sequence_number: u128) -> [u8; 16] {
+        self.iv.unwrap_or_else(|| sequence_number.to_be_bytes())
+    }
+}
+
+/// Resolves the `#EXT-X-KEY` applying to each segment. A key applies to all
+/// following segments until the next key, also across discontinuities.
+///
+/// Every segment is decrypted on its own with a new decryptor, so no state
+/// is carried over to the segments after a discontinuity.
+fn get_segment_keys(segments: &[m3u8_rs::MediaSegment]) -> Result<Vec<Option<SegmentKey>>, anyhow::Error> {
+    let mut current_key = None;
+    let mut segment_keys = Vec::with_capacity(segments.len());
+
+    for segment in segments {
+        if let Some(key) = &segment.key {
+            let method = match &key.method {
+                KeyMethod::None => None,
+                KeyMethod::AES128 => Some(EncryptionMethod::Aes128),
+                KeyMethod::SampleAES => Some(EncryptionMethod::SampleAes),
+                KeyMethod::Other(other) => anyhow::bail!("m3u8 \"{other}\" decryption not implemented"),
+            };
+
+            current_key = match method {
+                Some(method) => {
+                    let iv = match &key.iv {
+                        Some(iv) => {
+                            let iv_hex = iv
+                                .strip_prefix("0x")
+                                .or_else(|| iv.strip_prefix("0X"))
+                                .with_context(|| format!("decryption iv not in hexadecimal format: {}", iv))?;
+                            let iv =
+                                u128::from_str_radix(iv_hex, 16).context("failed to parse decryption iv to integer")?;
+                            Some(iv.to_be_bytes())
+                        }
+                        None => None,
+                    };
+                    let uri = key.uri.clone().context("no uri for decryption key provided")?;
+
+                    Some(SegmentKey { method, uri, iv })
+                }
+                None => None,
+            };
+        }
+
+        segment_keys.push(current_key.clone());
+    }
+
+    Ok(segment_keys)
+}
+
+/// Media initialization section of fMP4 segments (`#EXT-X-MAP`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InitSection {
+    uri: String,
+    byte_range: Option<Range<u64>>,
+}
+
+/// Resolves the `#EXT-X-MAP` applying to each segment. A map applies to
+/// all following segments until the next map.
+fn get_segment_init_sections(segments: &[m3u8_rs::MediaSegment]) -> Vec<Option<InitSection>> {
+    let mut current_init_section = None;
+
+    segments
+        .iter()
+        .map(|segment| {
+            if let Some(map) = &segment.map {
+                let byte_range = map.byte_range.as_ref().map(|byte_range| {
+                    let start = byte_range.offset.unwrap_or(0);
+                    start..start + byte_range.length
+                });
+                current_init_section = Some(InitSection {
+                    uri: map.uri.clone(),
+                    byte_range,
+                });
+            }
+
+            current_init_section.clone()
+        })
+        .collect()
+}
+
+/// Resolves the `#EXT-X-BYTERANGE` of each segment. Without an offset, a
+/// range starts where the previous range of the same file ended.
+fn get_segment_byte_ranges(segments: &[m3u8_rs::MediaSegment]) -> Vec<Option<Range<u64>>> {
+    let mut previous_end: Option<(&str, u64)> = None;
+
+    segments
+        .iter()
+        .map(|segment| {
+            let byte_range = segment.byte_range.as_ref()?;
+            let start = match (byte_range.offset, previous_end) {
+                (Some(offset), _) => offset,
+                (None, Some((uri, end))) if uri == segment.uri => end,
+                (None, _) => 0,
+            };
+            let end = start + byte_range.length;
+            previous_end = Some((segment.uri.as_str(), end));
+
+            Some(start..end)
+        })
+        .collect()
+}
+
+fn decrypt_aes128_segment(mut segment: Vec<u8>, key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, anyhow::Error> {
+    if segment.is_empty() {
+        return Ok(segment);
+    }
+
+    let mut decryptor = cbc::Decryptor::<aes::Aes128>::new(key.as_ref().into(), iv.as_ref().into());
+    let data: InOutBuf<u8> = segment.as_mut_slice().into();
+    let (mut blocks, tail) = data.into_chunks();
+
+    if !tail.is_empty() {
+        anyhow::bail!("decryption blocks have tail");
+    }
+
+    decryptor.decrypt_blocks_inout_mut(blocks.reborrow());
+    let unpadded_len = match aes::cipher::block_padding::Pkcs7::unpad_blocks(blocks.into_out()) {
+        Ok(unpadded) => unpadded.len(),
+        Err(UnpadError) => anyhow::bail!("failed to unpad data"),
+    };
+    segment.truncate(unpadded_len);
+
+    Ok(segment)
+}
+
+fn is_media_url(url: &Url) -> bool {
+    is_m3u8_url(url)
+        || url
+            .path_segments()
+            .and_then(|segments| segments.last())
+            .map(|last| {
+                let lower = last.to_ascii_lowercase();
+                lower.ends_with(".mp4") && lower.len() != ".mp4".len()
+            })
+            .unwrap_or(false)
 }
 
 fn is_m3u8_url(url: &Url) -> bool {
@@ -1316,7 +3803,7 @@ fn is_m3u8_url(url: &Url) -> bool {
         .unwrap_or(false)
 }
 
-fn prepare_series_name_for_file(name: &str) -> Option<String> {
+pub(crate) fn prepare_series_name_for_file(name: &str) -> Option<String> {
     use regex::Regex;
 
     const NAME_LIMIT: usize = 160;
@@ -1379,49 +3866,70 @@ fn prepare_series_name_for_file(name: &str) -> Option<String> {
     }
 }
 
+fn get_season_folder_name(season_number: u32) -> String {
+    if season_number == 0 {
+        "Movies".to_string()
+    } else {
+        format!("Season {season_number:02}")
+    }
+}
+
 pub(crate) fn get_episode_name(
+    output_template: &OutputTemplate,
     anime_name: Option<&str>,
     language: Option<&VideoType>,
     episode_info: &EpisodeInfo,
-    include_title_if_possible: bool,
 ) -> String {
-    let mut output_name = String::new();
+    let title = episode_info.name.as_deref().and_then(prepare_series_name_for_file);
+
+    output_template.render(&TemplateValues {
+        series: anime_name,
+        language,
+        episode_info: Some(episode_info),
+        title: title.as_deref(),
+    })
+}
 
-    if let Some(anime_name) = anime_name {
-        output_name.push_str(anime_name);
-        output_name.push_str(" - ");
+/// Path of the episode without extension, in its season folder if enabled.
+pub(crate) fn get_episode_output_path(
+    save_directory: &Path,
+    output_template: &OutputTemplate,
+    season_folders: bool,
+    anime_name: Option<&str>,
+    download_task: &DownloadTask,
+) -> PathBuf {
+    let output_name = get_episode_name(
+        output_template,
+        anime_name,
+        Some(&download_task.language),
+        &download_task.episode_info,
+    );
+
+    match download_task.episode_info.season_number {
+        Some(season_number) if season_folders => {
+            let folder_name = get_season_folder_name(season_number);
+            let folder_name = prepare_series_name_for_file(&folder_name).unwrap_or(folder_name);
+            save_directory.join(folder_name).join(output_name)
+        }
+        _ => save_directory.join(output_name),
     }
+}
 
-    if let Some(season) = episode_info.season_number {
-        output_name.push_str(&format!("S{:02}", season));
+/// Extension of the file a video url is downloaded into. Whether it is an
+/// m3u8 stream is only guessed from the url, which may still redirect.
+pub(crate) fn get_expected_extension(
+    url: &str,
+    container: Container,
+    audio_extraction: Option<&AudioExtraction>,
+) -> &'static str {
+    match audio_extraction {
+        Some(audio_extraction) if !audio_extraction.keep_video => audio_extraction.format.extension(),
+        _ if Url::parse(url).is_ok_and(|url| is_m3u8_url(&url)) => container.extension(),
+        _ => "mp4",
     }
+}
 
-    let alignment_episode_number = episode_info
-        .max_episode_number_in_season
-        .map(|max_num| (max_num.checked_ilog10().unwrap_or(0) + 1) as usize);
-
-    output_name.push('E');
-    output_name.push_str(&format_episode_number(
-        &episode_info.episode_number,
-        alignment_episode_number,
-    ));
-
-    if let Some(language) = language {
-        if language != &VideoType::Unspecified(Language::Unspecified) {
-            output_name.push_str(&format!(" - {}", language));
-        }
-    }
-
-    if include_title_if_possible {
-        if let Some(title) = &episode_info.name {
-            output_name.push_str(&format!(" - {}", title));
-        }
-    }
-
-    output_name
-}
-
-fn format_episode_number(episode_number: &EpisodeNumber, alignment_episode_number: Option<usize>) -> String {
+pub(crate) fn format_episode_number(episode_number: &EpisodeNumber, alignment_episode_number: Option<usize>) -> String {
     match episode_number {
         EpisodeNumber::Number(episode_number) => {
             format!("{episode_number:0>fill$}", fill = alignment_episode_number.unwrap_or(2))
@@ -1449,8 +3957,737 @@ fn format_episode_number(episode_number: &EpisodeNumber, alignment_episode_numbe
 
 #[cfg(test)]
 mod tests {
-    use crate::download::format_episode_number;
-    use crate::downloaders::EpisodeNumber;
+    use std::collections::HashSet;
+    use std::future::Future;
+    use std::num::{NonZeroU32, NonZeroUsize};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use aes::cipher::block_padding::Pkcs7;
+    use aes::cipher::{BlockEncryptMut as _, KeyIvInit as _};
+    use reqwest_retry::{RetryDecision, RetryPolicy, Retryable};
+
+    use crate::download::retry::after::{parse_retry_after, remember_retry_after, RetryAfterPolicy, MAX_RETRY_AFTER};
+    use crate::download::retry::budget::{self, BudgetedRetryPolicy};
+    use crate::download::retry::strategy::classify_status;
+    use crate::download::{
+        decrypt_aes128_segment, estimate_series_eta, follow_redirects, format_episode_number, get_episode_output_path,
+        get_expected_extension, get_page_text, get_referer_origin, get_response_bytes_limited,
+        get_response_with_redirect_policy, get_segment_byte_ranges, get_segment_init_sections, get_segment_keys,
+        is_insufficient_space, is_length_within_tolerance, is_media_url, is_placeholder_video,
+        make_playlist_uris_absolute, parse_content_range_total, select_variant, sort_by_ip_family, AudioExtraction,
+        AudioFormat, CompletedSegments, Container, DownloadManager, Downloader, EncryptionMethod, EpisodeStatus,
+        FollowedRedirects, HostLimiter, InitSection, InternalDownloadTask, IpFamily, NetworkConfig, ProgressEvent,
+        ProgressStatus, Quality, RedirectPolicy, RetryBudget, SegmentKey,
+    };
+    use crate::downloaders::{
+        DownloadTask, EpisodeInfo, EpisodeNumber, Language, ScrapeOutcome, ScrapeOutcomes, SeriesInfo, VideoType,
+    };
+    use crate::extractors::ExtractedVideo;
+    use crate::output_template::OutputTemplate;
+
+    /// Answers each request with the response of the handler for the request
+    /// head and returns the base url of the server.
+    async fn spawn_http_server<F, Fut>(handler: F) -> String
+    where
+        F: Fn(String) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Vec<u8>> + Send,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+
+                tokio::spawn(async move {
+                    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+                    let mut request = vec![];
+                    let mut buffer = [0; 1024];
+
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+
+                    let response = handler(String::from_utf8_lossy(&request).into_owned()).await;
+                    let _ = stream.write_all(&response).await;
+                });
+            }
+        });
+
+        base_url
+    }
+
+    fn http_response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: {}\r\n{headers}\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn test_is_length_within_tolerance() {
+        assert!(is_length_within_tolerance(0, 0));
+        assert!(is_length_within_tolerance(100_000, 100_000));
+        assert!(is_length_within_tolerance(99_950, 100_000));
+        assert!(!is_length_within_tolerance(50_000, 100_000));
+        assert!(!is_length_within_tolerance(100_200, 100_000));
+    }
+
+    #[test]
+    fn test_segment_byte_ranges() {
+        let playlist = b"#EXTM3U
+#EXT-X-VERSION:4
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.0,
+#EXT-X-BYTERANGE:1000@0
+video.ts
+#EXTINF:10.0,
+#EXT-X-BYTERANGE:2000
+video.ts
+#EXTINF:10.0,
+#EXT-X-BYTERANGE:500@100
+other.ts
+#EXTINF:10.0,
+full.ts
+#EXT-X-ENDLIST
+";
+        let media_playlist = m3u8_rs::parse_media_playlist_res(playlist).unwrap();
+        let byte_ranges = get_segment_byte_ranges(&media_playlist.segments);
+
+        assert_eq!(byte_ranges, [Some(0..1000), Some(1000..3000), Some(100..600), None]);
+    }
+
+    #[test]
+    fn test_segment_init_sections() {
+        let playlist = b"#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.0,
+plain.ts
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:10.0,
+seg1.m4s
+#EXTINF:10.0,
+seg2.m4s
+#EXT-X-MAP:URI=\"init2.mp4\",BYTERANGE=\"720@100\"
+#EXTINF:10.0,
+seg3.m4s
+#EXT-X-ENDLIST
+";
+        let media_playlist = m3u8_rs::parse_media_playlist_res(playlist).unwrap();
+        let init_sections = get_segment_init_sections(&media_playlist.segments);
+        let init = InitSection {
+            uri: "init.mp4".to_string(),
+            byte_range: None,
+        };
+        let init2 = InitSection {
+            uri: "init2.mp4".to_string(),
+            byte_range: Some(100..820),
+        };
+
+        assert_eq!(init_sections, [None, Some(init.clone()), Some(init), Some(init2)]);
+    }
+
+    #[test]
+    fn test_find_free_output_path() {
+        let save_directory = std::env::temp_dir().join(format!("sdl-test-free-output-{}", std::process::id()));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        let downloader = Downloader::new(
+            indicatif::MultiProgress::new(),
+            false,
+            None,
+            None,
+            None,
+            None,
+            &NetworkConfig::default(),
+        )
+        .container(Container::Mkv);
+
+        let free_path = downloader.find_free_output_path(&save_directory, "video").unwrap();
+        assert_eq!(free_path, save_directory.join("video"));
+
+        std::fs::write(save_directory.join("video.mkv"), b"").unwrap();
+        std::fs::write(save_directory.join("video-1.ts"), b"").unwrap();
+        std::fs::write(save_directory.join("video-2.webm"), b"").unwrap();
+        let free_path = downloader.find_free_output_path(&save_directory, "video").unwrap();
+        assert_eq!(free_path, save_directory.join("video-2"));
+
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let output_path = std::env::temp_dir().join("video.mp4");
+        let downloader = |min_free_space| {
+            Downloader::new(
+                indicatif::MultiProgress::new(),
+                false,
+                None,
+                None,
+                None,
+                None,
+                &NetworkConfig::default(),
+            )
+            .min_free_space(min_free_space)
+        };
+
+        assert!(downloader(None).check_free_space(&output_path).is_ok());
+        assert!(downloader(Some(1)).check_free_space(&output_path).is_ok());
+
+        let err = downloader(Some(u64::MAX))
+            .check_free_space(&output_path)
+            .unwrap_err()
+            .context("failed to download");
+        assert!(is_insufficient_space(&err));
+        assert!(!is_insufficient_space(&anyhow::anyhow!("failed to download")));
+    }
+
+    #[test]
+    fn test_range_not_satisfiable() {
+        assert!(matches!(
+            classify_status(reqwest::StatusCode::RANGE_NOT_SATISFIABLE),
+            Some(Retryable::Fatal)
+        ));
+        assert!(matches!(
+            classify_status(reqwest::StatusCode::NOT_FOUND),
+            Some(Retryable::Fatal)
+        ));
+        assert!(matches!(
+            classify_status(reqwest::StatusCode::SERVICE_UNAVAILABLE),
+            Some(Retryable::Transient)
+        ));
+        assert!(classify_status(reqwest::StatusCode::PARTIAL_CONTENT).is_none());
+
+        assert_eq!(parse_content_range_total("bytes */1234"), Some(1234));
+        assert_eq!(parse_content_range_total("bytes 0-99/1234"), Some(1234));
+        assert_eq!(parse_content_range_total("bytes */*"), None);
+        assert_eq!(parse_content_range_total("items */1234"), None);
+    }
+
+    #[test]
+    fn test_episode_output_path() {
+        let episode_info = EpisodeInfo {
+            name: None,
+            season_number: Some(2),
+            episode_number: EpisodeNumber::Number(3),
+            max_episode_number_in_season: Some(12),
+        };
+        let task = DownloadTask::new(
+            episode_info,
+            VideoType::Sub(Language::German),
+            ExtractedVideo {
+                url: "https://example.com/hls/master.m3u8".to_string(),
+                referer: None,
+            },
+        );
+        let save_directory = Path::new("/videos");
+        let template = OutputTemplate::default();
+
+        assert_eq!(
+            get_episode_output_path(save_directory, &template, false, Some("Series"), &task),
+            Path::new("/videos/Series - S02E03 - GerSub")
+        );
+        assert_eq!(
+            get_episode_output_path(save_directory, &template, true, Some("Series"), &task),
+            Path::new("/videos/Season 02/Series - S02E03 - GerSub")
+        );
+
+        let audio_only = AudioExtraction {
+            format: AudioFormat::Opus,
+            keep_video: false,
+        };
+        assert_eq!(get_expected_extension(&task.download_url, Container::Mkv, None), "mkv");
+        assert_eq!(
+            get_expected_extension("https://example.com/video.mp4", Container::Mkv, None),
+            "mp4"
+        );
+        assert_eq!(
+            get_expected_extension(&task.download_url, Container::Mkv, Some(&audio_only)),
+            "opus"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        struct RetryAfterSecond;
+
+        impl RetryPolicy for RetryAfterSecond {
+            fn should_retry(&self, _n_past_retries: u32) -> RetryDecision {
+                RetryDecision::Retry {
+                    execute_after: chrono::Utc::now() + chrono::Duration::seconds(1),
+                }
+            }
+        }
+
+        let policy = BudgetedRetryPolicy(RetryAfterSecond);
+        let retries_within_budget = |budget| {
+            budget::scope(budget, async {
+                let retries = (0..10)
+                    .take_while(|&n| matches!(policy.should_retry(n), RetryDecision::Retry { .. }))
+                    .count();
+                (retries, budget::is_exhausted())
+            })
+        };
+
+        // Outside of a scope, the retries are not limited
+        assert!(matches!(policy.should_retry(100), RetryDecision::Retry { .. }));
+        assert!(!budget::is_exhausted());
+
+        assert_eq!(retries_within_budget(RetryBudget::default()).await, (10, false));
+        assert_eq!(
+            retries_within_budget(RetryBudget {
+                max_retries: Some(3),
+                max_wait: None,
+            })
+            .await,
+            (3, true)
+        );
+        assert_eq!(
+            retries_within_budget(RetryBudget {
+                max_retries: None,
+                max_wait: Some(Duration::from_millis(2500)),
+            })
+            .await,
+            (2, true)
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(784_111_757); // Sun, 06 Nov 1994 08:49:17 GMT
+
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+
+    #[test]
+    fn test_retry_after_policy() {
+        struct RetryImmediately;
+
+        impl RetryPolicy for RetryImmediately {
+            fn should_retry(&self, _n_past_retries: u32) -> RetryDecision {
+                RetryDecision::Retry {
+                    execute_after: chrono::Utc::now(),
+                }
+            }
+        }
+
+        let policy = RetryAfterPolicy(RetryImmediately);
+        let wait_before_retry = || match policy.should_retry(0) {
+            RetryDecision::Retry { execute_after } => Some((execute_after - chrono::Utc::now()).num_seconds()),
+            RetryDecision::DoNotRetry => None,
+        };
+        let wait_after_response = |status: u16, retry_after: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::RETRY_AFTER, retry_after.parse().unwrap());
+            remember_retry_after(reqwest::StatusCode::from_u16(status).unwrap(), &headers);
+            wait_before_retry()
+        };
+
+        assert!(matches!(wait_after_response(429, "30"), Some(29..=30)));
+        assert!(matches!(wait_after_response(503, "10"), Some(9..=10)));
+        assert_eq!(wait_after_response(500, "30"), Some(0));
+        assert_eq!(wait_after_response(429, "invalid"), Some(0));
+        assert_eq!(
+            wait_after_response(429, &(MAX_RETRY_AFTER.as_secs() + 1).to_string()),
+            None
+        );
+
+        // The wait only applies to the retry of the response requesting it
+        wait_after_response(429, "30");
+        assert_eq!(wait_before_retry(), Some(0));
+    }
+
+    #[test]
+    fn test_segment_keys_across_discontinuity() {
+        let playlist = b"#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:7
+#EXT-X-KEY:METHOD=AES-128,URI=\"key1\"
+#EXTINF:10.0,
+seg7.ts
+#EXTINF:10.0,
+seg8.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:10.0,
+ad9.ts
+#EXT-X-KEY:METHOD=AES-128,URI=\"key2\",IV=0x000000000000000000000000000000FF
+#EXTINF:10.0,
+seg10.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:10.0,
+seg11.ts
+#EXT-X-ENDLIST
+";
+        let media_playlist = m3u8_rs::parse_media_playlist_res(playlist).unwrap();
+        let segment_keys = get_segment_keys(&media_playlist.segments).unwrap();
+        let key1 = SegmentKey {
+            method: EncryptionMethod::Aes128,
+            uri: "key1".to_string(),
+            iv: None,
+        };
+        let key2 = SegmentKey {
+            method: EncryptionMethod::Aes128,
+            uri: "key2".to_string(),
+            iv: Some(0xFF_u128.to_be_bytes()),
+        };
+
+        assert_eq!(
+            segment_keys,
+            [Some(key1.clone()), Some(key1.clone()), Some(key1), Some(key2), None]
+        );
+
+        // The segment after the discontinuity is decrypted with its own sequence number as IV
+        let sequence_numbers = (u128::from(media_playlist.media_sequence)..).zip(&segment_keys);
+        let ivs: Vec<_> = sequence_numbers
+            .filter_map(|(sequence_number, segment_key)| Some(segment_key.as_ref()?.get_iv(sequence_number)))
+            .collect();
+        assert_eq!(
+            ivs,
+            [7, 8, 9, 0xFF].map(|iv: u128| iv.to_be_bytes()),
+            "IVs of the encrypted segments"
+        );
+
+        let key = [0x42; 16];
+        let plaintext = b"segment after the discontinuity".to_vec();
+        let mut buffer = vec![0; plaintext.len() + 16];
+        buffer[..plaintext.len()].copy_from_slice(&plaintext);
+        let encrypted = cbc::Encryptor::<aes::Aes128>::new(&key.into(), &9_u128.to_be_bytes().into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buffer, plaintext.len())
+            .unwrap()
+            .to_vec();
+
+        assert_eq!(decrypt_aes128_segment(encrypted, &key, &ivs[2]).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_make_playlist_uris_absolute() {
+        let playlist = b"#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-TARGETDURATION:10
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXT-X-KEY:METHOD=AES-128,URI=\"/keys/1\"
+#EXTINF:10.0,
+seg1.m4s
+#EXTINF:10.0,
+https://cdn2.example.com/seg2.m4s
+#EXT-X-ENDLIST
+";
+        let mut media_playlist = m3u8_rs::parse_media_playlist_res(playlist).unwrap();
+        let playlist_url = url::Url::parse("https://cdn.example.com/hls/720p/index.m3u8").unwrap();
+        make_playlist_uris_absolute(&mut media_playlist, &playlist_url).unwrap();
+
+        let segment_uris: Vec<_> = media_playlist
+            .segments
+            .iter()
+            .map(|segment| segment.uri.as_str())
+            .collect();
+        assert_eq!(
+            segment_uris,
+            [
+                "https://cdn.example.com/hls/720p/seg1.m4s",
+                "https://cdn2.example.com/seg2.m4s"
+            ]
+        );
+        assert_eq!(
+            media_playlist.segments[0].key.as_ref().unwrap().uri.as_deref(),
+            Some("https://cdn.example.com/keys/1")
+        );
+        assert_eq!(
+            media_playlist.segments[0].map.as_ref().unwrap().uri,
+            "https://cdn.example.com/hls/720p/init.mp4"
+        );
+
+        let mut written = Vec::new();
+        media_playlist.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("\nhttps://cdn.example.com/hls/720p/seg1.m4s\n"));
+    }
+
+    #[test]
+    fn test_progress_event() {
+        let event = ProgressEvent {
+            episode: "Yuruyuri - S01E001 - GerSub.mp4",
+            downloaded: 1024,
+            total: None,
+            speed: 512.0,
+            status: ProgressStatus::Downloading,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"episode":"Yuruyuri - S01E001 - GerSub.mp4","downloaded":1024,"total":null,"speed":512.0,"status":"downloading"}"#
+        );
+    }
+
+    #[test]
+    fn test_estimate_series_eta() {
+        assert_eq!(estimate_series_eta(Duration::from_secs(30), 0, 10), None);
+        assert_eq!(
+            estimate_series_eta(Duration::from_secs(300), 2, 10),
+            Some(Duration::from_secs(1200))
+        );
+        assert_eq!(
+            estimate_series_eta(Duration::from_secs(300), 10, 10),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_is_shared() {
+        const BYTES_PER_SECOND: f64 = 100_000.0;
+        const FILE_SIZE: usize = 100_000;
+
+        let base_url = spawn_http_server(|_| async { http_response("200 OK", "", &[0; FILE_SIZE]) }).await;
+        let save_directory = std::env::temp_dir().join(format!("sdl-test-rate-limiter-{}", std::process::id()));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        let rate_limiter = async_speed_limit::Limiter::new(BYTES_PER_SECOND);
+        let create_downloader = || {
+            Downloader::new(
+                indicatif::MultiProgress::new(),
+                false,
+                None,
+                None,
+                None,
+                None,
+                &NetworkConfig::default(),
+            )
+            .rate_limiter(Some(rate_limiter.clone()))
+        };
+        let (first_downloader, second_downloader) = (create_downloader(), create_downloader());
+        let download = |downloader: &Downloader, name: &str| {
+            downloader.download_to_file(InternalDownloadTask::new(
+                save_directory.join(name),
+                format!("{base_url}/{name}"),
+            ))
+        };
+
+        // Two concurrent downloads of 100 KB each must take about two seconds
+        // together, since their downloaders share the limit
+        let start = Instant::now();
+        let (first_result, second_result) = tokio::join!(
+            download(&first_downloader, "first.mp4"),
+            download(&second_downloader, "second.mp4")
+        );
+        let elapsed = start.elapsed().as_secs_f64();
+
+        assert_eq!(
+            std::fs::metadata(first_result.unwrap()).unwrap().len(),
+            FILE_SIZE as u64
+        );
+        assert_eq!(
+            std::fs::metadata(second_result.unwrap()).unwrap().len(),
+            FILE_SIZE as u64
+        );
+        assert!(elapsed >= 1.5, "downloads took only {elapsed} seconds");
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_host_limiter() {
+        let host_limiter = HostLimiter::new(NonZeroUsize::new(2).unwrap());
+        let url = |url: &str| url::Url::parse(url).unwrap();
+
+        let first = host_limiter.acquire(&url("https://cdn.example.com/1.ts")).await;
+        let second = host_limiter.clone().acquire(&url("https://CDN.example.com/2.ts")).await;
+        assert!(first.is_some() && second.is_some());
+
+        // The host is full, while other hosts are not affected
+        let third = host_limiter.acquire(&url("https://cdn.example.com/3.ts"));
+        assert!(tokio::time::timeout(Duration::from_millis(50), third).await.is_err());
+        assert!(host_limiter
+            .acquire(&url("https://other.example.com/1.ts"))
+            .await
+            .is_some());
+
+        drop(first);
+        let third = host_limiter.acquire(&url("https://cdn.example.com/3.ts"));
+        assert!(tokio::time::timeout(Duration::from_millis(50), third)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_host_limiter_m3u8() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let base_url = spawn_http_server({
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+
+            move |request: String| {
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+
+                async move {
+                    if request.starts_with("GET /index.m3u8 ") {
+                        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10.0,\n1.ts\n\
+                                        #EXTINF:10.0,\n2.ts\n#EXTINF:10.0,\n3.ts\n#EXT-X-ENDLIST\n";
+                        return http_response("200 OK", "", playlist.as_bytes());
+                    }
+
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    http_response("200 OK", "", &[0x47; 188])
+                }
+            }
+        })
+        .await;
+        let save_directory = std::env::temp_dir().join(format!("sdl-test-host-limiter-{}", std::process::id()));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        let downloader = Downloader::new(
+            indicatif::MultiProgress::new(),
+            false,
+            None,
+            None,
+            None,
+            None,
+            &NetworkConfig::default(),
+        )
+        .container(Container::Ts)
+        .segment_concurrency(NonZeroU32::new(3).unwrap())
+        .host_limiter(Some(HostLimiter::new(NonZeroUsize::MIN)));
+
+        // The permit of the playlist would block its own segments
+        let download = downloader.download_to_file(
+            InternalDownloadTask::new(save_directory.join("video"), format!("{base_url}/index.m3u8"))
+                .output_path_has_extension(false),
+        );
+        let path = tokio::time::timeout(Duration::from_secs(10), download)
+            .await
+            .expect("download with one request per host did not finish")
+            .unwrap();
+
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 3 * 188);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scrape_outcomes_in_summary() {
+        let episode_info = |season, episode| EpisodeInfo {
+            name: None,
+            season_number: Some(season),
+            episode_number: EpisodeNumber::Number(episode),
+            max_episode_number_in_season: None,
+        };
+        let scrape_outcomes = ScrapeOutcomes::default();
+        scrape_outcomes.record(ScrapeOutcome {
+            episode_info: episode_info(1, 2),
+            language: None,
+            failure: Some("no extractor for any stream platform: Foo".to_owned()),
+        });
+        scrape_outcomes.record(ScrapeOutcome {
+            episode_info: episode_info(1, 1),
+            language: Some(VideoType::Sub(Language::German)),
+            failure: None,
+        });
+
+        let downloader = Downloader::new(
+            indicatif::MultiProgress::new(),
+            false,
+            None,
+            None,
+            None,
+            None,
+            &NetworkConfig::default(),
+        );
+        let series_info = SeriesInfo {
+            title: "Series".to_owned(),
+            description: None,
+            status: None,
+            year: None,
+        };
+        let (download_manager, sender) = DownloadManager::new(downloader, None, std::env::temp_dir(), series_info);
+        drop(sender);
+
+        let outcomes = download_manager
+            .scrape_outcomes(scrape_outcomes.clone())
+            .progress_downloads()
+            .await;
+
+        let episodes: Vec<_> = outcomes.iter().map(|outcome| outcome.episode.as_str()).collect();
+        assert_eq!(episodes, ["S01E001", "S01E002"]);
+        assert_eq!(outcomes[0].status, EpisodeStatus::Skipped);
+        assert_eq!(
+            outcomes[1].status,
+            EpisodeStatus::Failed("no extractor for any stream platform: Foo".to_owned())
+        );
+        assert!(scrape_outcomes.take().is_empty());
+    }
+
+    #[test]
+    fn test_parse_completed_segments() {
+        assert_eq!(CompletedSegments::parse(""), CompletedSegments::default());
+
+        let completed_segments = CompletedSegments::parse("3 1024\n4 2048\n5 30");
+        assert_eq!(completed_segments.sequence_numbers, HashSet::from([3, 4]));
+        assert_eq!(completed_segments.output_len, 2048);
+        assert_eq!(completed_segments.record_len, "3 1024\n4 2048\n".len());
+
+        let completed_segments = CompletedSegments::parse("0 188\ngarbage\n2 564\n");
+        assert_eq!(completed_segments.sequence_numbers, HashSet::from([0]));
+        assert_eq!(completed_segments.output_len, 188);
+        assert_eq!(completed_segments.record_len, "0 188\n".len());
+    }
+
+    #[test]
+    fn test_select_variant() {
+        let master_playlist = b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360
+360.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080
+1080.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=9000000,RESOLUTION=3840x2160,URI=\"iframe.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=1400000,RESOLUTION=842x480
+480.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720
+720.m3u8
+";
+        let Ok(m3u8_rs::Playlist::MasterPlaylist(playlist)) = m3u8_rs::parse_playlist_res(master_playlist) else {
+            panic!("failed to parse master playlist");
+        };
+
+        let tests = [
+            (Quality::Best, "1080.m3u8"),
+            (Quality::Worst, "360.m3u8"),
+            (Quality::MaxHeight(1080), "1080.m3u8"),
+            (Quality::MaxHeight(720), "720.m3u8"),
+            (Quality::MaxHeight(600), "480.m3u8"),
+            (Quality::MaxHeight(2160), "1080.m3u8"),
+            (Quality::MaxHeight(240), "360.m3u8"),
+        ];
+
+        for (quality, expected_uri) in tests {
+            let variant = select_variant(&playlist.variants, quality).unwrap();
+            assert_eq!(variant.uri, expected_uri, "failed for {}", quality);
+        }
+    }
 
     #[test]
     fn test_fix_filename() {
@@ -1514,4 +4751,344 @@ mod tests {
             assert_eq!(format_episode_number(&input.0, input.1), output.to_string());
         }
     }
+
+    #[tokio::test]
+    async fn test_resume_with_single_request() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let base_url = spawn_http_server({
+            let requests = requests.clone();
+
+            move |request: String| {
+                requests.fetch_add(1, Ordering::SeqCst);
+
+                async move {
+                    if request.to_ascii_lowercase().contains("\r\nrange: bytes=3-\r\n") {
+                        http_response("206 Partial Content", "Content-Range: bytes 3-5/6\r\n", b"def")
+                    } else {
+                        http_response("200 OK", "", b"abcdef")
+                    }
+                }
+            }
+        })
+        .await;
+        let save_directory = std::env::temp_dir().join(format!("sdl-test-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        std::fs::write(save_directory.join("video.mp4"), b"abc").unwrap();
+        let downloader = Downloader::new(
+            indicatif::MultiProgress::new(),
+            false,
+            None,
+            None,
+            None,
+            None,
+            &NetworkConfig::default(),
+        );
+
+        let path = downloader
+            .download_to_file(
+                InternalDownloadTask::new(save_directory.join("video"), format!("{base_url}/video"))
+                    .output_path_has_extension(false)
+                    .resume(true),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(path).unwrap(), b"abcdef");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_m3u8_head() {
+        let base_url = spawn_http_server(|request: String| async move {
+            if request.starts_with("GET /hls/index.m3u8 ") {
+                let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10.0,\n1.ts\n\
+                                #EXTINF:10.0,\n2.ts\n#EXTINF:10.0,\n3.ts\n#EXT-X-ENDLIST\n";
+                http_response("200 OK", "", playlist.as_bytes())
+            } else {
+                http_response("200 OK", "", &[0x47; 188])
+            }
+        })
+        .await;
+        let cache_dir = std::env::temp_dir().join(format!("sdl-test-prefetch-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let downloader = Downloader::new(
+            indicatif::MultiProgress::new(),
+            false,
+            None,
+            None,
+            None,
+            None,
+            &NetworkConfig::default(),
+        );
+
+        let (playlist_path, sub_files) = downloader
+            .prefetch_m3u8_head(
+                InternalDownloadTask::new(cache_dir.join("0"), format!("{base_url}/hls/index.m3u8")),
+                2,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let playlist = std::fs::read(&playlist_path).unwrap();
+        let playlist = m3u8_rs::parse_media_playlist_res(&playlist).unwrap();
+        let uris: Vec<&str> = playlist.segments.iter().map(|segment| segment.uri.as_str()).collect();
+
+        assert_eq!(playlist_path, cache_dir.join("0.m3u8"));
+        assert!(sub_files.is_empty());
+        assert_eq!(uris, ["0.0.ts", "0.1.ts", format!("{base_url}/hls/3.ts").as_str()]);
+        assert_eq!(std::fs::metadata(cache_dir.join("0.1.ts")).unwrap().len(), 188);
+
+        let not_m3u8 = downloader
+            .prefetch_m3u8_head(
+                InternalDownloadTask::new(cache_dir.join("1"), format!("{base_url}/video.mp4")),
+                2,
+            )
+            .await
+            .unwrap();
+        assert!(not_m3u8.is_none());
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_redirects_at_media() {
+        let requested_paths = Arc::new(Mutex::new(vec![]));
+        let base_url = spawn_http_server({
+            let requested_paths = requested_paths.clone();
+
+            move |request: String| {
+                let path = request.split(' ').nth(1).unwrap_or_default().to_owned();
+                requested_paths.lock().unwrap().push(path.clone());
+
+                async move {
+                    match path.as_str() {
+                        "/e/abc" => http_response("302 Found", "Location: /media/video.mp4\r\n", b""),
+                        "/media/video.mp4" => http_response("302 Found", "Location: /cdn/video.mp4\r\n", b"redirect"),
+                        "/cdn/video.mp4" => http_response("200 OK", "", b"video"),
+                        _ => http_response("404 Not Found", "", b""),
+                    }
+                }
+            }
+        })
+        .await;
+        let redirect_policy = RedirectPolicy {
+            max_redirects: 10,
+            stop_at_media: true,
+        };
+
+        // The media hop is never requested
+        let followed = follow_redirects(None, format!("{base_url}/e/abc"), None, None, None, redirect_policy)
+            .await
+            .unwrap();
+        let FollowedRedirects::MediaUrl { url, redirect_count } = followed else {
+            panic!("the media url was requested");
+        };
+        assert_eq!(url.as_str(), format!("{base_url}/media/video.mp4"));
+        assert_eq!(redirect_count, 1);
+        assert_eq!(*requested_paths.lock().unwrap(), ["/e/abc"]);
+
+        // A download requests the media url on its own, and follows its redirect to the CDN
+        let response =
+            get_response_with_redirect_policy(None, format!("{base_url}/e/abc"), None, None, None, redirect_policy)
+                .await
+                .unwrap();
+        assert_eq!(response.response().text().await.unwrap(), "video");
+    }
+
+    #[test]
+    fn test_is_media_url() {
+        let is_media = |url: &str| is_media_url(&url::Url::parse(url).unwrap());
+
+        assert!(is_media("https://example.com/hls/master.m3u8?token=abc"));
+        assert!(is_media("https://example.com/video/Episode.MP4"));
+        assert!(!is_media("https://example.com/e/abcdef"));
+        assert!(!is_media("https://example.com/.mp4"));
+    }
+
+    #[test]
+    fn test_sort_by_ip_family() {
+        let addrs: Vec<std::net::SocketAddr> = ["[::1]:0", "10.0.0.1:0", "[::2]:0", "10.0.0.2:0"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            sort_by_ip_family(addrs.clone(), IpFamily::V4),
+            [addrs[1], addrs[3], addrs[0], addrs[2]]
+        );
+        assert_eq!(
+            sort_by_ip_family(addrs.clone(), IpFamily::V6),
+            [addrs[0], addrs[2], addrs[1], addrs[3]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_brotli_page() {
+        // Brotli stream of a single uncompressed meta-block
+        fn brotli_uncompressed(data: &[u8]) -> Vec<u8> {
+            assert!((1..=1 << 16).contains(&data.len()));
+            let header = ((data.len() as u32 - 1) << 4) | (1 << 20);
+            let mut encoded = header.to_le_bytes()[..3].to_vec();
+            encoded.extend_from_slice(data);
+            encoded.push(0b11); // last and empty meta-block
+            encoded
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/e/abc", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                assert_ne!(read, 0);
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            let body = brotli_uncompressed(br#"<script>var source = "https://example.com/master.m3u8";</script>"#);
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: br\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+            String::from_utf8(request).unwrap().to_ascii_lowercase()
+        });
+
+        let page = get_page_text(&url, None, None, None).await.unwrap();
+        let source = regex::Regex::new(r#"source = "([^"]+)""#)
+            .unwrap()
+            .captures(&page)
+            .map(|captures| captures[1].to_owned());
+        assert_eq!(source.as_deref(), Some("https://example.com/master.m3u8"));
+
+        let request = server.await.unwrap();
+        let accept_encoding = request
+            .lines()
+            .find_map(|line| line.strip_prefix("accept-encoding:"))
+            .unwrap();
+        assert!(accept_encoding.contains("gzip") && accept_encoding.contains("br"));
+    }
+
+    #[test]
+    fn test_get_referer_origin() {
+        assert_eq!(
+            get_referer_origin("https://player.example.com/e/abc?x=1").as_deref(),
+            Some("https://player.example.com")
+        );
+        assert_eq!(
+            get_referer_origin("http://example.com:8080/").as_deref(),
+            Some("http://example.com:8080")
+        );
+        assert_eq!(
+            get_referer_origin("https://example.com:443/e").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(get_referer_origin("data:text/plain,abc"), None);
+        assert_eq!(get_referer_origin("not a url"), None);
+    }
+
+    #[tokio::test]
+    async fn test_response_bytes_limited() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/index.m3u8", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            // Announced length, no length, and a body within the limit
+            let responses = [
+                "Content-Length: 32\r\n\r\n#EXTM3U\n#EXT-X-TARGETDURATION:10\n",
+                "\r\n#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXT-X-ENDLIST\n",
+                "Content-Length: 8\r\n\r\n#EXTM3U\n",
+            ];
+
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    assert_ne!(read, 0);
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let response = format!("HTTP/1.1 200 OK\r\nConnection: close\r\n{response}");
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let client = reqwest::Client::new();
+
+        for _ in 0..2 {
+            let response = client.get(&url).send().await.unwrap();
+            let err = get_response_bytes_limited(response, 16).await.unwrap_err();
+            assert_eq!(err.to_string(), "response body is larger than 16 bytes");
+        }
+
+        let response = client.get(&url).send().await.unwrap();
+        let body = get_response_bytes_limited(response, 16).await.unwrap();
+        assert_eq!(&body[..], b"#EXTM3U\n");
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_placeholder_video() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/video.mp4", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    assert_ne!(read, 0);
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let response = "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 8\r\n\r\nGIF89a\0\0";
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let save_directory = std::env::temp_dir().join(format!("sdl-test-placeholder-{}", std::process::id()));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        let output_path = save_directory.join("video.mp4");
+        let downloader = |min_file_size| {
+            Downloader::new(
+                indicatif::MultiProgress::new(),
+                false,
+                None,
+                None,
+                None,
+                None,
+                &NetworkConfig::default(),
+            )
+            .min_file_size(min_file_size)
+        };
+
+        let err = downloader(64 * 1024)
+            .download_to_file(InternalDownloadTask::new(output_path.clone(), url.clone()))
+            .await
+            .unwrap_err();
+        assert!(is_placeholder_video(&err));
+        assert!(!output_path.exists());
+
+        let path = downloader(0)
+            .download_to_file(InternalDownloadTask::new(output_path.clone(), url))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 8);
+
+        server.await.unwrap();
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
 }
@@ -6,15 +6,49 @@ use once_cell::sync::Lazy;
 use regex::Regex;
 use thirtyfour::prelude::ElementQueryable;
 use thirtyfour::{By, WebDriver, WebElement};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use url::Url;
 
 use super::{
-    AllOrSpecific, DownloadRequest, DownloadSettings, DownloadTask, EpisodeInfo, EpisodeNumber, InstantiatedDownloader,
-    Language, SeriesInfo, VideoType,
+    dedup_candidates, extract_from_candidates, extract_pending_episodes, record_unsupported_episode,
+    retain_only_extractor, AllOrSpecific, DownloadRequest, DownloadSettings, DownloadTask, EpisodeInfo, EpisodeNumber,
+    InstantiatedDownloader, Language, ListedEpisode, ListedSeason, PendingEpisode, RecentEpisodes, ScrapeOutcome,
+    SeriesInfo, SeriesStatus, StreamCandidate, TypePreference, VideoType,
 };
-use crate::downloaders::utils::sleep_random;
+use crate::downloaders::selectors::SelectorOverrides;
+use crate::downloaders::utils::{sleep_page_load, with_scrape_timeout};
 use crate::downloaders::{Downloader, EpisodesRequest};
-use crate::extractors::extract_video_url_with_extractor_from_url_unchecked;
+
+static YEAR_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b((?:19|20)[0-9]{2})\b").unwrap());
+
+/// Overlay, which blocks the page until its accept button is clicked.
+struct PageGate {
+    name: &'static str,
+    overlay: &'static str,
+    accept_button: &'static str,
+}
+
+/// Known consent and age gates. The selectors have to be updated here, if
+/// the site changes them.
+const PAGE_GATES: &[PageGate] = &[
+    PageGate {
+        name: "consent",
+        overlay: ".fc-consent-root",
+        accept_button: ".fc-consent-root .fc-cta-consent",
+    },
+    PageGate {
+        name: "age",
+        overlay: "#ageVerification, .age-verification",
+        accept_button: "#ageVerification .accept, .age-verification .accept",
+    },
+];
+
+/// How often visible gates are tried to be dismissed, before giving up.
+const PAGE_GATE_ATTEMPTS: u32 = 3;
+
+/// Parts of the page title of error pages, in lowercase.
+const NOT_FOUND_MARKERS: &[&str] = &["404", "nicht gefunden", "not found"];
 
 static URL_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"(?i)^https?://(?:www\.)?(?:(aniworld)\.to/anime|(s)\.to/serie)/stream/([^/\s]+)(?:/(?:(?:staffel-([1-9][0-9]*)(?:/(?:episode-([1-9][0-9]*)/?)?)?)|(?:(filme)(?:/(?:film-([1-9][0-9]*)/?)?)?))?)?$"#)
@@ -39,23 +73,48 @@ impl<'driver> Downloader<'driver> for AniWorldSerienStream<'driver> {
 
 impl InstantiatedDownloader for AniWorldSerienStream<'_> {
     async fn get_series_info(&self) -> Result<SeriesInfo, anyhow::Error> {
-        self.driver.goto(self.parsed_url.get_series_url()).await?;
+        let series_url = self.parsed_url.get_series_url();
+        with_scrape_timeout("going to the series page", self.driver.goto(&series_url)).await??;
+
+        // The page may still be usable, if dismissing the gates hangs
+        if let Err(err) = with_scrape_timeout("dismissing page gates", dismiss_page_gates(self.driver)).await {
+            log::debug!("Ignoring page gates: {}", err);
+        }
+
+        // Otherwise a missing series would only fail on the title below
+        if with_scrape_timeout("finding the series title", self.driver.find(By::Css(".series-title")))
+            .await?
+            .is_err()
+        {
+            let page_title = self.driver.title().await.unwrap_or_default();
+            let current_url = self.driver.current_url().await.ok();
+
+            if is_missing_series_page(&page_title, current_url.as_ref(), &series_url) {
+                anyhow::bail!("series does not exist or was taken offline: {}", series_url);
+            }
+        }
 
-        let title = self
-            .driver
-            .execute(
+        let title = with_scrape_timeout(
+            "getting the title",
+            self.driver.execute(
                 r#"return document.querySelector(".series-title > h1 > span").innerText;"#,
                 vec![],
-            )
-            .await
-            .context("failed to get title")?
-            .json()
-            .as_str()
-            .context("failed to get title as string")?
-            .trim()
-            .to_owned();
-
-        let description = if let Ok(element) = self.driver.find(By::Css("p[data-full-description]")).await {
+            ),
+        )
+        .await?
+        .context("failed to get title")?
+        .json()
+        .as_str()
+        .context("failed to get title as string")?
+        .trim()
+        .to_owned();
+
+        let description_element = with_scrape_timeout(
+            "finding the description",
+            self.driver.find(By::Css("p[data-full-description]")),
+        )
+        .await?;
+        let description = if let Ok(element) = description_element {
             element.attr("data-full-description").await.ok()
         } else {
             None
@@ -71,25 +130,107 @@ impl InstantiatedDownloader for AniWorldSerienStream<'_> {
             }
         });
 
+        // The production years are shown as e.g. "2011 - Heute" below the title
+        let start_date = self.get_optional_text("span[itemprop='startDate']").await;
+        let end_date = self.get_optional_text("span[itemprop='endDate']").await;
+
         Ok(SeriesInfo {
             title,
             description,
-            status: None,
-            year: None,
+            status: end_date.as_deref().and_then(parse_series_status),
+            year: start_date.as_deref().and_then(parse_year),
         })
     }
 
+    async fn list_episodes(&self) -> Result<Vec<ListedSeason>, anyhow::Error> {
+        let seasons = self
+            .enumerate_seasons()
+            .await?
+            .into_iter()
+            .map(|(season, episodes)| {
+                let max_episode = episodes.iter().map(|episode| episode.number).max();
+
+                ListedSeason {
+                    season_number: season,
+                    episodes: episodes
+                        .into_iter()
+                        .map(|episode| ListedEpisode {
+                            episode_info: EpisodeInfo {
+                                name: episode.name,
+                                season_number: Some(season),
+                                episode_number: EpisodeNumber::Number(episode.number),
+                                max_episode_number_in_season: max_episode,
+                            },
+                            languages: episode.languages,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Ok(seasons)
+    }
+
     async fn download<F: FnMut() -> Duration>(
         &self,
         request: DownloadRequest,
         settings: DownloadSettings<F>,
-        sender: UnboundedSender<DownloadTask>,
+        sender: Sender<DownloadTask>,
     ) -> Result<(), anyhow::Error> {
         let mut scraper = Scraper::new(self.driver, &self.parsed_url, request, settings, sender)?;
         scraper.scrape().await
     }
 }
 
+impl AniWorldSerienStream<'_> {
+    async fn get_optional_text(&self, selector: &str) -> Option<String> {
+        let element = with_scrape_timeout("finding an optional text", self.driver.find(By::Css(selector)))
+            .await
+            .ok()?
+            .ok()?;
+        let text = element.text().await.ok()?;
+        let trimmed_text = text.trim();
+
+        if trimmed_text.is_empty() {
+            None
+        } else {
+            Some(trimmed_text.to_owned())
+        }
+    }
+
+    /// Visits every season page and collects the episodes listed there.
+    async fn enumerate_seasons(&self) -> Result<Vec<(u32, Vec<SeasonEpisode>)>, anyhow::Error> {
+        let season_url = self.parsed_url.get_season_url(1);
+        with_scrape_timeout("going to the season page", self.driver.goto(season_url))
+            .await?
+            .context("failed to go to season page")?;
+        sleep_page_load().await;
+
+        let seasons_info = with_scrape_timeout("getting the seasons", get_seasons_info(self.driver))
+            .await?
+            .context("failed to get seasons info")?;
+        let mut seasons = Vec::new();
+
+        for (season, goto) in get_season_visits(1, &seasons_info.seasons) {
+            if goto {
+                let season_url = self.parsed_url.get_season_url(season);
+                with_scrape_timeout("going to the season page", self.driver.goto(season_url))
+                    .await?
+                    .context("failed to go to season page")?;
+                sleep_page_load().await;
+            }
+
+            let episodes = with_scrape_timeout("getting the episode list", get_season_episodes(self.driver))
+                .await?
+                .with_context(|| format!("failed to get episodes of S{season:02}"))?;
+
+            seasons.push((season, episodes));
+        }
+
+        Ok(seasons)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct ParsedUrl {
     site: Site,
@@ -153,6 +294,18 @@ impl TryFrom<&str> for ParsedUrl {
 }
 
 impl ParsedUrl {
+    /// Reconciles the season of the url with the selected seasons. Explicitly
+    /// selected seasons take precedence, e.g. `.../staffel-3` with `-s 3-`
+    /// selects the seasons from 3 on. Otherwise only the season of the url is
+    /// used, if it has one.
+    fn restrict_to_season(&self, seasons: Option<&AllOrSpecific>) -> AllOrSpecific {
+        match (seasons, &self.season) {
+            (Some(seasons), _) => seasons.clone(),
+            (None, Some(url_season)) => AllOrSpecific::Specific(vec![url_season.season..=url_season.season]),
+            (None, None) => AllOrSpecific::All,
+        }
+    }
+
     fn get_series_url(&self) -> String {
         format!("{}/{}", self.site.get_base_url(), self.name)
     }
@@ -165,6 +318,11 @@ impl ParsedUrl {
         }
     }
 
+    /// Name of the series in the archive, e.g. `aniworld detektiv-conan`.
+    fn get_archive_name(&self) -> String {
+        format!("{} {}", self.site.get_archive_name(), self.name.to_ascii_lowercase())
+    }
+
     fn get_episode_url(&self, season: u32, episode: u32) -> String {
         if season == 0 {
             format!("{}/film-{}", self.get_season_url(season), episode)
@@ -181,6 +339,13 @@ enum Site {
 }
 
 impl Site {
+    fn get_archive_name(&self) -> &'static str {
+        match self {
+            Site::AniWorld => "aniworld",
+            Site::SerienStream => "sto",
+        }
+    }
+
     fn get_base_url(&self) -> &'static str {
         match self {
             Site::AniWorld => "https://aniworld.to/anime/stream",
@@ -194,7 +359,8 @@ struct Scraper<'driver, 'url, F: FnMut() -> Duration> {
     parsed_url: &'url ParsedUrl,
     request: DownloadRequest,
     settings: DownloadSettings<F>,
-    sender: UnboundedSender<DownloadTask>,
+    sender: Sender<DownloadTask>,
+    pending_sender: Option<UnboundedSender<PendingEpisode>>,
     language_selectors: Vec<(VideoType, By)>,
 }
 
@@ -204,10 +370,15 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
         parsed_url: &'url ParsedUrl,
         request: DownloadRequest,
         settings: DownloadSettings<F>,
-        sender: UnboundedSender<DownloadTask>,
+        sender: Sender<DownloadTask>,
     ) -> Result<Self, anyhow::Error> {
-        let language_selectors = Self::get_language_selectors(&parsed_url.site, &request.language)
-            .with_context(|| format!("Selected language is not supported for this site: {}", request.language))?;
+        let language_selectors = Self::get_language_selectors(
+            &parsed_url.site,
+            &request.language,
+            settings.prefer,
+            &settings.selector_overrides,
+        )
+        .with_context(|| format!("Selected language is not supported for this site: {}", request.language))?;
 
         Ok(Self {
             driver,
@@ -215,52 +386,192 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
             request,
             settings,
             sender,
+            pending_sender: None,
             language_selectors,
         })
     }
 
     async fn scrape(&mut self) -> Result<(), anyhow::Error> {
+        let concurrent_extractions = self.settings.concurrent_extractions.get() as usize;
+
+        if concurrent_extractions == 1 {
+            return self.scrape_requested().await;
+        }
+
+        // The browser collects the stream candidates, while the extraction happens concurrently
+        let (pending_sender, pending_receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.pending_sender = Some(pending_sender);
+
+        let extract_future = extract_pending_episodes(
+            UnboundedReceiverStream::new(pending_receiver),
+            self.sender.clone(),
+            concurrent_extractions,
+            self.settings.extractor_timeout,
+            self.settings.strict,
+            &self.settings.unsupported_episodes,
+            &self.settings.scrape_outcomes,
+        );
+        let scrape_future = async {
+            let result = self.scrape_requested().await;
+            self.pending_sender = None; // close channel, so that the extraction finishes
+            result
+        };
+
+        let (scrape_result, extraction_got_error) = tokio::join!(scrape_future, extract_future);
+        scrape_result?;
+
+        if extraction_got_error {
+            anyhow::bail!("failed to get video url for some episodes");
+        }
+
+        Ok(())
+    }
+
+    async fn scrape_requested(&mut self) -> Result<(), anyhow::Error> {
         let episodes_request = std::mem::replace(&mut self.request.episodes, EpisodesRequest::Unspecified);
 
         match episodes_request {
             EpisodesRequest::Unspecified => {
                 if let Some(season) = &self.parsed_url.season {
                     if let Some(episode) = season.episode {
+                        self.settings.selected_episodes.add(1);
                         self.scrape_episode(season.season, episode, true).await
                     } else {
                         self.scrape_season(season.season, &AllOrSpecific::All).await
                     }
                 } else {
-                    self.scrape_seasons(&AllOrSpecific::All).await
+                    self.scrape_seasons(&AllOrSpecific::All, &AllOrSpecific::All).await
                 }
             }
             EpisodesRequest::Episodes(episodes) => {
                 let season = self.parsed_url.season.as_ref().map(|season| season.season).unwrap_or(1);
                 self.scrape_season(season, &episodes).await
             }
-            EpisodesRequest::Seasons(seasons) => self.scrape_seasons(&seasons).await,
+            EpisodesRequest::Seasons(seasons) => {
+                let seasons = self.parsed_url.restrict_to_season(Some(&seasons));
+                self.scrape_seasons(&seasons, &AllOrSpecific::All).await
+            }
+            EpisodesRequest::Combined { seasons, episodes } => {
+                let seasons = self.parsed_url.restrict_to_season(Some(&seasons));
+                self.scrape_seasons(&seasons, &episodes).await
+            }
+            EpisodesRequest::Recent { seasons, recent } => {
+                let seasons = self.parsed_url.restrict_to_season(seasons.as_ref());
+                self.scrape_recent(&seasons, recent).await
+            }
+            EpisodesRequest::Picked(episodes) => self.scrape_picked(episodes).await,
+        }
+    }
+
+    /// Goes to each of the episodes, which are already known to be listed.
+    async fn scrape_picked(&mut self, mut episodes: Vec<(u32, u32)>) -> Result<(), anyhow::Error> {
+        self.settings.selected_episodes.add(episodes.len());
+        self.settings.maybe_shuffle(&mut episodes);
+        let mut got_error = false;
+
+        for (season, episode) in episodes {
+            if let Err(err) = self.scrape_episode(season, episode, true).await {
+                log::warn!("Failed to get video url for S{season:02}E{episode:03}: {err:#}");
+                got_error = true;
+            }
         }
+
+        if got_error {
+            anyhow::bail!("failed to download all picked episodes");
+        }
+
+        Ok(())
     }
 
-    async fn scrape_seasons(&mut self, seasons: &AllOrSpecific) -> Result<(), anyhow::Error> {
+    /// Lists the episodes of all selected seasons first, since the most
+    /// recent ones are only known afterwards.
+    async fn scrape_recent(&mut self, seasons: &AllOrSpecific, recent: RecentEpisodes) -> Result<(), anyhow::Error> {
         let first_episode_url = self.parsed_url.get_episode_url(1, 1);
-        self.driver
-            .goto(first_episode_url)
+        self.goto(&first_episode_url)
             .await
             .context("failed to go to episode page")?;
-        sleep_random(1000..=2000).await; // wait until page has loaded
+        sleep_page_load().await;
         self.settings.maybe_ddos_wait().await;
 
-        let seasons_info = self.get_seasons_info().await.context("failed to get seasons info")?;
-        let season_start = if seasons_info.has_season_zero { 0 } else { 1 };
+        let seasons_info = with_scrape_timeout("getting the seasons", get_seasons_info(self.driver))
+            .await?
+            .context("failed to get seasons info")?;
+        let selected_seasons: Vec<u32> = seasons_info
+            .seasons
+            .into_iter()
+            .filter(|&season| seasons.contains(season))
+            .collect();
+        let mut listed_episodes = vec![];
+
+        for (season, goto) in get_season_visits(1, &selected_seasons) {
+            if goto {
+                self.goto(&self.parsed_url.get_episode_url(season, 1))
+                    .await
+                    .context("failed to go to episode page")?;
+                sleep_page_load().await;
+                self.settings.maybe_ddos_wait().await;
+            }
+
+            let available_episodes =
+                with_scrape_timeout("getting the episodes", get_available_episodes(self.driver)).await?;
+            listed_episodes.extend(available_episodes.into_iter().map(|episode| (season, episode)));
+        }
+
+        let mut selected_episodes = recent.select(listed_episodes);
+
+        if selected_episodes.is_empty() {
+            log::info!("No new episodes found");
+            return Ok(());
+        }
+
+        self.settings.selected_episodes.add(selected_episodes.len());
+        self.settings.maybe_shuffle(&mut selected_episodes);
+
         let mut got_error = false;
 
-        for season in season_start..=seasons_info.max_season {
-            if seasons.contains(season) {
-                if let Err(err) = self.scrape_season(season, &AllOrSpecific::All).await {
-                    log::warn!("Failed to download S{season:02}: {err:#}");
-                    got_error = true;
-                }
+        for (season, episode) in selected_episodes {
+            if let Err(err) = self.scrape_episode(season, episode, true).await {
+                log::warn!("Failed to get video url for S{season:02}E{episode:03}: {err:#}");
+                got_error = true;
+            }
+        }
+
+        if got_error {
+            anyhow::bail!("failed to download all recent episodes");
+        }
+
+        Ok(())
+    }
+
+    async fn scrape_seasons(&mut self, seasons: &AllOrSpecific, episodes: &AllOrSpecific) -> Result<(), anyhow::Error> {
+        let first_episode_url = self.parsed_url.get_episode_url(1, 1);
+        self.goto(&first_episode_url)
+            .await
+            .context("failed to go to episode page")?;
+        sleep_page_load().await;
+        self.settings.maybe_ddos_wait().await;
+
+        let seasons_info = with_scrape_timeout("getting the seasons", get_seasons_info(self.driver))
+            .await?
+            .context("failed to get seasons info")?;
+
+        // The movies are listed like a season, but not every series has them
+        if matches!(seasons, AllOrSpecific::Specific(_)) && seasons.contains(0) && !seasons_info.seasons.contains(&0) {
+            log::warn!("Series has no movies");
+        }
+
+        let mut selected_seasons: Vec<u32> = seasons_info
+            .seasons
+            .into_iter()
+            .filter(|&season| seasons.contains(season))
+            .collect();
+        self.settings.maybe_shuffle(&mut selected_seasons);
+        let mut got_error = false;
+
+        for season in selected_seasons {
+            if let Err(err) = self.scrape_season(season, episodes).await {
+                log::warn!("Failed to download S{season:02}: {err:#}");
+                got_error = true;
             }
         }
 
@@ -275,40 +586,43 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
         let first_episode_url = self.parsed_url.get_episode_url(season, 1);
         let mut already_is_on_page = false;
 
-        if let Ok(current_url) = self.driver.current_url().await {
+        if let Ok(Ok(current_url)) = with_scrape_timeout("getting the current url", self.driver.current_url()).await {
             if current_url.as_str().eq_ignore_ascii_case(&first_episode_url) {
                 already_is_on_page = true;
             }
         }
 
         if !already_is_on_page {
-            self.driver
-                .goto(first_episode_url)
+            self.goto(&first_episode_url)
                 .await
                 .context("failed to go to episode page")?;
-            sleep_random(1000..=2000).await; // wait until page has loaded
+            sleep_page_load().await;
             self.settings.maybe_ddos_wait().await;
         }
 
-        let max_episodes = self
-            .get_episode_info(season, 1)
-            .await
-            .context("failed to get episode info")?
-            .max_episode_number_in_season
-            .context("failed to get maximum episode number in season")?;
+        // Only episodes listed on the site are scraped, since there may be gaps
+        let available_episodes =
+            with_scrape_timeout("getting the episodes", get_available_episodes(self.driver)).await?;
+
+        if available_episodes.is_empty() {
+            anyhow::bail!("failed to find episodes in season");
+        }
 
-        let mut goto = false;
+        let mut selected_episodes: Vec<u32> = available_episodes
+            .into_iter()
+            .filter(|&episode| episodes.contains(episode))
+            .collect();
+        self.settings.selected_episodes.add(selected_episodes.len());
+        self.settings.maybe_shuffle(&mut selected_episodes);
         let mut got_error = false;
 
-        for episode in 1..=max_episodes {
-            if episodes.contains(episode) {
-                if let Err(err) = self.scrape_episode(season, episode, goto).await {
-                    log::warn!("Failed to get video url for S{season:02}E{episode:03}: {err:#}");
-                    got_error = true;
-                }
-            }
+        for (index, episode) in selected_episodes.into_iter().enumerate() {
+            let goto = index != 0 || episode != 1; // the first episode is already loaded
 
-            goto = true;
+            if let Err(err) = self.scrape_episode(season, episode, goto).await {
+                log::warn!("Failed to get video url for S{season:02}E{episode:03}: {err:#}");
+                got_error = true;
+            }
         }
 
         if got_error {
@@ -319,44 +633,103 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
     }
 
     async fn scrape_episode(&mut self, season: u32, episode: u32, goto: bool) -> Result<(), anyhow::Error> {
-        if goto {
-            self.driver
-                .goto(self.parsed_url.get_episode_url(season, episode))
-                .await
-                .context("failed to go to episode page")?;
-            sleep_random(1000..=2000).await; // wait until page has loaded
-            self.settings.maybe_ddos_wait().await;
+        let result = async {
+            if goto {
+                self.goto(&self.parsed_url.get_episode_url(season, episode))
+                    .await
+                    .context("failed to go to episode page")?;
+                sleep_page_load().await;
+                self.settings.maybe_ddos_wait().await;
+            }
+
+            self.send_stream_to_downloader(season, episode).await
+        }
+        .await;
+
+        // Failures after the episode was sent to the extraction are recorded there
+        if let Err(err) = &result {
+            self.settings.scrape_outcomes.record(ScrapeOutcome {
+                episode_info: EpisodeInfo {
+                    name: None,
+                    season_number: Some(season),
+                    episode_number: EpisodeNumber::Number(episode),
+                    max_episode_number_in_season: None,
+                },
+                language: None,
+                failure: Some(format!("{err:#}")),
+            });
         }
 
-        self.send_stream_to_downloader(season, episode).await
+        result
     }
 
-    fn get_language_selectors(site: &Site, video_type: &VideoType) -> Option<Vec<(VideoType, By)>> {
-        let mut supported_video_types_and_selector = [
+    async fn goto(&self, url: &str) -> Result<(), anyhow::Error> {
+        let operation = format!("going to {}", url);
+        let mut retries = 0;
+
+        loop {
+            match with_scrape_timeout(&operation, self.driver.goto(url)).await? {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    if retries >= self.settings.extract_retries {
+                        return Err(err.into());
+                    }
+
+                    retries += 1;
+                    log::trace!(
+                        "Failed to go to {}, retrying ({}/{}): {}",
+                        url,
+                        retries,
+                        self.settings.extract_retries,
+                        err
+                    );
+                    tokio::time::sleep(Duration::from_secs(retries.min(10) as u64)).await;
+                }
+            }
+        }
+    }
+
+    fn get_language_selectors(
+        site: &Site,
+        video_type: &VideoType,
+        prefer: Option<TypePreference>,
+        selector_overrides: &SelectorOverrides,
+    ) -> Option<Vec<(VideoType, By)>> {
+        let default_selectors = [
             (
                 VideoType::Dub(Language::German),
-                By::Css(r#"div.changeLanguageBox > img[title="Deutsch"]"#),
+                r#"div.changeLanguageBox > img[title="Deutsch"]"#,
             ),
             (
                 VideoType::Sub(Language::German),
-                By::Css(
-                    r#"div.changeLanguageBox > img[title*="Untertitel Deutsch"], div.changeLanguageBox > img[title*="deutschen Untertitel"]"#,
-                ),
+                r#"div.changeLanguageBox > img[title*="Untertitel Deutsch"], div.changeLanguageBox > img[title*="deutschen Untertitel"]"#,
             ),
             (
                 VideoType::Dub(Language::English),
-                By::Css(r#"div.changeLanguageBox > img[title="Englisch"]"#),
+                r#"div.changeLanguageBox > img[title="Englisch"]"#,
             ),
             (
                 VideoType::Sub(Language::English),
-                By::Css(
-                    r#"div.changeLanguageBox > img[title*="Untertitel Englisch"], div.changeLanguageBox > img[title*="englischen Untertitel"]"#,
-                ),
+                r#"div.changeLanguageBox > img[title*="Untertitel Englisch"], div.changeLanguageBox > img[title*="englischen Untertitel"]"#,
             ),
         ];
+        // The selectors can be overridden in the selectors file, in case the site changes them
+        let mut supported_video_types_and_selector = default_selectors.map(|(video_type, default_selector)| {
+            let selector = selector_overrides
+                .get(site.get_archive_name(), &video_type.to_string())
+                .unwrap_or(default_selector);
+            (video_type, By::Css(selector))
+        });
 
-        match site {
-            Site::AniWorld => {
+        match (prefer, site) {
+            (Some(prefer), _) => {
+                // Stable, so that German is still tried before English
+                supported_video_types_and_selector.sort_by_key(|(video_type, _)| match (prefer, video_type) {
+                    (TypePreference::Dub, VideoType::Dub(_)) | (TypePreference::Sub, VideoType::Sub(_)) => 0,
+                    _ => 1,
+                });
+            }
+            (None, Site::AniWorld) => {
                 // Anime are preferred as sub over dub, unless it is the native dub
                 supported_video_types_and_selector.sort_by(|(type_a, _), (type_b, _)| match (type_a, type_b) {
                     (VideoType::Dub(Language::German), _) => Ordering::Less,
@@ -366,7 +739,7 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
                     _ => Ordering::Equal,
                 });
             }
-            Site::SerienStream => {}
+            (None, Site::SerienStream) => {}
         }
 
         video_type.convert_to_non_unspecified_video_types_with_data(supported_video_types_and_selector)
@@ -384,45 +757,6 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
         None
     }
 
-    async fn get_seasons_info(&self) -> Result<SeasonsInfo, anyhow::Error> {
-        let seasons = self
-            .driver
-            .query(By::Css("#stream > ul:first-of-type > li"))
-            .all_from_selector()
-            .await
-            .unwrap();
-        let mut has_movies = false;
-        let mut max_season = None;
-
-        for season in seasons {
-            let text = season.text().await.unwrap();
-            let text = text.trim();
-
-            if text.eq_ignore_ascii_case("Filme") {
-                has_movies = true;
-                continue;
-            }
-
-            let Ok(number) = text.parse::<u32>() else {
-                continue;
-            };
-
-            max_season = match max_season {
-                Some(old_max) => Some(number.max(old_max)),
-                None => Some(number),
-            };
-        }
-
-        if let Some(max_season) = max_season {
-            Ok(SeasonsInfo {
-                has_season_zero: has_movies,
-                max_season,
-            })
-        } else {
-            anyhow::bail!("failed to find max season");
-        }
-    }
-
     async fn get_episode_info(&self, current_season: u32, current_episode: u32) -> Option<EpisodeInfo> {
         let episode_title = if let Ok(element) = self.driver.find(By::Css(".episodeGermanTitle")).await {
             element.text().await.ok().and_then(|title| {
@@ -438,27 +772,7 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
             None
         };
 
-        let episodes = self
-            .driver
-            .query(By::Css("li > a[data-episode-id]"))
-            .all_from_selector()
-            .await
-            .unwrap();
-        let mut max_episode = None;
-
-        for episode in episodes {
-            let number_text = episode.text().await.unwrap();
-
-            let Ok(number) = number_text.parse::<u32>() else {
-                log::trace!("Failed to parse episode as number: {}", number_text);
-                continue;
-            };
-
-            max_episode = match max_episode {
-                Some(old_max) => Some(number.max(old_max)),
-                None => Some(number),
-            };
-        }
+        let max_episode = get_available_episodes(self.driver).await.last().copied();
 
         Some(EpisodeInfo {
             name: episode_title,
@@ -473,10 +787,81 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
         current_season: u32,
         current_episode: u32,
     ) -> Result<(), anyhow::Error> {
-        let episode_info = self
-            .get_episode_info(current_season, current_episode)
+        let episode_info = with_scrape_timeout(
+            "getting the episode info",
+            self.get_episode_info(current_season, current_episode),
+        )
+        .await?
+        .context("failed to get episode info")?;
+        let (video_type, mut stream_candidates) =
+            with_scrape_timeout("getting the streams", self.get_stream_candidates()).await??;
+
+        // Checked first, since an archived episode is skipped, even if it has no matching streams anymore
+        let archive_key = episode_info.archive_key(&self.parsed_url.get_archive_name(), &video_type);
+
+        if self.settings.archive.contains(&archive_key) {
+            log::info!("Skipping {}, since it is in the archive", episode_info.season_episode());
+            self.settings.scrape_outcomes.record(ScrapeOutcome {
+                episode_info,
+                language: Some(video_type),
+                failure: None,
+            });
+            return Ok(());
+        }
+
+        dedup_candidates(&mut stream_candidates);
+
+        if let Some(only_extractor) = &self.settings.only_extractor {
+            retain_only_extractor(&mut stream_candidates, only_extractor)?;
+        }
+
+        let pending_episode = PendingEpisode {
+            episode_info,
+            language: video_type,
+            candidates: stream_candidates,
+            archive_key: Some(archive_key),
+        };
+
+        if let Some(pending_sender) = &self.pending_sender {
+            pending_sender.send(pending_episode).unwrap();
+            self.settings.maybe_ddos_wait().await;
+            return Ok(());
+        }
+
+        let extract_result =
+            extract_from_candidates(&pending_episode.candidates, self.settings.extractor_timeout).await;
+        self.settings.maybe_ddos_wait().await;
+        let extracted_candidate = match extract_result {
+            Ok(extracted_candidate) => extracted_candidate,
+            Err(err)
+                if record_unsupported_episode(
+                    &err,
+                    &pending_episode.episode_info,
+                    self.settings.strict,
+                    &self.settings.unsupported_episodes,
+                ) =>
+            {
+                self.settings.scrape_outcomes.record(ScrapeOutcome {
+                    episode_info: pending_episode.episode_info,
+                    language: Some(pending_episode.language),
+                    failure: Some(format!("{err:#}")),
+                });
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        self.sender
+            .send(DownloadTask::from_extracted_candidate(
+                pending_episode,
+                extracted_candidate,
+            ))
             .await
-            .context("failed to get episode info")?;
+            .unwrap();
+
+        Ok(())
+    }
+
+    async fn get_stream_candidates(&self) -> Result<(VideoType, Vec<StreamCandidate>), anyhow::Error> {
         let (video_type, lang_element) = self
             .get_language_element()
             .await
@@ -495,6 +880,7 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
         }
 
         let current_url = self.driver.current_url().await.unwrap();
+        let mut stream_candidates = Vec::with_capacity(available_streams.len());
 
         for stream in available_streams {
             let Some(link_target) = stream.attr("data-link-target").await.unwrap() else {
@@ -520,50 +906,422 @@ impl<'driver, 'url, F: FnMut() -> Duration> Scraper<'driver, 'url, F> {
                 .trim()
                 .to_owned();
 
-            log::trace!("Trying to use '{stream_platform_name}' stream server...");
+            stream_candidates.push(StreamCandidate {
+                platform_name: stream_platform_name,
+                url: redirect_link.as_str().to_owned(),
+                referer: Some(current_url.as_str().to_owned()),
+            });
+        }
 
-            let extracted_video = extract_video_url_with_extractor_from_url_unchecked(
-                redirect_link.as_str(),
-                &stream_platform_name,
-                None,
-                Some(current_url.as_str().to_owned()),
-            )
-            .await;
-
-            match extracted_video {
-                Some(Ok(extracted_video)) => {
-                    self.sender
-                        .send(DownloadTask::new(episode_info, video_type, extracted_video))
-                        .unwrap();
-                    self.settings.maybe_ddos_wait().await;
-                    return Ok(());
-                }
-                Some(Err(err)) => {
-                    log::trace!("Failed to extract video url from stream: {:#}", err);
-                    self.settings.maybe_ddos_wait().await;
+        Ok((video_type, stream_candidates))
+    }
+}
+
+/// Clicks the accept button of visible consent or age gates, which would
+/// otherwise hide the content of the page.
+async fn dismiss_page_gates(driver: &WebDriver) {
+    for attempt in 1..=PAGE_GATE_ATTEMPTS {
+        let mut any_visible = false;
+
+        for gate in PAGE_GATES {
+            if !is_visible(driver, gate.overlay).await {
+                continue;
+            }
+
+            any_visible = true;
+            let clicked = match driver.find(By::Css(gate.accept_button)).await {
+                Ok(button) => button.click().await.is_ok(),
+                Err(_) => false,
+            };
+
+            if clicked {
+                log::info!("Dismissed {} gate", gate.name);
+            } else {
+                log::debug!(
+                    "Failed to click accept button of {} gate ({}/{})",
+                    gate.name,
+                    attempt,
+                    PAGE_GATE_ATTEMPTS
+                );
+            }
+        }
+
+        if !any_visible {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    for gate in PAGE_GATES {
+        if is_visible(driver, gate.overlay).await {
+            log::warn!("Failed to dismiss {} gate, the page may be incomplete", gate.name);
+        }
+    }
+}
+
+async fn is_visible(driver: &WebDriver, selector: &str) -> bool {
+    let Ok(elements) = driver.find_all(By::Css(selector)).await else {
+        return false;
+    };
+
+    for element in elements {
+        if element.is_displayed().await.unwrap_or(false) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Seasons in the order to visit them, and whether their page has to be
+/// loaded, since only the one of `loaded_season` is loaded at first.
+fn get_season_visits(loaded_season: u32, seasons: &[u32]) -> Vec<(u32, bool)> {
+    seasons
+        .iter()
+        .enumerate()
+        .map(|(index, &season)| (season, index != 0 || season != loaded_season))
+        .collect()
+}
+
+async fn get_seasons_info(driver: &WebDriver) -> Result<SeasonsInfo, anyhow::Error> {
+    let season_elements = driver
+        .query(By::Css("#stream > ul:first-of-type > li"))
+        .all_from_selector()
+        .await
+        .unwrap();
+    let mut seasons = Vec::new();
+
+    for season in season_elements {
+        let text = season.text().await.unwrap();
+        let text = text.trim();
+
+        if text.eq_ignore_ascii_case("Filme") {
+            seasons.push(0);
+            continue;
+        }
+
+        let Ok(number) = text.parse::<u32>() else {
+            continue;
+        };
+
+        seasons.push(number);
+    }
+
+    if !seasons.iter().any(|&season| season != 0) {
+        anyhow::bail!("failed to find any season");
+    }
+
+    seasons.sort_unstable();
+    seasons.dedup();
+
+    Ok(SeasonsInfo { seasons })
+}
+
+/// Numbers of the episodes listed on the current season or episode page, in
+/// ascending order. There may be gaps, if episodes are missing on the site.
+async fn get_available_episodes(driver: &WebDriver) -> Vec<u32> {
+    let episode_elements = driver
+        .query(By::Css("li > a[data-episode-id]"))
+        .all_from_selector()
+        .await
+        .unwrap();
+    let mut episodes = Vec::new();
+
+    for episode in episode_elements {
+        let number_text = episode.text().await.unwrap();
+
+        let Ok(number) = number_text.parse::<u32>() else {
+            log::trace!("Failed to parse episode as number: {}", number_text);
+            continue;
+        };
+
+        episodes.push(number);
+    }
+
+    episodes.sort_unstable();
+    episodes.dedup();
+    episodes
+}
+
+async fn get_season_episodes(driver: &WebDriver) -> Result<Vec<SeasonEpisode>, anyhow::Error> {
+    let rows = driver
+        .query(By::Css("table.seasonEpisodesList tbody tr"))
+        .all_from_selector()
+        .await
+        .context("failed to find episode list")?;
+    let mut episodes = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let number = match row.find(By::Css(r#"meta[itemprop="episodeNumber"]"#)).await {
+            Ok(element) => element.attr("content").await.ok().flatten(),
+            Err(_) => None,
+        };
+        let Some(number) = number.and_then(|number| number.trim().parse::<u32>().ok()) else {
+            log::trace!("Failed to parse episode number in episode list");
+            continue;
+        };
+
+        let mut name = None;
+
+        for selector in [".seasonEpisodeTitle strong", ".seasonEpisodeTitle span"] {
+            if let Ok(element) = row.find(By::Css(selector)).await {
+                if let Ok(text) = element.text().await {
+                    let trimmed = text.trim();
+
+                    if !trimmed.is_empty() {
+                        name = Some(trimmed.to_owned());
+                        break;
+                    }
                 }
-                None => {
-                    log::trace!("Failed to find extractor for stream platform: {}", stream_platform_name);
+            }
+        }
+
+        let mut languages = Vec::new();
+
+        if let Ok(flags) = row.find_all(By::Css("img.flag")).await {
+            for flag in flags {
+                let Ok(Some(src)) = flag.attr("src").await else {
                     continue;
+                };
+
+                if let Some(language) = parse_flag_language(&src) {
+                    if !languages.contains(&language) {
+                        languages.push(language);
+                    }
                 }
             }
         }
 
-        anyhow::bail!("failed to get video url for episode")
+        episodes.push(SeasonEpisode {
+            number,
+            name,
+            languages,
+        });
+    }
+
+    Ok(episodes)
+}
+
+/// Maps the flag image of a language, e.g. `/public/img/japanese-german.svg`,
+/// to the video type. The first part is the audio, the second the subtitles.
+fn parse_flag_language(src: &str) -> Option<VideoType> {
+    let file_name = src.rsplit('/').next()?;
+    let flag = file_name.split('.').next()?.to_ascii_lowercase();
+
+    match flag.as_str() {
+        "german" => Some(VideoType::Dub(Language::German)),
+        "english" => Some(VideoType::Dub(Language::English)),
+        "japanese-german" | "english-german" => Some(VideoType::Sub(Language::German)),
+        "japanese-english" => Some(VideoType::Sub(Language::English)),
+        _ => None,
+    }
+}
+
+/// Maps the end of production, which is either a year, "Heute" or a status
+/// in German or English, to the status of the series.
+fn parse_series_status(end_date: &str) -> Option<SeriesStatus> {
+    let end_date = end_date.trim().to_lowercase();
+
+    match end_date.as_str() {
+        "heute" | "today" | "laufend" | "ongoing" | "airing" => Some(SeriesStatus::Airing),
+        "abgeschlossen" | "beendet" | "completed" | "finished" => Some(SeriesStatus::Completed),
+        "pausiert" | "on hiatus" | "hiatus" => Some(SeriesStatus::OnHiatus),
+        "abgebrochen" | "abgesetzt" | "cancelled" | "canceled" => Some(SeriesStatus::Cancelled),
+        _ if parse_year(&end_date).is_some() => Some(SeriesStatus::Completed),
+        _ => None,
+    }
+}
+
+/// Whether the page is an error page, or the site redirected away from the
+/// series, e.g. to the start page, because it does not exist.
+fn is_missing_series_page(page_title: &str, current_url: Option<&Url>, series_url: &str) -> bool {
+    let page_title = page_title.to_lowercase();
+
+    if NOT_FOUND_MARKERS.iter().any(|marker| page_title.contains(marker)) {
+        return true;
+    }
+
+    match (current_url, Url::parse(series_url)) {
+        (Some(current_url), Ok(series_url)) => !current_url
+            .path()
+            .to_lowercase()
+            .starts_with(&series_url.path().to_lowercase()),
+        _ => false,
     }
 }
 
+fn parse_year(text: &str) -> Option<u32> {
+    YEAR_REGEX.captures(text)?.get(1)?.as_str().parse().ok()
+}
+
+#[derive(Debug, Clone)]
+struct SeasonEpisode {
+    number: u32,
+    name: Option<String>,
+    languages: Vec<VideoType>,
+}
+
 #[derive(Debug, Clone)]
 struct SeasonsInfo {
-    has_season_zero: bool,
-    max_season: u32,
+    /// Available seasons in ascending order, where 0 are the movies
+    seasons: Vec<u32>,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{AniWorldSerienStream, ParsedUrlSeason, Site};
+    use std::time::Duration;
+
+    use url::Url;
+
+    use super::{
+        get_season_visits, is_missing_series_page, parse_flag_language, parse_series_status, parse_year,
+        AniWorldSerienStream, ParsedUrlSeason, Scraper, SelectorOverrides, Site,
+    };
     use crate::downloaders::aniworldserienstream::ParsedUrl;
-    use crate::downloaders::Downloader;
+    use crate::downloaders::{
+        AllOrSpecific, Downloader, Language, SeriesStatus, TypePreference, VideoType, RANGE_OPEN_END,
+    };
+
+    #[test]
+    fn test_parse_flag_language() {
+        assert_eq!(
+            parse_flag_language("/public/img/german.svg"),
+            Some(VideoType::Dub(Language::German))
+        );
+        assert_eq!(
+            parse_flag_language("/public/img/japanese-german.svg"),
+            Some(VideoType::Sub(Language::German))
+        );
+        assert_eq!(
+            parse_flag_language("/public/img/english-german.svg"),
+            Some(VideoType::Sub(Language::German))
+        );
+        assert_eq!(
+            parse_flag_language("/public/img/japanese-english.svg"),
+            Some(VideoType::Sub(Language::English))
+        );
+        assert_eq!(parse_flag_language("/public/img/unknown.svg"), None);
+    }
+
+    #[test]
+    fn test_parse_series_status() {
+        assert_eq!(parse_series_status("Heute"), Some(SeriesStatus::Airing));
+        assert_eq!(parse_series_status(" 2011 "), Some(SeriesStatus::Completed));
+        assert_eq!(parse_series_status("Abgeschlossen"), Some(SeriesStatus::Completed));
+        assert_eq!(parse_series_status("On Hiatus"), Some(SeriesStatus::OnHiatus));
+        assert_eq!(parse_series_status("Abgesetzt"), Some(SeriesStatus::Cancelled));
+        assert_eq!(parse_series_status("Unbekannt"), None);
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2011"), Some(2011));
+        assert_eq!(parse_year("Produktionsjahr 1998 - 2001"), Some(1998));
+        assert_eq!(parse_year("12345"), None);
+        assert_eq!(parse_year("Heute"), None);
+    }
+
+    #[test]
+    fn test_language_selector_overrides() {
+        let overrides = SelectorOverrides::from_toml("[aniworld]\nGerSub = '.german-sub'").unwrap();
+        let selector = |site: Site| {
+            let selectors = Scraper::<fn() -> Duration>::get_language_selectors(
+                &site,
+                &VideoType::Sub(Language::German),
+                None,
+                &overrides,
+            )
+            .unwrap();
+            format!("{:?}", selectors[0].1)
+        };
+
+        assert!(selector(Site::AniWorld).contains(".german-sub"));
+        assert!(!selector(Site::SerienStream).contains(".german-sub"));
+    }
+
+    #[test]
+    fn test_language_selector_order() {
+        let order = |site: Site, video_type: VideoType, prefer: Option<TypePreference>| {
+            Scraper::<fn() -> Duration>::get_language_selectors(&site, &video_type, prefer, &Default::default())
+                .unwrap()
+                .into_iter()
+                .map(|(video_type, _)| video_type.to_string())
+                .collect::<Vec<_>>()
+        };
+        let unspecified = VideoType::Unspecified(Language::Unspecified);
+
+        assert_eq!(
+            order(Site::AniWorld, unspecified, None),
+            ["GerDub", "GerSub", "EngSub", "EngDub"]
+        );
+        assert_eq!(
+            order(Site::SerienStream, unspecified, None),
+            ["GerDub", "GerSub", "EngDub", "EngSub"]
+        );
+
+        for site in [Site::AniWorld, Site::SerienStream] {
+            assert_eq!(
+                order(site, unspecified, Some(TypePreference::Dub)),
+                ["GerDub", "EngDub", "GerSub", "EngSub"]
+            );
+            assert_eq!(
+                order(site, unspecified, Some(TypePreference::Sub)),
+                ["GerSub", "EngSub", "GerDub", "EngDub"]
+            );
+            assert_eq!(
+                order(
+                    site,
+                    VideoType::Unspecified(Language::English),
+                    Some(TypePreference::Dub)
+                ),
+                ["EngDub", "EngSub"]
+            );
+            assert_eq!(
+                order(site, VideoType::Sub(Language::Unspecified), Some(TypePreference::Dub)),
+                ["GerSub", "EngSub"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_season_visits() {
+        // The movies come first, so the page of season 1 has to be loaded again
+        assert_eq!(get_season_visits(1, &[0, 1, 2]), [(0, true), (1, true), (2, true)]);
+        assert_eq!(get_season_visits(1, &[1, 2]), [(1, false), (2, true)]);
+        assert_eq!(get_season_visits(1, &[2, 3]), [(2, true), (3, true)]);
+        assert!(get_season_visits(1, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_is_missing_series_page() {
+        let series_url = "https://aniworld.to/anime/stream/yuruyuri-happy-go-lily";
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert!(!is_missing_series_page(
+            "YuruYuri Happy Go Lily | AniWorld.to",
+            Some(&url("https://aniworld.to/anime/stream/yuruyuri-happy-go-lily")),
+            series_url
+        ));
+        assert!(!is_missing_series_page(
+            "",
+            Some(&url(
+                "https://aniworld.to/anime/stream/YuruYuri-Happy-Go-Lily/staffel-1"
+            )),
+            series_url
+        ));
+        assert!(!is_missing_series_page("", None, series_url));
+        assert!(is_missing_series_page(
+            "404 - Seite nicht gefunden",
+            Some(&url("https://aniworld.to/anime/stream/yuruyuri-happy-go-lily")),
+            series_url
+        ));
+        assert!(is_missing_series_page(
+            "AniWorld.to",
+            Some(&url("https://aniworld.to/")),
+            series_url
+        ));
+    }
 
     #[tokio::test]
     async fn test_supports_url() {
@@ -635,4 +1393,54 @@ mod tests {
             assert_eq!(ParsedUrl::try_from(&*format!("{input}/")).unwrap(), output);
         }
     }
+
+    #[test]
+    fn test_restrict_to_season() {
+        let parsed_url = ParsedUrl::try_from("https://s.to/serie/stream/detektiv-conan/staffel-3").unwrap();
+        let seasons = parsed_url.restrict_to_season(Some(&AllOrSpecific::Specific(vec![3..=RANGE_OPEN_END])));
+        assert!(!seasons.contains(2));
+        assert!(seasons.contains(3));
+        assert!(seasons.contains(30));
+
+        let seasons = parsed_url.restrict_to_season(None);
+        assert!(seasons.contains(3));
+        assert!(!seasons.contains(4));
+
+        let parsed_url = ParsedUrl::try_from("https://s.to/serie/stream/detektiv-conan").unwrap();
+        assert_eq!(parsed_url.restrict_to_season(None), AllOrSpecific::All);
+    }
+
+    #[test]
+    fn test_episode_urls() {
+        let parsed_url = ParsedUrl::try_from("https://s.to/serie/stream/detektiv-conan").unwrap();
+
+        assert_eq!(
+            parsed_url.get_season_url(0),
+            "https://s.to/serie/stream/detektiv-conan/filme"
+        );
+        assert_eq!(
+            parsed_url.get_episode_url(0, 3),
+            "https://s.to/serie/stream/detektiv-conan/filme/film-3"
+        );
+        assert_eq!(
+            parsed_url.get_episode_url(2, 5),
+            "https://s.to/serie/stream/detektiv-conan/staffel-2/episode-5"
+        );
+
+        // The urls of the movies are parsed back into season 0
+        let movie_url = parsed_url.get_episode_url(0, 1);
+        assert_eq!(
+            ParsedUrl::try_from(&*movie_url).unwrap().season,
+            Some(ParsedUrlSeason {
+                season: 0,
+                episode: Some(1),
+            })
+        );
+        assert_eq!(
+            ParsedUrl::try_from("https://aniworld.to/anime/stream/detektiv-conan/filme")
+                .unwrap()
+                .get_episode_url(0, 2),
+            "https://aniworld.to/anime/stream/detektiv-conan/filme/film-2"
+        );
+    }
 }
@@ -1,17 +1,28 @@
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::fmt::Display;
 use std::num::NonZeroU32;
 use std::ops::{Deref, RangeInclusive};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use clap::ValueEnum;
 use enum_dispatch::enum_dispatch;
 use enum_iterator::Sequence;
-use tokio::sync::mpsc::UnboundedSender;
+use futures_util::StreamExt;
+use rand::seq::SliceRandom;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use self::aniworldserienstream::AniWorldSerienStream;
-use crate::extractors::ExtractedVideo;
+use self::selectors::SelectorOverrides;
+use crate::extractors::{extract_video_url_with_extractor_from_url_unchecked, normalized_name, ExtractedVideo};
 
 pub mod aniworldserienstream;
+pub mod selectors;
 
 macro_rules! enum_dispatch {
     {
@@ -81,7 +92,32 @@ create_functions_for_extractors! {
     AniWorldSerienStream,
 }
 
-#[derive(Debug, Clone)]
+/// Whether the url points directly to a media file, which can be downloaded
+/// without a series downloader or extractor.
+pub fn is_direct_media_url(url: &str) -> bool {
+    const MEDIA_EXTENSIONS: [&str; 7] = ["m3u8", "m3u", "mp4", "mkv", "webm", "mov", "ts"];
+
+    let Ok(url) = url::Url::parse(url) else {
+        return false;
+    };
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    let Some(file_name) = url.path_segments().and_then(|mut segments| segments.next_back()) else {
+        return false;
+    };
+
+    match file_name.rsplit_once('.') {
+        Some((_, extension)) => MEDIA_EXTENSIONS
+            .iter()
+            .any(|media_extension| extension.eq_ignore_ascii_case(media_extension)),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SeriesInfo {
     pub title: String,
     pub description: Option<String>,
@@ -89,7 +125,7 @@ pub struct SeriesInfo {
     pub year: Option<u32>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum SeriesStatus {
     Airing,
     Completed,
@@ -211,6 +247,21 @@ impl Language {
             Language::German => "German",
         }
     }
+
+    /// Whether the language matches a language tag like `de`, `ger` or `en-US`.
+    pub fn matches_language_tag(&self, language_tag: &str) -> bool {
+        let primary_tag = language_tag
+            .split(['-', '_'])
+            .next()
+            .unwrap_or(language_tag)
+            .to_ascii_lowercase();
+
+        match self {
+            Language::Unspecified => true,
+            Language::English => matches!(primary_tag.as_str(), "en" | "eng"),
+            Language::German => matches!(primary_tag.as_str(), "de" | "deu" | "ger"),
+        }
+    }
 }
 
 impl<'a> TryFrom<&'a str> for Language {
@@ -229,6 +280,14 @@ impl<'a> TryFrom<&'a str> for Language {
     }
 }
 
+/// Video type, which is tried first, if only the language or nothing is
+/// requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TypePreference {
+    Dub,
+    Sub,
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadRequest {
     pub language: VideoType,
@@ -240,8 +299,55 @@ pub enum EpisodesRequest {
     Unspecified,
     Episodes(AllOrSpecific),
     Seasons(AllOrSpecific),
+    /// Only the selected episodes within each of the selected seasons.
+    Combined {
+        seasons: AllOrSpecific,
+        episodes: AllOrSpecific,
+    },
+    /// Only the most recent of the episodes listed in the selected seasons,
+    /// e.g. of a series, which is still airing.
+    Recent {
+        /// Without them, the season of the url or all seasons
+        seasons: Option<AllOrSpecific>,
+        recent: RecentEpisodes,
+    },
+    /// Exactly these episodes, given as season and episode numbers, e.g. as
+    /// picked interactively from the listed ones.
+    Picked(Vec<(u32, u32)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecentEpisodes {
+    /// The given number of the last episodes
+    Latest(NonZeroU32),
+    /// All episodes after the given one
+    Since { season: u32, episode: u32 },
 }
 
+impl RecentEpisodes {
+    /// Selects from the listed episodes, given as season and episode numbers,
+    /// which are returned in order.
+    pub fn select(&self, mut episodes: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+        episodes.sort_unstable();
+        episodes.dedup();
+
+        match *self {
+            RecentEpisodes::Latest(latest) => {
+                let skip = episodes.len().saturating_sub(latest.get() as usize);
+                episodes.split_off(skip)
+            }
+            RecentEpisodes::Since { season, episode } => {
+                episodes.retain(|&listed| listed > (season, episode));
+                episodes
+            }
+        }
+    }
+}
+
+/// End of open ranges like `5-`. Such a range contains every number from its
+/// start on, so it is resolved by only scraping what is listed on the site.
+pub const RANGE_OPEN_END: u32 = u32::MAX - 1;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AllOrSpecific {
     All,
@@ -261,6 +367,35 @@ impl AllOrSpecific {
 pub struct DownloadSettings<F: FnMut() -> Duration> {
     pub ddos_wait_episodes: Option<NonZeroU32>,
     pub ddos_wait_time: F,
+    pub extract_retries: u32,
+    pub concurrent_extractions: NonZeroU32,
+    /// Maximum duration of extracting the video url from a single stream
+    pub extractor_timeout: Option<Duration>,
+    /// Archive keys of episodes, which are skipped.
+    pub archive: HashSet<String>,
+    /// Scrape the selected episodes in random order.
+    pub shuffle: bool,
+    /// Episodes, for which no stream platform has an extractor, are recorded
+    /// in `unsupported_episodes` instead of failing the download, so that
+    /// they can be told apart from other failures.
+    pub strict: bool,
+    /// Set once an episode failed in strict mode, since none of its stream
+    /// platforms has an extractor. Shared with the caller, who checks it
+    /// after the download.
+    pub unsupported_episodes: Arc<AtomicBool>,
+    /// Episodes, which are not sent to the downloader. Shared with the
+    /// caller, so that they are part of the summary.
+    pub scrape_outcomes: ScrapeOutcomes,
+    /// Episodes selected so far. Shared with the downloader for the ETA of
+    /// the whole series.
+    pub selected_episodes: SelectedEpisodes,
+    /// Overrides the order of video types of the site.
+    pub prefer: Option<TypePreference>,
+    /// Only the streams of this extractor are used, without falling back to
+    /// other stream platforms.
+    pub only_extractor: Option<String>,
+    /// Replace the compiled selectors of the sites.
+    pub selector_overrides: SelectorOverrides,
     counter: u32,
 }
 
@@ -269,10 +404,84 @@ impl<F: FnMut() -> Duration> DownloadSettings<F> {
         Self {
             ddos_wait_episodes,
             ddos_wait_time,
+            extract_retries: 5,
+            concurrent_extractions: NonZeroU32::MIN,
+            extractor_timeout: None,
+            archive: HashSet::new(),
+            shuffle: false,
+            strict: false,
+            unsupported_episodes: Arc::new(AtomicBool::new(false)),
+            scrape_outcomes: ScrapeOutcomes::default(),
+            selected_episodes: SelectedEpisodes::default(),
+            prefer: None,
+            only_extractor: None,
+            selector_overrides: SelectorOverrides::default(),
             counter: 0,
         }
     }
 
+    pub fn extract_retries(mut self, extract_retries: u32) -> Self {
+        self.extract_retries = extract_retries;
+        self
+    }
+
+    pub fn concurrent_extractions(mut self, concurrent_extractions: NonZeroU32) -> Self {
+        self.concurrent_extractions = concurrent_extractions;
+        self
+    }
+
+    pub fn extractor_timeout(mut self, extractor_timeout: Option<Duration>) -> Self {
+        self.extractor_timeout = extractor_timeout;
+        self
+    }
+
+    pub fn archive(mut self, archive: HashSet<String>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn unsupported_episodes(mut self, unsupported_episodes: Arc<AtomicBool>) -> Self {
+        self.unsupported_episodes = unsupported_episodes;
+        self
+    }
+
+    pub fn scrape_outcomes(mut self, scrape_outcomes: ScrapeOutcomes) -> Self {
+        self.scrape_outcomes = scrape_outcomes;
+        self
+    }
+
+    pub fn prefer(mut self, prefer: Option<TypePreference>) -> Self {
+        self.prefer = prefer;
+        self
+    }
+
+    pub fn only_extractor(mut self, only_extractor: Option<String>) -> Self {
+        self.only_extractor = only_extractor;
+        self
+    }
+
+    pub fn selector_overrides(mut self, selector_overrides: SelectorOverrides) -> Self {
+        self.selector_overrides = selector_overrides;
+        self
+    }
+
+    /// Shuffles the items, if the episodes are scraped in random order.
+    fn maybe_shuffle<T>(&self, items: &mut [T]) {
+        if self.shuffle {
+            items.shuffle(&mut rand::thread_rng());
+        }
+    }
+
     async fn maybe_ddos_wait(&mut self) {
         if let Some(counter_match) = &self.ddos_wait_episodes {
             self.counter += 1;
@@ -291,6 +500,11 @@ pub struct DownloadTask {
     pub language: VideoType,
     pub download_url: String,
     pub referer: Option<String>,
+    pub extractor: Option<String>,
+    /// Streams to try, if downloading from the extracted one fails.
+    pub fallback_candidates: Vec<StreamCandidate>,
+    /// Stable id of the episode, which is recorded after a successful download.
+    pub archive_key: Option<String>,
 }
 
 impl DownloadTask {
@@ -300,8 +514,76 @@ impl DownloadTask {
             language,
             download_url: extracted_video.url,
             referer: extracted_video.referer,
+            extractor: None,
+            fallback_candidates: vec![],
+            archive_key: None,
         }
     }
+
+    pub fn from_extracted_candidate(pending_episode: PendingEpisode, extracted_candidate: ExtractedCandidate) -> Self {
+        Self::new(
+            pending_episode.episode_info,
+            pending_episode.language,
+            extracted_candidate.video,
+        )
+        .extractor(Some(extracted_candidate.extractor))
+        .fallback_candidates(extracted_candidate.remaining_candidates)
+        .archive_key(pending_episode.archive_key)
+    }
+
+    pub fn extractor(mut self, extractor: Option<String>) -> Self {
+        self.extractor = extractor;
+        self
+    }
+
+    pub fn fallback_candidates(mut self, fallback_candidates: Vec<StreamCandidate>) -> Self {
+        self.fallback_candidates = fallback_candidates;
+        self
+    }
+
+    pub fn archive_key(mut self, archive_key: Option<String>) -> Self {
+        self.archive_key = archive_key;
+        self
+    }
+}
+
+impl Serialize for DownloadTask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DownloadTask", 6)?;
+        state.serialize_field("season_number", &self.episode_info.season_number)?;
+        state.serialize_field("episode_number", &self.episode_info.episode_number)?;
+        state.serialize_field("language", &self.language.to_string())?;
+        state.serialize_field("download_url", &self.download_url)?;
+        state.serialize_field("referer", &self.referer)?;
+        state.serialize_field("extractor", &self.extractor)?;
+        state.end()
+    }
+}
+
+/// A stream platform of an episode, which still has to be extracted.
+#[derive(Debug, Clone)]
+pub struct StreamCandidate {
+    pub platform_name: String,
+    pub url: String,
+    pub referer: Option<String>,
+}
+
+/// A successfully extracted stream candidate.
+#[derive(Debug, Clone)]
+pub struct ExtractedCandidate {
+    pub video: ExtractedVideo,
+    pub extractor: String,
+    /// The candidates after the extracted one, in order.
+    pub remaining_candidates: Vec<StreamCandidate>,
+}
+
+/// An episode whose stream candidates are known, but not yet extracted.
+#[derive(Debug, Clone)]
+pub struct PendingEpisode {
+    pub episode_info: EpisodeInfo,
+    pub language: VideoType,
+    pub candidates: Vec<StreamCandidate>,
+    pub archive_key: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -312,21 +594,96 @@ pub struct EpisodeInfo {
     pub max_episode_number_in_season: Option<u32>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl EpisodeInfo {
+    pub fn season_episode(&self) -> String {
+        let episode = match &self.episode_number {
+            EpisodeNumber::Number(number) => format!("{number:03}"),
+            EpisodeNumber::String(string) => string.clone(),
+        };
+
+        match self.season_number {
+            Some(season) => format!("S{season:02}E{episode}"),
+            None => format!("E{episode}"),
+        }
+    }
+
+    /// Stable id of the episode in the given language, e.g. `aniworld detektiv-conan S01E002 GerDub`.
+    pub fn archive_key(&self, series_key: &str, language: &VideoType) -> String {
+        format!("{} {} {}", series_key, self.season_episode(), language)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
 pub enum EpisodeNumber {
     Number(u32),
     String(String),
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesStructure {
+    pub seasons: Vec<SeasonStructure>,
+}
+
+impl SeriesStructure {
+    pub fn from_listed_seasons(seasons: &[ListedSeason]) -> Self {
+        let seasons = seasons
+            .iter()
+            .map(|season| SeasonStructure {
+                season_number: season.season_number,
+                episode_count: season.episodes.len(),
+                episodes: season
+                    .episodes
+                    .iter()
+                    .map(|episode| EpisodeStructure {
+                        episode_number: episode.episode_info.episode_number.clone(),
+                        name: episode.episode_info.name.clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        SeriesStructure { seasons }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeasonStructure {
+    pub season_number: u32,
+    pub episode_count: usize,
+    pub episodes: Vec<EpisodeStructure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EpisodeStructure {
+    pub episode_number: EpisodeNumber,
+    pub name: Option<String>,
+}
+
+/// An episode as listed on the site, with the languages it is available in.
+#[derive(Debug, Clone)]
+pub struct ListedEpisode {
+    pub episode_info: EpisodeInfo,
+    pub languages: Vec<VideoType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListedSeason {
+    pub season_number: u32,
+    pub episodes: Vec<ListedEpisode>,
+}
+
 #[enum_dispatch]
 pub trait InstantiatedDownloader {
     async fn get_series_info(&self) -> Result<SeriesInfo, anyhow::Error>;
 
+    async fn list_episodes(&self) -> Result<Vec<ListedSeason>, anyhow::Error>;
+
     async fn download<F: FnMut() -> Duration>(
         &self,
         request: DownloadRequest,
         settings: DownloadSettings<F>,
-        sender: UnboundedSender<DownloadTask>,
+        sender: Sender<DownloadTask>,
     ) -> Result<(), anyhow::Error>;
 }
 
@@ -336,12 +693,343 @@ pub trait Downloader<'driver>: InstantiatedDownloader {
     async fn supports_url(url: &str) -> bool;
 }
 
+/// Tries the candidates in order and returns the first successfully
+/// extracted one.
+/// An extraction exceeding the timeout counts as failed. Retryable errors,
+/// e.g. a failed request, are retried once before moving on.
+/// If no candidate has an extractor, a [`NoExtractorError`] is returned.
+pub async fn extract_from_candidates(
+    candidates: &[StreamCandidate],
+    timeout: Option<Duration>,
+) -> Result<ExtractedCandidate, anyhow::Error> {
+    let mut unsupported_platforms = vec![];
+
+    'candidates: for (index, candidate) in candidates.iter().enumerate() {
+        log::trace!("Trying to use '{}' stream server...", candidate.platform_name);
+
+        let mut retried = false;
+
+        loop {
+            let extract_future = extract_video_url_with_extractor_from_url_unchecked(
+                &candidate.url,
+                &candidate.platform_name,
+                None,
+                candidate.referer.clone(),
+            );
+            let extracted_video = match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, extract_future).await {
+                    Ok(extracted_video) => extracted_video,
+                    Err(_) => {
+                        log::info!(
+                            "Timed out after {}s extracting video url from stream: {}",
+                            timeout.as_secs(),
+                            candidate.platform_name
+                        );
+                        continue 'candidates;
+                    }
+                },
+                None => extract_future.await,
+            };
+
+            match extracted_video {
+                Some(Ok(extracted_video)) => {
+                    if !unsupported_platforms.is_empty() {
+                        log::debug!(
+                            "Skipped stream platforms without extractor: {}",
+                            unsupported_platforms.join(", ")
+                        );
+                    }
+
+                    let extractor_name = normalized_name(&candidate.platform_name).unwrap_or(&candidate.platform_name);
+                    return Ok(ExtractedCandidate {
+                        video: extracted_video,
+                        extractor: extractor_name.to_string(),
+                        remaining_candidates: candidates[index + 1..].to_vec(),
+                    });
+                }
+                Some(Err(err)) => {
+                    let retry = !retried && err.is_retryable();
+                    log::trace!(
+                        "Failed to extract video url from stream: {:#}",
+                        anyhow::Error::from(err)
+                    );
+
+                    if retry {
+                        log::trace!("Retrying '{}' stream server...", candidate.platform_name);
+                        retried = true;
+                        continue;
+                    }
+                }
+                None => {
+                    log::trace!(
+                        "Failed to find extractor for stream platform: {}",
+                        candidate.platform_name
+                    );
+                    unsupported_platforms.push(candidate.platform_name.clone());
+                }
+            }
+
+            break;
+        }
+    }
+
+    if !candidates.is_empty() && unsupported_platforms.len() == candidates.len() {
+        return Err(NoExtractorError {
+            platforms: unsupported_platforms,
+        }
+        .into());
+    }
+
+    if unsupported_platforms.is_empty() {
+        anyhow::bail!("failed to get video url for episode")
+    } else {
+        anyhow::bail!(
+            "failed to get video url for episode, no extractor for: {}",
+            unsupported_platforms.join(", ")
+        )
+    }
+}
+
+/// Keeps the first stream of each platform in the order of the site, since
+/// the mirrors of a platform usually fail for the same reason.
+fn dedup_candidates(candidates: &mut Vec<StreamCandidate>) {
+    let mut seen_platforms = HashSet::new();
+
+    candidates.retain(|candidate| {
+        let platform = match normalized_name(&candidate.platform_name) {
+            Some(extractor_name) => extractor_name.to_owned(),
+            None => candidate.platform_name.to_ascii_lowercase(),
+        };
+
+        seen_platforms.insert(platform)
+    });
+}
+
+/// Keeps only the streams of the extractor. Fails, if the episode has none,
+/// since other stream platforms must not be used instead.
+fn retain_only_extractor(candidates: &mut Vec<StreamCandidate>, extractor: &str) -> Result<(), anyhow::Error> {
+    let extractor_name = normalized_name(extractor).unwrap_or(extractor);
+    let platforms = candidates
+        .iter()
+        .map(|candidate| candidate.platform_name.clone())
+        .collect::<Vec<_>>();
+
+    candidates.retain(|candidate| normalized_name(&candidate.platform_name) == Some(extractor_name));
+
+    if candidates.is_empty() {
+        anyhow::bail!(
+            "no stream of {} available, only of: {}",
+            extractor_name,
+            platforms.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// None of the stream platforms of an episode has an extractor.
+#[derive(Debug)]
+pub struct NoExtractorError {
+    pub platforms: Vec<String>,
+}
+
+impl Display for NoExtractorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no extractor for any stream platform: {}", self.platforms.join(", "))
+    }
+}
+
+impl std::error::Error for NoExtractorError {}
+
+/// An episode, which never reached the downloader.
+#[derive(Debug, Clone)]
+pub struct ScrapeOutcome {
+    pub episode_info: EpisodeInfo,
+    /// Unknown, if scraping failed before the language was selected
+    pub language: Option<VideoType>,
+    /// The reason, or `None` if the episode was skipped, since it is in the archive
+    pub failure: Option<String>,
+}
+
+/// Shared list of the episodes, which were skipped or failed while scraping.
+#[derive(Debug, Clone, Default)]
+pub struct ScrapeOutcomes(Arc<Mutex<Vec<ScrapeOutcome>>>);
+
+impl ScrapeOutcomes {
+    pub fn record(&self, outcome: ScrapeOutcome) {
+        self.0.lock().unwrap().push(outcome);
+    }
+
+    /// Returns the recorded outcomes, and clears them.
+    pub fn take(&self) -> Vec<ScrapeOutcome> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Shared number of the episodes selected while scraping, which grows with
+/// each listed season. The downloader estimates the time left for the whole
+/// series from it.
+#[derive(Debug, Clone, Default)]
+pub struct SelectedEpisodes(Arc<AtomicUsize>);
+
+impl SelectedEpisodes {
+    pub fn add(&self, count: usize) {
+        self.0.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns `None`, until the first episodes are selected.
+    pub fn get(&self) -> Option<usize> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|&count| count != 0)
+    }
+}
+
+/// Returns whether the error is a [`NoExtractorError`] in strict mode, in
+/// which case the episode is logged as failed and recorded in
+/// `unsupported_episodes`, instead of failing like other errors.
+fn record_unsupported_episode(
+    err: &anyhow::Error,
+    episode_info: &EpisodeInfo,
+    strict: bool,
+    unsupported_episodes: &AtomicBool,
+) -> bool {
+    let Some(err) = err.downcast_ref::<NoExtractorError>().filter(|_| strict) else {
+        return false;
+    };
+
+    log::error!("Failed to get video url for {}: {}", episode_info.season_episode(), err);
+    unsupported_episodes.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Extracts the pending episodes concurrently and sends them to the
+/// downloader. Returns whether extracting any episode failed.
+pub async fn extract_pending_episodes(
+    rx_stream: UnboundedReceiverStream<PendingEpisode>,
+    sender: Sender<DownloadTask>,
+    max_concurrent: usize,
+    timeout: Option<Duration>,
+    strict: bool,
+    unsupported_episodes: &AtomicBool,
+    scrape_outcomes: &ScrapeOutcomes,
+) -> bool {
+    let got_error = Cell::new(false);
+    let sender = &sender;
+    let got_error_borrowed = &got_error;
+
+    rx_stream
+        .for_each_concurrent(max_concurrent, move |pending_episode| async move {
+            match extract_from_candidates(&pending_episode.candidates, timeout).await {
+                Ok(extracted_candidate) => {
+                    sender
+                        .send(DownloadTask::from_extracted_candidate(
+                            pending_episode,
+                            extracted_candidate,
+                        ))
+                        .await
+                        .unwrap();
+                }
+                Err(err) => {
+                    if !record_unsupported_episode(&err, &pending_episode.episode_info, strict, unsupported_episodes) {
+                        log::warn!(
+                            "Failed to get video url for {}: {:#}",
+                            pending_episode.episode_info.season_episode(),
+                            err
+                        );
+                        got_error_borrowed.set(true);
+                    }
+
+                    scrape_outcomes.record(ScrapeOutcome {
+                        episode_info: pending_episode.episode_info,
+                        language: Some(pending_episode.language),
+                        failure: Some(format!("{err:#}")),
+                    });
+                }
+            }
+        })
+        .await;
+
+    got_error.get()
+}
+
 pub mod utils {
+    use std::fmt::{Display, Formatter};
+    use std::future::Future;
+    use std::ops::RangeInclusive;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::time::Duration;
 
     use rand::distributions::uniform::SampleRange;
     use rand::Rng;
 
+    static PAGE_LOAD_WAIT_MIN_MS: AtomicU64 = AtomicU64::new(1000);
+    static PAGE_LOAD_WAIT_MAX_MS: AtomicU64 = AtomicU64::new(2000);
+    /// Zero means that there is no timeout.
+    static SCRAPE_TIMEOUT_MS: AtomicU64 = AtomicU64::new(0);
+
+    /// A navigation or element query of a site did not finish in time.
+    #[derive(Debug)]
+    pub struct ScrapeTimeoutError {
+        operation: String,
+        timeout: Duration,
+    }
+
+    impl Display for ScrapeTimeoutError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "timed out after {}s while {}",
+                self.timeout.as_secs_f64(),
+                self.operation
+            )
+        }
+    }
+
+    impl std::error::Error for ScrapeTimeoutError {}
+
+    /// Sets the time a single navigation or element query of a site may take.
+    pub fn set_scrape_timeout(timeout: Option<Duration>) {
+        let ms = timeout.map_or(0, |timeout| timeout.as_millis().try_into().unwrap_or(u64::MAX));
+        SCRAPE_TIMEOUT_MS.store(ms, Ordering::Relaxed);
+    }
+
+    /// Runs the operation, but fails if it takes longer than the scrape
+    /// timeout, since the browser may hang on a site without ever erroring.
+    pub async fn with_scrape_timeout<F: Future>(operation: &str, future: F) -> Result<F::Output, ScrapeTimeoutError> {
+        let ms = SCRAPE_TIMEOUT_MS.load(Ordering::Relaxed);
+        let timeout = (ms != 0).then(|| Duration::from_millis(ms));
+        with_timeout(operation, timeout, future).await
+    }
+
+    /// Like [`with_scrape_timeout`], but with the given timeout.
+    pub(crate) async fn with_timeout<F: Future>(
+        operation: &str,
+        timeout: Option<Duration>,
+        future: F,
+    ) -> Result<F::Output, ScrapeTimeoutError> {
+        let Some(timeout) = timeout else {
+            return Ok(future.await);
+        };
+
+        tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| ScrapeTimeoutError {
+                operation: operation.to_owned(),
+                timeout,
+            })
+    }
+
+    /// Sets the range in milliseconds to wait for a page to load after navigating.
+    pub fn set_page_load_wait(ms_range: RangeInclusive<u64>) {
+        PAGE_LOAD_WAIT_MIN_MS.store(*ms_range.start(), Ordering::Relaxed);
+        PAGE_LOAD_WAIT_MAX_MS.store(*ms_range.end(), Ordering::Relaxed);
+    }
+
+    pub async fn sleep_page_load() {
+        let min = PAGE_LOAD_WAIT_MIN_MS.load(Ordering::Relaxed);
+        let max = PAGE_LOAD_WAIT_MAX_MS.load(Ordering::Relaxed);
+        sleep_random(min..=max).await
+    }
+
     pub async fn sleep_random<R: SampleRange<u64>>(ms_range: R) {
         if ms_range.is_empty() {
             return;
@@ -353,13 +1041,159 @@ pub mod utils {
     }
 
     pub async fn sleep_jitter(ms_sleep: u64, ms_jitter: u64) {
+        tokio::time::sleep(jitter_duration(ms_sleep, ms_jitter)).await
+    }
+
+    /// Random duration in `ms_sleep ± ms_jitter` milliseconds.
+    pub fn jitter_duration(ms_sleep: u64, ms_jitter: u64) -> Duration {
         let min = ms_sleep.saturating_sub(ms_jitter);
         let max = ms_sleep.saturating_add(ms_jitter);
 
-        if min == max && min != 0 {
-            tokio::time::sleep(Duration::from_millis(min)).await;
+        if min == max {
+            Duration::from_millis(min)
         } else {
-            sleep_random(min..=max).await
+            Duration::from_millis(rand::thread_rng().gen_range(min..=max))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    use crate::downloaders::utils::with_timeout;
+    use crate::downloaders::{
+        dedup_candidates, extract_from_candidates, retain_only_extractor, DownloadSettings, NoExtractorError,
+        RecentEpisodes, StreamCandidate,
+    };
+
+    #[test]
+    fn test_select_recent_episodes() {
+        let episodes = vec![(2, 1), (1, 12), (2, 3), (0, 1), (2, 2), (1, 11)];
+
+        assert_eq!(
+            RecentEpisodes::Latest(NonZeroU32::new(3).unwrap()).select(episodes.clone()),
+            [(2, 1), (2, 2), (2, 3)]
+        );
+        assert_eq!(
+            RecentEpisodes::Latest(NonZeroU32::new(10).unwrap()).select(episodes.clone()),
+            [(0, 1), (1, 11), (1, 12), (2, 1), (2, 2), (2, 3)]
+        );
+        assert_eq!(
+            RecentEpisodes::Since { season: 1, episode: 12 }.select(episodes.clone()),
+            [(2, 1), (2, 2), (2, 3)]
+        );
+        assert!(RecentEpisodes::Since { season: 2, episode: 3 }
+            .select(episodes)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_maybe_shuffle() {
+        let episodes: Vec<u32> = (1..=100).collect();
+
+        let mut unshuffled = episodes.clone();
+        DownloadSettings::new(None, || Duration::ZERO).maybe_shuffle(&mut unshuffled);
+        assert_eq!(unshuffled, episodes);
+
+        let mut shuffled = episodes.clone();
+        DownloadSettings::new(None, || Duration::ZERO)
+            .shuffle(true)
+            .maybe_shuffle(&mut shuffled);
+        assert_ne!(shuffled, episodes);
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, episodes);
+    }
+
+    #[tokio::test]
+    async fn test_no_extractor() {
+        let candidates: Vec<_> = ["UnknownHoster", "OtherHoster"]
+            .into_iter()
+            .map(|platform_name| StreamCandidate {
+                platform_name: platform_name.to_owned(),
+                url: "https://example.com/e/abc".to_owned(),
+                referer: None,
+            })
+            .collect();
+
+        let err = extract_from_candidates(&candidates, None).await.unwrap_err();
+        let err = err.downcast_ref::<NoExtractorError>().unwrap();
+        assert_eq!(err.platforms, ["UnknownHoster", "OtherHoster"]);
+
+        let err = extract_from_candidates(&[], None).await.unwrap_err();
+        assert!(err.downcast_ref::<NoExtractorError>().is_none());
+    }
+
+    #[test]
+    fn test_retain_only_extractor() {
+        let candidates: Vec<_> = ["Vidoza", "VOE", "UnknownHoster"]
+            .into_iter()
+            .map(|platform_name| StreamCandidate {
+                platform_name: platform_name.to_owned(),
+                url: "https://example.com/e/abc".to_owned(),
+                referer: None,
+            })
+            .collect();
+
+        let mut voe_candidates = candidates.clone();
+        retain_only_extractor(&mut voe_candidates, "voe").unwrap();
+        assert_eq!(voe_candidates.len(), 1);
+        assert_eq!(voe_candidates[0].platform_name, "VOE");
+
+        let mut filemoon_candidates = candidates;
+        let err = retain_only_extractor(&mut filemoon_candidates, "filemoon").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no stream of Filemoon available, only of: Vidoza, VOE, UnknownHoster"
+        );
+    }
+
+    #[test]
+    fn test_dedup_candidates() {
+        let mut candidates: Vec<_> = [
+            ("VOE", "voe1"),
+            ("Vidoza", "vidoza1"),
+            ("Voe", "voe2"),
+            ("UnknownHoster", "unknown1"),
+            ("Filemoon", "filemoon1"),
+            ("unknownhoster", "unknown2"),
+            ("MoonF", "filemoon2"),
+            ("VOE", "voe3"),
+        ]
+        .into_iter()
+        .map(|(platform_name, path)| StreamCandidate {
+            platform_name: platform_name.to_owned(),
+            url: format!("https://aniworld.to/redirect/{path}"),
+            referer: None,
+        })
+        .collect();
+
+        dedup_candidates(&mut candidates);
+
+        let urls: Vec<_> = candidates.iter().map(|candidate| candidate.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://aniworld.to/redirect/voe1",
+                "https://aniworld.to/redirect/vidoza1",
+                "https://aniworld.to/redirect/unknown1",
+                "https://aniworld.to/redirect/filemoon1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_timeout() {
+        let timeout = Some(Duration::from_millis(50));
+
+        assert_eq!(with_timeout("loading", timeout, async { 1 }).await.unwrap(), 1);
+        let err = with_timeout("going to the episode page", timeout, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "timed out after 0.05s while going to the episode page");
+
+        let slow = tokio::time::sleep(Duration::from_millis(100));
+        assert!(with_timeout("loading", None, slow).await.is_ok());
+    }
+}
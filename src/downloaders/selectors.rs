@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// Name of the file in the data directory, which overrides selectors.
+pub const SELECTORS_FILE_NAME: &str = "selectors.toml";
+
+/// CSS selectors, which replace the compiled defaults of a site, so that
+/// changes of the site markup can be patched without a new release.
+///
+/// Each site has its own table, whose keys are the language names also used
+/// in the file names, e.g.:
+/// ```toml
+/// [aniworld]
+/// GerSub = 'div.changeLanguageBox > img[title*="Untertitel Deutsch"]'
+///
+/// [sto]
+/// EngDub = 'div.changeLanguageBox > img[title="Englisch"]'
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct SelectorOverrides {
+    sites: HashMap<String, HashMap<String, String>>,
+}
+
+impl SelectorOverrides {
+    pub fn from_toml(content: &str) -> Result<Self, anyhow::Error> {
+        toml::from_str(content).context("failed to parse selectors")
+    }
+
+    /// Selector of the site for the key, if it is overridden.
+    pub fn get(&self, site: &str, key: &str) -> Option<&str> {
+        self.sites.get(site)?.get(key).map(String::as_str)
+    }
+}
+
+/// Reads the overrides from the data directory. A missing file is treated as
+/// empty.
+pub(crate) async fn load(data_dir: &Path) -> Result<SelectorOverrides, anyhow::Error> {
+    let path = data_dir.join(SELECTORS_FILE_NAME);
+    let content = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(SelectorOverrides::default()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+
+    SelectorOverrides::from_toml(&content).with_context(|| format!("invalid {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downloaders::selectors::SelectorOverrides;
+
+    #[test]
+    fn test_selector_overrides() {
+        let overrides = SelectorOverrides::from_toml(
+            r#"
+            [aniworld]
+            GerSub = 'img[title="Deutsch"]'
+            EngSub = ".english"
+
+            [sto]
+            EngDub = ".dub"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(overrides.get("aniworld", "GerSub"), Some(r#"img[title="Deutsch"]"#));
+        assert_eq!(overrides.get("aniworld", "EngSub"), Some(".english"));
+        assert_eq!(overrides.get("aniworld", "EngDub"), None);
+        assert_eq!(overrides.get("sto", "EngDub"), Some(".dub"));
+        assert_eq!(overrides.get("unknown", "EngDub"), None);
+
+        assert!(SelectorOverrides::from_toml("")
+            .unwrap()
+            .get("aniworld", "GerSub")
+            .is_none());
+        assert!(SelectorOverrides::from_toml("[aniworld]\nGerSub = 1").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use crate::downloaders::{EpisodeInfo, EpisodeNumber};
+
+/// Command, which is run after each successful download, e.g. `notify-send {}`.
+///
+/// The command is split into arguments like a shell would, but without
+/// running one, so that the substituted values never need to be quoted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExecCommand {
+    args: Vec<String>,
+}
+
+impl ExecCommand {
+    /// Runs the command for a downloaded file, and fails if it exits with a
+    /// non-zero code.
+    pub(crate) async fn run(
+        &self,
+        file_path: &Path,
+        series: &str,
+        episode_info: &EpisodeInfo,
+    ) -> Result<(), anyhow::Error> {
+        let args = self.render(file_path, series, episode_info);
+        let output = tokio::process::Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .with_context(|| format!("failed to run \"{}\"", args[0]))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+
+            if stderr.is_empty() {
+                anyhow::bail!("command exited with {}", output.status);
+            } else {
+                anyhow::bail!("command exited with {}: {}", output.status, stderr);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(&self, file_path: &Path, series: &str, episode_info: &EpisodeInfo) -> Vec<String> {
+        let season = episode_info
+            .season_number
+            .map(|season| season.to_string())
+            .unwrap_or_default();
+        let episode = match &episode_info.episode_number {
+            EpisodeNumber::Number(number) => number.to_string(),
+            EpisodeNumber::String(string) => string.clone(),
+        };
+
+        self.args
+            .iter()
+            .map(|arg| {
+                arg.replace("{series}", series)
+                    .replace("{season}", &season)
+                    .replace("{episode}", &episode)
+                    .replace("{}", &file_path.to_string_lossy())
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ExecCommand {
+    type Err = String;
+
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        let args = split_args(command)?;
+
+        if args.is_empty() {
+            return Err("command is empty".to_owned());
+        }
+
+        Ok(Self { args })
+    }
+}
+
+/// Splits at whitespace outside of single or double quotes. A backslash
+/// escapes the next character, except within single quotes.
+pub(crate) fn split_args(command: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => current.get_or_insert_with(String::new).push(c),
+            (_, '\\') => match chars.next() {
+                Some(escaped) => current.get_or_insert_with(String::new).push(escaped),
+                None => return Err("command ends with a backslash".to_owned()),
+            },
+            (Some(_), c) => current.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(current.take()),
+            (None, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err("unclosed quote in command".to_owned());
+    }
+
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::downloaders::{EpisodeInfo, EpisodeNumber};
+    use crate::exec::{split_args, ExecCommand};
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("cp  {} /mnt/media").unwrap(), ["cp", "{}", "/mnt/media"]);
+        assert_eq!(
+            split_args(r#"sh -c 'echo "$0"' "two words" a\ b ''"#).unwrap(),
+            ["sh", "-c", "echo \"$0\"", "two words", "a b", ""]
+        );
+        assert!(split_args("echo 'unclosed").is_err());
+        assert!("   ".parse::<ExecCommand>().is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let command = "scan --show {series} --season={season} {episode} {}"
+            .parse::<ExecCommand>()
+            .unwrap();
+        let episode_info = EpisodeInfo {
+            name: None,
+            season_number: Some(2),
+            episode_number: EpisodeNumber::Number(5),
+            max_episode_number_in_season: None,
+        };
+        assert_eq!(
+            command.render(Path::new("/videos/Dark S02E05.mp4"), "Dark", &episode_info),
+            ["scan", "--show", "Dark", "--season=2", "5", "/videos/Dark S02E05.mp4"]
+        );
+    }
+}
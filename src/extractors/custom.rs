@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use serde::Deserialize;
+use url::Url;
+
+use super::{ExtractError, ExtractFrom, ExtractedVideo};
+
+/// Name of the file in the data directory, which defines extractors.
+pub const EXTRACTORS_FILE_NAME: &str = "extractors.toml";
+
+static EXTRACTORS: OnceCell<CustomExtractors> = OnceCell::new();
+
+/// Extractors for simple hosts, which only need the video url to be found in
+/// the page source, so that they can be added without a new release.
+///
+/// Each extractor is an entry of the `extractor` array, e.g.:
+/// ```toml
+/// [[extractor]]
+/// name = "Examplehost"
+/// host = '(www\.)?examplehost\.(com|net)'
+/// source = 'file:\s*"(?P<url>[^"]+)"'
+/// referer = "https://examplehost.com/"
+/// ```
+#[derive(Debug, Default)]
+pub struct CustomExtractors {
+    extractors: Vec<CustomExtractor>,
+}
+
+#[derive(Debug)]
+pub struct CustomExtractor {
+    name: String,
+    /// Has to match the whole host of the url
+    host: Regex,
+    /// Has a capture group named `url` for the video url
+    source: Regex,
+    /// Sent when downloading the video, instead of the url of the page
+    referer: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomExtractorsConfig {
+    #[serde(default, rename = "extractor")]
+    extractors: Vec<CustomExtractorConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomExtractorConfig {
+    name: String,
+    host: String,
+    source: String,
+    referer: Option<String>,
+}
+
+impl CustomExtractors {
+    pub fn from_toml(content: &str) -> Result<Self, anyhow::Error> {
+        let config: CustomExtractorsConfig = toml::from_str(content).context("failed to parse extractors")?;
+        let mut names = HashSet::new();
+        let mut extractors = Vec::with_capacity(config.extractors.len());
+
+        for config in config.extractors {
+            let name = config.name;
+
+            if !names.insert(name.to_ascii_lowercase()) {
+                anyhow::bail!("extractor {name} is defined twice");
+            }
+
+            // Only the built-in extractors are known, as long as none are set
+            if super::normalized_name(&name).is_some() {
+                anyhow::bail!("extractor {name} has the name of a built-in extractor");
+            }
+
+            let host = Regex::new(&format!("^(?i:{})$", config.host))
+                .with_context(|| format!("invalid host pattern of extractor {name}"))?;
+            let source =
+                Regex::new(&config.source).with_context(|| format!("invalid source pattern of extractor {name}"))?;
+
+            if !source.capture_names().any(|capture_name| capture_name == Some("url")) {
+                anyhow::bail!("source pattern of extractor {name} has no capture group named url");
+            }
+
+            extractors.push(CustomExtractor {
+                name,
+                host,
+                source,
+                referer: config.referer,
+            });
+        }
+
+        Ok(Self { extractors })
+    }
+
+    /// Extractor with the name, ignoring the case.
+    pub fn get(&self, name: &str) -> Option<&CustomExtractor> {
+        self.extractors
+            .iter()
+            .find(|extractor| extractor.name.eq_ignore_ascii_case(name))
+    }
+
+    /// First extractor, which supports the url.
+    pub fn find_for_url(&self, url: &str) -> Option<&CustomExtractor> {
+        self.extractors.iter().find(|extractor| extractor.supports_url(url))
+    }
+}
+
+impl CustomExtractor {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn supports_url(&self, url: &str) -> bool {
+        Url::parse(url)
+            .map(|url| {
+                matches!(url.scheme(), "http" | "https") && url.host_str().is_some_and(|host| self.host.is_match(host))
+            })
+            .unwrap_or(false)
+    }
+
+    pub async fn extract_video_url(&self, from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
+        let page_url = match &from {
+            ExtractFrom::Url { url, .. } => Url::parse(url).ok(),
+            ExtractFrom::Source(_) => None,
+        };
+        let source = from.get_source(self.referer.as_deref()).await?;
+        let video_url = self
+            .source
+            .captures(&source)
+            .and_then(|captures| captures.name("url"))
+            .map(|video_url| video_url.as_str().replace("\\/", "/"))
+            .ok_or(ExtractError::PatternNotFound("Custom: failed to find a video url"))?;
+        let video_url = match &page_url {
+            Some(page_url) => page_url
+                .join(&video_url)
+                .map_err(|_| ExtractError::Decode("Custom: failed to resolve video url"))?
+                .to_string(),
+            None => video_url,
+        };
+
+        Ok(ExtractedVideo {
+            url: video_url,
+            referer: self
+                .referer
+                .clone()
+                .or_else(|| page_url.map(|page_url| page_url.to_string())),
+        })
+    }
+}
+
+/// Reads the extractors from the data directory. A missing file is treated
+/// as empty.
+pub(crate) async fn load(data_dir: &Path) -> Result<CustomExtractors, anyhow::Error> {
+    let path = data_dir.join(EXTRACTORS_FILE_NAME);
+    let content = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(CustomExtractors::default()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+
+    CustomExtractors::from_toml(&content).with_context(|| format!("invalid {}", path.display()))
+}
+
+/// Sets the extractors used besides the built-in ones. Only the first call
+/// has an effect. The library does not load the extractors file, so users of
+/// the library have to call this before the first extraction.
+pub fn set_extractors(extractors: CustomExtractors) {
+    let _ = EXTRACTORS.set(extractors);
+}
+
+/// Extractor with the name, if one is defined.
+pub(crate) fn get(name: &str) -> Option<&'static CustomExtractor> {
+    EXTRACTORS.get()?.get(name)
+}
+
+/// Extractor, which supports the url, if one is defined.
+pub(crate) fn find_for_url(url: &str) -> Option<&'static CustomExtractor> {
+    EXTRACTORS.get()?.find_for_url(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extractors::custom::CustomExtractors;
+    use crate::extractors::ExtractFrom;
+
+    #[tokio::test]
+    async fn test_custom_extractors() {
+        let extractors = CustomExtractors::from_toml(
+            r#"
+            [[extractor]]
+            name = "Examplehost"
+            host = '(www\.)?examplehost\.(com|net)'
+            source = 'file:\s*"(?P<url>[^"]+)"'
+
+            [[extractor]]
+            name = "Otherhost"
+            host = 'otherhost\.to'
+            source = '<source src="(?P<url>[^"]+)"'
+            referer = "https://otherhost.to/"
+            "#,
+        )
+        .unwrap();
+
+        let examplehost = extractors.get("examplehost").unwrap();
+        assert_eq!(examplehost.name(), "Examplehost");
+        assert!(examplehost.supports_url("https://www.ExampleHost.net/e/abc"));
+        assert!(!examplehost.supports_url("https://examplehost.com.evil.to/e/abc"));
+        assert!(!examplehost.supports_url("ftp://examplehost.com/e/abc"));
+        assert_eq!(
+            extractors
+                .find_for_url("https://otherhost.to/v/1")
+                .map(|extractor| extractor.name()),
+            Some("Otherhost")
+        );
+        assert!(extractors.find_for_url("https://unknown.to/v/1").is_none());
+
+        let source = r#"player.setup({ file: "https:\/\/cdn.examplehost.com\/v.m3u8" });"#;
+        let extracted = examplehost
+            .extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, "https://cdn.examplehost.com/v.m3u8");
+        assert_eq!(extracted.referer, None);
+
+        let source = r#"<video><source src="https://cdn.otherhost.to/v.mp4"></video>"#;
+        let extracted = extractors
+            .get("Otherhost")
+            .unwrap()
+            .extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.referer.as_deref(), Some("https://otherhost.to/"));
+        assert!(examplehost
+            .extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .is_err());
+
+        assert!(CustomExtractors::from_toml("").unwrap().get("Examplehost").is_none());
+    }
+
+    #[test]
+    fn test_invalid_custom_extractors() {
+        let extractor = |name: &str, host: &str, source: &str| {
+            CustomExtractors::from_toml(&format!(
+                "[[extractor]]\nname = '{name}'\nhost = '{host}'\nsource = '{source}'\n"
+            ))
+        };
+
+        assert!(extractor("Examplehost", r"examplehost\.com", r#"file:"(?P<url>[^"]+)""#).is_ok());
+        assert!(extractor("Examplehost", r"examplehost\.com", r#"file:"([^"]+)""#).is_err());
+        assert!(extractor("Examplehost", r"examplehost\.(com", r#"file:"(?P<url>[^"]+)""#).is_err());
+        assert!(extractor("VOE", r"examplehost\.com", r#"file:"(?P<url>[^"]+)""#).is_err());
+        assert!(CustomExtractors::from_toml(
+            "[[extractor]]\nname = 'A'\nhost = 'a'\nsource = '(?P<url>a)'\n\
+             [[extractor]]\nname = 'a'\nhost = 'b'\nsource = '(?P<url>b)'\n"
+        )
+        .is_err());
+        assert!(CustomExtractors::from_toml("[[extractor]]\nname = 'A'\nhost = 'a'\n").is_err());
+    }
+}
@@ -1,13 +1,22 @@
 use std::time::SystemTime;
 
-use anyhow::Context;
 use once_cell::sync::Lazy;
 use rand::seq::SliceRandom;
 use regex::Regex;
 
 use super::utils::is_url_host_and_has_path;
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
-use crate::download::get_page_text;
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use crate::download::{get_page_text, get_response};
+
+/// Known domains of Doodstream, whose mirrors serve the same videos.
+const DOMAINS: &[&str] = &[
+    "dood.li",
+    "ds2play.com",
+    "dooood.com",
+    "d0000d.com",
+    "dood.wf",
+    "doods.pro",
+];
 
 pub struct Doodstream;
 
@@ -20,10 +29,14 @@ impl Extractor for Doodstream {
     }
 
     async fn supports_url(url: &str) -> Option<bool> {
-        Some(is_url_host_and_has_path(url, "dood.li", true, true))
+        Some(
+            DOMAINS
+                .iter()
+                .any(|domain| is_url_host_and_has_path(url, domain, true, true)),
+        )
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
         static FETCH_REGEX: Lazy<Regex> = Lazy::new(|| {
             Regex::new(r#"(?s)\$\.get\(\s*['"](/pass_md5/[\w-]+/([\w-]+))['"]\s*,\s*function\(\s*data\s*\)"#).unwrap()
         });
@@ -37,22 +50,23 @@ impl Extractor for Doodstream {
             } => (url.to_string(), user_agent.clone(), Some(url.to_string())),
             ExtractFrom::Source(_) => ("https://dood.li/".to_string(), None, None),
         };
-        let current_url = url::Url::parse(&current_url).context("Doodstream: failed to retrieve sources")?;
+        let current_url =
+            url::Url::parse(&current_url).map_err(|_| ExtractError::Unsupported("Doodstream: failed to parse url"))?;
 
         let source = from.get_source(None).await?;
         let (relative_fetch_url, token) = FETCH_REGEX
             .captures(&source)
             .and_then(|captures| captures.get(1).zip(captures.get(2)))
             .map(|(m1, m2)| (m1.as_str().to_string(), m2.as_str().to_string()))
-            .context("Doodstream: failed to retrieve sources")?;
+            .ok_or(ExtractError::PatternNotFound("Doodstream: failed to retrieve sources"))?;
 
         let video_base_url = {
             let fetch_url = current_url
                 .join(&relative_fetch_url)
-                .context("Doodstream: failed to retrieve sources")?;
+                .map_err(|_| ExtractError::Decode("Doodstream: failed to resolve fetch url"))?;
             get_page_text(fetch_url, user_agent.as_deref(), fetch_referer.as_deref(), None)
                 .await
-                .context("Doodstream: failed to retrieve sources")?
+                .map_err(|err| ExtractError::SourceFetch(err.context("Doodstream: failed to retrieve sources")))?
         };
         let random_string = {
             let mut rng = rand::thread_rng();
@@ -67,25 +81,81 @@ impl Extractor for Doodstream {
             let start = SystemTime::now();
             let since_the_epoch = start
                 .duration_since(std::time::UNIX_EPOCH)
-                .context("Doodstream: failed to retrieve sources: system time before Unix epoch")?;
+                .map_err(|_| ExtractError::Decode("Doodstream: system time before Unix epoch"))?;
             since_the_epoch.as_millis()
         };
 
         let video_url = format!("{video_base_url}{random_string}?token={token}&expiry={unix_time_millis}");
         let video_url_referer = current_url
             .join("/")
-            .context("Doodstream: failed to retrieve sources")?;
+            .map_err(|_| ExtractError::Decode("Doodstream: failed to resolve referer"))?;
 
-        Ok(ExtractedVideo {
-            url: video_url,
-            referer: Some(video_url_referer.as_str().to_string()),
-        })
+        // The host of the video url is often blocked, while a mirror still works
+        let mut video_url_candidates = vec![video_url];
+        video_url_candidates.extend(get_mirror_urls(&video_url_candidates[0]));
+
+        for video_url in video_url_candidates {
+            if is_reachable(&video_url, user_agent.as_deref(), video_url_referer.as_str()).await {
+                return Ok(ExtractedVideo {
+                    url: video_url,
+                    referer: Some(video_url_referer.as_str().to_string()),
+                });
+            }
+
+            log::debug!("Doodstream: video url is not reachable: {}", video_url);
+        }
+
+        Err(ExtractError::SourceFetch(anyhow::anyhow!(
+            "Doodstream: video url is not reachable on any mirror"
+        )))
     }
 }
 
+/// Only the first byte is requested, and the body is dropped without reading it.
+async fn is_reachable(url: &str, user_agent: Option<&str>, referer: &str) -> bool {
+    let range_header = [(reqwest::header::RANGE, "bytes=0-0")];
+
+    match get_response(None, url, user_agent, Some(referer), Some(&range_header)).await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Replaces the Doodstream domain of the url with each of the other known
+/// domains, keeping any subdomain.
+fn get_mirror_urls(video_url: &str) -> Vec<String> {
+    let Ok(url) = url::Url::parse(video_url) else {
+        return vec![];
+    };
+    let Some(host) = url.host_str().map(|host| host.to_ascii_lowercase()) else {
+        return vec![];
+    };
+    let Some((subdomain, current_domain)) = DOMAINS.iter().find_map(|domain| {
+        if host == *domain {
+            Some(("", *domain))
+        } else {
+            host.strip_suffix(domain)
+                .filter(|prefix| prefix.ends_with('.'))
+                .map(|prefix| (prefix, *domain))
+        }
+    }) else {
+        return vec![];
+    };
+
+    DOMAINS
+        .iter()
+        .filter(|domain| **domain != current_domain)
+        .filter_map(|domain| {
+            let mut mirror_url = url.clone();
+            mirror_url.set_host(Some(&format!("{subdomain}{domain}"))).ok()?;
+            Some(mirror_url.to_string())
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Doodstream;
+    use super::{get_mirror_urls, Doodstream};
     use crate::extractors::Extractor;
 
     #[tokio::test]
@@ -93,4 +163,21 @@ mod tests {
         let url = "https://dood.li/e/s23ywsyo2fbm";
         assert!(Doodstream::supports_url(url).await.unwrap_or(false));
     }
+
+    #[test]
+    fn test_doodstream_mirror_urls() {
+        assert_eq!(
+            get_mirror_urls("https://xy12.ds2play.com/abc/def?token=t&expiry=1"),
+            vec![
+                "https://xy12.dood.li/abc/def?token=t&expiry=1",
+                "https://xy12.dooood.com/abc/def?token=t&expiry=1",
+                "https://xy12.d0000d.com/abc/def?token=t&expiry=1",
+                "https://xy12.dood.wf/abc/def?token=t&expiry=1",
+                "https://xy12.doods.pro/abc/def?token=t&expiry=1",
+            ]
+        );
+        assert_eq!(get_mirror_urls("https://dood.li/abc").len(), 5);
+        assert!(get_mirror_urls("https://notdood.li/abc").is_empty());
+        assert!(get_mirror_urls("https://cdn.example.com/abc").is_empty());
+    }
 }
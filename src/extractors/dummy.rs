@@ -1,4 +1,4 @@
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
 
 pub struct Dummy;
 
@@ -14,14 +14,14 @@ impl Extractor for Dummy {
         None
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
         match from {
             ExtractFrom::Url {
                 url,
                 user_agent: _,
                 referer,
             } => Ok(ExtractedVideo { url, referer }),
-            ExtractFrom::Source(_) => anyhow::bail!("Dummy: page source is not supported"),
+            ExtractFrom::Source(_) => Err(ExtractError::Unsupported("Dummy: page source is not supported")),
         }
     }
 }
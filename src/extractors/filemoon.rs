@@ -3,7 +3,7 @@ use regex::Regex;
 use reqwest::header::HeaderName;
 use url::Url;
 
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
 use crate::download;
 use crate::extractors::utils::decode_packed_codes;
 
@@ -21,7 +21,7 @@ impl Extractor for Filemoon {
         None
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
         static REDIRECT_REGEX: Lazy<Regex> =
             Lazy::new(|| Regex::new(r#"<iframe *(?:[^>]+ )?src=(?:'([^']+)'|"([^"]+)")[^>]*>"#).unwrap());
         static SCRIPT_REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -57,7 +57,8 @@ impl Extractor for Filemoon {
                 referer.as_deref(),
                 Some(&EXTRA_HEADERS),
             )
-            .await?;
+            .await
+            .map_err(ExtractError::SourceFetch)?;
         }
 
         for script in SCRIPT_REGEX.captures_iter(&source) {
@@ -86,7 +87,7 @@ impl Extractor for Filemoon {
             }
         }
 
-        anyhow::bail!("Filemoon: failed to retrieve sources")
+        Err(ExtractError::PatternNotFound("Filemoon: failed to retrieve sources"))
     }
 }
 
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use url::Url;
+
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the generic extractor is used for urls no other extractor supports.
+/// It can always be selected explicitly by name.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Best-effort extractor for pages embedding a common video player,
+/// e.g. `jwplayer(...).setup({sources: [{file: "..."}]})`.
+pub struct Generic;
+
+impl Extractor for Generic {
+    const DISPLAY_NAME: &'static str = "Generic";
+    const NAMES: &'static [&'static str] = &["Generic"];
+
+    fn supported_from() -> SupportedFrom {
+        SupportedFrom::all()
+    }
+
+    async fn supports_url(url: &str) -> Option<bool> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(
+            Url::parse(url)
+                .map(|url| matches!(url.scheme(), "http" | "https"))
+                .unwrap_or(false),
+        )
+    }
+
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
+        let page_url = match &from {
+            ExtractFrom::Url { url, .. } => Url::parse(url).ok(),
+            ExtractFrom::Source(_) => None,
+        };
+        let source = from.get_source(None).await?;
+        let video_url =
+            find_video_url(&source).ok_or(ExtractError::PatternNotFound("Generic: failed to find a video url"))?;
+        let video_url = match &page_url {
+            Some(page_url) => page_url
+                .join(&video_url)
+                .map_err(|_| ExtractError::Decode("Generic: failed to resolve video url"))?
+                .to_string(),
+            None => video_url,
+        };
+
+        Ok(ExtractedVideo {
+            url: video_url,
+            referer: page_url.map(|page_url| page_url.to_string()),
+        })
+    }
+}
+
+fn find_video_url(source: &str) -> Option<String> {
+    static JWPLAYER_FILE_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"\bfile\s*:\s*["']([^"']+\.(?:m3u8|mp4|webm)(?:\?[^"']*)?)["']"#).unwrap());
+    static JSON_SOURCES_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#""sources"\s*:\s*\[\s*\{[^}]*?"(?:file|src)"\s*:\s*"([^"]+)""#).unwrap());
+    static SOURCE_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<source\s[^>]*?src=["']([^"']+)["']"#).unwrap());
+
+    [&*JWPLAYER_FILE_REGEX, &*JSON_SOURCES_REGEX, &*SOURCE_TAG_REGEX]
+        .into_iter()
+        .find_map(|regex| regex.captures(source)?.get(1))
+        .map(|video_url| video_url.as_str().replace("\\/", "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Generic;
+    use crate::extractors::{ExtractFrom, Extractor};
+
+    #[tokio::test]
+    async fn test_generic() {
+        let source = r#"<script>
+            jwplayer("vplayer").setup({
+                sources: [{file:"https://cdn.example.com/hls/abc/master.m3u8?t=123"}],
+                image: "https://cdn.example.com/abc.jpg",
+            });
+        </script>"#;
+        let extracted = Generic::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, "https://cdn.example.com/hls/abc/master.m3u8?t=123");
+
+        let source = r#"var config = {"sources":[{"type":"video/mp4","src":"https:\/\/cdn.example.com\/v.mp4"}]};"#;
+        let extracted = Generic::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, "https://cdn.example.com/v.mp4");
+
+        let source = r#"<video><source src="https://cdn.example.com/v.webm" type="video/webm"></video>"#;
+        let extracted = Generic::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, "https://cdn.example.com/v.webm");
+
+        let source = r#"<div>No video here</div>"#;
+        assert!(Generic::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .is_err());
+    }
+}
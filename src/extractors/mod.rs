@@ -1,18 +1,24 @@
+use std::fmt::Display;
+
 use bitmask_enum::bitmask;
+use url::Url;
 
 use crate::download;
 use crate::extractors::doodstream::Doodstream;
 use crate::extractors::dummy::Dummy;
 use crate::extractors::filemoon::Filemoon;
+use crate::extractors::generic::Generic;
 use crate::extractors::speedfiles::Speedfiles;
 use crate::extractors::streamtape::Streamtape;
 use crate::extractors::vidmoly::Vidmoly;
 use crate::extractors::vidoza::Vidoza;
 use crate::extractors::voe::Voe;
 
+pub mod custom;
 pub mod doodstream;
 pub mod dummy;
 pub mod filemoon;
+pub mod generic;
 pub mod speedfiles;
 pub mod streamtape;
 pub mod vidmoly;
@@ -137,35 +143,75 @@ macro_rules! extract_video_url_with_extractor_from_source {
 
 macro_rules! create_functions_for_extractors {
     ($( $ext:ty ),* $(,)?) => {
+        // The extractors of the config file come first, so that they can also
+        // replace a broken built-in extractor for a host
+
         pub fn normalized_name(extractor: &str) -> Option<&'static str> {
+            if let Some(custom_extractor) = custom::get(extractor) {
+                return Some(custom_extractor.name());
+            }
+
             normalized_name!(extractor, $($ext),*)
         }
 
         pub fn exists_extractor_with_name(extractor: &str) -> bool {
-            exists_extractor_with_name!(extractor, $($ext),*)
+            custom::get(extractor).is_some() || exists_extractor_with_name!(extractor, $($ext),*)
         }
 
         pub async fn exists_extractor_for_url(url: &str, extractor: Option<&str>) -> bool {
+            let custom_supports_url = match extractor {
+                Some(extractor) => custom::get(extractor).map(|custom_extractor| custom_extractor.supports_url(url)),
+                None => custom::find_for_url(url).map(|_| true),
+            };
+
+            if let Some(custom_supports_url) = custom_supports_url {
+                return custom_supports_url;
+            }
+
             exists_extractor_for_url!(url, extractor, $($ext),*)
         }
 
         pub fn extractor_supports_source(extractor: &str) -> Option<bool> {
+            if custom::get(extractor).is_some() {
+                return Some(true);
+            }
+
             extractor_supports_source!(extractor, $($ext),*)
         }
 
-        pub async fn extract_video_url(url: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, anyhow::Error>> {
+        pub async fn extract_video_url(url: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, ExtractError>> {
+            if let Some(custom_extractor) = custom::find_for_url(url) {
+                return Some(custom_extractor.extract_video_url(ExtractFrom::Url { url: url.to_owned(), user_agent, referer }).await);
+            }
+
             extract_video_url!(url, user_agent, referer, $($ext),*)
         }
 
-        pub async fn extract_video_url_with_extractor_from_url(url: &str, extractor: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, anyhow::Error>> {
+        pub async fn extract_video_url_with_extractor_from_url(url: &str, extractor: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, ExtractError>> {
+            if let Some(custom_extractor) = custom::get(extractor) {
+                if !custom_extractor.supports_url(url) {
+                    return None;
+                }
+
+                return Some(custom_extractor.extract_video_url(ExtractFrom::Url { url: url.to_owned(), user_agent, referer }).await);
+            }
+
             extract_video_url_with_extractor_from_url!(url, extractor, user_agent, referer, $($ext),*)
         }
 
-        pub async fn extract_video_url_with_extractor_from_url_unchecked(url: &str, extractor: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, anyhow::Error>> {
+        pub async fn extract_video_url_with_extractor_from_url_unchecked(url: &str, extractor: &str, user_agent: Option<String>, referer: Option<String>) -> Option<Result<ExtractedVideo, ExtractError>> {
+            if let Some(custom_extractor) = custom::get(extractor) {
+                return Some(custom_extractor.extract_video_url(ExtractFrom::Url { url: url.to_owned(), user_agent, referer }).await);
+            }
+
             extract_video_url_with_extractor_from_url_unchecked!(url, extractor, user_agent, referer, $($ext),*)
         }
 
-        pub async fn extract_video_url_with_extractor_from_source(source: String, extractor: &str) -> Option<Result<ExtractedVideo, anyhow::Error>> {
+        pub async fn extract_video_url_with_extractor_from_source(source: String, extractor: &str) -> Option<Result<ExtractedVideo, ExtractError>> {
+            if let Some(custom_extractor) = custom::get(extractor) {
+                return Some(custom_extractor.extract_video_url(ExtractFrom::Source(source)).await);
+            }
+
             extract_video_url_with_extractor_from_source!(source, extractor, $($ext),*)
         }
 
@@ -233,6 +279,7 @@ create_functions_for_extractors! {
     Vidmoly,
     Vidoza,
     Voe,
+    Generic, // last, so that all other extractors take precedence
 }
 
 #[derive(Debug, Clone)]
@@ -246,16 +293,36 @@ pub enum ExtractFrom {
 }
 
 impl ExtractFrom {
-    pub async fn get_source(self, referer: Option<&str>) -> Result<String, anyhow::Error> {
+    pub async fn get_source(self, referer: Option<&str>) -> Result<String, ExtractError> {
         match self {
             ExtractFrom::Url {
                 url,
                 user_agent,
                 referer: referer_input,
-            } => download::get_page_text(url, user_agent.as_deref(), referer_input.as_deref().or(referer), None).await,
+            } => download::get_page_text(url, user_agent.as_deref(), referer_input.as_deref().or(referer), None)
+                .await
+                .map_err(ExtractError::SourceFetch),
             ExtractFrom::Source(source) => Ok(source),
         }
     }
+
+    /// Like [`Self::get_source`], but also returns the url of the page after
+    /// redirects, if it was fetched.
+    pub async fn get_source_and_url(self, referer: Option<&str>) -> Result<(String, Option<Url>), ExtractError> {
+        match self {
+            ExtractFrom::Url {
+                url,
+                user_agent,
+                referer: referer_input,
+            } => {
+                download::get_page_text_and_url(url, user_agent.as_deref(), referer_input.as_deref().or(referer), None)
+                    .await
+                    .map(|(source, page_url)| (source, Some(page_url)))
+                    .map_err(ExtractError::SourceFetch)
+            }
+            ExtractFrom::Source(source) => Ok((source, None)),
+        }
+    }
 }
 
 #[bitmask]
@@ -270,6 +337,47 @@ pub struct ExtractedVideo {
     pub referer: Option<String>,
 }
 
+/// Why extracting the video url failed, so that callers can decide whether
+/// to try the same stream again or move on to the next one.
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The extractor does not support the url or kind of input.
+    Unsupported(&'static str),
+    /// Fetching the page or another resource failed, e.g. due to the network.
+    SourceFetch(anyhow::Error),
+    /// The page does not contain what the extractor looks for, e.g. due to a changed site.
+    PatternNotFound(&'static str),
+    /// Data found on the page could not be decoded.
+    Decode(&'static str),
+}
+
+impl ExtractError {
+    /// Whether trying the same stream again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ExtractError::SourceFetch(_))
+    }
+}
+
+impl Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Unsupported(message)
+            | ExtractError::PatternNotFound(message)
+            | ExtractError::Decode(message) => f.write_str(message),
+            ExtractError::SourceFetch(_) => f.write_str("failed to fetch source"),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::SourceFetch(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 pub trait Extractor {
     const DISPLAY_NAME: &'static str;
     const NAMES: &'static [&'static str];
@@ -278,7 +386,7 @@ pub trait Extractor {
 
     async fn supports_url(url: &str) -> Option<bool>;
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error>;
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError>;
 }
 
 pub mod utils {
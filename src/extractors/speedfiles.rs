@@ -1,10 +1,11 @@
-use anyhow::Context;
+use std::fmt::Display;
+
 use base64::Engine;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
 use super::utils::is_url_host_and_has_path;
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
 
 pub struct Speedfiles;
 
@@ -20,94 +21,206 @@ impl Extractor for Speedfiles {
         Some(is_url_host_and_has_path(url, "speedfiles.net", true, false))
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
-        // Regex for base64 assignments
-        static VIDEO_URL_REGEX: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(
-                r#"(?:var|let|const) \w+ = ["']((?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{4}|[A-Za-z0-9+/]{3}=|[A-Za-z0-9+/]{2}={2}))["'];"#,
-            )
-            .unwrap()
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
+        // Regexes for base64 assignments and literals decoded by atob
+        static CANDIDATE_REGEXES: Lazy<[Regex; 2]> = Lazy::new(|| {
+            [
+                Regex::new(
+                    r#"(?:var|let|const) \w+ = ["']((?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{4}|[A-Za-z0-9+/]{3}=|[A-Za-z0-9+/]{2}={2}))["'];"#,
+                )
+                .unwrap(),
+                Regex::new(
+                    r#"atob\(["']((?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{4}|[A-Za-z0-9+/]{3}=|[A-Za-z0-9+/]{2}={2}))["']\)"#,
+                )
+                .unwrap(),
+            ]
         });
 
-        fn decode_url(input: &str) -> Option<String> {
-            // Decode base64
-            let d = base64::prelude::BASE64_STANDARD.decode(input).ok()?;
-
-            // Flip case of ascii letters and reverse vector
-            let d = d
-                .into_iter()
-                .map(|x| {
-                    if x.is_ascii_alphabetic() {
-                        // https://stackoverflow.com/questions/42245397/c-most-efficient-way-to-change-uppercase-to-lowercase-and-vice-versa-without
-                        x ^ 32
-                    } else {
-                        x
-                    }
-                })
-                .rev()
-                .collect::<Vec<_>>();
-
-            // Decode base64 and reverse vector
-            let mut d = base64::prelude::BASE64_STANDARD.decode(d).ok()?;
-            d.reverse();
-
-            // Parse hex to integer and subtract 3
-            let d = d
-                .chunks(2)
-                .try_fold(Vec::with_capacity(d.len().div_ceil(2)), |mut acc, x| {
-                    let hex = u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok()?;
-                    let sub = hex.checked_sub(3)?;
-                    acc.push(sub);
-                    Some(acc)
-                })?;
-
-            // Flip case of ascii letters and reverse vector
-            let d = d
-                .into_iter()
-                .map(|x| {
-                    if x.is_ascii_alphabetic() {
-                        // https://stackoverflow.com/questions/42245397/c-most-efficient-way-to-change-uppercase-to-lowercase-and-vice-versa-without
-                        x ^ 32
-                    } else {
-                        x
+        let source = from.get_source(None).await?;
+        let candidates = CANDIDATE_REGEXES
+            .iter()
+            .flat_map(|regex| regex.captures_iter(&source))
+            .filter_map(|captures| captures.get(1));
+
+        for (index, candidate) in candidates.enumerate() {
+            for (strategy_name, strategy) in DECODE_STRATEGIES {
+                match strategy(candidate.as_str()) {
+                    Ok(video_url) => {
+                        return Ok(ExtractedVideo {
+                            url: video_url,
+                            referer: None,
+                        })
                     }
-                })
-                .rev()
-                .collect::<Vec<_>>();
+                    Err(stage) => log::trace!(
+                        "Speedfiles: {} decoding of candidate {} failed at {}",
+                        strategy_name,
+                        index,
+                        stage
+                    ),
+                }
+            }
+        }
+
+        Err(ExtractError::PatternNotFound("Speedfiles: failed to retrieve sources"))
+    }
+}
+
+/// Decoding strategies, which are tried in order for every candidate. A new
+/// strategy can be added here, if the obfuscation changes.
+const DECODE_STRATEGIES: [(&str, fn(&str) -> Result<String, DecodeStage>); 2] =
+    [("hex", decode_url_hex), ("plain", decode_url_plain)];
+
+/// Step of decoding, which failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeStage {
+    Base64Step1,
+    Base64Step2,
+    Base64Step3,
+    HexParse,
+    Utf8,
+    UrlParse,
+}
+
+impl Display for DecodeStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeStage::Base64Step1 => write!(f, "base64 step 1"),
+            DecodeStage::Base64Step2 => write!(f, "base64 step 2"),
+            DecodeStage::Base64Step3 => write!(f, "base64 step 3"),
+            DecodeStage::HexParse => write!(f, "hex parse"),
+            DecodeStage::Utf8 => write!(f, "utf-8"),
+            DecodeStage::UrlParse => write!(f, "url parse"),
+        }
+    }
+}
+
+/// The current obfuscation, which hides a hex encoded and shifted string
+/// between layers of base64.
+fn decode_url_hex(input: &str) -> Result<String, DecodeStage> {
+    let d = decode_base64(input.as_bytes(), DecodeStage::Base64Step1)?;
+    let d = flip_case_and_reverse(d);
+
+    let mut d = decode_base64(&d, DecodeStage::Base64Step2)?;
+    d.reverse();
+
+    let d = parse_hex_shifted(&d)?;
+    let d = flip_case_and_reverse(d);
+
+    let d = decode_base64(&d, DecodeStage::Base64Step3)?;
+    parse_url(d)
+}
 
-            // Decode base64
-            let d = base64::prelude::BASE64_STANDARD.decode(d).ok()?;
+/// A simpler obfuscation without the hex layer.
+fn decode_url_plain(input: &str) -> Result<String, DecodeStage> {
+    let d = decode_base64(input.as_bytes(), DecodeStage::Base64Step1)?;
+    let d = flip_case_and_reverse(d);
 
-            // As string
-            let s = String::from_utf8(d).ok()?;
+    let d = decode_base64(&d, DecodeStage::Base64Step2)?;
+    parse_url(d)
+}
+
+fn decode_base64(input: &[u8], stage: DecodeStage) -> Result<Vec<u8>, DecodeStage> {
+    base64::prelude::BASE64_STANDARD.decode(input).map_err(|_| stage)
+}
 
-            if url::Url::parse(&s).is_err() {
-                // If not a url, return None
-                return None;
+/// Flips the case of ascii letters and reverses the bytes.
+fn flip_case_and_reverse(input: Vec<u8>) -> Vec<u8> {
+    input
+        .into_iter()
+        .map(|x| {
+            if x.is_ascii_alphabetic() {
+                // https://stackoverflow.com/questions/42245397/c-most-efficient-way-to-change-uppercase-to-lowercase-and-vice-versa-without
+                x ^ 32
+            } else {
+                x
             }
+        })
+        .rev()
+        .collect()
+}
 
-            Some(s)
-        }
+/// Parses pairs of hex digits to bytes and subtracts 3 from each.
+fn parse_hex_shifted(input: &[u8]) -> Result<Vec<u8>, DecodeStage> {
+    input
+        .chunks(2)
+        .map(|x| {
+            let hex = std::str::from_utf8(x).map_err(|_| DecodeStage::HexParse)?;
+            let hex = u8::from_str_radix(hex, 16).map_err(|_| DecodeStage::HexParse)?;
+            hex.checked_sub(3).ok_or(DecodeStage::HexParse)
+        })
+        .collect()
+}
 
-        let source = from.get_source(None).await?;
-        VIDEO_URL_REGEX
-            .captures_iter(&source)
-            .filter_map(|captures| captures.get(1))
-            .filter_map(|capture| decode_url(capture.as_str()))
-            .map(|video_url| ExtractedVideo {
-                url: video_url,
-                referer: None,
-            })
-            .next()
-            .context("Speedfiles: failed to retrieve sources")
-    }
+fn parse_url(input: Vec<u8>) -> Result<String, DecodeStage> {
+    let s = String::from_utf8(input).map_err(|_| DecodeStage::Utf8)?;
+    url::Url::parse(&s).map_err(|_| DecodeStage::UrlParse)?;
+    Ok(s)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Speedfiles;
+    use base64::Engine;
+
+    use super::{
+        decode_url_hex, decode_url_plain, flip_case_and_reverse, parse_hex_shifted, parse_url, DecodeStage, Speedfiles,
+    };
     use crate::extractors::{ExtractFrom, Extractor};
 
+    fn encode_base64(input: &[u8]) -> Vec<u8> {
+        base64::prelude::BASE64_STANDARD.encode(input).into_bytes()
+    }
+
+    /// Inverse of the hex decoding, starting at the given hex layer.
+    fn encode_hex_layer(hex: &[u8]) -> String {
+        let mut reversed = hex.to_vec();
+        reversed.reverse();
+        let d = flip_case_and_reverse(encode_base64(&reversed));
+        String::from_utf8(encode_base64(&d)).unwrap()
+    }
+
+    fn encode_url_hex(url: &str) -> String {
+        let d = flip_case_and_reverse(encode_base64(url.as_bytes()));
+        let hex = d.iter().map(|x| format!("{:02x}", x + 3)).collect::<String>();
+        encode_hex_layer(hex.as_bytes())
+    }
+
+    #[test]
+    fn test_decode_stages() {
+        assert_eq!(flip_case_and_reverse(b"aB1c".to_vec()), b"C1bA");
+        assert_eq!(parse_hex_shifted(b"4b6c"), Ok(b"Hi".to_vec()));
+        assert_eq!(parse_hex_shifted(b"4z"), Err(DecodeStage::HexParse));
+        assert_eq!(parse_hex_shifted(b"02"), Err(DecodeStage::HexParse));
+        assert_eq!(parse_url(vec![0xff]), Err(DecodeStage::Utf8));
+        assert_eq!(parse_url(b"no url".to_vec()), Err(DecodeStage::UrlParse));
+
+        let url = "https://speedfiles.net/store_access/d2bb8bb75e7d?token=abc";
+        assert_eq!(decode_url_hex(&encode_url_hex(url)), Ok(url.to_string()));
+        assert_eq!(decode_url_hex("not base64!"), Err(DecodeStage::Base64Step1));
+
+        let not_base64 = flip_case_and_reverse(b"not base64!".to_vec());
+        let step2 = String::from_utf8(encode_base64(&not_base64)).unwrap();
+        assert_eq!(decode_url_hex(&step2), Err(DecodeStage::Base64Step2));
+
+        assert_eq!(decode_url_hex(&encode_hex_layer(b"zz")), Err(DecodeStage::HexParse));
+
+        let hex = b"not base64!"
+            .iter()
+            .map(|x| format!("{:02x}", x + 3))
+            .collect::<String>();
+        assert_eq!(
+            decode_url_hex(&encode_hex_layer(hex.as_bytes())),
+            Err(DecodeStage::Base64Step3)
+        );
+    }
+
+    #[test]
+    fn test_decode_url_plain() {
+        let url = "https://speedfiles.net/store_access/d2bb8bb75e7d";
+        let d = flip_case_and_reverse(encode_base64(url.as_bytes()));
+        let input = String::from_utf8(encode_base64(&d)).unwrap();
+        assert_eq!(decode_url_plain(&input), Ok(url.to_string()));
+    }
+
     #[tokio::test]
     async fn test_speedfiles() {
         let url = "https://speedfiles.net/d2bb8bb75e7d";
@@ -1,10 +1,23 @@
-use anyhow::Context;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use url::Url;
 
 use super::utils::is_url_host_and_has_path;
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+
+/// Known domains of Streamtape.
+const HOSTS: &[&str] = &[
+    "streamtape.com",
+    "streamtape.to",
+    "streamtape.net",
+    "streamtape.xyz",
+    "streamta.pe",
+    "shavetape.cash",
+    "watchadsontape.com",
+    "tapecontent.net",
+];
+
+const DEFAULT_BASE_URL: &str = "https://streamtape.com";
 
 pub struct Streamtape;
 
@@ -17,14 +30,20 @@ impl Extractor for Streamtape {
     }
 
     async fn supports_url(url: &str) -> Option<bool> {
-        Some(is_url_host_and_has_path(url, "streamtape.com", true, true))
+        Some(HOSTS.iter().any(|host| is_url_host_and_has_path(url, host, true, true)))
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
         static ROBOT_LINK_REGEX: Lazy<Regex> =
             Lazy::new(|| Regex::new(r#"<div\s*[^>]*?id="robotlink"[^>]*?>[^<]*?(/get_video[^<]+?)</div>"#).unwrap());
         static TOKEN_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"&token=([^&?\s'"]+)"#).unwrap());
 
+        // The robot link is relative to the domain the page was loaded from
+        let base_url = match &from {
+            ExtractFrom::Url { url, .. } => get_base_url(url),
+            ExtractFrom::Source(_) => None,
+        }
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
         let source = from.get_source(None).await?;
         ROBOT_LINK_REGEX
             .captures(&source)
@@ -37,7 +56,7 @@ impl Extractor for Streamtape {
                 token.map(|token| (robot_url, token))
             })
             .and_then(|(robot_url, token)| {
-                let mut streamtape_url = Url::parse(&format!("https://streamtape.com{}", robot_url)).ok()?;
+                let mut streamtape_url = Url::parse(&format!("{}{}", base_url, robot_url)).ok()?;
                 let new_query = form_urlencoded::Serializer::new(String::new())
                     .extend_pairs(streamtape_url.query_pairs().filter(|(key, _)| key != "token"))
                     .append_pair("token", token)
@@ -52,13 +71,19 @@ impl Extractor for Streamtape {
 
                 Some(extracted_video)
             })
-            .context("Streamtape: failed to retrieve sources")
+            .ok_or(ExtractError::PatternNotFound("Streamtape: failed to retrieve sources"))
     }
 }
 
+fn get_base_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(format!("{}://{}", url.scheme(), host))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Streamtape;
+    use super::{get_base_url, Streamtape};
     use crate::extractors::{ExtractFrom, Extractor};
 
     #[tokio::test]
@@ -84,4 +109,30 @@ mod tests {
         let extracted = Streamtape::extract_video_url(ExtractFrom::Source(source.to_string())).await;
         assert_eq!(extracted.unwrap().url, expected.to_string());
     }
+
+    #[tokio::test]
+    async fn test_streamtape_mirrors() {
+        for url in [
+            "https://streamtape.to/e/jv430mJ2bOszzOB",
+            "https://streamta.pe/v/jv430mJ2bOszzOB",
+            "https://watchadsontape.com/e/jv430mJ2bOszzOB",
+            "https://www.tapecontent.net/e/jv430mJ2bOszzOB",
+        ] {
+            assert!(Streamtape::supports_url(url).await.unwrap_or(false), "{url}");
+        }
+
+        assert!(
+            !Streamtape::supports_url("https://streamtape.example/e/jv430mJ2bOszzOB")
+                .await
+                .unwrap_or(false)
+        );
+        assert_eq!(
+            get_base_url("https://streamtape.to/e/jv430mJ2bOszzOB").as_deref(),
+            Some("https://streamtape.to")
+        );
+        assert_eq!(
+            get_base_url("http://www.streamta.pe/v/jv430mJ2bOszzOB/video.mp4").as_deref(),
+            Some("http://www.streamta.pe")
+        );
+    }
 }
@@ -1,9 +1,14 @@
-use anyhow::Context;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use url::Url;
 
 use super::utils::is_url_host_and_has_path;
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+
+/// Known domains of Vidmoly.
+const HOSTS: &[&str] = &["vidmoly.to", "vidmoly.net", "vidmoly.me"];
+
+const DEFAULT_REFERER: &str = "https://vidmoly.to/";
 
 pub struct Vidmoly;
 
@@ -16,27 +21,38 @@ impl Extractor for Vidmoly {
     }
 
     async fn supports_url(url: &str) -> Option<bool> {
-        Some(is_url_host_and_has_path(url, "vidmoly.to", true, true))
+        Some(HOSTS.iter().any(|host| is_url_host_and_has_path(url, host, true, true)))
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
-        static VIDEO_URL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?s)file:\s*"([^"]+\.m3u8[^"]*)""#).unwrap());
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
+        static VIDEO_URL_REGEX: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#"(?s)file:\s*"([^"]+\.(?:m3u8|mp4)[^"]*)""#).unwrap());
 
-        let source = from.get_source(None).await?;
+        let (source, page_url) = from.get_source_and_url(None).await?;
+        // The video is only served with the referer of the domain the page was
+        // loaded from, which may differ from the embed url after redirects
+        let page_url = page_url.unwrap_or_else(|| Url::parse(DEFAULT_REFERER).unwrap());
         VIDEO_URL_REGEX
             .captures(&source)
             .and_then(|captures| captures.get(1))
+            .and_then(|video_url| page_url.join(video_url.as_str()).ok())
             .map(|video_url| ExtractedVideo {
-                url: video_url.as_str().to_string(),
-                referer: Some("https://vidmoly.to/".to_string()),
+                url: video_url.into(),
+                referer: Some(get_referer(&page_url)),
             })
-            .context("Vidmoly: failed to retrieve sources")
+            .ok_or(ExtractError::PatternNotFound("Vidmoly: failed to retrieve sources"))
     }
 }
 
+fn get_referer(page_url: &Url) -> String {
+    format!("{}/", page_url.origin().ascii_serialization())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Vidmoly;
+    use url::Url;
+
+    use super::{get_referer, Vidmoly};
     use crate::extractors::{ExtractFrom, Extractor};
 
     #[tokio::test]
@@ -78,4 +94,40 @@ mod tests {
         let extracted = Vidmoly::extract_video_url(ExtractFrom::Source(source.to_string())).await;
         assert_eq!(extracted.unwrap().url, expected.to_string());
     }
+
+    #[tokio::test]
+    async fn test_vidmoly_mp4() {
+        assert!(Vidmoly::supports_url("https://vidmoly.net/embed-z4knfpsh2q3o.html")
+            .await
+            .unwrap_or(false));
+        assert!(Vidmoly::supports_url("https://vidmoly.me/w/z4knfpsh2q3o")
+            .await
+            .unwrap_or(false));
+
+        let source = r#"sources: [{file:"https://box-1031-f.vmeas.cloud/v/01/01384/z4knfpsh2q3o_n.mp4?t=abc"}],
+    tracks: [{file: "/dl?op=get_slides&length=1425", kind: "thumbnails"}]"#;
+        let extracted = Vidmoly::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            extracted.url,
+            "https://box-1031-f.vmeas.cloud/v/01/01384/z4knfpsh2q3o_n.mp4?t=abc"
+        );
+        assert_eq!(extracted.referer.as_deref(), Some("https://vidmoly.to/"));
+
+        assert_eq!(
+            get_referer(&Url::parse("https://vidmoly.net/embed-z4knfpsh2q3o.html").unwrap()),
+            "https://vidmoly.net/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vidmoly_relative() {
+        let source = r#"sources: [{file:"/hls/z4knfpsh2q3o/master.m3u8"}]"#;
+        let extracted = Vidmoly::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, "https://vidmoly.to/hls/z4knfpsh2q3o/master.m3u8");
+        assert_eq!(extracted.referer.as_deref(), Some("https://vidmoly.to/"));
+    }
 }
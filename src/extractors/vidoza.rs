@@ -1,9 +1,10 @@
-use anyhow::Context;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
 use super::utils::is_url_host_and_has_path;
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+
+const VIDOZA_REFERER: &str = "https://vidoza.net/";
 
 pub struct Vidoza;
 
@@ -22,19 +23,22 @@ impl Extractor for Vidoza {
         )
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
-        static VIDEO_URL_REGEX: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r#"(?s)sourcesCode:\s\[\{\ssrc:\s"(.+)", type"#).unwrap());
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
+        static SOURCES_CODE_REGEX: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#"sourcesCode:\s*\[\s*\{\s*src:\s*["']([^"']+)["']"#).unwrap());
+        static SOURCE_TAG_REGEX: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#"<source\s[^>]*?src=["']([^"']+)["']"#).unwrap());
 
         let source = from.get_source(None).await?;
-        VIDEO_URL_REGEX
+        SOURCES_CODE_REGEX
             .captures(&source)
+            .or_else(|| SOURCE_TAG_REGEX.captures(&source))
             .and_then(|captures| captures.get(1))
             .map(|video_url| ExtractedVideo {
                 url: video_url.as_str().to_string(),
-                referer: None,
+                referer: Some(VIDOZA_REFERER.to_string()),
             })
-            .context("Vidoza: failed to retrieve sources")
+            .ok_or(ExtractError::PatternNotFound("Vidoza: failed to retrieve sources"))
     }
 }
 
@@ -78,4 +82,21 @@ mod tests {
         let extracted = Vidoza::extract_video_url(ExtractFrom::Source(source.to_string())).await;
         assert_eq!(extracted.unwrap().url, expected.to_string());
     }
+
+    #[tokio::test]
+    async fn test_vidoza_source_tag() {
+        let url = "https://videzz.net/embed-something.html";
+        assert!(Vidoza::supports_url(url).await.unwrap_or(false));
+
+        let source = r#"<video id="player" class="video-js" controls preload="none" width="100%" height="100%">
+            <source src="https://str38.vidoza.net/vod/v2/sd4rxefuyqqj/v.mp4" type="video/mp4" label="SD" res="720">
+        </video>"#;
+        let expected = "https://str38.vidoza.net/vod/v2/sd4rxefuyqqj/v.mp4";
+
+        let extracted = Vidoza::extract_video_url(ExtractFrom::Source(source.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(extracted.url, expected.to_string());
+        assert_eq!(extracted.referer.as_deref(), Some("https://vidoza.net/"));
+    }
 }
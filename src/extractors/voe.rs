@@ -1,9 +1,31 @@
-use anyhow::Context;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
 use base64::Engine;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-use super::{ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use super::utils::is_url_host_and_has_path;
+use super::{ExtractError, ExtractFrom, ExtractedVideo, Extractor, SupportedFrom};
+use crate::download::get_page_text;
+
+/// Known domains of Voe, which frequently changes them.
+const HOSTS: &[&str] = &[
+    "voe.sx",
+    "tubelessceliolymph.com",
+    "simpulumlamerop.com",
+    "urochsunloath.com",
+    "nathanfromsubject.com",
+    "yip.su",
+    "metagnathtuggers.com",
+    "donaldlineelse.com",
+    "jonathansociallike.com",
+    "maxfinishseveral.com",
+    "lauradaydo.com",
+];
+
+/// Strings, which only occur on Voe pages.
+const PAGE_MARKERS: &[&str] = &["VOE | Content Delivery Network", "voe-network.net", "VOEPlayer"];
 
 pub struct Voe;
 
@@ -15,11 +37,20 @@ impl Extractor for Voe {
         SupportedFrom::all()
     }
 
-    async fn supports_url(_url: &str) -> Option<bool> {
-        None
+    async fn supports_url(url: &str) -> Option<bool> {
+        if HOSTS.iter().any(|host| is_url_host_and_has_path(url, host, true, true)) {
+            return Some(true);
+        }
+
+        // Unknown domains are only checked, if the url looks like a Voe video
+        if !has_video_path(url) {
+            return None;
+        }
+
+        is_voe_page(url).await.then_some(true)
     }
 
-    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, anyhow::Error> {
+    async fn extract_video_url(from: ExtractFrom) -> Result<ExtractedVideo, ExtractError> {
         static REDIRECT_REGEX: Lazy<Regex> =
             Lazy::new(|| Regex::new(r#"window\.location\.href *= *(?:'([^']+)'|"([^"]+)") *;"#).unwrap());
 
@@ -51,7 +82,7 @@ impl Extractor for Voe {
 }
 
 impl Voe {
-    fn extract1(source: &str) -> Result<ExtractedVideo, anyhow::Error> {
+    fn extract1(source: &str) -> Result<ExtractedVideo, ExtractError> {
         static VIDEO_URL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"'hls': '([^']+)'"#).unwrap());
 
         VIDEO_URL_REGEX
@@ -66,10 +97,10 @@ impl Voe {
 
                 ExtractedVideo { url, referer: None }
             })
-            .context("Voe: failed to retrieve sources")
+            .ok_or(ExtractError::PatternNotFound("Voe: failed to retrieve sources"))
     }
 
-    fn extract2(source: &str) -> Result<ExtractedVideo, anyhow::Error> {
+    fn extract2(source: &str) -> Result<ExtractedVideo, ExtractError> {
         static VIDEO_URL_REGEX: Lazy<Regex> = Lazy::new(|| {
             Regex::new(
                 r#"let \w+ = '((?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{4}|[A-Za-z0-9+/]{3}=|[A-Za-z0-9+/]{2}={2}))';"#,
@@ -99,15 +130,58 @@ impl Voe {
                 url: video_url,
                 referer: None,
             })
-            .context("Voe: failed to retrieve sources")
+            .ok_or(ExtractError::PatternNotFound("Voe: failed to retrieve sources"))
+    }
+}
+
+fn has_video_path(url: &str) -> bool {
+    static VIDEO_PATH_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"^/(?:e/)?[a-zA-Z0-9]{12}/?$"#).unwrap());
+
+    url::Url::parse(url)
+        .map(|url| matches!(url.scheme(), "http" | "https") && VIDEO_PATH_REGEX.is_match(url.path()))
+        .unwrap_or(false)
+}
+
+/// Fetches the page and checks for Voe markers. The result is cached, since
+/// the url is checked multiple times during the extractor detection.
+async fn is_voe_page(url: &str) -> bool {
+    static CHECKED_URLS: Lazy<Mutex<HashMap<String, bool>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+    if let Some(is_voe) = CHECKED_URLS.lock().unwrap().get(url) {
+        return *is_voe;
     }
+
+    let is_voe = match get_page_text(url, None, None, None).await {
+        Ok(source) => PAGE_MARKERS.iter().any(|marker| source.contains(marker)),
+        Err(err) => {
+            log::trace!("Voe: failed to check page {}: {:#}", url, err);
+            false
+        }
+    };
+    CHECKED_URLS.lock().unwrap().insert(url.to_owned(), is_voe);
+
+    is_voe
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Voe;
+    use super::{has_video_path, Voe};
     use crate::extractors::{ExtractFrom, Extractor};
 
+    #[tokio::test]
+    async fn test_voe_supports_url() {
+        assert_eq!(Voe::supports_url("https://voe.sx/e/n6odadstvbey").await, Some(true));
+        assert_eq!(
+            Voe::supports_url("https://tubelessceliolymph.com/e/n6odadstvbey").await,
+            Some(true)
+        );
+        assert_eq!(Voe::supports_url("https://example.com/watch?v=1").await, None);
+
+        assert!(has_video_path("https://unknown-mirror.com/e/n6odadstvbey"));
+        assert!(has_video_path("https://unknown-mirror.com/n6odadstvbey"));
+        assert!(!has_video_path("https://unknown-mirror.com/videos/n6odadstvbey"));
+    }
+
     #[tokio::test]
     async fn test_voe1() {
         let source = "var sources = {
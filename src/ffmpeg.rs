@@ -104,14 +104,32 @@ fn ffmpeg_download_url() -> Result<String, anyhow::Error> {
 
 pub(crate) struct Ffmpeg {
     data_dir: PathBuf,
+    /// Supplied by the user, and used instead of searching or downloading one
+    custom_path: Option<PathBuf>,
 }
 
 impl Ffmpeg {
     pub(crate) fn new(data_dir: PathBuf) -> Self {
-        Ffmpeg { data_dir }
+        Ffmpeg {
+            data_dir,
+            custom_path: None,
+        }
+    }
+
+    pub(crate) fn custom_path(mut self, custom_path: Option<PathBuf>) -> Self {
+        self.custom_path = custom_path;
+        self
     }
 
     pub(crate) async fn auto_download(&self, downloader: &Downloader) -> Result<PathBuf, anyhow::Error> {
+        if let Some(custom_path) = &self.custom_path {
+            if !custom_path.is_file() {
+                anyhow::bail!("FFmpeg not found at \"{}\"", custom_path.display());
+            }
+
+            return Ok(custom_path.clone());
+        }
+
         if let Some(path) = self.get_ffmpeg_path() {
             return Ok(path);
         }
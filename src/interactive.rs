@@ -0,0 +1,165 @@
+use std::io::IsTerminal;
+
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{MultiSelect, Select};
+
+use crate::downloaders::{DownloadRequest, EpisodeNumber, EpisodesRequest, ListedSeason, SeriesInfo, VideoType};
+
+/// Whether the user can be asked, which is not the case if the prompts,
+/// which are drawn to stderr, are redirected or the input is not a terminal.
+pub(crate) fn is_available() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// Asks for the language, if several match the requested one, and for the
+/// episodes available in it. Returns `None`, if nothing was picked.
+pub(crate) fn pick_episodes(
+    series_info: &SeriesInfo,
+    seasons: &[ListedSeason],
+    requested: VideoType,
+) -> Result<Option<DownloadRequest>, anyhow::Error> {
+    let theme = ColorfulTheme::default();
+    let languages = get_available_languages(seasons, requested);
+
+    let language = match languages.as_slice() {
+        [] => anyhow::bail!("no episodes available in the requested language"),
+        [language] => *language,
+        _ => {
+            let items = languages.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let Some(index) = Select::with_theme(&theme)
+                .with_prompt(format!("Language of {}", series_info.title))
+                .items(&items)
+                .default(0)
+                .interact_opt()?
+            else {
+                return Ok(None);
+            };
+
+            languages[index]
+        }
+    };
+
+    let episodes = get_episodes_in_language(seasons, language);
+    let items = episodes.iter().map(|(_, _, line)| line).collect::<Vec<_>>();
+    let Some(picked) = MultiSelect::with_theme(&theme)
+        .with_prompt(format!("Episodes in {language} (space to pick, enter to download)"))
+        .items(&items)
+        .interact_opt()?
+    else {
+        return Ok(None);
+    };
+
+    if picked.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(DownloadRequest {
+        language,
+        episodes: EpisodesRequest::Picked(
+            picked
+                .into_iter()
+                .map(|index| (episodes[index].0, episodes[index].1))
+                .collect(),
+        ),
+    }))
+}
+
+/// Languages of the listed episodes, which match the requested one, in the
+/// order they first appear.
+fn get_available_languages(seasons: &[ListedSeason], requested: VideoType) -> Vec<VideoType> {
+    let mut languages = vec![];
+
+    for episode in seasons.iter().flat_map(|season| &season.episodes) {
+        for language in requested.convert_to_non_unspecified_video_types(&episode.languages) {
+            if !languages.contains(language) {
+                languages.push(*language);
+            }
+        }
+    }
+
+    languages
+}
+
+/// Season number, episode number and line shown in the picker of each
+/// episode available in the language.
+fn get_episodes_in_language(seasons: &[ListedSeason], language: VideoType) -> Vec<(u32, u32, String)> {
+    seasons
+        .iter()
+        .flat_map(|season| {
+            season
+                .episodes
+                .iter()
+                .map(move |episode| (season.season_number, episode))
+        })
+        .filter(|(_, episode)| episode.languages.contains(&language))
+        .filter_map(|(season_number, episode)| {
+            let &EpisodeNumber::Number(episode_number) = &episode.episode_info.episode_number else {
+                return None;
+            };
+            let mut line = episode.episode_info.season_episode();
+
+            if let Some(name) = &episode.episode_info.name {
+                line.push(' ');
+                line.push_str(name);
+            }
+
+            Some((season_number, episode_number, line))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downloaders::{EpisodeInfo, EpisodeNumber, Language, ListedEpisode, ListedSeason, VideoType};
+    use crate::interactive::{get_available_languages, get_episodes_in_language};
+
+    #[test]
+    fn test_pick_options() {
+        let episode = |season: u32, episode: u32, name: Option<&str>, languages: Vec<VideoType>| ListedEpisode {
+            episode_info: EpisodeInfo {
+                name: name.map(ToOwned::to_owned),
+                season_number: Some(season),
+                episode_number: EpisodeNumber::Number(episode),
+                max_episode_number_in_season: None,
+            },
+            languages,
+        };
+        let seasons = vec![
+            ListedSeason {
+                season_number: 1,
+                episodes: vec![
+                    episode(1, 1, Some("Secrets"), vec![VideoType::Dub(Language::German)]),
+                    episode(
+                        1,
+                        2,
+                        None,
+                        vec![VideoType::Sub(Language::English), VideoType::Dub(Language::German)],
+                    ),
+                ],
+            },
+            ListedSeason {
+                season_number: 2,
+                episodes: vec![episode(2, 1, None, vec![VideoType::Sub(Language::English)])],
+            },
+        ];
+
+        assert_eq!(
+            get_available_languages(&seasons, VideoType::Unspecified(Language::Unspecified)),
+            [VideoType::Dub(Language::German), VideoType::Sub(Language::English)]
+        );
+        assert_eq!(
+            get_available_languages(&seasons, VideoType::Sub(Language::Unspecified)),
+            [VideoType::Sub(Language::English)]
+        );
+        assert!(get_available_languages(&seasons, VideoType::Raw).is_empty());
+
+        assert_eq!(
+            get_episodes_in_language(&seasons, VideoType::Dub(Language::German)),
+            [(1, 1, "S01E001 Secrets".to_owned()), (1, 2, "S01E002".to_owned())]
+        );
+        assert_eq!(
+            get_episodes_in_language(&seasons, VideoType::Sub(Language::English)),
+            [(1, 2, "S01E002".to_owned()), (2, 1, "S02E001".to_owned())]
+        );
+    }
+}
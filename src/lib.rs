@@ -1,7 +1,20 @@
 #![allow(warnings, unused)]
 
+pub(crate) mod archive;
+pub(crate) mod chrome;
+pub mod cookies;
+pub(crate) mod dirs;
 pub(crate) mod download;
 pub mod downloaders;
+pub(crate) mod exec;
 pub mod extractors;
+pub(crate) mod ffmpeg;
 pub(crate) mod logger;
+pub(crate) mod nfo;
+pub(crate) mod output_template;
+pub(crate) mod sample_aes;
+pub mod session;
+pub(crate) mod subtitles;
 pub(crate) mod utils;
+
+pub use session::Session;
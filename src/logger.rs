@@ -1,6 +1,9 @@
+use std::path::Path;
+
+use anyhow::Context;
 use chrono::Local;
 use env_logger::fmt::{Color, Style, StyledValue};
-use env_logger::{Builder, Logger};
+use env_logger::{Builder, Logger, Target, WriteStyle};
 use log::{Level, LevelFilter};
 
 pub(crate) fn default_logger(debug: bool) -> Logger {
@@ -10,6 +13,22 @@ pub(crate) fn default_logger(debug: bool) -> Logger {
         .build()
 }
 
+/// Appends all messages uncolored to the file, regardless of the level of
+/// the console.
+pub(crate) fn file_logger(path: &Path) -> Result<Logger, anyhow::Error> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open \"{}\"", path.display()))?;
+
+    Ok(formatted_local_time_builder("%Y-%m-%d %H:%M:%S.%3f")
+        .filter_level(LevelFilter::Trace)
+        .write_style(WriteStyle::Never)
+        .target(Target::Pipe(Box::new(file)))
+        .build())
+}
+
 fn formatted_local_time_builder(fmt: &'static str) -> Builder {
     let mut builder = Builder::new();
 
@@ -97,6 +116,9 @@ pub(crate) mod log_wrapper {
     pub struct LogWrapper<L: Log> {
         bar: Arc<Mutex<Option<MultiProgress>>>,
         log: L,
+        /// Additional log, e.g. to a file, which is not written to the console
+        /// and therefore does not suspend the progress bars
+        file_log: Option<Box<dyn Log>>,
     }
 
     impl<L: Log + 'static> LogWrapper<L> {
@@ -104,9 +126,15 @@ pub(crate) mod log_wrapper {
             Self {
                 bar: Arc::new(Mutex::new(bar)),
                 log,
+                file_log: None,
             }
         }
 
+        pub fn file_log(mut self, file_log: Option<Box<dyn Log>>) -> Self {
+            self.file_log = file_log;
+            self
+        }
+
         /// Installs this as the lobal logger,
         ///
         /// tries to find the correct argument to set_max_level
@@ -141,6 +169,13 @@ pub(crate) mod log_wrapper {
     }
 
     impl SetLogWrapper {
+        /// A wrapper without an installed logger, e.g. if sdl is used as a library.
+        pub fn detached() -> Self {
+            Self {
+                bar: Arc::new(Mutex::new(None)),
+            }
+        }
+
         pub fn set_multi(&mut self, multi: Option<MultiProgress>) {
             *self.bar.lock().unwrap() = multi;
         }
@@ -149,6 +184,10 @@ pub(crate) mod log_wrapper {
     impl<L: Log> Log for LogWrapper<L> {
         fn enabled(&self, metadata: &log::Metadata) -> bool {
             self.log.enabled(metadata)
+                || self
+                    .file_log
+                    .as_ref()
+                    .is_some_and(|file_log| file_log.enabled(metadata))
         }
 
         fn log(&self, record: &log::Record) {
@@ -160,10 +199,18 @@ pub(crate) mod log_wrapper {
                     self.log.log(record);
                 }
             }
+
+            if let Some(file_log) = &self.file_log {
+                file_log.log(record);
+            }
         }
 
         fn flush(&self) {
             self.log.flush();
+
+            if let Some(file_log) = &self.file_log {
+                file_log.flush();
+            }
         }
     }
 }
@@ -1,40 +1,99 @@
 #![cfg_attr(not(debug_assertions), allow(warnings, unused))]
-use std::borrow::Cow;
+use std::cell::RefCell;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use anyhow::Context;
 use chrono::Local;
 use clap::Parser;
 use cli::{Args, Extractor};
-use download::{DownloadManager, Downloader, InternalDownloadTask};
+use download::{
+    DefaultClientConfig, DownloadManager, EpisodeOutcome, EpisodeStatus, HostLimiter, InternalDownloadTask,
+};
+use downloaders::selectors::SelectorOverrides;
 use downloaders::{DownloadRequest, DownloadTask, InstantiatedDownloader};
-use extractors::{extract_video_url, extract_video_url_with_extractor_from_url};
-use ffmpeg::Ffmpeg;
-use logger::log_wrapper::{LogWrapper, SetLogWrapper};
-use tokio_stream::wrappers::UnboundedReceiverStream;
-
+use extractors::{extract_video_url_with_extractor_from_url, ExtractedVideo};
+use futures_util::StreamExt;
+use logger::log_wrapper::LogWrapper;
+use mpv::MpvPrefetch;
+use output_template::TemplateValues;
+use session::Session;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+pub(crate) mod archive;
 pub(crate) mod chrome;
 pub(crate) mod cli;
+pub(crate) mod cookies;
 pub(crate) mod dirs;
 pub(crate) mod download;
 pub(crate) mod downloaders;
+pub(crate) mod exec;
 pub(crate) mod extractors;
 pub(crate) mod ffmpeg;
+pub(crate) mod interactive;
 pub(crate) mod logger;
+pub(crate) mod metadata;
 pub(crate) mod mpv;
+pub(crate) mod nfo;
+pub(crate) mod output_template;
+pub(crate) mod sample_aes;
+pub(crate) mod session;
+pub(crate) mod subtitles;
+pub(crate) mod update;
 pub(crate) mod utils;
 
+/// A url to download, together with the arguments which apply to it.
+struct Entry {
+    url: String,
+    direct_media: bool,
+    save_directory: PathBuf,
+    args: Args,
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     // Parse arguments
     let args = cli::Args::parse();
     let debug = args.debug;
-    let url = args.url.deref();
-    let extractor = args.extractor.as_ref();
 
     // Set up logger
     let logger = logger::default_logger(debug);
-    let mut log_wrapper = LogWrapper::new(None, logger).try_init().unwrap();
+    let (file_logger, file_logger_error) = match args.log_file.as_deref().map(logger::file_logger) {
+        Some(Ok(file_logger)) => (Some(Box::new(file_logger) as Box<dyn log::Log>), None),
+        Some(Err(err)) => (None, Some(err)),
+        None => (None, None),
+    };
+    let log_wrapper = LogWrapper::new(None, logger).file_log(file_logger).try_init().unwrap();
+
+    if let Some(err) = file_logger_error {
+        log::error!("Failed to set up log file: {:#}", err);
+        std::process::exit(1);
+    }
+
+    // Configure client for page fetches
+    let proxy = match args
+        .proxy
+        .as_ref()
+        .map(|proxy_url| reqwest::Proxy::all(proxy_url.clone()))
+    {
+        Some(Ok(proxy)) => Some(proxy),
+        Some(Err(err)) => {
+            log::error!("Failed to set up proxy: {:#}", err);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    download::set_default_client_config(DefaultClientConfig {
+        retries: args.extract_retries,
+        proxy,
+        user_agent: args.user_agent.clone(),
+        network: args.get_network_config(),
+    })
+    .unwrap();
 
     // Create data dir
     let data_dir = match dirs::get_data_dir().await {
@@ -45,23 +104,248 @@ async fn main() {
         }
     };
 
-    // Get save directory
-    let save_directory = match dirs::get_save_directory(None) {
-        Ok(dir) => dir,
+    // Load the selector overrides, which patch changed site markup
+    let selector_overrides = match downloaders::selectors::load(&data_dir).await {
+        Ok(overrides) => overrides,
         Err(err) => {
-            log::error!("Failed to get save directory: {:#}", err);
+            log::error!("Failed to load selector overrides: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Load the extractors of simple hosts, which are defined in the config file
+    match extractors::custom::load(&data_dir).await {
+        Ok(custom_extractors) => extractors::custom::set_extractors(custom_extractors),
+        Err(err) => {
+            log::error!("Failed to load custom extractors: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Load the cookies, which are sent with the requests of matching hosts
+    if let Some(cookies_path) = &args.cookies {
+        match cookies::load(cookies_path).await {
+            Ok(loaded_cookies) => cookies::set_cookies(loaded_cookies),
+            Err(err) => {
+                log::error!("Failed to load cookies: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Check for a newer release, if requested
+    if args.check_updates {
+        if let Err(err) = update::check_for_updates(&data_dir).await {
+            log::warn!("Failed to check for updates: {:#}", err);
+        }
+    }
+
+    extractors::generic::set_enabled(args.generic);
+    downloaders::utils::set_page_load_wait(args.page_load_wait_ms.clone());
+    downloaders::utils::set_scrape_timeout(args.get_scrape_timeout());
+
+    // The browser and FFmpeg are set up once for all entries
+    let session_builder = Session::builder()
+        .data_dir(data_dir)
+        .backend(args.browser)
+        .driver_timeout(args.get_driver_timeout())
+        .browser_args(args.chrome_args.clone())
+        .ffmpeg_path(args.ffmpeg_path.clone())
+        .ublock_rules(args.ublock_rules.clone())
+        .ublock(!args.no_ublock)
+        .update_ublock(args.update_ublock)
+        .user_data_dir(args.user_data_dir.clone())
+        .user_agent(args.user_agent.clone())
+        .proxy(args.proxy.clone())
+        .network(args.get_network_config())
+        .retries(args.retries.inner().copied())
+        .debug(debug)
+        .hide_progress_bars(args.progress_json)
+        .log_wrapper(log_wrapper);
+
+    // Collect the urls, which are multiple for a batch file
+    if args.from_file.is_some() && args.url.is_some() {
+        log::error!("A url cannot be combined with --from-file");
+        std::process::exit(1);
+    }
+
+    // Shared by all entries
+    let concurrent_series = args.concurrent_series;
+    let rate_limiter = args
+        .limit_rate
+        .map(|rate| async_speed_limit::Limiter::new(rate.get() as f64));
+    let host_limiter = args.per_host_concurrency.map(HostLimiter::new);
+    let unsupported_episodes = Arc::new(AtomicBool::new(false));
+
+    let mut any_failed = false;
+    let entries_args = match args.from_file.clone() {
+        Some(path) => match cli::read_batch_file(&path) {
+            Ok(batch_entries) => batch_entries
+                .into_iter()
+                .filter_map(|(line_number, entry_args)| match entry_args {
+                    Ok(entry_args) if entry_args.url.is_some() => Some(entry_args),
+                    Ok(_) => {
+                        log::error!("Line {} of the batch file has no url", line_number);
+                        any_failed = true;
+                        None
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Failed to parse line {} of the batch file: {}",
+                            line_number,
+                            err.to_string().trim()
+                        );
+                        any_failed = true;
+                        None
+                    }
+                })
+                .collect(),
+            Err(err) => {
+                log::error!("Failed to read batch file: {:#}", err);
+                std::process::exit(1);
+            }
+        },
+        None => vec![args],
+    };
+
+    // Fail fast if extractor name, url or save directory is invalid
+    let mut entries = vec![];
+
+    for entry_args in entries_args {
+        let url = entry_args.url.clone().unwrap();
+
+        let Some(direct_media) = check_url(&url, &entry_args).await else {
+            any_failed = true;
+            continue;
+        };
+
+        let Some(save_directory) = check_save_directory(&entry_args).await else {
+            any_failed = true;
+            continue;
+        };
+
+        entries.push(Entry {
+            url,
+            direct_media,
+            save_directory,
+            args: entry_args,
+        });
+    }
+
+    if entries.is_empty() {
+        std::process::exit(1);
+    }
+
+    // Set up FFmpeg, and ChromeDriver if needed, but not more browsers than series
+    let series_count = entries
+        .iter()
+        .filter(|entry| entry.args.extractor.is_none() && !entry.direct_media)
+        .count();
+    let drivers = NonZeroUsize::new(series_count.min(concurrent_series.get())).unwrap_or(NonZeroUsize::MIN);
+    let session = match session_builder.browser(series_count > 0).drivers(drivers).start().await {
+        Ok(session) => session,
+        Err(err) => {
+            log::error!("Failed to set up: {:#}", err);
             std::process::exit(1);
         }
     };
 
-    // Fail fast if extractor name or url is invalid
-    if let Some(extractor) = extractor {
+    // Stop gracefully on Ctrl-C, and immediately on a second one
+    let cancellation_token = CancellationToken::new();
+    let signal_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Cancelling downloads, press Ctrl-C again to exit immediately");
+            signal_token.cancel();
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
+
+    // Do much of the bulk work, where only downloads need to clean up after cancellation.
+    // A failed entry does not stop the following ones. Each entry borrows a browser,
+    // so that as many series are scraped at once as browsers were started.
+    let free_drivers = RefCell::new(session.drivers().collect::<Vec<_>>());
+    let concurrent_entries = free_drivers.borrow().len().max(1);
+    let entries_failed = futures_util::stream::iter(entries)
+        .map(|entry| {
+            let (session, free_drivers, cancellation_token, selector_overrides) =
+                (&session, &free_drivers, &cancellation_token, &selector_overrides);
+            let unsupported_episodes = unsupported_episodes.clone();
+            let (rate_limiter, host_limiter) = (rate_limiter.clone(), host_limiter.clone());
+
+            async move {
+                if cancellation_token.is_cancelled() {
+                    return false;
+                }
+
+                let driver = free_drivers.borrow_mut().pop();
+                let downloads_files = entry.args.downloads_files();
+                let work_future = do_after_chrome_driver(
+                    session,
+                    driver,
+                    rate_limiter,
+                    host_limiter,
+                    unsupported_episodes,
+                    selector_overrides,
+                    cancellation_token,
+                    entry,
+                );
+                let should_error_quit = if downloads_files {
+                    work_future.await
+                } else {
+                    tokio::select! {
+                        should_error_quit = work_future => should_error_quit,
+                        _ = cancellation_token.cancelled() => true,
+                    }
+                };
+
+                free_drivers.borrow_mut().extend(driver);
+                should_error_quit
+            }
+        })
+        .buffer_unordered(concurrent_entries)
+        .collect::<Vec<_>>()
+        .await;
+
+    any_failed |= entries_failed.into_iter().any(|should_error_quit| should_error_quit);
+
+    // Quit ChromeDriver
+    session.quit().await;
+
+    if cancellation_token.is_cancelled() {
+        std::process::exit(130);
+    }
+
+    if any_failed {
+        std::process::exit(1);
+    }
+
+    if unsupported_episodes.load(Ordering::Relaxed) {
+        std::process::exit(3);
+    }
+}
+
+/// Checks whether the url can be downloaded, and returns if it is a direct
+/// media url. Errors are logged.
+async fn check_url(url: &str, args: &Args) -> Option<bool> {
+    if let Some(only_extractor) = &args.only_extractor {
+        if !extractors::exists_extractor_with_name(only_extractor) {
+            log::error!("Failed to find an extractor named: {}", only_extractor);
+            return None;
+        }
+    }
+
+    if let Some(extractor) = &args.extractor {
         let extractor_name = match extractor {
             Extractor::Auto => None,
             Extractor::Name(extractor_name) => {
                 if !extractors::exists_extractor_with_name(extractor_name) {
                     log::error!("Failed to find an extractor named: {}", extractor_name);
-                    std::process::exit(1);
+                    return None;
                 }
 
                 Some(extractor_name.deref())
@@ -79,121 +363,131 @@ async fn main() {
                 log::error!("Failed to find an extractor for the url: {}", url);
             }
 
-            std::process::exit(1);
+            return None;
         }
     } else if !downloaders::exists_downloader_for_url(url).await {
-        log::error!("No downloader found for the url: {}", url);
-        std::process::exit(1);
-    }
-
-    // Set up FFmpeg, and ChromeDriver if needed
-    let asset_downloader = Downloader::new(&mut log_wrapper, debug, None, None, None);
-    let ffmpeg = Ffmpeg::new(data_dir.clone());
-
-    let (mut chrome, ffmpeg_install_result) = if extractor.is_none() {
-        let chrome_ffmpeg_future = futures_util::future::join(
-            chrome::ChromeDriver::get(&data_dir, &asset_downloader, !debug),
-            ffmpeg.auto_download(&asset_downloader),
-        );
-        let (chrome, ffmpeg_install_result) = tokio::select! {
-            biased;
-
-            result = chrome_ffmpeg_future => result,
-            _ = asset_downloader.tick() => unreachable!(),
-        };
-
-        let chrome = match chrome {
-            Ok(chrome) => chrome,
-            Err(err) => {
-                log::error!("Failed to create ChromeDriver: {:#}", err);
-                std::process::exit(1);
+        if downloaders::is_direct_media_url(url) {
+            if args.list || args.dump_json || args.print_filename {
+                log::error!("--list, --dump-json and --print-filename are not supported for direct media urls");
+                return None;
             }
-        };
-
-        (Some(chrome), ffmpeg_install_result)
-    } else {
-        let ffmpeg_install_result = tokio::select! {
-            biased;
-
-            result = ffmpeg.auto_download(&asset_downloader) => result,
-            _ = asset_downloader.tick() => unreachable!(),
-        };
 
-        (None, ffmpeg_install_result)
-    };
+            log::debug!("No downloader found, downloading the url directly");
+            return Some(true);
+        } else {
+            log::error!("No downloader found for the url: {}", url);
+            return None;
+        }
+    }
 
-    // Do much of the bulk work
-    let should_error_quit = do_after_chrome_driver(
-        ffmpeg_install_result,
-        asset_downloader,
-        chrome.as_mut().map(|(chrome, _)| chrome),
-        log_wrapper,
-        save_directory,
-        args,
-    )
-    .await;
+    Some(false)
+}
 
-    // Quit ChromeDriver
-    if let Some((chrome, mut chrome_process)) = chrome {
-        if let Err(err) = chrome.quit().await {
-            log::warn!("Failed to quit ChromeDriver: {}", err);
+/// Resolves the save directory, and creates it if files are written into it.
+/// Errors are logged.
+async fn check_save_directory(args: &Args) -> Option<PathBuf> {
+    let save_directory = match dirs::get_save_directory(args.output_dir.clone()) {
+        Ok(save_directory) => save_directory,
+        Err(err) => {
+            log::error!("Failed to get save directory: {:#}", err);
+            return None;
         }
-
-        if let Err(err) = chrome_process.kill() {
-            log::warn!("Failed to kill ChromeDriver: {}", err);
+    };
+    let writes_files = args.downloads_files() || args.write_info_json;
+
+    if writes_files {
+        if let Err(err) = dirs::prepare_save_directory(&save_directory).await {
+            log::error!(
+                "Failed to use \"{}\" as save directory: {:#}",
+                save_directory.display(),
+                err
+            );
+            return None;
         }
     }
 
-    if should_error_quit {
-        std::process::exit(1);
-    }
+    Some(save_directory)
 }
 
 async fn do_after_chrome_driver(
-    ffmpeg_install_result: Result<PathBuf, anyhow::Error>,
-    asset_downloader: Downloader,
-    chrome: Option<&mut thirtyfour::WebDriver>,
-    mut log_wrapper: SetLogWrapper,
-    save_directory: PathBuf,
-    args: Args,
+    session: &Session,
+    driver: Option<&thirtyfour::WebDriver>,
+    rate_limiter: Option<async_speed_limit::Limiter>,
+    host_limiter: Option<HostLimiter>,
+    unsupported_episodes: Arc<AtomicBool>,
+    selector_overrides: &SelectorOverrides,
+    cancellation_token: &CancellationToken,
+    entry: Entry,
 ) -> bool {
+    let Entry {
+        url,
+        direct_media,
+        save_directory,
+        args,
+    } = entry;
+    let url = url.deref();
     let debug = args.debug;
     let extractor = args.extractor.as_ref();
-    let url = args.url.deref();
     let max_concurrent = args.concurrent_downloads.inner().copied();
-
-    let ffmpeg_path = match ffmpeg_install_result {
-        Ok(path) => path,
-        Err(err) => {
-            log::error!("Failed to get path to FFmpeg: {:#}", err);
-            return true;
-        }
-    };
-
-    asset_downloader.clear();
+    let quality = args.quality;
+    let subtitles = args.get_subtitle_options();
 
     // Download episodes
-    let user_agent = match &chrome {
-        Some(driver) => chrome::get_user_agent(driver).await,
-        None => None,
-    };
-    let episodes_downloader = if !args.mpv {
-        Some(Downloader::new(
-            &mut log_wrapper,
-            debug,
-            Some(ffmpeg_path),
-            user_agent,
-            Some(args.retries.inner().copied()),
-        ))
+    let episodes_downloader = if args.downloads_files() {
+        Some(
+            session
+                .create_downloader()
+                .audio_extraction(args.get_audio_extraction())
+                .headers(args.headers.clone())
+                .segment_concurrency(args.segment_concurrency)
+                .rate_limiter(rate_limiter)
+                .host_limiter(host_limiter)
+                .container(args.container)
+                .require_ffmpeg(args.require_ffmpeg)
+                .keep_intermediate(args.keep_ts)
+                .verify(args.verify)
+                .progress_json(args.progress_json)
+                .redirect_policy(args.get_redirect_policy())
+                .retry_budget(args.get_retry_budget())
+                .send_origin(args.send_origin)
+                .min_free_space(args.min_free_space.map(NonZeroU64::get))
+                .min_file_size(args.min_file_size)
+                .write_playlist(args.write_playlist)
+                .cancellation_token(cancellation_token.clone()),
+        )
     } else {
         None
     };
 
-    if let Some(extractor) = extractor {
-        let extractor_result = if let Extractor::Name(extractor_name) = extractor {
-            extract_video_url_with_extractor_from_url(url, extractor_name, None, None).await
-        } else {
-            extract_video_url(url, None, None).await
+    // Download upcoming episodes ahead while playing
+    let mpv_prefetch = match NonZeroUsize::new(args.mpv_prefetch) {
+        Some(depth) if args.mpv => Some(MpvPrefetch {
+            downloader: session
+                .create_downloader()
+                .headers(args.headers.clone())
+                .cancellation_token(cancellation_token.clone()),
+            depth,
+            quality,
+            subtitles,
+        }),
+        _ => None,
+    };
+
+    if extractor.is_some() || direct_media {
+        let extractor_result = match extractor {
+            Some(Extractor::Name(extractor_name)) => extract_video_url_with_extractor_from_url(
+                url,
+                extractor_name,
+                session.user_agent().map(str::to_owned),
+                None,
+            )
+            .await
+            .map(|result| result.map_err(anyhow::Error::from)),
+            Some(Extractor::Auto) => Some(session.extract(url).await),
+            None => Some(Ok(ExtractedVideo {
+                url: url.to_owned(),
+                referer: None,
+            })),
         };
 
         let extracted_video = match extractor_result {
@@ -205,62 +499,62 @@ async fn do_after_chrome_driver(
             None => unreachable!(),
         };
 
-        let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S.%3f").to_string();
-        let mut i = 0u32;
-
-        let output_path = loop {
-            let name = if i == 0 {
-                Cow::Borrowed(&timestamp)
-            } else {
-                Cow::Owned(format!("{}-{}", timestamp, i))
+        if args.dry_run {
+            let extractor_name = match extractor {
+                Some(Extractor::Name(extractor_name)) => extractors::normalized_name(extractor_name),
+                _ => None,
             };
 
-            let mp4_name = format!("{}.mp4", name);
-            let mp4_exists = match save_directory.join(&mp4_name).try_exists() {
-                Ok(exists) => exists,
-                Err(err) => {
-                    log::error!("Failed to check if the file \"{}\" exists: {}", mp4_name, err);
-                    return true;
-                }
-            };
+            return metadata::check_video(
+                &extracted_video.url,
+                session.user_agent(),
+                extracted_video.referer.as_deref(),
+                extractor_name,
+                &args.headers,
+                args.get_redirect_policy(),
+            )
+            .await;
+        }
 
-            let ts_name = format!("{}.ts", name);
-            let ts_exists = match save_directory.join(&ts_name).try_exists() {
-                Ok(exists) => exists,
+        // Only the title is known, which is the current time
+        let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S.%3f").to_string();
+        let base_name = args.output_template.render(&TemplateValues {
+            title: Some(&timestamp),
+            ..Default::default()
+        });
+        let base_name = if base_name.is_empty() { timestamp } else { base_name };
+
+        let result = if let Some(episodes_downloader) = episodes_downloader {
+            // Named like the files of series, but with the current time as title
+            let output_path = match episodes_downloader.find_free_output_path(&save_directory, &base_name) {
+                Ok(output_path) => output_path,
                 Err(err) => {
-                    log::error!("Failed to check if the file \"{}\" exists: {}", ts_name, err);
+                    log::error!("Failed to find a name for the file: {:#}", err);
                     return true;
                 }
             };
-
-            if !mp4_exists && !ts_exists {
-                break save_directory.join(name.deref());
-            }
-
-            match i.checked_add(1) {
-                Some(next_i) => i = next_i,
-                None => {
-                    log::error!("Failed to find a name for the file");
-                    return true;
-                }
-            }
-        };
-
-        let result = if let Some(episodes_downloader) = episodes_downloader {
             let download_future = episodes_downloader.download_to_file(
                 InternalDownloadTask::new(output_path, extracted_video.url)
                     .output_path_has_extension(false)
-                    .referer(extracted_video.referer),
+                    .referer(extracted_video.referer)
+                    .quality(quality)
+                    .subtitles(subtitles)
+                    .resume(args.continue_downloads),
             );
 
             tokio::select! {
                 biased;
 
-                result = download_future => result,
+                result = download_future => result.map(|_| ()),
                 _ = episodes_downloader.tick() => unreachable!(),
             }
         } else {
-            mpv::start_mpv(&extracted_video.url, debug)
+            mpv::start_mpv(
+                &extracted_video.url,
+                extracted_video.referer.as_deref(),
+                &args.get_mpv_options(),
+            )
+            .await
         };
 
         if let Err(err) = result {
@@ -273,10 +567,29 @@ async fn do_after_chrome_driver(
             return true;
         }
     } else {
-        let series_downloader = downloaders::find_downloader_for_url(chrome.unwrap(), debug, url)
+        if let Ok(parsed_url) = url::Url::parse(url) {
+            if let Err(err) = chrome::add_cookies(driver.unwrap(), &parsed_url).await {
+                log::warn!("Failed to add cookies to the browser: {:#}", err);
+            }
+        }
+
+        let series_downloader = downloaders::find_downloader_for_url(driver.unwrap(), debug, url)
             .await
             .unwrap();
-        let download_settings = args.get_download_settings();
+        let download_settings = args
+            .get_download_settings()
+            .unsupported_episodes(unsupported_episodes)
+            .selector_overrides(selector_overrides.clone());
+        let download_settings = match &args.archive {
+            Some(archive_path) => match archive::read_archive(archive_path).await {
+                Ok(archive) => download_settings.archive(archive),
+                Err(err) => {
+                    log::error!("Failed to read archive: {:#}", err);
+                    return true;
+                }
+            },
+            None => download_settings,
+        };
         let series_info = match series_downloader.get_series_info().await {
             Ok(info) => info,
             Err(err) => {
@@ -285,29 +598,140 @@ async fn do_after_chrome_driver(
             }
         };
 
-        let download_request = DownloadRequest {
-            language: args.get_video_type(),
-            episodes: args.get_episodes_request(),
+        if args.list {
+            match series_downloader.list_episodes().await {
+                Ok(seasons) => metadata::print_episode_list(&series_info, &seasons),
+                Err(err) => {
+                    log::error!("Failed to list episodes: {:#}", err);
+                    return true;
+                }
+            }
+
+            return false;
+        }
+
+        if args.write_info_json {
+            let info_json_result = match series_downloader.list_episodes().await {
+                Ok(seasons) => metadata::write_info_json(&save_directory, &series_info, &seasons).await,
+                Err(err) => Err(err).context("failed to get series structure"),
+            };
+
+            match info_json_result {
+                Ok(path) => log::info!("Wrote series info to \"{}\"", path.display()),
+                Err(err) => log::warn!("Failed to write series info: {:#}", err),
+            }
+        }
+
+        let download_request = if args.interactive && interactive::is_available() {
+            let seasons = match series_downloader.list_episodes().await {
+                Ok(seasons) => seasons,
+                Err(err) => {
+                    log::error!("Failed to list episodes: {:#}", err);
+                    return true;
+                }
+            };
+
+            match interactive::pick_episodes(&series_info, &seasons, args.get_video_type()) {
+                Ok(Some(download_request)) => download_request,
+                Ok(None) => {
+                    log::info!("No episodes picked");
+                    return false;
+                }
+                Err(err) => {
+                    log::error!("Failed to pick episodes: {:#}", err);
+                    return true;
+                }
+            }
+        } else {
+            if args.interactive {
+                log::warn!("Not running in a terminal, downloading without picking episodes");
+            }
+
+            DownloadRequest {
+                language: args.get_video_type(),
+                episodes: args.get_episodes_request(),
+            }
         };
 
-        if let Some(episodes_downloader) = episodes_downloader {
+        if args.dump_json || args.dry_run || args.print_filename {
+            let (tx, rx) = tokio::sync::mpsc::channel::<DownloadTask>(download::QUEUED_DOWNLOAD_TASKS);
+            let rx_stream = ReceiverStream::new(rx);
+
+            let print_future = async {
+                if args.dry_run {
+                    metadata::check_episodes(
+                        rx_stream,
+                        session.user_agent(),
+                        &args.headers,
+                        args.get_redirect_policy(),
+                    )
+                    .await
+                } else if args.print_filename {
+                    metadata::print_filenames(
+                        rx_stream,
+                        &series_info,
+                        &save_directory,
+                        &args.output_template,
+                        args.season_folders,
+                        args.container,
+                        args.get_audio_extraction().as_ref(),
+                    )
+                    .await;
+                    false
+                } else {
+                    metadata::dump_episodes_json(rx_stream, &series_info).await;
+                    false
+                }
+            };
+            let (downloader_result, any_check_failed) = tokio::join!(
+                series_downloader.download(download_request, download_settings, tx),
+                print_future,
+            );
+
+            if let Err(err) = downloader_result {
+                log::error!("Failed to download series: {:#}", err);
+                return true;
+            }
+
+            return any_check_failed;
+        } else if let Some(episodes_downloader) = episodes_downloader {
             let (download_manager, sender) =
                 DownloadManager::new(episodes_downloader, max_concurrent, save_directory, series_info);
-
-            let (downloader_result, _) = tokio::join!(
-                series_downloader.download(download_request, download_settings, sender),
-                download_manager.progress_downloads(),
-            );
+            let download_manager = download_manager
+                .quality(quality)
+                .subtitles(subtitles)
+                .resume(args.continue_downloads)
+                .overwrite(args.overwrite)
+                .skip_existing(args.skip_existing)
+                .output_template(args.output_template.clone())
+                .season_folders(args.season_folders)
+                .archive(args.archive.clone())
+                .extractor_timeout(args.get_extractor_timeout())
+                .write_nfo(args.write_nfo)
+                .exec(args.exec.clone())
+                .cancellation_token(cancellation_token.clone());
+
+            let (downloader_result, outcomes) = session::download_with_manager(
+                &series_downloader,
+                download_request,
+                download_settings,
+                download_manager,
+                sender,
+                cancellation_token,
+            )
+            .await;
+            print_summary(&outcomes);
 
             if let Err(err) = downloader_result {
                 log::error!("Failed to download series: {:#}", err);
                 return true;
             }
         } else {
-            let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<DownloadTask>();
-            let rx_stream = UnboundedReceiverStream::new(rx);
+            let (tx, rx) = tokio::sync::mpsc::channel::<DownloadTask>(download::QUEUED_DOWNLOAD_TASKS);
+            let rx_stream = ReceiverStream::new(rx);
 
-            let mpv_future = mpv::start_mpv_with_ipc(rx_stream, series_info, debug);
+            let mpv_options = args.get_mpv_options();
+            let mpv_future = mpv::start_mpv_with_ipc(rx_stream, series_info, &mpv_options, mpv_prefetch);
             tokio::pin!(mpv_future);
 
             let (downloader_errored, mpv_result) = tokio::select! {
@@ -331,3 +755,44 @@ async fn do_after_chrome_driver(
 
     false
 }
+
+/// Prints each episode with whether it was downloaded, skipped or failed,
+/// followed by the totals.
+fn print_summary(outcomes: &[EpisodeOutcome]) {
+    if outcomes.is_empty() {
+        return;
+    }
+
+    let episode_width = outcomes.iter().map(|outcome| outcome.episode.len()).max().unwrap_or(0);
+    let (mut downloaded, mut skipped, mut failed) = (0, 0, 0);
+
+    for outcome in outcomes {
+        let (symbol, reason) = match &outcome.status {
+            EpisodeStatus::Downloaded => {
+                downloaded += 1;
+                (console::style("✓").green(), None)
+            }
+            EpisodeStatus::Skipped => {
+                skipped += 1;
+                (console::style("⊘").yellow(), None)
+            }
+            EpisodeStatus::Failed(reason) => {
+                failed += 1;
+                (console::style("✗").red(), Some(reason))
+            }
+        };
+        let symbol = symbol.for_stderr();
+        let episode = &outcome.episode;
+
+        match reason {
+            Some(reason) => log::warn!(
+                "{symbol} {episode:episode_width$}  {}: {}",
+                outcome.name,
+                console::style(reason).red().for_stderr()
+            ),
+            None => log::info!("{symbol} {episode:episode_width$}  {}", outcome.name),
+        }
+    }
+
+    log::info!("{downloaded} downloaded, {skipped} skipped, {failed} failed");
+}
@@ -0,0 +1,381 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use futures_util::StreamExt;
+use reqwest::header::HeaderName;
+use serde::Serialize;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::download::{
+    follow_redirects, get_episode_output_path, get_expected_extension, prepare_series_name_for_file, AudioExtraction,
+    Container, FollowedRedirects, RedirectPolicy,
+};
+use crate::downloaders::{DownloadTask, ListedSeason, SeriesInfo, SeriesStructure};
+use crate::output_template::OutputTemplate;
+
+#[derive(Serialize)]
+struct InfoJson<'a> {
+    #[serde(flatten)]
+    series_info: &'a SeriesInfo,
+    #[serde(flatten)]
+    structure: &'a SeriesStructure,
+}
+
+#[derive(Serialize)]
+struct EpisodeJson<'a> {
+    series_title: &'a str,
+    #[serde(flatten)]
+    task: &'a DownloadTask,
+}
+
+pub(crate) async fn write_info_json(
+    save_directory: &Path,
+    series_info: &SeriesInfo,
+    seasons: &[ListedSeason],
+) -> Result<PathBuf, anyhow::Error> {
+    let series_name = prepare_series_name_for_file(&series_info.title).unwrap_or_else(|| "Series".to_string());
+    let path = save_directory.join(format!("{series_name}.info.json"));
+
+    let structure = SeriesStructure::from_listed_seasons(seasons);
+    let info_json = InfoJson {
+        series_info,
+        structure: &structure,
+    };
+    let json = serde_json::to_string_pretty(&info_json).context("failed to serialize series info")?;
+
+    tokio::fs::write(&path, json)
+        .await
+        .with_context(|| format!("failed to write \"{}\"", path.display()))?;
+
+    Ok(path)
+}
+
+/// Prints every received episode as a single line of JSON to stdout.
+pub(crate) async fn dump_episodes_json(mut rx_stream: ReceiverStream<DownloadTask>, series_info: &SeriesInfo) {
+    while let Some(task) = rx_stream.next().await {
+        let episode_json = EpisodeJson {
+            series_title: &series_info.title,
+            task: &task,
+        };
+
+        match serde_json::to_string(&episode_json) {
+            Ok(json) => println!("{json}"),
+            Err(err) => log::warn!("Failed to serialize {}: {}", task.episode_info.season_episode(), err),
+        }
+    }
+}
+
+/// Prints the path every received episode would be downloaded to, one per
+/// line to stdout.
+pub(crate) async fn print_filenames(
+    mut rx_stream: ReceiverStream<DownloadTask>,
+    series_info: &SeriesInfo,
+    save_directory: &Path,
+    output_template: &OutputTemplate,
+    season_folders: bool,
+    container: Container,
+    audio_extraction: Option<&AudioExtraction>,
+) {
+    let series_name = prepare_series_name_for_file(&series_info.title);
+
+    while let Some(task) = rx_stream.next().await {
+        let output_path = get_episode_output_path(
+            save_directory,
+            output_template,
+            season_folders,
+            series_name.as_deref(),
+            &task,
+        );
+        let extension = get_expected_extension(&task.download_url, container, audio_extraction);
+        let mut file_name = output_path.into_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+
+        println!("{}", PathBuf::from(file_name).display());
+    }
+}
+
+/// Result of checking whether the video url of an episode is reachable.
+struct CheckedVideo {
+    name: String,
+    language: Option<String>,
+    extractor: Option<String>,
+    result: Result<(), anyhow::Error>,
+}
+
+/// Checks that the video url of every received episode is reachable, and
+/// prints the results as a table to stdout. Returns whether any check failed.
+pub(crate) async fn check_episodes(
+    mut rx_stream: ReceiverStream<DownloadTask>,
+    user_agent: Option<&str>,
+    headers: &[(HeaderName, String)],
+    redirect_policy: RedirectPolicy,
+) -> bool {
+    let mut checked_videos = vec![];
+
+    while let Some(task) = rx_stream.next().await {
+        let name = task.episode_info.season_episode();
+        let result = check_video_url(
+            &task.download_url,
+            user_agent,
+            task.referer.as_deref(),
+            headers,
+            redirect_policy,
+        )
+        .await;
+
+        match &result {
+            Ok(()) => log::debug!("Video url of {} is reachable", name),
+            Err(err) => log::debug!("Video url of {} is not reachable: {:#}", name, err),
+        }
+
+        checked_videos.push(CheckedVideo {
+            name,
+            language: Some(task.language.to_string()),
+            extractor: task.extractor,
+            result,
+        });
+    }
+
+    print_check_table(checked_videos)
+}
+
+/// Checks that a single video url is reachable, and prints the result as a
+/// table to stdout. Returns whether the check failed.
+pub(crate) async fn check_video(
+    url: &str,
+    user_agent: Option<&str>,
+    referer: Option<&str>,
+    extractor: Option<&str>,
+    headers: &[(HeaderName, String)],
+    redirect_policy: RedirectPolicy,
+) -> bool {
+    let checked_video = CheckedVideo {
+        name: "Video".to_string(),
+        language: None,
+        extractor: extractor.map(|extractor| extractor.to_string()),
+        result: check_video_url(url, user_agent, referer, headers, redirect_policy).await,
+    };
+
+    print_check_table(vec![checked_video])
+}
+
+/// Requests only the first byte of the video, which is enough to know that
+/// the url is live. The user agent is the one of the downloads.
+/// If the redirects stop at media, a redirect to a media url counts as live
+/// without requesting it.
+async fn check_video_url(
+    url: &str,
+    user_agent: Option<&str>,
+    referer: Option<&str>,
+    headers: &[(HeaderName, String)],
+    redirect_policy: RedirectPolicy,
+) -> Result<(), anyhow::Error> {
+    let mut extra_headers = headers
+        .iter()
+        .map(|(name, value)| (name.clone(), value.as_str()))
+        .collect::<Vec<_>>();
+    extra_headers.push((reqwest::header::RANGE, "bytes=0-0"));
+
+    let response = match follow_redirects(None, url, user_agent, referer, Some(&extra_headers), redirect_policy).await?
+    {
+        FollowedRedirects::Response(response) => response,
+        FollowedRedirects::MediaUrl { url, .. } => {
+            log::debug!("Stopped redirects at media url {}", url);
+            return Ok(());
+        }
+    };
+    let status = response.status();
+
+    if !status.is_success() {
+        anyhow::bail!("server responded with status {}", status);
+    }
+
+    Ok(())
+}
+
+fn print_check_table(checked_videos: Vec<CheckedVideo>) -> bool {
+    let any_failed = checked_videos.iter().any(|checked_video| checked_video.result.is_err());
+    print!("{}", format_check_table(&checked_videos));
+    any_failed
+}
+
+fn format_check_table(checked_videos: &[CheckedVideo]) -> String {
+    let header = ["Episode", "Language", "Extractor", "Result"];
+    let rows = checked_videos
+        .iter()
+        .map(|checked_video| {
+            let result = match &checked_video.result {
+                Ok(()) => "PASS".to_string(),
+                Err(err) => format!("FAIL: {err:#}"),
+            };
+
+            [
+                checked_video.name.clone(),
+                checked_video.language.clone().unwrap_or_else(|| "-".to_string()),
+                checked_video.extractor.clone().unwrap_or_else(|| "-".to_string()),
+                result,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.map(|column| column.chars().count());
+
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: [&str; 4]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut output = format_row(header);
+
+    for row in &rows {
+        output.push_str(&format_row([&row[0], &row[1], &row[2], &row[3]]));
+    }
+
+    output
+}
+
+/// Prints the seasons and episodes as a tree to stdout.
+pub(crate) fn print_episode_list(series_info: &SeriesInfo, seasons: &[ListedSeason]) {
+    print!("{}", format_episode_list(series_info, seasons));
+}
+
+fn format_episode_list(series_info: &SeriesInfo, seasons: &[ListedSeason]) -> String {
+    let mut output = format!("{}\n", series_info.title);
+
+    for (season_index, season) in seasons.iter().enumerate() {
+        let is_last_season = season_index + 1 == seasons.len();
+        let (season_branch, season_indent) = if is_last_season {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let season_name = match season.season_number {
+            0 => "Movies".to_string(),
+            season_number => format!("Season {season_number}"),
+        };
+        output.push_str(&format!(
+            "{season_branch}{season_name} ({} episodes)\n",
+            season.episodes.len()
+        ));
+
+        for (episode_index, episode) in season.episodes.iter().enumerate() {
+            let episode_branch = if episode_index + 1 == season.episodes.len() {
+                "└── "
+            } else {
+                "├── "
+            };
+            let mut line = episode.episode_info.season_episode();
+
+            if let Some(name) = &episode.episode_info.name {
+                line.push(' ');
+                line.push_str(name);
+            }
+
+            if !episode.languages.is_empty() {
+                let languages = episode
+                    .languages
+                    .iter()
+                    .map(|language| language.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                line.push_str(&format!(" [{languages}]"));
+            }
+
+            output.push_str(&format!("{season_indent}{episode_branch}{line}\n"));
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downloaders::{
+        EpisodeInfo, EpisodeNumber, Language, ListedEpisode, ListedSeason, SeriesInfo, VideoType,
+    };
+    use crate::metadata::{format_check_table, format_episode_list, CheckedVideo};
+
+    #[test]
+    fn test_format_episode_list() {
+        let series_info = SeriesInfo {
+            title: "Dark".to_string(),
+            description: None,
+            status: None,
+            year: None,
+        };
+        let episode = |season: u32, episode: u32, name: Option<&str>, languages: Vec<VideoType>| ListedEpisode {
+            episode_info: EpisodeInfo {
+                name: name.map(|name| name.to_string()),
+                season_number: Some(season),
+                episode_number: EpisodeNumber::Number(episode),
+                max_episode_number_in_season: None,
+            },
+            languages,
+        };
+        let seasons = [
+            ListedSeason {
+                season_number: 0,
+                episodes: vec![episode(0, 1, None, vec![VideoType::Dub(Language::German)])],
+            },
+            ListedSeason {
+                season_number: 1,
+                episodes: vec![
+                    episode(
+                        1,
+                        1,
+                        Some("Geheimnisse"),
+                        vec![VideoType::Dub(Language::German), VideoType::Sub(Language::German)],
+                    ),
+                    episode(1, 2, Some("Lügen"), vec![]),
+                ],
+            },
+        ];
+
+        let expected = concat!(
+            "Dark\n",
+            "├── Movies (1 episodes)\n",
+            "│   └── S00E001 [GerDub]\n",
+            "└── Season 1 (2 episodes)\n",
+            "    ├── S01E001 Geheimnisse [GerDub, GerSub]\n",
+            "    └── S01E002 Lügen\n",
+        );
+        assert_eq!(format_episode_list(&series_info, &seasons), expected);
+    }
+
+    #[test]
+    fn test_format_check_table() {
+        let checked_videos = [
+            CheckedVideo {
+                name: "S01E001".to_string(),
+                language: Some("GerDub".to_string()),
+                extractor: Some("Voe".to_string()),
+                result: Ok(()),
+            },
+            CheckedVideo {
+                name: "S01E002".to_string(),
+                language: Some("GerSub".to_string()),
+                extractor: None,
+                result: Err(anyhow::anyhow!("server responded with status 404 Not Found")),
+            },
+        ];
+
+        let expected = concat!(
+            "Episode  Language  Extractor  Result\n",
+            "S01E001  GerDub    Voe        PASS\n",
+            "S01E002  GerSub    -          FAIL: server responded with status 404 Not Found\n",
+        );
+        assert_eq!(format_check_table(&checked_videos), expected);
+    }
+}
@@ -1,42 +1,194 @@
 use std::io::Cursor;
-use std::process::{Command, Stdio};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::Context;
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::Lazy;
 use rand::Rng;
 use tokio::io::AsyncWriteExt;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
 
-use crate::download::get_episode_name;
+use crate::download::{self, Downloader, InternalDownloadTask, Quality, SubtitleOptions};
 use crate::downloaders::{DownloadTask, SeriesInfo};
+use crate::exec::split_args;
+use crate::output_template::{OutputTemplate, TemplateValues};
 
-pub(crate) fn start_mpv(url: &str, debug: bool) -> Result<(), anyhow::Error> {
+/// Options of mpv, which apply to every played episode.
+pub(crate) struct MpvOptions {
+    pub(crate) quality: Quality,
+    /// Passed through from `--mpv-args`
+    pub(crate) args: MpvArgs,
+    pub(crate) debug: bool,
+}
+
+impl MpvOptions {
+    /// Per-file options of a streamed url. The variant of m3u8 streams is
+    /// selected by mpv, so the quality is translated into `hls-bitrate`.
+    async fn stream_options(&self, url: &str, referer: Option<&str>, title: String) -> Vec<(String, String)> {
+        let hls_bitrate = match self.quality {
+            Quality::Best => "max".to_owned(),
+            Quality::Worst => "min".to_owned(),
+            // mpv picks the highest bitrate not exceeding the one of the selected variant
+            Quality::MaxHeight(_) => match download::get_variant_bandwidth(url, referer, self.quality).await {
+                Ok(Some(bandwidth)) => bandwidth.to_string(),
+                Ok(None) => "max".to_owned(),
+                Err(err) => {
+                    log::debug!("Failed to select variant for mpv: {:#}", err);
+                    "max".to_owned()
+                }
+            },
+        };
+
+        let mut options = vec![
+            ("force-media-title".to_owned(), title),
+            ("hls-bitrate".to_owned(), hls_bitrate),
+        ];
+        options.extend(self.args.0.iter().cloned());
+        options
+    }
+
+    /// Per-file options of a prefetched playlist, whose variant was already
+    /// selected. FFmpeg only plays its local segments together with the
+    /// remote ones, if the protocols are allowed explicitly.
+    fn prefetched_options(&self, title: String, sub_files: &[PathBuf]) -> Vec<(String, String)> {
+        let mut options = vec![
+            ("force-media-title".to_owned(), title),
+            (
+                "demuxer-lavf-o".to_owned(),
+                "protocol_whitelist=[file,http,https,tcp,tls,crypto,data]".to_owned(),
+            ),
+        ];
+
+        if !sub_files.is_empty() {
+            let sub_files = sub_files.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>();
+            options.push(("sub-files".to_owned(), sub_files.join(PATH_LIST_SEPARATOR)));
+        }
+
+        options.extend(self.args.0.iter().cloned());
+        options
+    }
+}
+
+/// Separator of the items of path list options in mpv.
+const PATH_LIST_SEPARATOR: &str = if cfg!(windows) { ";" } else { ":" };
+
+/// Options passed through to mpv, e.g. `--hwdec=auto --sub-file=subs.srt`,
+/// as pairs of name and value. A flag without a value is `yes`, or `no` if it
+/// starts with `--no-`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct MpvArgs(Vec<(String, String)>);
+
+impl FromStr for MpvArgs {
+    type Err = String;
+
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        split_args(args)?
+            .into_iter()
+            .map(|arg| {
+                let Some(option) = arg.strip_prefix("--").filter(|option| !option.is_empty()) else {
+                    return Err(format!("not an option: {arg}"));
+                };
+
+                Ok(match option.split_once('=') {
+                    Some((name, value)) => (name.to_owned(), value.to_owned()),
+                    None => match option.strip_prefix("no-") {
+                        Some(name) => (name.to_owned(), "no".to_owned()),
+                        None => (option.to_owned(), "yes".to_owned()),
+                    },
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Arguments, which play the url with the options, without them applying to
+/// other files of the playlist.
+fn file_group_args(url: &str, options: &[(String, String)]) -> Vec<String> {
+    let mut args = vec!["--{".to_owned()];
+    args.extend(options.iter().map(|(name, value)| format!("--{name}={value}")));
+    args.push(url.to_owned());
+    args.push("--}".to_owned());
+    args
+}
+
+/// Options argument of `loadfile`, where each value is quoted by its length.
+fn loadfile_options(options: &[(String, String)]) -> String {
+    options
+        .iter()
+        .map(|(name, value)| format!("{name}=%{}%{value}", value.len()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Segments of each upcoming m3u8 stream, which are downloaded ahead.
+const PREFETCHED_SEGMENTS: usize = 3;
+
+/// Downloads the playlist and the first segments of upcoming episodes into a
+/// temporary cache while the current one is playing, so that mpv can start
+/// them without buffering.
+pub(crate) struct MpvPrefetch {
+    pub(crate) downloader: Downloader,
+    /// Number of episodes, which are prefetched ahead
+    pub(crate) depth: NonZeroUsize,
+    pub(crate) quality: Quality,
+    /// Downloaded next to the episodes, and attached to them in mpv
+    pub(crate) subtitles: Option<SubtitleOptions>,
+}
+
+/// Temporary directory of the prefetched episodes, which is deleted once dropped.
+struct CacheDir(PathBuf);
+
+impl CacheDir {
+    async fn create() -> Result<Self, anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("sdl-mpv-cache-{}", std::process::id()));
+        tokio::fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("failed to create cache directory \"{}\"", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for CacheDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub(crate) async fn start_mpv(url: &str, referer: Option<&str>, options: &MpvOptions) -> Result<(), anyhow::Error> {
     let mut mpv_cmd = tokio::process::Command::new(mpv_name());
 
-    if !debug {
+    if !options.debug {
         mpv_cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
         mpv_cmd.arg("--no-terminal");
     }
 
     let title = "sdl";
+    let file_options = options.stream_options(url, referer, title.to_owned()).await;
 
     mpv_cmd
-        .arg("--{")
-        .arg(format!("--force-media-title={title}"))
-        .arg(url)
-        .arg("--}")
+        .args(file_group_args(url, &file_options))
         .spawn()
         .map(|_| ())
         .context("failed to start mpv")
 }
 
 pub(crate) async fn start_mpv_with_ipc(
-    mut rx_stream: UnboundedReceiverStream<DownloadTask>,
+    mut rx_stream: ReceiverStream<DownloadTask>,
     series_info: SeriesInfo,
-    debug: bool,
+    options: &MpvOptions,
+    prefetch: Option<MpvPrefetch>,
 ) -> Result<(), anyhow::Error> {
+    let cache_dir = match prefetch {
+        Some(_) => Some(CacheDir::create().await?),
+        None => None,
+    };
+
     let ipc_path_mpv = if cfg!(unix) {
         let mut i = 0u32;
 
@@ -60,28 +212,30 @@ pub(crate) async fn start_mpv_with_ipc(
         format!(r"mpvsocket_sdl{:0>4}", rand.gen_range(0..=9999))
     };
 
-    let (first_url, first_title) = match rx_stream.next().await {
+    let (first_url, first_options) = match rx_stream.next().await {
         Some(task) => {
-            let url = task.download_url;
-            let title = get_episode_name(Some(&series_info.title), Some(&task.language), &task.episode_info, true);
-            (url, title)
+            let title = get_media_title(&series_info.title, &task);
+            let file_options = options
+                .stream_options(&task.download_url, task.referer.as_deref(), title)
+                .await;
+            (task.download_url, file_options)
         }
         None => anyhow::bail!("failed to get at least one episode url"),
     };
 
-    let mut mpv_cmd = Command::new(mpv_name());
+    let mut mpv_cmd = tokio::process::Command::new(mpv_name());
 
-    if !debug {
+    if !options.debug {
         mpv_cmd.stdout(Stdio::null()).stderr(Stdio::null());
         mpv_cmd.arg("--no-terminal");
     }
 
-    mpv_cmd
+    // Kept open, in case an episode ends before the next one is appended
+    let mut mpv_process = mpv_cmd
+        .arg("--idle=yes")
+        .arg("--force-window=yes")
         .arg(format!("--input-ipc-server={ipc_path_mpv}"))
-        .arg("--{")
-        .arg(format!("--force-media-title={first_title}"))
-        .arg(first_url)
-        .arg("--}")
+        .args(file_group_args(&first_url, &first_options))
         .spawn()
         .context("failed to start mpv")?;
 
@@ -91,7 +245,37 @@ pub(crate) async fn start_mpv_with_ipc(
         format!("@{}", ipc_path_mpv)
     };
 
-    let mpv_ipc_result = run_mpv_ipc(&ipc_path_rs, rx_stream, series_info).await;
+    let series_title = &series_info.title;
+    let mpv_ipc_result = match (&prefetch, &cache_dir) {
+        (Some(prefetch), Some(cache_dir)) => {
+            let playlist = rx_stream
+                .enumerate()
+                .map(|(index, task)| prefetch_episode(prefetch, options, &cache_dir.0, index, series_title, task))
+                .buffered(prefetch.depth.get());
+
+            tokio::select! {
+                biased;
+
+                result = run_mpv_ipc(&ipc_path_rs, playlist) => result,
+                _ = prefetch.downloader.tick() => unreachable!(),
+            }
+        }
+        _ => {
+            let playlist = rx_stream.then(|task| async move {
+                let title = get_media_title(series_title, &task);
+                let file_options = options
+                    .stream_options(&task.download_url, task.referer.as_deref(), title)
+                    .await;
+                (task.download_url, file_options)
+            });
+            run_mpv_ipc(&ipc_path_rs, playlist).await
+        }
+    };
+
+    // The prefetched episodes are needed until mpv exits
+    if cache_dir.is_some() {
+        let _ = mpv_process.wait().await;
+    }
 
     if cfg!(unix) {
         let _ = tokio::fs::remove_file(&ipc_path_mpv).await;
@@ -100,10 +284,52 @@ pub(crate) async fn start_mpv_with_ipc(
     mpv_ipc_result
 }
 
+/// Downloads the head of the episode into the cache directory, and returns
+/// the path of its playlist together with its options, which attach the
+/// downloaded subtitles. The url is returned instead, if it is no m3u8 stream
+/// or the prefetch fails.
+async fn prefetch_episode(
+    prefetch: &MpvPrefetch,
+    options: &MpvOptions,
+    cache_dir: &Path,
+    index: usize,
+    series_title: &str,
+    task: DownloadTask,
+) -> (String, Vec<(String, String)>) {
+    let title = get_media_title(series_title, &task);
+    let internal_task = InternalDownloadTask::new(cache_dir.join(index.to_string()), task.download_url.clone())
+        .referer(task.referer.clone())
+        .quality(prefetch.quality)
+        .subtitles(prefetch.subtitles);
+
+    match prefetch
+        .downloader
+        .prefetch_m3u8_head(internal_task, PREFETCHED_SEGMENTS)
+        .await
+    {
+        Ok(Some((playlist_path, sub_files))) => {
+            let file_options = options.prefetched_options(title, &sub_files);
+            return (playlist_path.to_string_lossy().into_owned(), file_options);
+        }
+        Ok(None) => {}
+        Err(err) => log::warn!(
+            "Failed to prefetch {}, streaming it instead: {:#}",
+            task.episode_info.season_episode(),
+            err
+        ),
+    }
+
+    let file_options = options
+        .stream_options(&task.download_url, task.referer.as_deref(), title)
+        .await;
+    (task.download_url, file_options)
+}
+
+/// Appends the urls or paths to the playlist of mpv, together with their
+/// per-file options.
 async fn run_mpv_ipc(
     ipc_path_rs: &str,
-    mut rx_stream: UnboundedReceiverStream<DownloadTask>,
-    series_info: SeriesInfo,
+    playlist: impl Stream<Item = (String, Vec<(String, String)>)>,
 ) -> Result<(), anyhow::Error> {
     // Try for 10 seconds to connect to IPC
     let ipc = {
@@ -127,35 +353,39 @@ async fn run_mpv_ipc(
     let (_, ipc_write) = ipc.into_split();
     let mut ipc_write = ipc_write.compat_write();
 
-    while let Some(task) = rx_stream.next().await {
-        let url = task.download_url;
-        let title = get_episode_name(Some(&series_info.title), Some(&task.language), &task.episode_info, true);
-        let title_len = title.as_bytes().len();
-        let title_arg = format!("force-media-title=%{title_len}%{title}");
+    let mut playlist = std::pin::pin!(playlist);
 
-        let mut mpv_cmd = serde_json::json!({
+    while let Some((url, file_options)) = playlist.next().await {
+        let mpv_cmd = serde_json::json!({
             "command": [
                 "loadfile",
                 url,
                 "append-play",
-                title_arg
+                loadfile_options(&file_options)
             ]
-        })
-        .to_string();
-        mpv_cmd.push('\n');
-
-        let mut message = Cursor::new(mpv_cmd);
-        ipc_write
-            .write_all_buf(&mut message)
+        });
+        send_mpv_command(&mut ipc_write, mpv_cmd)
             .await
-            .context("failed to send url to mpv playlist: failed write")?;
-        ipc_write
-            .flush()
-            .await
-            .context("failed to send url to mpv playlist: failed flush")?;
+            .context("failed to send url to mpv playlist")?;
     }
 
-    Ok(())
+    // Nothing is appended anymore, so mpv can exit after the last episode
+    let mpv_cmd = serde_json::json!({ "command": ["set_property", "idle", "no"] });
+    send_mpv_command(&mut ipc_write, mpv_cmd)
+        .await
+        .context("failed to stop mpv from idling")
+}
+
+async fn send_mpv_command(
+    ipc_write: &mut (impl tokio::io::AsyncWrite + Unpin),
+    mpv_cmd: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    let mut mpv_cmd = mpv_cmd.to_string();
+    mpv_cmd.push('\n');
+
+    let mut message = Cursor::new(mpv_cmd);
+    ipc_write.write_all_buf(&mut message).await.context("failed write")?;
+    ipc_write.flush().await.context("failed flush")
 }
 
 fn mpv_name() -> &'static str {
@@ -165,3 +395,63 @@ fn mpv_name() -> &'static str {
         "mpv.exe"
     }
 }
+
+fn get_media_title(series_title: &str, task: &DownloadTask) -> String {
+    static MEDIA_TITLE_TEMPLATE: Lazy<OutputTemplate> =
+        Lazy::new(|| "{series} - S{season:02}E{episode} - {lang} - {title}".parse().unwrap());
+
+    MEDIA_TITLE_TEMPLATE.render(&TemplateValues {
+        series: Some(series_title),
+        language: Some(&task.language),
+        episode_info: Some(&task.episode_info),
+        title: task.episode_info.name.as_deref(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mpv::{file_group_args, loadfile_options, MpvArgs};
+
+    fn options(options: &[(&str, &str)]) -> Vec<(String, String)> {
+        options
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_mpv_args() {
+        assert_eq!(
+            "--hwdec=auto --fs --no-osc '--sub-file=two words.srt'".parse(),
+            Ok(MpvArgs(options(&[
+                ("hwdec", "auto"),
+                ("fs", "yes"),
+                ("osc", "no"),
+                ("sub-file", "two words.srt"),
+            ])))
+        );
+        assert_eq!("".parse(), Ok(MpvArgs::default()));
+        assert!("--fs https://example.com".parse::<MpvArgs>().is_err());
+        assert!("--".parse::<MpvArgs>().is_err());
+    }
+
+    #[test]
+    fn test_file_options() {
+        let file_options = options(&[("force-media-title", "Yuruyuri, S01E01"), ("hls-bitrate", "max")]);
+
+        assert_eq!(
+            file_group_args("https://example.com/master.m3u8", &file_options),
+            [
+                "--{",
+                "--force-media-title=Yuruyuri, S01E01",
+                "--hls-bitrate=max",
+                "https://example.com/master.m3u8",
+                "--}",
+            ]
+        );
+        assert_eq!(
+            loadfile_options(&file_options),
+            "force-media-title=%16%Yuruyuri, S01E01,hls-bitrate=%3%max"
+        );
+    }
+}
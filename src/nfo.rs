@@ -0,0 +1,162 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::downloaders::{EpisodeInfo, EpisodeNumber, SeriesInfo, SeriesStatus};
+
+/// Writes the Kodi `tvshow.nfo` of the series into its directory.
+pub(crate) async fn write_tvshow_nfo(
+    series_directory: &Path,
+    series_info: &SeriesInfo,
+) -> Result<PathBuf, anyhow::Error> {
+    let path = series_directory.join("tvshow.nfo");
+    write_nfo(&path, &format_tvshow_nfo(series_info)).await?;
+    Ok(path)
+}
+
+/// Writes the Kodi `.nfo` of an episode next to its video file.
+pub(crate) async fn write_episode_nfo(
+    video_path: &Path,
+    series_info: &SeriesInfo,
+    episode_info: &EpisodeInfo,
+) -> Result<PathBuf, anyhow::Error> {
+    let path = video_path.with_extension("nfo");
+    write_nfo(&path, &format_episode_nfo(series_info, episode_info)).await?;
+    Ok(path)
+}
+
+async fn write_nfo(path: &Path, content: &str) -> Result<(), anyhow::Error> {
+    tokio::fs::write(path, content)
+        .await
+        .with_context(|| format!("failed to write \"{}\"", path.display()))
+}
+
+fn format_tvshow_nfo(series_info: &SeriesInfo) -> String {
+    let mut elements = vec![("title", series_info.title.clone())];
+
+    if let Some(description) = &series_info.description {
+        elements.push(("plot", description.clone()));
+    }
+
+    if let Some(year) = series_info.year {
+        elements.push(("year", year.to_string()));
+    }
+
+    if let Some(status) = series_info.status {
+        let status = match status {
+            SeriesStatus::Airing => "Continuing",
+            SeriesStatus::Completed => "Ended",
+            SeriesStatus::OnHiatus => "On Hiatus",
+            SeriesStatus::Cancelled => "Canceled",
+        };
+        elements.push(("status", status.to_string()));
+    }
+
+    format_document("tvshow", &elements)
+}
+
+fn format_episode_nfo(series_info: &SeriesInfo, episode_info: &EpisodeInfo) -> String {
+    let mut elements = vec![];
+
+    if let Some(name) = &episode_info.name {
+        elements.push(("title", name.clone()));
+    }
+
+    elements.push(("showtitle", series_info.title.clone()));
+
+    if let Some(season_number) = episode_info.season_number {
+        elements.push(("season", season_number.to_string()));
+    }
+
+    let episode = match &episode_info.episode_number {
+        EpisodeNumber::Number(number) => number.to_string(),
+        EpisodeNumber::String(string) => string.clone(),
+    };
+    elements.push(("episode", episode));
+
+    format_document("episodedetails", &elements)
+}
+
+fn format_document(root: &str, elements: &[(&str, String)]) -> String {
+    let mut document = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    writeln!(document, "<{root}>").unwrap();
+
+    for (name, value) in elements {
+        writeln!(document, "  <{name}>{}</{name}>", escape_xml(value)).unwrap();
+    }
+
+    writeln!(document, "</{root}>").unwrap();
+    document
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_xml, format_episode_nfo, format_tvshow_nfo};
+    use crate::downloaders::{EpisodeInfo, EpisodeNumber, SeriesInfo, SeriesStatus};
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("Tom & Jerry <3"), "Tom &amp; Jerry &lt;3");
+        assert_eq!(escape_xml("\"Don't\"\u{1}"), "&quot;Don&apos;t&quot;");
+    }
+
+    #[test]
+    fn test_format_nfo() {
+        let series_info = SeriesInfo {
+            title: "Kill & Kill".to_string(),
+            description: Some("A <short> plot".to_string()),
+            status: Some(SeriesStatus::Airing),
+            year: Some(2011),
+        };
+        assert_eq!(
+            format_tvshow_nfo(&series_info),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n",
+                "<tvshow>\n",
+                "  <title>Kill &amp; Kill</title>\n",
+                "  <plot>A &lt;short&gt; plot</plot>\n",
+                "  <year>2011</year>\n",
+                "  <status>Continuing</status>\n",
+                "</tvshow>\n",
+            )
+        );
+
+        let episode_info = EpisodeInfo {
+            name: None,
+            season_number: Some(2),
+            episode_number: EpisodeNumber::Number(5),
+            max_episode_number_in_season: Some(12),
+        };
+        assert_eq!(
+            format_episode_nfo(&series_info, &episode_info),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n",
+                "<episodedetails>\n",
+                "  <showtitle>Kill &amp; Kill</showtitle>\n",
+                "  <season>2</season>\n",
+                "  <episode>5</episode>\n",
+                "</episodedetails>\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::download::format_episode_number;
+use crate::downloaders::{EpisodeInfo, Language, VideoType};
+
+pub(crate) const DEFAULT_OUTPUT_TEMPLATE: &str = "{series} - S{season:02}E{episode} - {lang}.{ext}";
+
+/// Fields of a template are separated by this, and are left out if none of
+/// their tokens has a value.
+const FIELD_SEPARATOR: &str = " - ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Series,
+    Season,
+    Episode,
+    Lang,
+    Title,
+    Ext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Token { token: Token, width: Option<usize> },
+}
+
+/// Template for the names of downloaded files, e.g. `{series} - S{season:02}E{episode}`.
+///
+/// The extension is determined by the download, so `{ext}` is only allowed
+/// at the end of the template and always appended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OutputTemplate {
+    template: String,
+    fields: Vec<Vec<Part>>,
+}
+
+/// Values of the tokens. Tokens without value are rendered as empty.
+#[derive(Debug, Default)]
+pub(crate) struct TemplateValues<'a> {
+    pub(crate) series: Option<&'a str>,
+    pub(crate) language: Option<&'a VideoType>,
+    pub(crate) episode_info: Option<&'a EpisodeInfo>,
+    pub(crate) title: Option<&'a str>,
+}
+
+impl OutputTemplate {
+    pub(crate) fn render(&self, values: &TemplateValues) -> String {
+        let mut rendered_fields = Vec::with_capacity(self.fields.len());
+
+        for field in &self.fields {
+            let mut rendered_field = String::new();
+            let mut has_token = false;
+            let mut has_value = false;
+
+            for part in field {
+                match part {
+                    Part::Literal(literal) => rendered_field.push_str(literal),
+                    Part::Token { token, width } => {
+                        has_token = true;
+
+                        if let Some(value) = Self::render_token(*token, *width, values) {
+                            has_value = true;
+                            rendered_field.push_str(&value);
+                        }
+                    }
+                }
+            }
+
+            if !has_token || has_value {
+                rendered_fields.push(rendered_field);
+            }
+        }
+
+        rendered_fields.join(FIELD_SEPARATOR)
+    }
+
+    fn render_token(token: Token, width: Option<usize>, values: &TemplateValues) -> Option<String> {
+        match token {
+            Token::Series => values.series.map(|series| series.to_owned()),
+            Token::Season => values
+                .episode_info
+                .and_then(|episode_info| episode_info.season_number)
+                .map(|season| format!("{season:0>fill$}", fill = width.unwrap_or(0))),
+            Token::Episode => values.episode_info.map(|episode_info| {
+                let alignment = width.or_else(|| {
+                    episode_info
+                        .max_episode_number_in_season
+                        .map(|max_num| (max_num.checked_ilog10().unwrap_or(0) + 1) as usize)
+                });
+                format_episode_number(&episode_info.episode_number, alignment)
+            }),
+            Token::Lang => values
+                .language
+                .filter(|language| **language != VideoType::Unspecified(Language::Unspecified))
+                .map(|language| language.to_string()),
+            Token::Title => values.title.map(|title| title.to_owned()),
+            Token::Ext => None,
+        }
+    }
+
+    fn parse_field(field: &str) -> Result<Vec<Part>, String> {
+        let mut parts = vec![];
+        let mut rest = field;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("unclosed token in \"{field}\""));
+            };
+            let token_str = &rest[start + 1..start + end];
+            let (name, width) = match token_str.split_once(':') {
+                Some((name, width)) => match width.parse::<usize>() {
+                    Ok(width) => (name, Some(width)),
+                    Err(_) => return Err(format!("invalid width of token \"{{{token_str}}}\"")),
+                },
+                None => (token_str, None),
+            };
+            let token = match name {
+                "series" => Token::Series,
+                "season" => Token::Season,
+                "episode" => Token::Episode,
+                "lang" => Token::Lang,
+                "title" => Token::Title,
+                "ext" => Token::Ext,
+                _ => return Err(format!("unknown token \"{{{token_str}}}\"")),
+            };
+
+            if width.is_some() && !matches!(token, Token::Season | Token::Episode) {
+                return Err(format!("token \"{{{name}}}\" does not support a width"));
+            }
+
+            parts.push(Part::Token { token, width });
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        Ok(parts)
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut fields = template
+            .split(FIELD_SEPARATOR)
+            .map(Self::parse_field)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Remove trailing {ext}, since the extension is appended by the download
+        if let Some(last_field) = fields.last_mut() {
+            if let Some(Part::Token { token: Token::Ext, .. }) = last_field.last() {
+                last_field.pop();
+
+                if let Some(Part::Literal(literal)) = last_field.last_mut() {
+                    if let Some(stripped) = literal.strip_suffix('.') {
+                        *literal = stripped.to_owned();
+                    }
+
+                    if literal.is_empty() {
+                        last_field.pop();
+                    }
+                }
+            }
+        }
+
+        let has_ext = fields
+            .iter()
+            .flatten()
+            .any(|part| matches!(part, Part::Token { token: Token::Ext, .. }));
+
+        if has_ext {
+            return Err("token \"{ext}\" is only allowed at the end".to_owned());
+        }
+
+        Ok(OutputTemplate {
+            template: template.to_owned(),
+            fields,
+        })
+    }
+}
+
+impl Default for OutputTemplate {
+    fn default() -> Self {
+        DEFAULT_OUTPUT_TEMPLATE.parse().unwrap()
+    }
+}
+
+impl Display for OutputTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downloaders::{EpisodeInfo, EpisodeNumber, Language, VideoType};
+    use crate::output_template::{OutputTemplate, TemplateValues};
+
+    #[test]
+    fn test_output_template() {
+        let episode_info = EpisodeInfo {
+            name: Some("The Beginning".to_owned()),
+            season_number: Some(1),
+            episode_number: EpisodeNumber::Number(2),
+            max_episode_number_in_season: Some(12),
+        };
+        let language = VideoType::Sub(Language::German);
+        let values = TemplateValues {
+            series: Some("Series"),
+            language: Some(&language),
+            episode_info: Some(&episode_info),
+            title: episode_info.name.as_deref(),
+        };
+
+        let default_template = OutputTemplate::default();
+        assert_eq!(default_template.render(&values), "Series - S01E02 - GerSub");
+        assert_eq!(
+            default_template.render(&TemplateValues {
+                series: None,
+                language: Some(&VideoType::Unspecified(Language::Unspecified)),
+                ..values
+            }),
+            "S01E02"
+        );
+        assert_eq!(default_template.render(&TemplateValues::default()), "");
+
+        let template: OutputTemplate = "{series} {season}x{episode:03} - {title}.{ext}".parse().unwrap();
+        assert_eq!(template.render(&values), "Series 1x002 - The Beginning");
+
+        assert!("{series} - {unknown}".parse::<OutputTemplate>().is_err());
+        assert!("{series".parse::<OutputTemplate>().is_err());
+        assert!("{lang:02}".parse::<OutputTemplate>().is_err());
+        assert!("{ext} - {series}".parse::<OutputTemplate>().is_err());
+    }
+}
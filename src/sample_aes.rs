@@ -0,0 +1,708 @@
+//! Decryption of SAMPLE-AES encrypted MPEG-TS segments.
+//!
+//! Only parts of the H.264 slices and AAC frames are encrypted, as described in
+//! Apple's "MPEG-2 Stream Encryption Format for HTTP Live Streaming". Since
+//! the emulation prevention bytes are removed before and inserted again after
+//! decrypting, the size of the video data changes, so the affected PES packets
+//! are packetized again.
+
+use std::collections::{HashMap, HashSet};
+
+use aes::cipher::{BlockDecryptMut as _, KeyIvInit as _};
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_HEADER_SIZE: usize = 4;
+const TS_SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AC3_SAMPLE_AES: u8 = 0xc1;
+const STREAM_TYPE_EAC3_SAMPLE_AES: u8 = 0xc2;
+const STREAM_TYPE_AAC_SAMPLE_AES: u8 = 0xcf;
+const STREAM_TYPE_H264_SAMPLE_AES: u8 = 0xdb;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EncryptedStream {
+    H264,
+    Aac,
+}
+
+struct PacketHeader {
+    pid: u16,
+    payload_unit_start: bool,
+    adaptation_field_start: Option<usize>,
+    payload_start: usize,
+}
+
+impl PacketHeader {
+    fn parse(packet: &[u8]) -> Result<Self, anyhow::Error> {
+        if packet.len() != TS_PACKET_SIZE || packet[0] != TS_SYNC_BYTE {
+            anyhow::bail!("invalid MPEG-TS packet");
+        }
+
+        let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let adaptation_field_control = (packet[3] >> 4) & 0b11;
+
+        let (adaptation_field_start, payload_start) = match adaptation_field_control {
+            0b01 => (None, TS_HEADER_SIZE),
+            0b10 => (Some(TS_HEADER_SIZE), TS_PACKET_SIZE),
+            0b11 => {
+                let payload_start = TS_HEADER_SIZE + 1 + packet[TS_HEADER_SIZE] as usize;
+
+                if payload_start > TS_PACKET_SIZE {
+                    anyhow::bail!("invalid MPEG-TS adaptation field length");
+                }
+
+                (Some(TS_HEADER_SIZE), payload_start)
+            }
+            _ => (None, TS_PACKET_SIZE),
+        };
+
+        Ok(PacketHeader {
+            pid,
+            payload_unit_start,
+            adaptation_field_start,
+            payload_start,
+        })
+    }
+
+    /// Contents of the adaptation field, without the length byte.
+    fn adaptation_field<'a>(&self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        self.adaptation_field_start.map(|start| {
+            let end = (start + 1 + packet[start] as usize).min(TS_PACKET_SIZE);
+            &packet[start + 1..end]
+        })
+    }
+
+    fn payload<'a>(&self, packet: &'a [u8]) -> &'a [u8] {
+        &packet[self.payload_start..]
+    }
+}
+
+struct PendingPes {
+    /// Index into the output and the original packet
+    packets: Vec<(usize, Vec<u8>)>,
+    data: Vec<u8>,
+}
+
+pub(crate) fn decrypt_segment(segment: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, anyhow::Error> {
+    if segment.len() % TS_PACKET_SIZE != 0 || segment.first() != Some(&TS_SYNC_BYTE) {
+        anyhow::bail!("SAMPLE-AES decryption is only supported for MPEG-TS segments");
+    }
+
+    let mut pmt_pids = HashSet::new();
+    let mut encrypted_streams = HashMap::new();
+    let mut pending_pes: HashMap<u16, PendingPes> = HashMap::new();
+    let mut continuity_counters = HashMap::new();
+    let mut output: Vec<Vec<u8>> = Vec::with_capacity(segment.len() / TS_PACKET_SIZE);
+
+    for packet in segment.chunks_exact(TS_PACKET_SIZE) {
+        let header = PacketHeader::parse(packet)?;
+
+        if header.pid == PAT_PID {
+            if header.payload_unit_start {
+                parse_pat(header.payload(packet), &mut pmt_pids)?;
+            }
+
+            output.push(packet.to_vec());
+        } else if pmt_pids.contains(&header.pid) {
+            let mut packet = packet.to_vec();
+
+            if header.payload_unit_start {
+                rewrite_pmt(&mut packet[header.payload_start..], &mut encrypted_streams)?;
+            }
+
+            output.push(packet);
+        } else if let Some(&stream) = encrypted_streams.get(&header.pid) {
+            if header.payload_unit_start {
+                if let Some(pes) = pending_pes.remove(&header.pid) {
+                    finish_pes(header.pid, pes, stream, key, iv, &mut output, &mut continuity_counters);
+                }
+
+                pending_pes.insert(
+                    header.pid,
+                    PendingPes {
+                        packets: vec![],
+                        data: vec![],
+                    },
+                );
+            }
+
+            // Packets before the first start of a PES packet are kept as is
+            match pending_pes.get_mut(&header.pid) {
+                Some(pes) => {
+                    pes.packets.push((output.len(), packet.to_vec()));
+                    pes.data.extend_from_slice(header.payload(packet));
+                    output.push(vec![]);
+                }
+                None => output.push(packet.to_vec()),
+            }
+        } else {
+            output.push(packet.to_vec());
+        }
+    }
+
+    for (pid, pes) in pending_pes {
+        let stream = encrypted_streams[&pid];
+        finish_pes(pid, pes, stream, key, iv, &mut output, &mut continuity_counters);
+    }
+
+    Ok(output.concat())
+}
+
+fn parse_pat(payload: &[u8], pmt_pids: &mut HashSet<u16>) -> Result<(), anyhow::Error> {
+    let section_range = get_section_range(payload, 0x00).ok_or_else(|| anyhow::anyhow!("invalid MPEG-TS PAT"))?;
+    let section = &payload[section_range];
+
+    for program in section[8..section.len() - 4].chunks_exact(4) {
+        let program_number = u16::from_be_bytes([program[0], program[1]]);
+
+        if program_number != 0 {
+            pmt_pids.insert((u16::from(program[2] & 0x1f) << 8) | u16::from(program[3]));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the SAMPLE-AES stream types with the ones of the decrypted streams.
+fn rewrite_pmt(payload: &mut [u8], encrypted_streams: &mut HashMap<u16, EncryptedStream>) -> Result<(), anyhow::Error> {
+    let section_range = get_section_range(payload, 0x02).ok_or_else(|| anyhow::anyhow!("invalid MPEG-TS PMT"))?;
+    let section = &mut payload[section_range];
+    let crc_start = section.len() - 4;
+
+    if crc_start < 12 {
+        anyhow::bail!("invalid MPEG-TS PMT");
+    }
+
+    let program_info_length = (usize::from(section[10] & 0x0f) << 8) | usize::from(section[11]);
+    let mut position = 12 + program_info_length;
+    let mut changed = false;
+
+    while position + 5 <= crc_start {
+        let pid = (u16::from(section[position + 1] & 0x1f) << 8) | u16::from(section[position + 2]);
+        let es_info_length = (usize::from(section[position + 3] & 0x0f) << 8) | usize::from(section[position + 4]);
+
+        match section[position] {
+            STREAM_TYPE_H264_SAMPLE_AES => {
+                section[position] = STREAM_TYPE_H264;
+                encrypted_streams.insert(pid, EncryptedStream::H264);
+                changed = true;
+            }
+            STREAM_TYPE_AAC_SAMPLE_AES => {
+                section[position] = STREAM_TYPE_AAC;
+                encrypted_streams.insert(pid, EncryptedStream::Aac);
+                changed = true;
+            }
+            STREAM_TYPE_AC3_SAMPLE_AES | STREAM_TYPE_EAC3_SAMPLE_AES => {
+                anyhow::bail!("SAMPLE-AES decryption of AC-3 audio not implemented");
+            }
+            _ => {}
+        }
+
+        position += 5 + es_info_length;
+    }
+
+    if changed {
+        let crc = crc32_mpeg2(&section[..crc_start]);
+        section[crc_start..].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+/// Returns the range of the complete PSI section, including the CRC.
+fn get_section_range(payload: &[u8], table_id: u8) -> Option<std::ops::Range<usize>> {
+    let section_start = 1 + *payload.first()? as usize; // skip pointer field
+    let section = payload.get(section_start..)?;
+
+    if section.len() < 3 || section[0] != table_id {
+        return None;
+    }
+
+    let section_length = (usize::from(section[1] & 0x0f) << 8) | usize::from(section[2]);
+
+    if section_length < 9 || section.len() < 3 + section_length {
+        return None;
+    }
+
+    Some(section_start..section_start + 3 + section_length)
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn finish_pes(
+    pid: u16,
+    pes: PendingPes,
+    stream: EncryptedStream,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    output: &mut [Vec<u8>],
+    continuity_counters: &mut HashMap<u16, u8>,
+) {
+    let data = decrypt_pes(pes.data, stream, key, iv);
+    let first_continuity_counter = pes.packets.first().map(|(_, packet)| packet[3] & 0x0f).unwrap_or(0);
+    let continuity_counter = continuity_counters.entry(pid).or_insert(first_continuity_counter);
+    let mut remaining = data.as_slice();
+
+    // Refill the original packets to keep the interleaving and the adaptation fields (e.g. PCR)
+    for (output_index, packet) in &pes.packets {
+        let header = PacketHeader::parse(packet).unwrap();
+        let capacity = TS_PACKET_SIZE - header.payload_start;
+        let (payload, rest) = remaining.split_at(capacity.min(remaining.len()));
+        remaining = rest;
+
+        output[*output_index] = build_packet(
+            pid,
+            header.payload_unit_start,
+            header.adaptation_field(packet),
+            payload,
+            continuity_counter,
+        );
+    }
+
+    // Additional packets, if the data does not fit anymore
+    if let Some((last_output_index, _)) = pes.packets.last() {
+        while !remaining.is_empty() {
+            let (payload, rest) = remaining.split_at((TS_PACKET_SIZE - TS_HEADER_SIZE).min(remaining.len()));
+            remaining = rest;
+
+            let packet = build_packet(pid, false, None, payload, continuity_counter);
+            output[*last_output_index].extend_from_slice(&packet);
+        }
+    }
+}
+
+fn build_packet(
+    pid: u16,
+    payload_unit_start: bool,
+    adaptation_field: Option<&[u8]>,
+    payload: &[u8],
+    continuity_counter: &mut u8,
+) -> Vec<u8> {
+    if payload.is_empty() && adaptation_field.is_none() {
+        return vec![];
+    }
+
+    let mut packet = Vec::with_capacity(TS_PACKET_SIZE);
+    let adaptation_field_size = TS_PACKET_SIZE - TS_HEADER_SIZE - payload.len();
+    let adaptation_field_control = match (adaptation_field_size, payload.is_empty()) {
+        (0, _) => 0b01,
+        (_, false) => 0b11,
+        (_, true) => 0b10,
+    };
+    let packet_continuity_counter = if payload.is_empty() {
+        continuity_counter.wrapping_sub(1) & 0x0f
+    } else {
+        let current = *continuity_counter;
+        *continuity_counter = (current + 1) & 0x0f;
+        current
+    };
+
+    packet.push(TS_SYNC_BYTE);
+    packet.push(if payload_unit_start { 0x40 } else { 0x00 } | (pid >> 8) as u8 & 0x1f);
+    packet.push(pid as u8);
+    packet.push((adaptation_field_control << 4) | packet_continuity_counter);
+
+    if adaptation_field_size > 0 {
+        packet.push((adaptation_field_size - 1) as u8);
+
+        if adaptation_field_size > 1 {
+            match adaptation_field {
+                Some(adaptation_field) if !adaptation_field.is_empty() => packet.extend_from_slice(adaptation_field),
+                _ => packet.push(0x00), // no flags set
+            }
+
+            packet.resize(TS_HEADER_SIZE + adaptation_field_size, 0xff); // stuffing
+        }
+    }
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn decrypt_pes(mut data: Vec<u8>, stream: EncryptedStream, key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    if data.len() < 9 || data[..3] != [0x00, 0x00, 0x01] {
+        return data;
+    }
+
+    let header_length = (9 + data[8] as usize).min(data.len());
+    let elementary_stream = data.split_off(header_length);
+
+    match stream {
+        EncryptedStream::H264 => data.extend(decrypt_h264(&elementary_stream, key, iv)),
+        EncryptedStream::Aac => data.extend(decrypt_aac(elementary_stream, key, iv)),
+    }
+
+    // Update PES packet length, if it was specified
+    if data[4..6] != [0x00, 0x00] {
+        let pes_packet_length = u16::try_from(data.len() - 6).unwrap_or(0);
+        data[4..6].copy_from_slice(&pes_packet_length.to_be_bytes());
+    }
+
+    data
+}
+
+fn decrypt_h264(elementary_stream: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(elementary_stream.len());
+    let mut position = 0;
+
+    for nal_unit_range in find_nal_units(elementary_stream) {
+        output.extend_from_slice(&elementary_stream[position..nal_unit_range.start]);
+
+        let nal_unit = &elementary_stream[nal_unit_range.clone()];
+        let nal_unit_type = nal_unit[0] & 0x1f;
+
+        // Only non-IDR and IDR slices are encrypted
+        if nal_unit.len() > 48 && (nal_unit_type == 1 || nal_unit_type == 5) {
+            output.extend(decrypt_nal_unit(nal_unit, key, iv));
+        } else {
+            output.extend_from_slice(nal_unit);
+        }
+
+        position = nal_unit_range.end;
+    }
+
+    output.extend_from_slice(&elementary_stream[position..]);
+    output
+}
+
+/// Returns the ranges of the NAL units, excluding start codes.
+fn find_nal_units(elementary_stream: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut starts = vec![];
+    let mut position = 0;
+
+    while position + 3 <= elementary_stream.len() {
+        if elementary_stream[position..position + 3] == [0x00, 0x00, 0x01] {
+            starts.push(position + 3);
+            position += 3;
+        } else {
+            position += 1;
+        }
+    }
+
+    let mut nal_units = Vec::with_capacity(starts.len());
+
+    for (index, &start) in starts.iter().enumerate() {
+        let mut end = starts
+            .get(index + 1)
+            .map(|&next_start| next_start - 3)
+            .unwrap_or(elementary_stream.len());
+
+        // Trailing zeros belong to the next start code
+        while end > start && elementary_stream[end - 1] == 0x00 {
+            end -= 1;
+        }
+
+        if end > start {
+            nal_units.push(start..end);
+        }
+    }
+
+    nal_units
+}
+
+/// The first 32 bytes are unencrypted, followed by one encrypted block and up to
+/// nine unencrypted blocks, which repeats. The last block is always unencrypted.
+/// The decrypted data may contain start codes, so it is escaped again.
+fn decrypt_nal_unit(nal_unit: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut data = remove_emulation_prevention_bytes(nal_unit);
+    let mut decryptor = cbc::Decryptor::<aes::Aes128>::new(key.as_slice().into(), iv.as_slice().into());
+    let mut position = 32;
+
+    while position + 16 < data.len() {
+        decryptor.decrypt_block_mut(aes::Block::from_mut_slice(&mut data[position..position + 16]));
+        position += 160;
+    }
+
+    add_emulation_prevention_bytes(&data)
+}
+
+fn remove_emulation_prevention_bytes(nal_unit: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(nal_unit.len());
+    let mut zeros = 0;
+
+    for &byte in nal_unit {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        output.push(byte);
+    }
+
+    output
+}
+
+fn add_emulation_prevention_bytes(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 64);
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte <= 0x03 {
+            output.push(0x03);
+            zeros = 0;
+        }
+
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        output.push(byte);
+    }
+
+    // Only a trailing cabac_zero_word ends with a zero, which would otherwise be
+    // taken as part of the next start code
+    if output.last() == Some(&0x00) {
+        output.push(0x03);
+    }
+
+    output
+}
+
+fn decrypt_aac(mut elementary_stream: Vec<u8>, key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut position = 0;
+
+    while position + 7 <= elementary_stream.len() {
+        let header = &elementary_stream[position..];
+
+        if header[0] != 0xff || header[1] & 0xf0 != 0xf0 {
+            break;
+        }
+
+        let header_length = if header[1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_length =
+            (usize::from(header[3] & 0x03) << 11) | (usize::from(header[4]) << 3) | (usize::from(header[5]) >> 5);
+
+        if frame_length < header_length || position + frame_length > elementary_stream.len() {
+            break;
+        }
+
+        decrypt_aac_frame(
+            &mut elementary_stream[position + header_length..position + frame_length],
+            key,
+            iv,
+        );
+        position += frame_length;
+    }
+
+    elementary_stream
+}
+
+/// The first 16 bytes and the last incomplete block are unencrypted.
+fn decrypt_aac_frame(frame: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    if frame.len() <= 16 {
+        return;
+    }
+
+    let encrypted_end = frame.len() - frame.len() % 16;
+    let mut decryptor = cbc::Decryptor::<aes::Aes128>::new(key.as_slice().into(), iv.as_slice().into());
+
+    for block in frame[16..encrypted_end].chunks_exact_mut(16) {
+        decryptor.decrypt_block_mut(aes::Block::from_mut_slice(block));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::cipher::BlockEncryptMut as _;
+
+    use super::*;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const IV: [u8; 16] = *b"fedcba9876543210";
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x0100;
+    const AUDIO_PID: u16 = 0x0101;
+
+    fn psi_packet(pid: u16, table_id: u8, body: &[u8]) -> Vec<u8> {
+        let mut section = vec![table_id, 0xb0, 0x00, 0x00, 0x01, 0xc1, 0x00, 0x00];
+        section.extend_from_slice(body);
+        let section_length = (section.len() - 3 + 4) as u16;
+        section[1] |= (section_length >> 8) as u8;
+        section[2] = section_length as u8;
+        let crc = crc32_mpeg2(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+
+        let mut payload = vec![0x00]; // pointer field
+        payload.extend_from_slice(&section);
+        payload.resize(TS_PACKET_SIZE - TS_HEADER_SIZE, 0xff);
+        build_packet(pid, true, None, &payload, &mut 0)
+    }
+
+    fn pes_packets(pid: u16, stream_id: u8, elementary_stream: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0x00, 0x00, 0x01, stream_id, 0x00, 0x00, 0x80, 0x00, 0x00];
+        if stream_id != 0xe0 {
+            let pes_packet_length = (elementary_stream.len() + 3) as u16;
+            pes[4..6].copy_from_slice(&pes_packet_length.to_be_bytes());
+        }
+        pes.extend_from_slice(elementary_stream);
+
+        let mut continuity_counter = 0;
+        pes.chunks(TS_PACKET_SIZE - TS_HEADER_SIZE)
+            .enumerate()
+            .flat_map(|(index, payload)| build_packet(pid, index == 0, None, payload, &mut continuity_counter))
+            .collect()
+    }
+
+    fn elementary_stream_of(segment: &[u8], pid: u16) -> Vec<u8> {
+        let pes: Vec<u8> = segment
+            .chunks_exact(TS_PACKET_SIZE)
+            .filter_map(|packet| {
+                let header = PacketHeader::parse(packet).unwrap();
+                (header.pid == pid).then(|| header.payload(packet).to_vec())
+            })
+            .flatten()
+            .collect();
+        pes[9 + pes[8] as usize..].to_vec()
+    }
+
+    fn encrypt(blocks: &mut [u8]) {
+        let mut encryptor = cbc::Encryptor::<aes::Aes128>::new(KEY.as_slice().into(), IV.as_slice().into());
+
+        for block in blocks.chunks_exact_mut(16) {
+            encryptor.encrypt_block_mut(aes::Block::from_mut_slice(block));
+        }
+    }
+
+    #[test]
+    fn test_crc32_mpeg2() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_e6e7);
+    }
+
+    #[test]
+    fn test_remove_emulation_prevention_bytes() {
+        assert_eq!(
+            remove_emulation_prevention_bytes(&[0x65, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03]),
+            vec![0x65, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_add_emulation_prevention_bytes() {
+        let data = [0x65, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00];
+        let escaped = add_emulation_prevention_bytes(&data);
+
+        assert_eq!(
+            escaped,
+            [0x65, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x03, 0x00, 0x00, 0x03]
+        );
+        assert!(find_nal_units(&escaped).is_empty());
+        assert_eq!(remove_emulation_prevention_bytes(&escaped), data);
+    }
+
+    #[test]
+    fn test_decrypt_segment() {
+        // Start codes and escapes in the clear data, also within the encrypted blocks
+        let clear_nal_unit: Vec<u8> = std::iter::once(0x65)
+            .chain((0..400u32).map(|i| match i % 40 {
+                4..=6 => 0x00,
+                20 | 21 => 0x00,
+                22 => 0x03,
+                _ => (i % 251) as u8 | 0x01,
+            }))
+            .collect();
+        let mut encrypted_nal_unit = clear_nal_unit.clone();
+        let encrypted_positions: Vec<usize> = (32..encrypted_nal_unit.len() - 16).step_by(160).collect();
+        let mut encrypted_blocks: Vec<u8> = encrypted_positions
+            .iter()
+            .flat_map(|&position| encrypted_nal_unit[position..position + 16].to_vec())
+            .collect();
+        encrypt(&mut encrypted_blocks);
+        for (&position, block) in encrypted_positions.iter().zip(encrypted_blocks.chunks_exact(16)) {
+            encrypted_nal_unit[position..position + 16].copy_from_slice(block);
+        }
+        let encrypted_nal_unit = add_emulation_prevention_bytes(&encrypted_nal_unit);
+
+        let sps = [0x67, 0x64, 0x00, 0x1f, 0xac];
+        let mut clear_video = vec![0x00, 0x00, 0x00, 0x01];
+        clear_video.extend_from_slice(&sps);
+        clear_video.extend_from_slice(&[0x00, 0x00, 0x01]);
+        let mut encrypted_video = clear_video.clone();
+        clear_video.extend(add_emulation_prevention_bytes(&clear_nal_unit));
+        encrypted_video.extend_from_slice(&encrypted_nal_unit);
+
+        let frame_length = 7 + 100;
+        let mut clear_audio = vec![
+            0xff,
+            0xf1,
+            0x50,
+            0x80,
+            (frame_length >> 3) as u8,
+            ((frame_length & 0x07) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        clear_audio.extend((0..100u8).map(|i| i.wrapping_mul(7)));
+        let mut encrypted_audio = clear_audio.clone();
+        encrypt(&mut encrypted_audio[7 + 16..7 + 96]);
+
+        let pat = psi_packet(PAT_PID, 0x00, &[0x00, 0x01, 0xe0 | (PMT_PID >> 8) as u8, PMT_PID as u8]);
+        let pmt = psi_packet(
+            PMT_PID,
+            0x02,
+            &[
+                0xe1,
+                0x00,
+                0xf0,
+                0x00,
+                STREAM_TYPE_H264_SAMPLE_AES,
+                0xe0 | (VIDEO_PID >> 8) as u8,
+                VIDEO_PID as u8,
+                0xf0,
+                0x00,
+                STREAM_TYPE_AAC_SAMPLE_AES,
+                0xe0 | (AUDIO_PID >> 8) as u8,
+                AUDIO_PID as u8,
+                0xf0,
+                0x00,
+            ],
+        );
+        let segment = [
+            pat,
+            pmt,
+            pes_packets(VIDEO_PID, 0xe0, &encrypted_video),
+            pes_packets(AUDIO_PID, 0xc0, &encrypted_audio),
+        ]
+        .concat();
+
+        assert_ne!(elementary_stream_of(&segment, VIDEO_PID), clear_video);
+        assert_ne!(elementary_stream_of(&segment, AUDIO_PID), clear_audio);
+
+        let decrypted = decrypt_segment(&segment, &KEY, &IV).unwrap();
+
+        assert_eq!(decrypted.len() % TS_PACKET_SIZE, 0);
+        assert_eq!(elementary_stream_of(&decrypted, VIDEO_PID), clear_video);
+        assert_eq!(elementary_stream_of(&decrypted, AUDIO_PID), clear_audio);
+
+        let pmt_packet = &decrypted[TS_PACKET_SIZE..2 * TS_PACKET_SIZE];
+        let pmt_payload = &pmt_packet[TS_HEADER_SIZE..];
+        let pmt_section = &pmt_payload[get_section_range(pmt_payload, 0x02).unwrap()];
+        assert_eq!(pmt_section[12], STREAM_TYPE_H264);
+        assert_eq!(pmt_section[17], STREAM_TYPE_AAC);
+        assert_eq!(
+            crc32_mpeg2(&pmt_section[..pmt_section.len() - 4]).to_be_bytes(),
+            pmt_section[pmt_section.len() - 4..]
+        );
+    }
+
+    #[test]
+    fn test_decrypt_segment_rejects_non_ts() {
+        assert!(decrypt_segment(&[0x00; TS_PACKET_SIZE], &KEY, &IV).is_err());
+    }
+}
@@ -0,0 +1,479 @@
+use std::fs::File;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::chrome::{self, ChromeDriver, WebDriverBackend};
+use crate::dirs;
+use crate::download::{DownloadManager, Downloader, EpisodeOutcome, NetworkConfig};
+use crate::downloaders::{
+    self, DispatchDownloader, DownloadRequest, DownloadSettings, DownloadTask, InstantiatedDownloader,
+};
+use crate::extractors::{self, ExtractedVideo};
+use crate::ffmpeg::Ffmpeg;
+use crate::logger::log_wrapper::SetLogWrapper;
+
+/// The browser and FFmpeg, which are set up once and then shared by all
+/// downloads of the session.
+///
+/// Custom extractors and cookies are shared by the whole process instead, and
+/// have to be set with [`extractors::custom::set_extractors`] and
+/// [`crate::cookies::set_cookies`] before the first download. Selector
+/// overrides are passed with the [`DownloadSettings`].
+pub struct Session {
+    /// Empty without a browser, and otherwise one per series scraped at once
+    drivers: Vec<(thirtyfour::WebDriver, Child)>,
+    /// Held until the session ends, so that no other run uses the browser profiles
+    _profile_locks: Vec<File>,
+    /// Shared by all downloaders, so that the progress bars of concurrent
+    /// series are drawn together
+    multi_progress: indicatif::MultiProgress,
+    /// `None` if FFmpeg is neither installed nor could be downloaded
+    ffmpeg_path: Option<PathBuf>,
+    user_agent: Option<String>,
+    proxy: Option<reqwest::Proxy>,
+    network: NetworkConfig,
+    retries: Option<NonZeroU32>,
+    debug: bool,
+}
+
+pub struct SessionBuilder {
+    data_dir: Option<PathBuf>,
+    browser: bool,
+    drivers: NonZeroUsize,
+    backend: WebDriverBackend,
+    ublock_rules: Option<PathBuf>,
+    user_data_dir: Option<PathBuf>,
+    user_agent: Option<String>,
+    ublock: bool,
+    update_ublock: bool,
+    driver_timeout: Duration,
+    browser_args: Vec<String>,
+    ffmpeg_path: Option<PathBuf>,
+    proxy: Option<Url>,
+    network: NetworkConfig,
+    retries: Option<NonZeroU32>,
+    debug: bool,
+    hide_progress_bars: bool,
+    log_wrapper: Option<SetLogWrapper>,
+}
+
+impl SessionBuilder {
+    /// Directory of the browser, FFmpeg and other assets. Defaults to the data
+    /// directory of sdl.
+    pub fn data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
+    /// Whether to start a browser, which is needed for downloading series,
+    /// but not for extracting video urls.
+    pub fn browser(mut self, browser: bool) -> Self {
+        self.browser = browser;
+        self
+    }
+
+    /// Number of browsers, each with its own profile, so that several series
+    /// can be scraped at once. Only used if a browser is started.
+    pub fn drivers(mut self, drivers: NonZeroUsize) -> Self {
+        self.drivers = drivers;
+        self
+    }
+
+    pub(crate) fn backend(mut self, backend: WebDriverBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub(crate) fn ublock_rules(mut self, ublock_rules: Option<PathBuf>) -> Self {
+        self.ublock_rules = ublock_rules;
+        self
+    }
+
+    /// Chrome profile, which keeps cookies between sessions. Defaults to a
+    /// directory in the data directory.
+    pub fn user_data_dir(mut self, user_data_dir: Option<PathBuf>) -> Self {
+        self.user_data_dir = user_data_dir;
+        self
+    }
+
+    /// User agent of the browser and all requests. Defaults to the one of the
+    /// browser, if it is started.
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Whether to load uBlock Origin into the browser.
+    pub fn ublock(mut self, ublock: bool) -> Self {
+        self.ublock = ublock;
+        self
+    }
+
+    /// Looks for a new release of uBlock Origin, even if it was checked recently.
+    pub(crate) fn update_ublock(mut self, update_ublock: bool) -> Self {
+        self.update_ublock = update_ublock;
+        self
+    }
+
+    /// Duration after which connecting to the starting WebDriver is given up.
+    pub fn driver_timeout(mut self, driver_timeout: Duration) -> Self {
+        self.driver_timeout = driver_timeout;
+        self
+    }
+
+    /// Additional command line arguments of the browser, e.g. `--lang=de-DE`.
+    /// They are added last, so that they take precedence over the defaults.
+    pub fn browser_args(mut self, browser_args: Vec<String>) -> Self {
+        self.browser_args = browser_args;
+        self
+    }
+
+    /// FFmpeg binary, which is used instead of an installed or downloaded one.
+    pub fn ffmpeg_path(mut self, ffmpeg_path: Option<PathBuf>) -> Self {
+        self.ffmpeg_path = ffmpeg_path;
+        self
+    }
+
+    /// Proxy for scraping and downloads.
+    pub fn proxy(mut self, proxy: Option<Url>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// IP family preference, connect timeout and connection pool of downloads.
+    pub(crate) fn network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Number of download retries, where `None` retries infinitely.
+    pub fn retries(mut self, retries: Option<NonZeroU32>) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Shows the browser window and the output of the WebDriver.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Hides the progress bars of all downloads, e.g. when the progress is
+    /// printed as JSON instead.
+    pub(crate) fn hide_progress_bars(mut self, hide_progress_bars: bool) -> Self {
+        self.hide_progress_bars = hide_progress_bars;
+        self
+    }
+
+    /// Log messages are printed above the progress bars of this logger.
+    pub(crate) fn log_wrapper(mut self, log_wrapper: SetLogWrapper) -> Self {
+        self.log_wrapper = Some(log_wrapper);
+        self
+    }
+
+    /// Fetches FFmpeg and starts the browser, if they are needed.
+    pub async fn start(mut self) -> Result<Session, anyhow::Error> {
+        let data_dir = match self.data_dir.take() {
+            Some(data_dir) => data_dir,
+            None => dirs::get_data_dir().await.context("failed to create data directory")?,
+        };
+        let proxy = self
+            .proxy
+            .as_ref()
+            .map(|proxy_url| reqwest::Proxy::all(proxy_url.clone()))
+            .transpose()
+            .context("failed to set up proxy")?;
+        let mut log_wrapper = self.log_wrapper.take().unwrap_or_else(SetLogWrapper::detached);
+        let multi_progress = indicatif::MultiProgress::new();
+        log_wrapper.set_multi(Some(multi_progress.clone()));
+
+        if self.hide_progress_bars {
+            multi_progress.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+        }
+
+        // Also fetches the browser and FFmpeg through the proxy
+        let asset_downloader = Downloader::new(
+            multi_progress.clone(),
+            self.debug,
+            None,
+            None,
+            Some(self.retries),
+            proxy.clone(),
+            &self.network,
+        );
+        let ffmpeg = Ffmpeg::new(data_dir.clone()).custom_path(self.ffmpeg_path.clone());
+        let has_custom_ffmpeg = self.ffmpeg_path.is_some();
+
+        let (drivers, profile_locks, ffmpeg_install_result) = if self.browser {
+            let driver_ffmpeg_future = futures_util::future::join(
+                self.start_driver(&data_dir, &asset_downloader, self.update_ublock),
+                ffmpeg.auto_download(&asset_downloader),
+            );
+            let (driver, ffmpeg_install_result) = tokio::select! {
+                biased;
+
+                result = driver_ffmpeg_future => result,
+                _ = asset_downloader.tick() => unreachable!(),
+            };
+            let mut drivers = vec![driver?];
+
+            // The browser is only fetched and uBlock Origin only updated for the first one
+            for _ in 1..self.drivers.get() {
+                let driver_result = tokio::select! {
+                    biased;
+
+                    result = self.start_driver(&data_dir, &asset_downloader, false) => result,
+                    _ = asset_downloader.tick() => unreachable!(),
+                };
+
+                match driver_result {
+                    Ok(driver) => drivers.push(driver),
+                    Err(err) => {
+                        log::warn!(
+                            "Failed to start another browser, scraping fewer series at once: {:#}",
+                            err
+                        );
+                        break;
+                    }
+                }
+            }
+
+            let (drivers, profile_locks): (Vec<_>, Vec<_>) = drivers.into_iter().unzip();
+            (
+                drivers,
+                profile_locks.into_iter().flatten().collect(),
+                ffmpeg_install_result,
+            )
+        } else {
+            let ffmpeg_install_result = tokio::select! {
+                biased;
+
+                result = ffmpeg.auto_download(&asset_downloader) => result,
+                _ = asset_downloader.tick() => unreachable!(),
+            };
+
+            (vec![], vec![], ffmpeg_install_result)
+        };
+
+        asset_downloader.clear();
+
+        let ffmpeg_path = match ffmpeg_install_result {
+            Ok(ffmpeg_path) => Some(ffmpeg_path),
+            // A binary supplied by the user is expected to be used
+            Err(err) if has_custom_ffmpeg => {
+                for driver in drivers {
+                    quit_driver(driver).await;
+                }
+
+                return Err(err).context("failed to get path to FFmpeg");
+            }
+            // Downloads still work without FFmpeg, only m3u8 streams are kept as .ts files
+            Err(err) => {
+                log::warn!(
+                    "Failed to get FFmpeg, m3u8 streams will not be converted and kept as .ts files: {:#}",
+                    err
+                );
+                None
+            }
+        };
+
+        let user_agent = match (self.user_agent, drivers.first()) {
+            (Some(user_agent), _) => Some(user_agent),
+            (None, Some((driver, _))) => chrome::get_user_agent(driver).await,
+            (None, None) => None,
+        };
+
+        Ok(Session {
+            drivers,
+            _profile_locks: profile_locks,
+            multi_progress,
+            ffmpeg_path,
+            user_agent,
+            proxy,
+            network: self.network,
+            retries: self.retries,
+            debug: self.debug,
+        })
+    }
+
+    /// Starts a browser with a profile, which is not used by another one.
+    async fn start_driver(
+        &self,
+        data_dir: &Path,
+        asset_downloader: &Downloader,
+        update_ublock: bool,
+    ) -> Result<((thirtyfour::WebDriver, Child), Option<File>), anyhow::Error> {
+        let (user_data_dir, profile_lock) = if self.backend == WebDriverBackend::Chrome {
+            let profile_dir = self
+                .user_data_dir
+                .clone()
+                .unwrap_or_else(|| data_dir.join("chrome-profile"));
+            let (user_data_dir, profile_lock) = chrome::lock_profile_dir(&profile_dir)
+                .await
+                .context("failed to lock browser profile")?;
+            (Some(user_data_dir), Some(profile_lock))
+        } else {
+            (None, None)
+        };
+        let driver = ChromeDriver::get(
+            data_dir,
+            asset_downloader,
+            !self.debug,
+            self.ublock_rules.as_deref(),
+            self.backend,
+            self.proxy.as_ref(),
+            self.user_agent.as_deref(),
+            user_data_dir.as_deref(),
+            self.ublock,
+            update_ublock,
+            self.driver_timeout,
+            &self.browser_args,
+        )
+        .await
+        .context("failed to create ChromeDriver")?;
+
+        Ok((driver, profile_lock))
+    }
+}
+
+impl Session {
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder {
+            data_dir: None,
+            browser: true,
+            drivers: NonZeroUsize::MIN,
+            backend: WebDriverBackend::Chrome,
+            ublock_rules: None,
+            user_data_dir: None,
+            user_agent: None,
+            ublock: true,
+            update_ublock: false,
+            driver_timeout: Duration::from_secs(5),
+            browser_args: vec![],
+            ffmpeg_path: None,
+            proxy: None,
+            network: NetworkConfig::default(),
+            retries: NonZeroU32::new(5),
+            debug: false,
+            hide_progress_bars: false,
+            log_wrapper: None,
+        }
+    }
+
+    pub(crate) fn driver(&self) -> Option<&thirtyfour::WebDriver> {
+        self.drivers.first().map(|(driver, _)| driver)
+    }
+
+    /// All browsers of the session, which may be fewer than requested, if
+    /// some failed to start.
+    pub(crate) fn drivers(&self) -> impl Iterator<Item = &thirtyfour::WebDriver> {
+        self.drivers.iter().map(|(driver, _)| driver)
+    }
+
+    pub(crate) fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    /// Creates a downloader, which uses the FFmpeg, user agent, proxy and
+    /// network settings of the session.
+    pub(crate) fn create_downloader(&self) -> Downloader {
+        Downloader::new(
+            self.multi_progress.clone(),
+            self.debug,
+            self.ffmpeg_path.clone(),
+            self.user_agent.clone(),
+            Some(self.retries),
+            self.proxy.clone(),
+            &self.network,
+        )
+    }
+
+    /// Extracts the video url from the page of a stream host.
+    pub async fn extract(&self, url: &str) -> Result<ExtractedVideo, anyhow::Error> {
+        match extractors::extract_video_url(url, self.user_agent.clone(), None).await {
+            Some(result) => Ok(result?),
+            None => anyhow::bail!("no extractor found for the url: {}", url),
+        }
+    }
+
+    /// Downloads the requested episodes of a series into the save directory.
+    /// The session is borrowed mutably, since the series is scraped with the
+    /// first browser, which must not scrape another series at the same time.
+    #[allow(dead_code)] // only used by the library
+    pub async fn download_series<F: FnMut() -> Duration>(
+        &mut self,
+        url: &str,
+        request: DownloadRequest,
+        settings: DownloadSettings<F>,
+        save_directory: PathBuf,
+    ) -> Result<(), anyhow::Error> {
+        let downloader = self.create_downloader();
+        let driver = self.driver().context("session was started without a browser")?;
+        let series_downloader = downloaders::find_downloader_for_url(driver, self.debug, url)
+            .await
+            .with_context(|| format!("no downloader found for the url: {url}"))?;
+        let series_info = series_downloader
+            .get_series_info()
+            .await
+            .context("failed to get series info")?;
+
+        let (download_manager, sender) = DownloadManager::new(downloader, None, save_directory, series_info);
+        download_with_manager(
+            &series_downloader,
+            request,
+            settings,
+            download_manager,
+            sender,
+            &CancellationToken::new(),
+        )
+        .await
+        .0
+    }
+
+    /// Quits the browsers.
+    pub async fn quit(self) {
+        for driver in self.drivers {
+            quit_driver(driver).await;
+        }
+    }
+}
+
+/// Scrapes the series, while the download manager downloads the episodes.
+/// Scraping stops, once the token is cancelled. The outcomes of the
+/// selected episodes are returned, even if scraping failed.
+pub(crate) async fn download_with_manager<F: FnMut() -> Duration>(
+    series_downloader: &DispatchDownloader<'_>,
+    request: DownloadRequest,
+    settings: DownloadSettings<F>,
+    download_manager: DownloadManager,
+    sender: Sender<DownloadTask>,
+    cancellation_token: &CancellationToken,
+) -> (Result<(), anyhow::Error>, Vec<EpisodeOutcome>) {
+    let download_manager = download_manager
+        .scrape_outcomes(settings.scrape_outcomes.clone())
+        .selected_episodes(settings.selected_episodes.clone());
+    let scrape_future = async {
+        tokio::select! {
+            result = series_downloader.download(request, settings, sender) => result,
+            _ = cancellation_token.cancelled() => Err(anyhow::anyhow!("download was cancelled")),
+        }
+    };
+    tokio::join!(scrape_future, download_manager.progress_downloads())
+}
+
+async fn quit_driver((driver, mut driver_process): (thirtyfour::WebDriver, Child)) {
+    if let Err(err) = driver.quit().await {
+        log::warn!("Failed to quit ChromeDriver: {}", err);
+    }
+
+    if let Err(err) = driver_process.kill() {
+        log::warn!("Failed to kill ChromeDriver: {}", err);
+    }
+}
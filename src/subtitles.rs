@@ -0,0 +1,305 @@
+use std::fmt::Write;
+
+/// Clock rate of the MPEG-TS timestamps in `X-TIMESTAMP-MAP`.
+const MPEGTS_CLOCK_RATE: f64 = 90_000.0;
+
+/// Cues starting at most this many seconds before their segment are still
+/// considered to have absolute timestamps, e.g. if they span two segments.
+const SEGMENT_START_TOLERANCE: f64 = 1.0;
+
+/// A WebVTT file of a segmented subtitle playlist.
+pub(crate) struct WebVttSegment {
+    pub(crate) content: String,
+    /// Seconds from the start of the playlist, i.e. the sum of the `#EXTINF`
+    /// durations of the previous segments
+    pub(crate) start: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    identifier: Option<String>,
+    start: f64,
+    end: f64,
+    settings: String,
+    text: String,
+}
+
+#[derive(Debug, Default)]
+struct ParsedWebVtt {
+    header: Vec<String>,
+    /// `MPEGTS` and `LOCAL` in seconds of the `X-TIMESTAMP-MAP` header
+    timestamp_map: Option<(u64, f64)>,
+    /// Style and region blocks, which have to come before the first cue
+    blocks: Vec<String>,
+    cues: Vec<Cue>,
+}
+
+/// Merges the segments into a single WebVTT file, whose cues are relative to
+/// the start of the stream.
+///
+/// The cues of a segment are shifted by its `X-TIMESTAMP-MAP`, relative to the
+/// one of the first segment. If they still start before the segment, they
+/// were relative to the segment, and are shifted by its start as well. Cues
+/// repeated at the start of the next segment are only kept once.
+pub(crate) fn merge_webvtt(segments: &[WebVttSegment]) -> String {
+    let mut header = None;
+    let mut blocks = vec![];
+    let mut cues: Vec<Cue> = vec![];
+    let mut first_mpegts = None;
+
+    for segment in segments {
+        let parsed = parse_webvtt(&segment.content);
+        let mut offset = match parsed.timestamp_map {
+            Some((mpegts, local)) => {
+                let first_mpegts = *first_mpegts.get_or_insert(mpegts);
+                (mpegts as f64 - first_mpegts as f64) / MPEGTS_CLOCK_RATE - local
+            }
+            None => 0.0,
+        };
+
+        if let Some(earliest) = parsed.cues.iter().map(|cue| cue.start + offset).reduce(f64::min) {
+            if earliest < segment.start - SEGMENT_START_TOLERANCE {
+                offset += segment.start;
+            }
+        }
+
+        if header.is_none() {
+            header = Some(parsed.header);
+            blocks = parsed.blocks;
+        }
+
+        for mut cue in parsed.cues {
+            cue.start += offset;
+            cue.end += offset;
+
+            if cues.last() != Some(&cue) {
+                cues.push(cue);
+            }
+        }
+    }
+
+    let mut output = String::new();
+
+    match header {
+        Some(header) if !header.is_empty() => output.push_str(&header.join("\n")),
+        _ => output.push_str("WEBVTT"),
+    }
+
+    output.push_str("\n\n");
+
+    for block in blocks {
+        output.push_str(&block);
+        output.push_str("\n\n");
+    }
+
+    for cue in cues {
+        if let Some(identifier) = &cue.identifier {
+            output.push_str(identifier);
+            output.push('\n');
+        }
+
+        let _ = write!(
+            output,
+            "{} --> {}",
+            format_timestamp(cue.start),
+            format_timestamp(cue.end)
+        );
+
+        if !cue.settings.is_empty() {
+            output.push(' ');
+            output.push_str(&cue.settings);
+        }
+
+        output.push('\n');
+        output.push_str(&cue.text);
+        output.push_str("\n\n");
+    }
+
+    output
+}
+
+fn parse_webvtt(content: &str) -> ParsedWebVtt {
+    let content = content.trim_start_matches('\u{FEFF}').replace("\r\n", "\n");
+    let mut parsed = ParsedWebVtt::default();
+    let mut blocks = content
+        .split("\n\n")
+        .map(|block| block.trim_matches('\n'))
+        .filter(|block| !block.is_empty());
+
+    let Some(header) = blocks.next() else {
+        return parsed;
+    };
+
+    for line in header.lines() {
+        match line.strip_prefix("X-TIMESTAMP-MAP=") {
+            Some(timestamp_map) => parsed.timestamp_map = parse_timestamp_map(timestamp_map),
+            None => parsed.header.push(line.to_owned()),
+        }
+    }
+
+    for block in blocks {
+        match parse_cue(block) {
+            Some(cue) => parsed.cues.push(cue),
+            // Comments are dropped, and other blocks are only allowed before the first cue
+            None if parsed.cues.is_empty() && !block.starts_with("NOTE") => parsed.blocks.push(block.to_owned()),
+            None => {}
+        }
+    }
+
+    parsed
+}
+
+fn parse_cue(block: &str) -> Option<Cue> {
+    let mut lines = block.lines();
+    let first_line = lines.next()?;
+    let (identifier, timing) = if first_line.contains("-->") {
+        (None, first_line)
+    } else {
+        (
+            Some(first_line.to_owned()),
+            lines.next().filter(|line| line.contains("-->"))?,
+        )
+    };
+
+    let (start, rest) = timing.split_once("-->")?;
+    let rest = rest.trim();
+    let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    Some(Cue {
+        identifier,
+        start: parse_timestamp(start.trim())?,
+        end: parse_timestamp(end)?,
+        settings: settings.trim().to_owned(),
+        text: lines.collect::<Vec<_>>().join("\n"),
+    })
+}
+
+/// Parses e.g. `MPEGTS:900000,LOCAL:00:00:00.000`.
+fn parse_timestamp_map(timestamp_map: &str) -> Option<(u64, f64)> {
+    let mut mpegts = None;
+    let mut local = None;
+
+    for part in timestamp_map.split(',') {
+        match part.trim().split_once(':')? {
+            ("MPEGTS", value) => mpegts = value.parse().ok(),
+            ("LOCAL", value) => local = parse_timestamp(value),
+            _ => {}
+        }
+    }
+
+    Some((mpegts?, local?))
+}
+
+/// Parses `hh:mm:ss.ttt` or `mm:ss.ttt` into seconds.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut parts = timestamp.rsplit(':');
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let hours: u64 = match parts.next() {
+        Some(hours) => hours.parse().ok()?,
+        None => 0,
+    };
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((hours * 3600 + minutes * 60) as f64 + seconds)
+}
+
+fn format_timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::subtitles::{format_timestamp, merge_webvtt, parse_timestamp, WebVttSegment};
+
+    fn segments(segments: &[(&str, f64)]) -> Vec<WebVttSegment> {
+        segments
+            .iter()
+            .map(|&(content, start)| WebVttSegment {
+                content: content.to_owned(),
+                start,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(parse_timestamp("01:02:03.250"), Some(3723.25));
+        assert_eq!(parse_timestamp("02:03.500"), Some(123.5));
+        assert_eq!(parse_timestamp("1:2:3:4.000"), None);
+        assert_eq!(format_timestamp(3723.25), "01:02:03.250");
+        assert_eq!(format_timestamp(-1.0), "00:00:00.000");
+    }
+
+    #[test]
+    fn test_merge_absolute_cues() {
+        let segments = segments(&[
+            (
+                "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\n\nSTYLE\n::cue { color: yellow }\n\n\
+                 1\n00:00:01.000 --> 00:00:02.000 line:90%\nHello\n\n00:09.500 --> 00:10.500\nAcross\n",
+                0.0,
+            ),
+            (
+                "WEBVTT\r\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\r\n\r\n\
+                 00:00:09.500 --> 00:00:10.500\r\nAcross\r\n\r\n00:00:11.000 --> 00:00:12.000\r\nWorld\r\n",
+                10.0,
+            ),
+            ("WEBVTT\n", 20.0),
+        ]);
+
+        assert_eq!(
+            merge_webvtt(&segments),
+            "WEBVTT\n\nSTYLE\n::cue { color: yellow }\n\n\
+             1\n00:00:01.000 --> 00:00:02.000 line:90%\nHello\n\n\
+             00:00:09.500 --> 00:00:10.500\nAcross\n\n\
+             00:00:11.000 --> 00:00:12.000\nWorld\n\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_relative_cues() {
+        // Without a timestamp map, the cues start at zero in each segment
+        let segments = segments(&[
+            ("WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello\n", 0.0),
+            ("WEBVTT\n\n00:00:01.500 --> 00:00:03.000\nWorld\n", 6.006),
+            (
+                "WEBVTT\n\nNOTE skipped\n\n00:00:00.000 --> 00:00:01.000\nAgain\n",
+                12.012,
+            ),
+        ]);
+
+        assert_eq!(
+            merge_webvtt(&segments),
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello\n\n\
+             00:00:07.506 --> 00:00:09.006\nWorld\n\n\
+             00:00:12.012 --> 00:00:13.012\nAgain\n\n"
+        );
+
+        // The timestamp map shifts the cues relative to the first segment
+        let segments = segments(&[
+            (
+                "WEBVTT\nX-TIMESTAMP-MAP=LOCAL:00:00:10.000,MPEGTS:900000\n\n00:00:11.000 --> 00:00:12.000\nHello\n",
+                0.0,
+            ),
+            (
+                "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:1800000,LOCAL:00:00:00.000\n\n00:00:02.000 --> 00:00:03.000\nWorld\n",
+                10.0,
+            ),
+        ]);
+
+        assert_eq!(
+            merge_webvtt(&segments),
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello\n\n00:00:12.000 --> 00:00:13.000\nWorld\n\n"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::cmp::Ordering;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+
+use crate::download;
+use crate::utils::parse_version_cache;
+
+const RELEASES_GITHUB_API_URL: &str = "https://api.github.com/repos/Funami580/sdl/releases/latest";
+const RELEASES_URL: &str = "https://github.com/Funami580/sdl/releases/latest";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Logs a message if a newer release is available. The GitHub API is queried
+/// at most once per [CHECK_INTERVAL], otherwise the cached result is used.
+pub(crate) async fn check_for_updates(data_dir: &Path) -> Result<(), anyhow::Error> {
+    let cache_file = data_dir.join("last_update_check");
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .context("system time before Unix epoch")?
+        .as_secs();
+
+    let cached_latest_version = match tokio::fs::read_to_string(&cache_file).await {
+        Ok(contents) => parse_version_cache(&contents).and_then(|(checked_at, latest_version)| {
+            if now.saturating_sub(checked_at) < CHECK_INTERVAL.as_secs() {
+                Some(latest_version)
+            } else {
+                None
+            }
+        }),
+        Err(err) => {
+            if err.kind() != ErrorKind::NotFound {
+                log::warn!("Failed to read update check cache file: {err}");
+            }
+
+            None
+        }
+    };
+
+    let latest_version = match cached_latest_version {
+        Some(latest_version) => {
+            log::trace!("Using cached update check result");
+            latest_version
+        }
+        None => {
+            let github_response = download::get_page_json(RELEASES_GITHUB_API_URL, None, None, None).await?;
+            let latest_version = github_response
+                .get("tag_name")
+                .and_then(|tag_name| tag_name.as_str())
+                .context("unexpected GitHub API json response")?
+                .trim()
+                .to_owned();
+
+            if let Err(err) = tokio::fs::write(&cache_file, format!("{now}\n{latest_version}")).await {
+                log::warn!("Failed to write update check cache file: {err}");
+            }
+
+            latest_version
+        }
+    };
+
+    let current_version = clap::crate_version!();
+
+    if compare_versions(&latest_version, current_version) == Some(Ordering::Greater) {
+        log::info!(
+            "A new version of sdl is available: {} (current: {}), see {}",
+            latest_version,
+            current_version,
+            RELEASES_URL
+        );
+    } else {
+        log::trace!("sdl is up-to-date");
+    }
+
+    Ok(())
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    let version = version.split(['-', '+']).next()?;
+
+    version.split('.').map(|part| part.parse::<u64>().ok()).collect()
+}
+
+fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    let mut a = parse_version(a)?;
+    let mut b = parse_version(b)?;
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+
+    Some(a.cmp(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::compare_versions;
+
+    #[test]
+    fn test_compare_versions() {
+        let tests = [
+            (("v0.1.4", "0.1.3"), Some(Ordering::Greater)),
+            (("v0.1.3", "0.1.3"), Some(Ordering::Equal)),
+            (("0.1", "0.1.0"), Some(Ordering::Equal)),
+            (("v0.1.2", "0.1.3"), Some(Ordering::Less)),
+            (("v0.2.0-beta", "0.1.3"), Some(Ordering::Greater)),
+            (("v1.10.0", "1.9.0"), Some(Ordering::Greater)),
+            (("nightly", "0.1.3"), None),
+        ];
+
+        for ((a, b), expected) in tests {
+            assert_eq!(compare_versions(a, b), expected, "failed for {a} and {b}");
+        }
+    }
+}
@@ -14,3 +14,32 @@ pub(crate) async fn remove_dir_all_ignore_not_exists(path: impl AsRef<Path>) ->
         _ => Ok(()),
     }
 }
+
+/// Parses the timestamp and latest version of a cached release lookup, which
+/// are written as `{timestamp}\n{version}`.
+pub(crate) fn parse_version_cache(contents: &str) -> Option<(u64, String)> {
+    let (checked_at, latest_version) = contents.trim().split_once('\n')?;
+    let checked_at = checked_at.trim().parse::<u64>().ok()?;
+    let latest_version = latest_version.trim();
+
+    if latest_version.is_empty() {
+        None
+    } else {
+        Some((checked_at, latest_version.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_version_cache;
+
+    #[test]
+    fn test_parse_version_cache() {
+        assert_eq!(
+            parse_version_cache("1700000000\nv0.1.3\n"),
+            Some((1700000000, "v0.1.3".to_string()))
+        );
+        assert_eq!(parse_version_cache("1700000000\n"), None);
+        assert_eq!(parse_version_cache("garbage"), None);
+    }
+}